@@ -1,16 +1,109 @@
-use scratchc::{common::location::SourceId, frontend::parser::lexer::Tokens};
+use std::path::PathBuf;
+
+use scratchc::{
+    common::{
+        diagnostic::{ColorChoice, DiagnosticRenderer, ToDiagnostic},
+        source_map::SourceMap,
+    },
+    frontend::{lower, resolve::Resolver, typeck},
+    mir::{self, MirRefinementConfig, MirRefinery},
+};
+
+const USAGE: &str = "usage: catnip <file.sl> [-o <output>] [--debug] [--emit=sb3|mir]";
 
 fn main() {
-    const EXAMPLE: &'static str = "
-    sprite Stage {
-        costumes {
-            @default background1: \"../prototyping/background.svg\"
+    let mut args = std::env::args().skip(1);
+    let mut input = None;
+    let mut output = None;
+    let mut debug = false;
+    let mut emit_mir = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next().map(PathBuf::from),
+            "--debug" => debug = true,
+            "--emit=sb3" => emit_mir = false,
+            "--emit=mir" => emit_mir = true,
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => {
+                eprintln!("{USAGE}");
+                std::process::exit(1);
+            }
+        }
+    }
+    let Some(input) = input else {
+        eprintln!("{USAGE}");
+        std::process::exit(1);
+    };
+    let extension = if emit_mir { "mir" } else { "sb3" };
+    let output = output.unwrap_or_else(|| input.with_extension(extension));
+
+    let mut source_map = SourceMap::new();
+    let source = match source_map.load(&input) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("error: could not read `{}`: {error}", input.display());
+            std::process::exit(1);
+        }
+    };
+
+    let mut resolver = Resolver::new(&mut source_map);
+    resolver.add_builtin_libraries();
+    let result = resolver.resolve(source);
+    let renderer = DiagnosticRenderer::new(&source_map, ColorChoice::Auto);
+    let program = match result {
+        Ok(program) => program,
+        Err(errors) => report(&renderer, &errors),
+    };
+    let types = match typeck::check(&program) {
+        Ok(types) => types,
+        Err(errors) => report(&renderer, &errors),
+    };
+    let mut project = match lower::lower(&program, &types, &source_map) {
+        Ok(project) => project,
+        Err(errors) => report(&renderer, &errors),
+    };
+
+    // Optimizations expect well-formed MIR, so It is verified before them too.
+    if let Err(errors) = mir::verify(&project) {
+        report(&renderer, &errors);
+    }
+    // Dead code is reported before inlining, which leaves behind procedures and locals
+    // nobody wrote, those are removed silently afterwards.
+    let dead_code = mir::eliminate_dead_code(&mut project);
+    if !dead_code.is_empty() {
+        renderer.emit(&dead_code);
+    }
+    mir::inline_procedures(&mut project);
+    mir::eliminate_dead_code(&mut project);
+    mir::fold_constants(&mut project);
+    if debug || cfg!(debug_assertions) {
+        if let Err(errors) = mir::verify(&project) {
+            report(&renderer, &errors);
+        }
+    }
+
+    if emit_mir {
+        if let Err(error) = std::fs::write(&output, project.to_string()) {
+            eprintln!("error: could not write `{}`: {error}", output.display());
+            std::process::exit(1);
         }
+        return;
+    }
+
+    let config = MirRefinementConfig::default().check_bounds(debug);
+    let builder = match MirRefinery::new(config).refine_project(project) {
+        Ok(builder) => builder,
+        Err(error) => report(&renderer, &[error]),
+    };
+    if let Err(error) = builder.bundle_project(&output) {
+        eprintln!("error: could not write `{}`: {error}", output.display());
+        std::process::exit(1);
     }
-    ";
+}
 
-    let mut tokens = Tokens::from_string(EXAMPLE, SourceId::dummy());
-    loop {
-        println!("{:?}", tokens.next().unwrap());
+fn report(renderer: &DiagnosticRenderer, errors: &[impl ToDiagnostic]) -> ! {
+    for error in errors.iter() {
+        renderer.emit(error);
     }
+    std::process::exit(1);
 }
@@ -1,4 +1,7 @@
-use std::{cell::RefMut, collections::HashMap};
+use std::{
+    cell::{RefCell, RefMut},
+    collections::HashMap,
+};
 
 use crate::schema::{self, ProjectBlocks};
 
@@ -8,20 +11,19 @@ pub struct BlocksBuilder<'blocks> {
     blocks: RefMut<'blocks, ProjectBlocks>,
 
     previous: Option<String>,
-    stack: Vec<String>,
-    awaiting_push: bool,
 
-    procedures: HashMap<String, ProcedureDefinition>,
+    procedures: &'blocks RefCell<HashMap<String, ProcedureDefinition>>,
 }
 
 impl<'blocks> BlocksBuilder<'blocks> {
-    pub(super) fn new(blocks: RefMut<'blocks, ProjectBlocks>) -> Self {
+    pub(super) fn new(
+        blocks: RefMut<'blocks, ProjectBlocks>,
+        procedures: &'blocks RefCell<HashMap<String, ProcedureDefinition>>,
+    ) -> Self {
         Self {
             blocks,
             previous: None,
-            stack: Vec::new(),
-            awaiting_push: true,
-            procedures: HashMap::new(),
+            procedures,
         }
     }
 
@@ -29,22 +31,8 @@ impl<'blocks> BlocksBuilder<'blocks> {
         BlockBuilder::new(self, block_id)
     }
 
-    fn await_push(&mut self) {
-        self.awaiting_push = true
-    }
-
-    fn peek_stack(&mut self) -> String {
-        self.stack.last().unwrap().clone()
-    }
-
-    fn pop_stack(&mut self) -> String {
-        self.stack.pop().unwrap()
-    }
-
     pub fn end_stack(&mut self) {
-        self.stack.clear();
         self.previous = None;
-        self.awaiting_push = true;
     }
 
     pub fn block<'a>(
@@ -73,11 +61,6 @@ impl<'blocks> BlocksBuilder<'blocks> {
             },
         );
 
-        if self.awaiting_push {
-            self.stack.push(id.clone());
-            self.awaiting_push = false;
-        }
-
         if !is_expression {
             if let Some(previous) = &self.previous {
                 let previous = self.blocks.blocks.get_mut(previous).unwrap();
@@ -90,6 +73,24 @@ impl<'blocks> BlocksBuilder<'blocks> {
         BlockBuilder::new(self, id)
     }
 
+    /// Builds substack of the `parent` block, returns Its first block.
+    /// Blocks created after this call are chained after the `parent`.
+    fn substack(&mut self, parent: &str, flow: impl FnOnce(&mut BlocksBuilder)) -> Option<String> {
+        self.previous = Some(parent.to_owned());
+        flow(self);
+        self.previous = Some(parent.to_owned());
+
+        // First block of the substack was chained after the parent.
+        self.blocks.blocks.get_mut(parent).unwrap().next.take()
+    }
+
+    fn set_substack(&mut self, block: &str, name: &str, substack: Option<String>) {
+        if let Some(substack) = substack {
+            self.get_block_builder(block.to_owned())
+                .set_input(name, &[schema::Value::Pointer(substack)]);
+        }
+    }
+
     pub fn control_if(
         &mut self,
         condition: impl FnOnce(&mut BlocksBuilder) -> String,
@@ -97,14 +98,11 @@ impl<'blocks> BlocksBuilder<'blocks> {
     ) {
         let control_block = self.block("control_if", false).finish(); // We need to drop mutable reference.
         let condition_id = condition(self);
-        self.await_push(); // Record first block being pushed.
-        flow(self);
-        let substack_id = self.pop_stack();
+        let substack = self.substack(&control_block, flow);
 
-        let mut control_block = self.get_block_builder(control_block);
-        control_block
-            .set_input("CONDITION", &[schema::Value::Pointer(condition_id)])
-            .set_input("SUBSTACK", &[schema::Value::Pointer(substack_id)]);
+        self.get_block_builder(control_block.clone())
+            .set_input("CONDITION", &[schema::Value::Pointer(condition_id)]);
+        self.set_substack(&control_block, "SUBSTACK", substack);
     }
 
     pub fn control_if_else(
@@ -115,117 +113,162 @@ impl<'blocks> BlocksBuilder<'blocks> {
     ) {
         let control_block = self.block("control_if_else", false).finish(); // We need to drop mutable reference.
         let condition_id = condition(self);
-        self.await_push(); // Record first block being pushed.
-        flow_true(self);
-        let substack_true_id = self.pop_stack();
-        self.await_push(); // Record first block being pushed.
-        flow_false(self);
-        let substack_false_id = self.pop_stack();
-
-        let mut control_block = self.get_block_builder(control_block);
-        control_block
-            .set_input("CONDITION", &[schema::Value::Pointer(condition_id)])
-            .set_input("SUBSTACK", &[schema::Value::Pointer(substack_true_id)])
-            .set_input("SUBSTACK2", &[schema::Value::Pointer(substack_false_id)]);
+        let substack_true = self.substack(&control_block, flow_true);
+        let substack_false = self.substack(&control_block, flow_false);
+
+        self.get_block_builder(control_block.clone())
+            .set_input("CONDITION", &[schema::Value::Pointer(condition_id)]);
+        self.set_substack(&control_block, "SUBSTACK", substack_true);
+        self.set_substack(&control_block, "SUBSTACK2", substack_false);
     }
 
-    pub fn define_procedure(
+    pub fn control_repeat(
         &mut self,
-        name: impl AsRef<str>,
-        arguments: HashMap<String, ProcedureArgumentType>,
-        warp: bool,
+        times: impl FnOnce(&mut BlocksBuilder) -> schema::Value,
+        flow: impl FnOnce(&mut BlocksBuilder),
     ) {
-        let mut def = ProcedureDefinition::default();
-        def.warp = warp;
+        let control_block = self.block("control_repeat", false).finish();
+        let times = times(self);
+        let substack = self.substack(&control_block, flow);
+
+        self.get_block_builder(control_block.clone())
+            .set_input("TIMES", &[times]);
+        self.set_substack(&control_block, "SUBSTACK", substack);
+    }
+
+    pub fn control_repeat_until(
+        &mut self,
+        condition: impl FnOnce(&mut BlocksBuilder) -> String,
+        flow: impl FnOnce(&mut BlocksBuilder),
+    ) {
+        let control_block = self.block("control_repeat_until", false).finish();
+        let condition_id = condition(self);
+        let substack = self.substack(&control_block, flow);
+
+        self.get_block_builder(control_block.clone())
+            .set_input("CONDITION", &[schema::Value::Pointer(condition_id)]);
+        self.set_substack(&control_block, "SUBSTACK", substack);
+    }
+
+    /// Forever is a cap block, nothing can be chained after It.
+    pub fn control_forever(&mut self, flow: impl FnOnce(&mut BlocksBuilder)) {
+        let control_block = self.block("control_forever", false).finish();
+        let substack = self.substack(&control_block, flow);
+        self.set_substack(&control_block, "SUBSTACK", substack);
+    }
+
+    /// Stops `"all"`, `"this script"` or `"other scripts in sprite"`.
+    pub fn control_stop(&mut self, option: impl AsRef<str>) {
+        self.block("control_stop", false).set_field(
+            "STOP_OPTION",
+            schema::BlockField::Argument(option.as_ref().to_owned()),
+        );
+    }
+
+    /// Creates clone of the sprite with the name, `"_myself_"` clones the sprite
+    /// running the block.
+    pub fn control_create_clone_of(&mut self, sprite: impl AsRef<str>) {
+        let mut menu = self.block("control_create_clone_of_menu", true);
+        menu.block_ref().shadow = true;
+        menu.set_field(
+            "CLONE_OPTION",
+            schema::BlockField::Argument(sprite.as_ref().to_owned()),
+        );
+        let menu = menu.finish();
+        self.block("control_create_clone_of", false)
+            .set_input("CLONE_OPTION", &[schema::Value::Pointer(menu)]);
+    }
+
+    /// Defines procedure declared with [`super::SpriteBuilder::declare_procedure`].
+    pub fn define_procedure(&mut self, name: impl AsRef<str>) {
+        let def = self.procedure(name);
 
         let proc_definition = self.block("procedures_definition", false).finish();
         let mut proc_prototype = self.block("procedures_prototype", true);
         proc_prototype.override_parent(Some(proc_definition.clone()));
         let proc_prototype = proc_prototype.finish();
 
-        {
-            let mut mutation = schema::BlockMutation::default();
-            mutation.warp = warp;
-
-            // Build proccode from arguments
-            let proccode = format!(
-                "{} {}",
-                name.as_ref(),
-                arguments
-                    .iter()
-                    .map(|(_, ty)| match ty {
-                        ProcedureArgumentType::NumberOrText => "%s",
-                        ProcedureArgumentType::Boolean => "%b",
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ")
+        let mut mutation = schema::BlockMutation {
+            proccode: def.proccode.clone(),
+            warp: def.warp,
+            returns: def.returns,
+            ..Default::default()
+        };
+        let mut proto_reporters = Vec::new();
+        for (id, name, ty) in def.arguments.iter() {
+            mutation.argument_ids.push(id.clone());
+            mutation.argument_names.push(name.clone());
+            mutation.argument_defaults.push(
+                match ty {
+                    ProcedureArgumentType::NumberOrText => "",
+                    ProcedureArgumentType::Boolean => "false",
+                }
+                .to_string(),
             );
-            def.proccode = proccode.clone();
-            mutation.proccode = proccode;
-
-            let mut proto_reporters = Vec::new();
-            for (name, ty) in arguments.iter() {
-                let id = generate_next_id();
-
-                // Assign arguments.
-                def.arguments.push((id.clone(), name.clone()));
-                mutation.argument_ids.push(id.clone());
-                mutation.argument_names.push(name.clone());
-                mutation.argument_defaults.push(
-                    match ty {
-                        ProcedureArgumentType::NumberOrText => "",
-                        ProcedureArgumentType::Boolean => "false",
-                    }
-                    .to_string(),
-                );
-
-                // Create reporter blocks.
-                let mut reporter = self.block(
-                    match ty {
-                        ProcedureArgumentType::NumberOrText => "argument_reporter_string_number",
-                        ProcedureArgumentType::Boolean => "argument_reporter_boolean",
-                    },
-                    true,
-                );
-                reporter.override_parent(Some(proc_prototype.clone()));
-                reporter.set_field("VALUE", schema::BlockField::Argument(name.clone()));
-
-                proto_reporters.push(reporter.finish());
-            }
 
-            let mut proc_prototype = self.get_block_builder(proc_prototype);
-            proc_prototype.block_ref().mutation = Some(mutation);
-            for (id, reporter) in def.arguments.iter().zip(proto_reporters.iter()) {
-                proc_prototype.set_input(id.0.clone(), &[schema::Value::Pointer(reporter.clone())]);
-            }
+            // Create reporter blocks.
+            let mut reporter = self.block(ty.reporter_opcode(), true);
+            reporter.override_parent(Some(proc_prototype.clone()));
+            reporter.set_field("VALUE", schema::BlockField::Argument(name.clone()));
+
+            proto_reporters.push(reporter.finish());
+        }
 
-            let proc_prototype = proc_prototype.finish();
-            self.get_block_builder(proc_definition)
-                .set_input("custom_block", &[schema::Value::Pointer(proc_prototype)]);
+        let mut proc_prototype = self.get_block_builder(proc_prototype);
+        proc_prototype.block_ref().mutation = Some(mutation);
+        for ((id, ..), reporter) in def.arguments.iter().zip(proto_reporters) {
+            proc_prototype.set_input(id, &[schema::Value::Pointer(reporter)]);
         }
+
+        let proc_prototype = proc_prototype.finish();
+        self.get_block_builder(proc_definition)
+            .set_input("custom_block", &[schema::Value::Pointer(proc_prototype)]);
     }
 
-    pub fn get_arguments_for_procedure(&self, name: impl AsRef<str>) -> &Vec<(String, String)> {
-        &self.procedures.get(name.as_ref()).unwrap().arguments
+    /// Returns value from the custom reporter, requires TurboWarp.
+    pub fn procedure_return(&mut self, value: schema::Value) {
+        self.block("procedures_return", false)
+            .set_input("VALUE", &[value]);
     }
 
-    pub fn call_procedure(&mut self, name: impl AsRef<str>, arguments: &[schema::Value]) {
-        let procedure = self.procedures.get(name.as_ref()).unwrap().clone();
+    fn procedure(&self, name: impl AsRef<str>) -> ProcedureDefinition {
+        self.procedures
+            .borrow()
+            .get(name.as_ref())
+            .unwrap_or_else(|| panic!("Procedure `{}` should be declared", name.as_ref()))
+            .clone()
+    }
 
-        let mut call = self.block("procedures_call", false);
+    /// Ids and names of the procedure's arguments.
+    pub fn get_arguments_for_procedure(&self, name: impl AsRef<str>) -> Vec<(String, String)> {
+        self.procedure(name)
+            .arguments
+            .into_iter()
+            .map(|(id, name, _)| (id, name))
+            .collect()
+    }
+
+    /// Calls procedure with arguments in order of Its declaration.
+    /// Procedures returning values are called with reporter blocks, returns id of the call.
+    pub fn call_procedure(&mut self, name: impl AsRef<str>, arguments: &[schema::Value]) -> String {
+        let procedure = self.procedure(name);
+
+        let mut call = self.block("procedures_call", procedure.returns);
         call.block_ref().mutation = Some(schema::BlockMutation {
             warp: procedure.warp,
+            returns: procedure.returns,
             proccode: procedure.proccode.clone(),
             argument_ids: procedure
                 .arguments
                 .iter()
-                .map(|arg| arg.0.clone())
+                .map(|(id, ..)| id.clone())
                 .collect(),
             ..Default::default()
         });
-        for (value, id) in arguments.iter().zip(procedure.arguments.iter()) {
-            call.set_input(&id.0, &[value.clone()]);
+        for (value, (id, ..)) in arguments.iter().zip(procedure.arguments.iter()) {
+            call.set_input(id, std::slice::from_ref(value));
         }
+        call.finish()
     }
 }
 
@@ -235,11 +278,22 @@ pub enum ProcedureArgumentType {
     Boolean,
 }
 
+impl ProcedureArgumentType {
+    fn reporter_opcode(&self) -> &'static str {
+        match self {
+            Self::NumberOrText => "argument_reporter_string_number",
+            Self::Boolean => "argument_reporter_boolean",
+        }
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct ProcedureDefinition {
-    pub arguments: Vec<(String, String)>,
+    /// Id, name and type of every argument.
+    pub arguments: Vec<(String, String, ProcedureArgumentType)>,
     pub proccode: String,
     pub warp: bool,
+    pub returns: bool,
 }
 
 pub struct BlockBuilder<'a, 'b: 'a> {
@@ -264,10 +318,15 @@ impl<'a, 'b: 'a> BlockBuilder<'a, 'b> {
     }
 
     pub fn set_input(&mut self, name: impl AsRef<str>, values: &[schema::Value]) -> &mut Self {
+        // Menus are shadow blocks.
+        let shadow = match &values[0] {
+            schema::Value::Pointer(ptr) => self.builder.blocks.blocks[ptr].shadow,
+            value => value.should_shadow(),
+        };
         self.block_ref().inputs.insert(
             name.as_ref().to_owned(),
             schema::BlockInput {
-                kind: if values[0].should_shadow() {
+                kind: if shadow {
                     1
                 } else {
                     if values.len() > 1 {
@@ -2,8 +2,6 @@ mod blocks;
 mod project;
 mod sprite;
 
-type ProjectCell = std::rc::Rc<std::cell::RefCell<crate::schema::Project>>;
-
 pub use blocks::*;
 pub use project::*;
 pub use sprite::*;
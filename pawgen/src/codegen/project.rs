@@ -9,8 +9,10 @@ use super::SpriteBuilder;
 
 /// Builder for scratch projects.
 /// This handles everything from json generation to asset bundling.
+/// Sprites are built independently, possibly on other threads, and become part
+/// of the project once they are added to It.
 pub struct ProjectBuilder {
-    pub(crate) project: super::ProjectCell,
+    pub(crate) project: schema::Project,
     pub(crate) assets: Vec<ProjectAsset>,
     pub(crate) stage_sprite: Option<SpriteBuilder>,
 }
@@ -24,6 +26,30 @@ pub struct ProjectAsset {
     pub(super) source: PathBuf,
 }
 
+impl ProjectAsset {
+    /// Reads the asset to compute Its hash.
+    pub fn new(name: impl AsRef<str>, source: impl AsRef<Path>) -> std::io::Result<Self> {
+        // Scratch tells formats of assets apart by their extension.
+        let Some(data_format) = source.as_ref().extension().and_then(|ext| ext.to_str()) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "asset has no extension or It isn't valid UTF-8",
+            ));
+        };
+        let file = std::fs::read(source.as_ref())?;
+        let hash = hex::encode(&md5::compute(file).0[..]);
+        let md5ext = format!("{hash}.{data_format}");
+
+        Ok(Self {
+            name: name.as_ref().to_owned(),
+            data_format: data_format.to_string(),
+            hash,
+            md5ext,
+            source: source.as_ref().to_owned(),
+        })
+    }
+}
+
 impl Into<schema::Costume> for &ProjectAsset {
     fn into(self) -> schema::Costume {
         // TODO: Read file and fetch more data from It.
@@ -57,21 +83,26 @@ impl Into<schema::Sound> for &ProjectAsset {
 impl ProjectBuilder {
     pub fn new() -> Self {
         Self {
-            project: super::ProjectCell::default(),
+            project: schema::Project::default(),
             assets: Vec::new(),
             stage_sprite: None,
         }
     }
 
     /// This method crates basic project data including for example
-    /// stage sprite.
+    /// stage sprite. The stage is added to the project when It is bundled.
     pub fn init_core(&mut self) {
-        let stage = self.create_sprite("Stage");
+        let stage = SpriteBuilder::new("Stage");
         stage.set_stage(true);
 
         self.stage_sprite = Some(stage);
     }
 
+    /// Gets reference to the project being built.
+    pub fn project(&self) -> &schema::Project {
+        &self.project
+    }
+
     pub fn get_stage(&mut self) -> &mut SpriteBuilder {
         self.stage_sprite.as_mut().unwrap()
     }
@@ -83,53 +114,31 @@ impl ProjectBuilder {
         name: impl AsRef<str>,
         source: impl AsRef<Path>,
     ) -> std::io::Result<ProjectAsset> {
-        let file = std::fs::read(source.as_ref())?;
-        let hash = hex::encode(&md5::compute(file).0[..]);
-        let data_format = source.as_ref().extension().unwrap().to_str().unwrap();
-        let md5ext = format!("{hash}.{data_format}");
-
-        let asset = ProjectAsset {
-            name: name.as_ref().to_owned(),
-            data_format: data_format.to_string(),
-            hash,
-            md5ext,
-            source: source.as_ref().to_owned(),
-        };
-
+        let asset = ProjectAsset::new(name, source)?;
         self.assets.push(asset.clone());
         Ok(asset)
     }
 
-    pub fn add_extension(&self, extension: impl AsRef<str>) {
+    pub fn add_extension(&mut self, extension: impl AsRef<str>) {
         self.project
-            .borrow_mut()
             .extensions
             .insert(extension.as_ref().to_owned());
     }
 
-    pub fn create_sprite(&self, name: impl AsRef<str>) -> SpriteBuilder {
-        self.project
-            .borrow_mut()
-            .targets
-            .push(schema::ProjectTarget {
-                name: name.as_ref().to_owned(),
-                volume: 100,
-                layer_order: 1,
-                size: 100,
-                direction: 90,
-                draggable: false,
-                rotation_style: "all around".to_string(),
-                visible: true,
-                ..Default::default()
-            });
-
-        SpriteBuilder::new(
-            self.project.clone(),
-            self.project.borrow().targets.len() - 1,
-        )
+    /// Adds the sprite with Its assets to the project, after sprites added before.
+    pub fn add_sprite(&mut self, sprite: SpriteBuilder) {
+        let (target, assets) = sprite.finish();
+        self.project.targets.push(target);
+        self.assets.extend(assets);
     }
 
-    pub fn bundle_project(self, output: impl AsRef<Path>) -> std::io::Result<()> {
+    pub fn bundle_project(mut self, output: impl AsRef<Path>) -> std::io::Result<()> {
+        if let Some(stage) = self.stage_sprite.take() {
+            let (target, assets) = stage.finish();
+            self.project.targets.insert(0, target);
+            self.assets.extend(assets);
+        }
+
         use zip::{write::SimpleFileOptions, ZipWriter};
 
         let zip_options =
@@ -139,10 +148,14 @@ impl ProjectBuilder {
         let mut zip = ZipWriter::new(BufWriter::new(target_writer));
 
         zip.start_file("project.json", zip_options)?;
-        let project = self.project.borrow();
-        zip.write_all(&serde_json::ser::to_vec(&*project)?)?;
+        zip.write_all(&serde_json::ser::to_vec(&self.project)?)?;
 
+        // Assets with identical contents share the same file.
+        let mut written = std::collections::HashSet::new();
         for asset in self.assets.iter() {
+            if !written.insert(&asset.md5ext) {
+                continue;
+            }
             zip.start_file(&asset.md5ext, zip_options)?;
             zip.write_all(&std::fs::read(&asset.source)?)?;
         }
@@ -1,25 +1,63 @@
-use std::cell::RefMut;
+use std::{
+    cell::{RefCell, RefMut},
+    collections::HashMap,
+    path::Path,
+};
 
 use crate::schema;
 
-use super::{generate_next_id, BlocksBuilder, ProjectAsset};
+use super::{
+    generate_next_id, BlocksBuilder, ProcedureArgumentType, ProcedureDefinition, ProjectAsset,
+};
 
+/// Builder of a single sprite, independent of the project and other sprites.
+/// It is added to the project by [`super::ProjectBuilder::add_sprite`].
 pub struct SpriteBuilder {
-    project_ref: super::ProjectCell,
-    idx: usize,
+    sprite: RefCell<schema::ProjectTarget>,
+    /// Assets bundled with the project for this sprite.
+    assets: RefCell<Vec<ProjectAsset>>,
+    /// Procedures declared in this sprite, by name.
+    procedures: RefCell<HashMap<String, ProcedureDefinition>>,
 }
 
 impl SpriteBuilder {
-    pub(super) fn new(project_ref: super::ProjectCell, idx: usize) -> Self {
-        Self { project_ref, idx }
+    pub fn new(name: impl AsRef<str>) -> Self {
+        Self {
+            sprite: RefCell::new(schema::ProjectTarget {
+                name: name.as_ref().to_owned(),
+                volume: 100,
+                layer_order: 1,
+                size: 100,
+                direction: 90,
+                draggable: false,
+                rotation_style: "all around".to_string(),
+                visible: true,
+                ..Default::default()
+            }),
+            assets: RefCell::default(),
+            procedures: RefCell::default(),
+        }
     }
 
     /// Gets mutable reference to the underlaying sprite this builder
     /// manages.
     pub fn sprite_ref<'builder>(&'builder self) -> RefMut<'builder, schema::ProjectTarget> {
-        RefMut::map(self.project_ref.borrow_mut(), |project| {
-            project.targets.iter_mut().nth(self.idx).unwrap()
-        })
+        self.sprite.borrow_mut()
+    }
+
+    /// Reads the asset, so It is bundled with the project once the sprite is added.
+    pub fn register_asset(
+        &self,
+        name: impl AsRef<str>,
+        source: impl AsRef<Path>,
+    ) -> std::io::Result<ProjectAsset> {
+        let asset = ProjectAsset::new(name, source)?;
+        self.assets.borrow_mut().push(asset.clone());
+        Ok(asset)
+    }
+
+    pub(super) fn finish(self) -> (schema::ProjectTarget, Vec<ProjectAsset>) {
+        (self.sprite.into_inner(), self.assets.into_inner())
     }
 
     pub fn add_costume(&self, asset: &ProjectAsset) {
@@ -57,6 +95,49 @@ impl SpriteBuilder {
         schema::Value::Variable(id.clone(), name.as_ref().to_owned())
     }
 
+    /// Declares variable with the id, so blocks built before It can refer to It.
+    pub fn add_variable(&self, id: impl AsRef<str>, name: impl AsRef<str>) {
+        self.sprite_ref().variables.insert(
+            id.as_ref().to_owned(),
+            schema::Variable {
+                display_name: name.as_ref().to_owned(),
+                value: schema::VariableValue::Number(0f64),
+            },
+        );
+    }
+
+    /// Creates an empty list, returns Its id and name.
+    pub fn make_list(&self, name: impl AsRef<str>) -> (String, String) {
+        let id = generate_next_id();
+        self.sprite_ref().lists.insert(
+            id.clone(),
+            schema::List {
+                display_name: name.as_ref().to_owned(),
+                items: Vec::new(),
+            },
+        );
+
+        (id, name.as_ref().to_owned())
+    }
+
+    /// Declares empty list with the id, so sprites built separately can refer to It.
+    pub fn add_list(&self, id: impl AsRef<str>, name: impl AsRef<str>) {
+        self.sprite_ref().lists.insert(
+            id.as_ref().to_owned(),
+            schema::List {
+                display_name: name.as_ref().to_owned(),
+                items: Vec::new(),
+            },
+        );
+    }
+
+    /// Declares broadcast, broadcasts of the whole project are declared by the stage.
+    pub fn add_broadcast(&self, id: impl AsRef<str>, name: impl AsRef<str>) {
+        self.sprite_ref()
+            .broadcasts
+            .insert(id.as_ref().to_owned(), name.as_ref().to_owned());
+    }
+
     pub fn set_stage(&self, is_stage: bool) -> &Self {
         let mut sprite = self.sprite_ref();
         sprite.is_stage = is_stage;
@@ -64,6 +145,13 @@ impl SpriteBuilder {
         self
     }
 
+    /// Sprites with higher layer order are drawn over those with lower one,
+    /// stage is always at layer 0.
+    pub fn set_layer_order(&self, layer_order: u32) -> &Self {
+        self.sprite_ref().layer_order = layer_order;
+        self
+    }
+
     pub fn set_volume(&self, volume: u32) -> &Self {
         self.sprite_ref().volume = volume;
         self
@@ -101,7 +189,43 @@ impl SpriteBuilder {
         self
     }
 
+    /// Declares procedure so It can be called before It is defined.
+    /// Arguments are in order of their declaration.
+    pub fn declare_procedure(
+        &self,
+        name: impl AsRef<str>,
+        arguments: impl IntoIterator<Item = (String, ProcedureArgumentType)>,
+        warp: bool,
+        returns: bool,
+    ) -> &Self {
+        let arguments: Vec<_> = arguments
+            .into_iter()
+            .map(|(name, ty)| (generate_next_id(), name, ty))
+            .collect();
+        let proccode = std::iter::once(name.as_ref())
+            .chain(arguments.iter().map(|(.., ty)| match ty {
+                ProcedureArgumentType::NumberOrText => "%s",
+                ProcedureArgumentType::Boolean => "%b",
+            }))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        self.procedures.borrow_mut().insert(
+            name.as_ref().to_owned(),
+            ProcedureDefinition {
+                arguments,
+                proccode,
+                warp,
+                returns,
+            },
+        );
+        self
+    }
+
     pub fn blocks_builder<'builder>(&'builder self) -> BlocksBuilder<'builder> {
-        BlocksBuilder::new(RefMut::map(self.sprite_ref(), |sprite| &mut sprite.blocks))
+        BlocksBuilder::new(
+            RefMut::map(self.sprite_ref(), |sprite| &mut sprite.blocks),
+            &self.procedures,
+        )
     }
 }
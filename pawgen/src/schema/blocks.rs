@@ -100,6 +100,8 @@ pub enum Value {
     Text(String),
     Number(f64),
     Variable(String, String),
+    /// Id and name of the broadcast.
+    Broadcast(String, String),
 }
 
 impl Value {
@@ -137,6 +139,13 @@ impl serde::Serialize for Value {
                 tuple.serialize_element(id)?;
                 tuple.end()
             }
+            Self::Broadcast(id, name) => {
+                let mut tuple = serializer.serialize_tuple(3)?;
+                tuple.serialize_element(&11)?;
+                tuple.serialize_element(name)?;
+                tuple.serialize_element(id)?;
+                tuple.end()
+            }
         }
     }
 }
@@ -160,6 +169,8 @@ pub struct BlockMutation {
     pub argument_names: Vec<String>,
     pub argument_defaults: Vec<String>,
     pub warp: bool,
+    /// Whether custom block is a reporter, requires TurboWarp.
+    pub returns: bool,
 }
 
 impl serde::Serialize for BlockMutation {
@@ -167,7 +178,7 @@ impl serde::Serialize for BlockMutation {
     where
         S: serde::Serializer,
     {
-        let mut ser = serializer.serialize_struct("mutation", 7)?;
+        let mut ser = serializer.serialize_struct("mutation", 8)?;
         ser.serialize_field("tagName", "mutation")?;
         ser.serialize_field::<[u8]>("children", &[])?;
 
@@ -185,6 +196,9 @@ impl serde::Serialize for BlockMutation {
             &serde_json::ser::to_string(&self.argument_defaults).unwrap(),
         )?;
         ser.serialize_field("warp", if self.warp { "true" } else { "false" })?;
+        if self.returns {
+            ser.serialize_field("return", "1")?;
+        }
 
         ser.end()
     }
@@ -14,8 +14,10 @@ pub struct ProjectTarget {
     pub name: String,
     /// Map of variable ids to their names and values.
     pub variables: HashMap<String, Variable>,
-    pub lists: HashMap<String, ()>,
-    pub broadcasts: HashMap<String, ()>,
+    /// Map of list ids to their names and items.
+    pub lists: HashMap<String, List>,
+    /// Map of broadcast ids to their names, only the stage has broadcasts.
+    pub broadcasts: HashMap<String, String>,
     /// Code for the sprite.
     pub blocks: ProjectBlocks,
     pub costumes: Vec<Costume>,
@@ -39,6 +41,13 @@ pub struct Variable {
     pub value: VariableValue,
 }
 
+/// Scratch list tuple. This consists of list name and Its items.
+#[derive(Debug, Clone)]
+pub struct List {
+    pub display_name: String,
+    pub items: Vec<VariableValue>,
+}
+
 /// Enum to represent multiple variable types that are possible in scratch.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(untagged)]
@@ -91,3 +100,29 @@ impl<'de> serde::Deserialize<'de> for Variable {
         todo!("Deserializer for 'Variable' is not yet implemented")
     }
 }
+
+impl serde::Serialize for List {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&self.display_name)?;
+        tuple.serialize_element(&self.items)?;
+        tuple.end()
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for List {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let (display_name, items) =
+            <(String, Vec<VariableValue>) as serde::Deserialize>::deserialize(deserializer)?;
+        Ok(Self {
+            display_name,
+            items,
+        })
+    }
+}
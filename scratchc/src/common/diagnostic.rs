@@ -0,0 +1,368 @@
+use std::{fmt::Write, io::IsTerminal};
+
+use super::{
+    location::{Loc, SourceId},
+    source_map::{LineCol, SourceFile, SourceMap},
+};
+
+/// Anything that can be reported to the user as a diagnostic.
+/// Every compiler error should implement this, so It can be rendered
+/// by [`DiagnosticRenderer`].
+pub trait ToDiagnostic {
+    fn to_diagnostic(&self) -> Diagnostic;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Note => "note",
+            Self::Help => "help",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            Self::Error => RED,
+            Self::Warning => YELLOW,
+            Self::Note => GREEN,
+            Self::Help => CYAN,
+        }
+    }
+}
+
+/// Message attached to a part of the source code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub loc: Loc,
+    pub message: Option<String>,
+    /// Primary labels point at the cause of the problem,
+    /// secondary ones only give more context.
+    pub is_primary: bool,
+}
+
+impl Label {
+    pub fn primary(loc: Loc) -> Self {
+        Self {
+            loc,
+            message: None,
+            is_primary: true,
+        }
+    }
+
+    pub fn secondary(loc: Loc) -> Self {
+        Self {
+            loc,
+            message: None,
+            is_primary: false,
+        }
+    }
+
+    pub fn with_message(mut self, message: impl AsRef<str>) -> Self {
+        self.message = Some(message.as_ref().to_owned());
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    pub labels: Vec<Label>,
+    /// Additional `note:` and `help:` messages shown below the snippet.
+    pub children: Vec<(Level, String)>,
+}
+
+impl Diagnostic {
+    pub fn new(level: Level, message: impl AsRef<str>) -> Self {
+        Self {
+            level,
+            message: message.as_ref().to_owned(),
+            labels: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl AsRef<str>) -> Self {
+        Self::new(Level::Error, message)
+    }
+
+    pub fn warning(message: impl AsRef<str>) -> Self {
+        Self::new(Level::Warning, message)
+    }
+
+    pub fn with_label(mut self, label: Label) -> Self {
+        self.labels.push(label);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl AsRef<str>) -> Self {
+        self.children.push((Level::Note, note.as_ref().to_owned()));
+        self
+    }
+
+    pub fn with_help(mut self, help: impl AsRef<str>) -> Self {
+        self.children.push((Level::Help, help.as_ref().to_owned()));
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == Level::Error
+    }
+}
+
+impl ToDiagnostic for Diagnostic {
+    fn to_diagnostic(&self) -> Diagnostic {
+        self.clone()
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const GREEN: &str = "\x1b[1;32m";
+const CYAN: &str = "\x1b[1;36m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Whether rendered diagnostics should contain ANSI colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Use colors only when stderr is a terminal and `NO_COLOR` is not set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn should_colorize(&self) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => std::env::var_os("NO_COLOR").is_none() && std::io::stderr().is_terminal(),
+        }
+    }
+}
+
+/// Renders diagnostics in rustc-like style with code snippets.
+pub struct DiagnosticRenderer<'sm> {
+    source_map: &'sm SourceMap,
+    colors: bool,
+}
+
+impl<'sm> DiagnosticRenderer<'sm> {
+    pub fn new(source_map: &'sm SourceMap, colors: ColorChoice) -> Self {
+        Self {
+            source_map,
+            colors: colors.should_colorize(),
+        }
+    }
+
+    /// Renders diagnostic and prints It to the stderr.
+    pub fn emit(&self, diagnostic: &impl ToDiagnostic) {
+        eprint!("{}", self.render(&diagnostic.to_diagnostic()));
+    }
+
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let mut out = String::new();
+        let _ = self.write_diagnostic(&mut out, diagnostic);
+        out
+    }
+
+    fn paint(&self, text: impl std::fmt::Display, color: &str) -> String {
+        if self.colors {
+            format!("{color}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn write_diagnostic(&self, out: &mut String, diagnostic: &Diagnostic) -> std::fmt::Result {
+        writeln!(
+            out,
+            "{}{}",
+            self.paint(diagnostic.level.as_str(), diagnostic.level.color()),
+            self.paint(format!(": {}", diagnostic.message), BOLD)
+        )?;
+
+        // Group labels by file keeping file of the first primary label in front.
+        let mut files: Vec<(SourceId, Vec<&Label>)> = Vec::new();
+        let mut labels: Vec<&Label> = diagnostic.labels.iter().collect();
+        labels.sort_by_key(|label| !label.is_primary);
+        for label in labels {
+            match files.iter_mut().find(|(file, _)| *file == label.loc.file) {
+                Some((_, group)) => group.push(label),
+                None => files.push((label.loc.file, vec![label])),
+            }
+        }
+
+        let gutter_width = files
+            .iter()
+            .flat_map(|(file, labels)| {
+                let file = self.source_map.get(*file);
+                labels
+                    .iter()
+                    .filter_map(move |label| file.map(|file| span_end(file, &label.loc).line + 1))
+            })
+            .max()
+            .unwrap_or(0)
+            .to_string()
+            .len();
+        let gutter = " ".repeat(gutter_width);
+
+        for (i, (file, labels)) in files.iter().enumerate() {
+            let Some(file) = self.source_map.get(*file) else {
+                // Location without the source, we can only print the messages.
+                for label in labels.iter() {
+                    if let Some(message) = &label.message {
+                        writeln!(out, "{gutter}{} {message}", self.paint("=", BLUE))?;
+                    }
+                }
+                continue;
+            };
+            let arrow = if i == 0 { "-->" } else { ":::" };
+            writeln!(
+                out,
+                "{gutter}{} {}:{}",
+                self.paint(arrow, BLUE),
+                file.name(),
+                file.line_col(labels[0].loc.span.start)
+            )?;
+            self.write_snippet(out, file, labels, diagnostic.level, &gutter)?;
+        }
+
+        if !diagnostic.children.is_empty() && !files.is_empty() {
+            writeln!(out, "{gutter} {}", self.paint("|", BLUE))?;
+        }
+        for (level, message) in diagnostic.children.iter() {
+            writeln!(
+                out,
+                "{gutter} {} {}: {message}",
+                self.paint("=", BLUE),
+                self.paint(level.as_str(), BOLD)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_snippet(
+        &self,
+        out: &mut String,
+        file: &SourceFile,
+        labels: &[&Label],
+        level: Level,
+        gutter: &str,
+    ) -> std::fmt::Result {
+        let bar = self.paint("|", BLUE);
+
+        // Lines that should be shown, long multi-line labels show only their ends.
+        let mut lines: Vec<usize> = Vec::new();
+        for label in labels.iter() {
+            let start = file.line_index(label.loc.span.start);
+            let end = span_end(file, &label.loc).line;
+            if end - start > 3 {
+                lines.extend([start, start + 1, end - 1, end]);
+            } else {
+                lines.extend(start..=end);
+            }
+        }
+        lines.sort();
+        lines.dedup();
+
+        writeln!(out, "{gutter} {bar}")?;
+        let mut previous: Option<usize> = None;
+        for &line in lines.iter() {
+            if let Some(previous) = previous {
+                if line > previous + 1 {
+                    writeln!(out, "{}", self.paint("...", BLUE))?;
+                }
+            }
+            previous = Some(line);
+
+            let text = file.line(line);
+            writeln!(
+                out,
+                "{} {bar} {}",
+                self.paint(format!("{:>width$}", line + 1, width = gutter.len()), BLUE),
+                expand_tabs(text)
+            )?;
+
+            for label in labels.iter() {
+                let start = file.line_col(label.loc.span.start);
+                let end = span_end(file, &label.loc);
+                if line < start.line || line > end.line {
+                    continue;
+                }
+
+                let from = if line == start.line { start.column } else { 0 };
+                let to = if line == end.line {
+                    end.column
+                } else {
+                    text.chars().count()
+                };
+                let from_visual = visual_width(text, from);
+                let to_visual = visual_width(text, to).max(from_visual + 1);
+
+                let (marker, color) = if label.is_primary {
+                    ("^", level.color())
+                } else {
+                    ("-", BLUE)
+                };
+                let mut underline = format!(
+                    "{}{}",
+                    " ".repeat(from_visual),
+                    self.paint(marker.repeat(to_visual - from_visual), color)
+                );
+                if line == end.line {
+                    if let Some(message) = &label.message {
+                        underline.push(' ');
+                        underline.push_str(&self.paint(message, color));
+                    }
+                }
+                writeln!(out, "{gutter} {bar} {underline}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Position right after the last character of the label. Spans are exclusive,
+/// so a span ending with a newline ends on the line of the newline, not on the next one.
+fn span_end(file: &SourceFile, loc: &Loc) -> LineCol {
+    let span = &loc.span;
+    let line = file.line_index(span.end.max(span.start + 1) - 1);
+    if file.line_index(span.end) == line {
+        file.line_col(span.end)
+    } else {
+        LineCol {
+            line,
+            column: file.line(line).chars().count(),
+        }
+    }
+}
+
+const TAB_WIDTH: usize = 4;
+
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+/// Width of the first `chars` characters of the line after tab expansion.
+fn visual_width(text: &str, chars: usize) -> usize {
+    text.chars()
+        .take(chars)
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
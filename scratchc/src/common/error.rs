@@ -1,4 +1,15 @@
-use crate::frontend::parser::lexer::{Token, TokenInfo};
+use std::path::PathBuf;
+
+use crate::{
+    frontend::parser::lexer::{Token, TokenInfo},
+    mir::DataType,
+};
+
+use super::{
+    diagnostic::{Diagnostic, Label, ToDiagnostic},
+    location::Loc,
+    source_map::LineCol,
+};
 
 #[derive(Debug, Clone)]
 pub enum SyntaxError {
@@ -6,4 +17,869 @@ pub enum SyntaxError {
         expected: Vec<Token>,
         found: TokenInfo,
     },
+    /// Something described by `what` (e.g. "expression") was expected.
+    Expected {
+        what: String,
+        found: TokenInfo,
+    },
+    UnexpectedEof {
+        expected: Vec<String>,
+        loc: Loc,
+    },
+    InvalidToken(Loc),
+    InvalidEscape(Loc),
+    /// Block declarations only accept `inputs` and `fields` sections.
+    UnknownBlockSection(Loc),
+}
+
+fn describe_expected(expected: &[String]) -> String {
+    match expected {
+        [] => "something else".to_owned(),
+        [one] => one.clone(),
+        [rest @ .., last] => format!("one of {} or {last}", rest.join(", ")),
+    }
+}
+
+impl ToDiagnostic for SyntaxError {
+    fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            Self::UnexpectedToken { expected, found } => {
+                let expected = describe_expected(
+                    &expected
+                        .iter()
+                        .map(|token| token.to_string())
+                        .collect::<Vec<_>>(),
+                );
+                Diagnostic::error(format!("expected {expected}, found {}", found.kind()))
+                    .with_label(
+                        Label::primary(found.loc()).with_message(format!("expected {expected}")),
+                    )
+            }
+            Self::Expected { what, found } => {
+                Diagnostic::error(format!("expected {what}, found {}", found.kind())).with_label(
+                    Label::primary(found.loc()).with_message(format!("expected {what} here")),
+                )
+            }
+            Self::UnexpectedEof { expected, loc } => {
+                let expected = describe_expected(expected);
+                Diagnostic::error(format!("expected {expected}, found end of file")).with_label(
+                    Label::primary(loc.clone()).with_message(format!("expected {expected}")),
+                )
+            }
+            Self::InvalidEscape(loc) => Diagnostic::error("invalid escape sequence in string")
+                .with_label(Label::primary(loc.clone()))
+                .with_help(r#"supported escapes are \n, \t, \", \\ and \uXXXX"#),
+            Self::UnknownBlockSection(loc) => Diagnostic::error("unknown block section")
+                .with_label(Label::primary(loc.clone()))
+                .with_note("block declarations can only contain `inputs` and `fields`"),
+            Self::InvalidToken(loc) => Diagnostic::error("unknown character")
+                .with_label(Label::primary(loc.clone()).with_message("this is not a valid token")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ResolveError {
+    Syntax(SyntaxError),
+    /// File of the `mod name` declaration couldn't be read.
+    /// Path is `None` when declaring file is not on the disk.
+    ModuleFileNotFound {
+        name: String,
+        loc: Loc,
+        path: Option<PathBuf>,
+    },
+    Unresolved {
+        name: String,
+        loc: Loc,
+        /// Module or sprite in which the name was searched for.
+        parent: Option<String>,
+    },
+    /// Name is provided by multiple glob imports.
+    Ambiguous {
+        name: String,
+        loc: Loc,
+        candidates: Vec<Loc>,
+    },
+    Duplicate {
+        name: String,
+        loc: Loc,
+        previous: Loc,
+    },
+    /// Path goes through something that is not a module or sprite.
+    NotANamespace {
+        name: String,
+        kind: &'static str,
+        loc: Loc,
+    },
+    SuperOfRoot(Loc),
+    EmptyImport(Loc),
+}
+
+impl ToDiagnostic for ResolveError {
+    fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            Self::Syntax(error) => error.to_diagnostic(),
+            Self::ModuleFileNotFound { name, loc, path } => {
+                let diagnostic = Diagnostic::error(format!("file not found for module `{name}`"))
+                    .with_label(Label::primary(loc.clone()));
+                match path {
+                    Some(path) => diagnostic.with_help(format!(
+                        "create `{}` or declare module inline with `mod {name} {{ ... }}`",
+                        path.display()
+                    )),
+                    None => diagnostic
+                        .with_note("modules can only be loaded relative to files on the disk"),
+                }
+            }
+            Self::Unresolved { name, loc, parent } => {
+                let message = match parent {
+                    Some(parent) => format!("cannot find `{name}` in `{parent}`"),
+                    None => format!("cannot find `{name}` in this scope"),
+                };
+                Diagnostic::error(message)
+                    .with_label(Label::primary(loc.clone()).with_message("not found"))
+            }
+            Self::Ambiguous {
+                name,
+                loc,
+                candidates,
+            } => candidates.iter().fold(
+                Diagnostic::error(format!("`{name}` is ambiguous"))
+                    .with_label(Label::primary(loc.clone()).with_message("ambiguous name"))
+                    .with_help("import the name explicitly to choose one of the candidates"),
+                |diagnostic, candidate| {
+                    diagnostic.with_label(
+                        Label::secondary(candidate.clone())
+                            .with_message(format!("`{name}` could come from this import")),
+                    )
+                },
+            ),
+            Self::Duplicate {
+                name,
+                loc,
+                previous,
+            } => Diagnostic::error(format!("`{name}` is defined multiple times"))
+                .with_label(Label::primary(loc.clone()).with_message("redefined here"))
+                .with_label(
+                    Label::secondary(previous.clone())
+                        .with_message(format!("previous definition of `{name}` here")),
+                ),
+            Self::NotANamespace { name, kind, loc } => {
+                Diagnostic::error(format!("`{name}` is a {kind}, not a module"))
+                    .with_label(Label::primary(loc.clone()).with_message("not a module or sprite"))
+            }
+            Self::SuperOfRoot(loc) => Diagnostic::error(
+                "there are too many leading `super` keywords",
+            )
+            .with_label(Label::primary(loc.clone()).with_message("goes beyond the crate root")),
+            Self::EmptyImport(loc) => Diagnostic::error("import doesn't name anything")
+                .with_label(Label::primary(loc.clone())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeError {
+    Mismatch {
+        expected: String,
+        found: String,
+        loc: Loc,
+    },
+    /// Path used as a type refers to something else, like a procedure.
+    NotAType {
+        name: String,
+        kind: &'static str,
+        loc: Loc,
+    },
+    NotAValue {
+        name: String,
+        kind: &'static str,
+        loc: Loc,
+    },
+    NotAStruct {
+        name: String,
+        kind: &'static str,
+        loc: Loc,
+    },
+    /// Path of `spawn` refers to something else than a sprite.
+    NotASprite {
+        name: String,
+        kind: &'static str,
+        loc: Loc,
+    },
+    /// `self` of the sprite is used other than to access Its fields.
+    SpriteSelfValue(Loc),
+    /// Only procedures and blocks can be called, `what` describes the callee.
+    NotCallable {
+        what: String,
+        loc: Loc,
+    },
+    ArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        loc: Loc,
+        definition: Loc,
+    },
+    /// Procedure or block that doesn't return anything is used as a value.
+    NoValue(Loc),
+    InvalidOperands {
+        op: &'static str,
+        lhs: String,
+        rhs: String,
+        loc: Loc,
+    },
+    InvalidOperand {
+        op: &'static str,
+        ty: String,
+        loc: Loc,
+    },
+    UnknownField {
+        ty: String,
+        field: String,
+        loc: Loc,
+    },
+    DuplicateField {
+        field: String,
+        loc: Loc,
+        previous: Loc,
+    },
+    MissingFields {
+        ty: String,
+        fields: Vec<String>,
+        loc: Loc,
+    },
+    NoMethod {
+        ty: String,
+        method: String,
+        loc: Loc,
+    },
+    /// Method is provided by multiple traits implemented for the type.
+    AmbiguousMethod {
+        ty: String,
+        method: String,
+        traits: Vec<String>,
+        loc: Loc,
+    },
+    NotATrait {
+        name: String,
+        kind: &'static str,
+        loc: Loc,
+    },
+    /// Methods can only be implemented for structs.
+    InvalidImplTarget {
+        ty: String,
+        loc: Loc,
+    },
+    /// Trait is implemented for the same type more than once.
+    ConflictingImpls {
+        trait_name: String,
+        ty: String,
+        loc: Loc,
+        previous: Loc,
+    },
+    DuplicateMethod {
+        ty: String,
+        method: String,
+        loc: Loc,
+        previous: Loc,
+    },
+    MissingTraitMethods {
+        trait_name: String,
+        ty: String,
+        methods: Vec<String>,
+        loc: Loc,
+    },
+    NotATraitMethod {
+        trait_name: String,
+        method: String,
+        loc: Loc,
+    },
+    /// Implemented method has different parameters or return type than in the trait.
+    TraitMethodMismatch {
+        trait_name: String,
+        method: String,
+        expected: String,
+        found: String,
+        loc: Loc,
+    },
+    InvalidAssignTarget(Loc),
+    /// Scratch doesn't allow changing values of procedure arguments.
+    AssignToParameter {
+        name: String,
+        loc: Loc,
+    },
+    UnexpectedReturnValue(Loc),
+    MissingReturnValue {
+        expected: String,
+        loc: Loc,
+    },
+    /// Procedure with a return type can reach Its end without returning.
+    MissingReturn {
+        name: String,
+        expected: String,
+        loc: Loc,
+    },
+    OutsideLoop {
+        keyword: &'static str,
+        loc: Loc,
+    },
+    RecursiveStruct {
+        name: String,
+        loc: Loc,
+    },
+    /// Return type of a shorthand procedure depends on Itself.
+    CannotInfer {
+        name: String,
+        loc: Loc,
+    },
+    /// Scratch blocks can only take and return primitive values.
+    NonPrimitiveBlockType {
+        ty: String,
+        loc: Loc,
+    },
+    /// Block input is not one of the block's parameters.
+    InvalidBlockInput(Loc),
+    /// Block field is not a text or number literal.
+    InvalidBlockField(Loc),
+    UnusedBlockParameter {
+        name: String,
+        loc: Loc,
+    },
+    BlockParameterReused {
+        name: String,
+        loc: Loc,
+        previous: Loc,
+    },
+}
+
+impl ToDiagnostic for TypeError {
+    fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            Self::Mismatch {
+                expected,
+                found,
+                loc,
+            } => Diagnostic::error("mismatched types").with_label(
+                Label::primary(loc.clone())
+                    .with_message(format!("expected `{expected}`, found `{found}`")),
+            ),
+            Self::NotAType { name, kind, loc } => {
+                Diagnostic::error(format!("expected type, found {kind} `{name}`"))
+                    .with_label(Label::primary(loc.clone()).with_message("not a type"))
+            }
+            Self::NotAValue { name, kind, loc } => {
+                Diagnostic::error(format!("expected value, found {kind} `{name}`"))
+                    .with_label(Label::primary(loc.clone()).with_message("not a value"))
+            }
+            Self::NotAStruct { name, kind, loc } => {
+                Diagnostic::error(format!("expected struct, found {kind} `{name}`"))
+                    .with_label(Label::primary(loc.clone()).with_message("not a struct"))
+            }
+            Self::NotASprite { name, kind, loc } => {
+                Diagnostic::error(format!("expected sprite, found {kind} `{name}`"))
+                    .with_label(Label::primary(loc.clone()).with_message("not a sprite"))
+            }
+            Self::SpriteSelfValue(loc) => Diagnostic::error("`self` of a sprite is not a value")
+                .with_label(Label::primary(loc.clone()))
+                .with_note("only fields of the sprite can be used, like `self.speed`"),
+            Self::NotCallable { what, loc } => {
+                Diagnostic::error(format!("{what} cannot be called"))
+                    .with_label(Label::primary(loc.clone()))
+                    .with_note("only procedures and blocks can be called")
+            }
+            Self::ArgumentCount {
+                name,
+                expected,
+                found,
+                loc,
+                definition,
+            } => Diagnostic::error(format!(
+                "`{name}` takes {expected} argument{} but {found} {} supplied",
+                if *expected == 1 { "" } else { "s" },
+                if *found == 1 { "was" } else { "were" },
+            ))
+            .with_label(
+                Label::primary(loc.clone()).with_message(format!("expected {expected} arguments")),
+            )
+            .with_label(Label::secondary(definition.clone()).with_message("defined here")),
+            Self::NoValue(loc) => Diagnostic::error("expression doesn't have a value").with_label(
+                Label::primary(loc.clone()).with_message("this doesn't return anything"),
+            ),
+            Self::InvalidOperands { op, lhs, rhs, loc } => {
+                Diagnostic::error(format!("cannot apply `{op}` to `{lhs}` and `{rhs}`"))
+                    .with_label(Label::primary(loc.clone()))
+            }
+            Self::InvalidOperand { op, ty, loc } => {
+                Diagnostic::error(format!("cannot apply unary `{op}` to `{ty}`"))
+                    .with_label(Label::primary(loc.clone()))
+            }
+            Self::UnknownField { ty, field, loc } => {
+                Diagnostic::error(format!("no field `{field}` on type `{ty}`"))
+                    .with_label(Label::primary(loc.clone()).with_message("unknown field"))
+            }
+            Self::DuplicateField {
+                field,
+                loc,
+                previous,
+            } => Diagnostic::error(format!("field `{field}` specified more than once"))
+                .with_label(Label::primary(loc.clone()).with_message("used again here"))
+                .with_label(Label::secondary(previous.clone()).with_message("first use here")),
+            Self::MissingFields { ty, fields, loc } => Diagnostic::error(format!(
+                "missing field{} {} in initializer of `{ty}`",
+                if fields.len() == 1 { "" } else { "s" },
+                fields
+                    .iter()
+                    .map(|field| format!("`{field}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+            .with_label(Label::primary(loc.clone())),
+            Self::NoMethod { ty, method, loc } => {
+                Diagnostic::error(format!("no method named `{method}` found for `{ty}`"))
+                    .with_label(Label::primary(loc.clone()).with_message("method not found"))
+            }
+            Self::AmbiguousMethod {
+                ty,
+                method,
+                traits,
+                loc,
+            } => Diagnostic::error(format!(
+                "multiple methods named `{method}` found for `{ty}`"
+            ))
+            .with_label(Label::primary(loc.clone()))
+            .with_note(format!(
+                "`{method}` is provided by traits {}",
+                traits
+                    .iter()
+                    .map(|name| format!("`{name}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            Self::NotATrait { name, kind, loc } => {
+                Diagnostic::error(format!("expected trait, found {kind} `{name}`"))
+                    .with_label(Label::primary(loc.clone()).with_message("not a trait"))
+            }
+            Self::InvalidImplTarget { ty, loc } => {
+                Diagnostic::error(format!("cannot implement methods for `{ty}`"))
+                    .with_label(Label::primary(loc.clone()))
+                    .with_note("methods can only be implemented for structs")
+            }
+            Self::ConflictingImpls {
+                trait_name,
+                ty,
+                loc,
+                previous,
+            } => Diagnostic::error(format!(
+                "conflicting implementations of trait `{trait_name}` for `{ty}`"
+            ))
+            .with_label(Label::primary(loc.clone()).with_message("conflicting implementation"))
+            .with_label(
+                Label::secondary(previous.clone()).with_message("first implementation here"),
+            ),
+            Self::DuplicateMethod {
+                ty,
+                method,
+                loc,
+                previous,
+            } => Diagnostic::error(format!(
+                "method `{method}` is defined multiple times for `{ty}`"
+            ))
+            .with_label(Label::primary(loc.clone()).with_message("redefined here"))
+            .with_label(
+                Label::secondary(previous.clone())
+                    .with_message(format!("previous definition of `{method}` here")),
+            ),
+            Self::MissingTraitMethods {
+                trait_name,
+                ty,
+                methods,
+                loc,
+            } => Diagnostic::error(format!(
+                "not all methods of trait `{trait_name}` are implemented for `{ty}`"
+            ))
+            .with_label(Label::primary(loc.clone()).with_message(format!(
+                "missing {}",
+                methods
+                    .iter()
+                    .map(|method| format!("`{method}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+            Self::NotATraitMethod {
+                trait_name,
+                method,
+                loc,
+            } => Diagnostic::error(format!(
+                "method `{method}` is not a member of trait `{trait_name}`"
+            ))
+            .with_label(Label::primary(loc.clone()).with_message("not a member of the trait")),
+            Self::TraitMethodMismatch {
+                trait_name,
+                method,
+                expected,
+                found,
+                loc,
+            } => Diagnostic::error(format!(
+                "method `{method}` doesn't match Its declaration in trait `{trait_name}`"
+            ))
+            .with_label(
+                Label::primary(loc.clone())
+                    .with_message(format!("expected `{expected}`, found `{found}`")),
+            ),
+            Self::InvalidAssignTarget(loc) => Diagnostic::error(
+                "invalid left-hand side of assignment",
+            )
+            .with_label(Label::primary(loc.clone()).with_message("cannot assign to this"))
+            .with_note(
+                "only local variables, fields of the sprite and their fields can be assigned to",
+            ),
+            Self::AssignToParameter { name, loc } => {
+                Diagnostic::error(format!("cannot assign to parameter `{name}`"))
+                    .with_label(Label::primary(loc.clone()))
+                    .with_help(format!(
+                        "copy It into a local variable: `let {name} = {name}`"
+                    ))
+            }
+            Self::UnexpectedReturnValue(loc) => {
+                Diagnostic::error("procedure doesn't have a return type")
+                    .with_label(Label::primary(loc.clone()).with_message("unexpected return value"))
+            }
+            Self::MissingReturnValue { expected, loc } => Diagnostic::error("missing return value")
+                .with_label(
+                    Label::primary(loc.clone()).with_message(format!("expected `{expected}`")),
+                ),
+            Self::MissingReturn {
+                name,
+                expected,
+                loc,
+            } => Diagnostic::error(format!("procedure `{name}` may not return a value"))
+                .with_label(
+                    Label::primary(loc.clone())
+                        .with_message(format!("expected to return `{expected}` on every path")),
+                ),
+            Self::OutsideLoop { keyword, loc } => {
+                Diagnostic::error(format!("`{keyword}` outside of a loop"))
+                    .with_label(Label::primary(loc.clone()))
+            }
+            Self::RecursiveStruct { name, loc } => {
+                Diagnostic::error(format!("recursive struct `{name}` has infinite size"))
+                    .with_label(Label::primary(loc.clone()))
+            }
+            Self::CannotInfer { name, loc } => {
+                Diagnostic::error(format!("cannot infer return type of `{name}`"))
+                    .with_label(Label::primary(loc.clone()).with_message("recursive call"))
+                    .with_help("specify return type explicitly")
+            }
+            Self::NonPrimitiveBlockType { ty, loc } => {
+                Diagnostic::error(format!("blocks cannot use `{ty}`"))
+                    .with_label(Label::primary(loc.clone()))
+                    .with_note("blocks can only use `number`, `text` and `boolean`")
+            }
+            Self::InvalidBlockInput(loc) => Diagnostic::error("block input must be a parameter")
+                .with_label(Label::primary(loc.clone())),
+            Self::InvalidBlockField(loc) => Diagnostic::error("block field must be a literal")
+                .with_label(Label::primary(loc.clone()))
+                .with_note("fields are constant, so they can only be text or number literals"),
+            Self::UnusedBlockParameter { name, loc } => {
+                Diagnostic::error(format!("parameter `{name}` is not used by any input"))
+                    .with_label(Label::primary(loc.clone()))
+            }
+            Self::BlockParameterReused {
+                name,
+                loc,
+                previous,
+            } => Diagnostic::error(format!("parameter `{name}` is used by multiple inputs"))
+                .with_label(Label::primary(loc.clone()))
+                .with_label(Label::secondary(previous.clone()).with_message("first used here")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LowerError {
+    /// Language construct that can't be compiled into scratch yet.
+    Unsupported {
+        what: &'static str,
+        loc: Loc,
+    },
+    AssetNotFound {
+        path: PathBuf,
+        loc: Loc,
+    },
+    /// More than one costume of the sprite is marked with `@default`.
+    MultipleDefaultCostumes {
+        loc: Loc,
+        previous: Loc,
+    },
+    /// Procedure declared in another sprite is called.
+    ForeignProcedure {
+        name: String,
+        sprite: String,
+        loc: Loc,
+    },
+    /// `#[inline]` attribute with arguments other than `never`.
+    InvalidInlineAttribute {
+        loc: Loc,
+    },
+    /// Stage is spawned or has a `@clone` procedure.
+    StageClone {
+        loc: Loc,
+    },
+    /// Procedure started by an event takes arguments.
+    HandlerArguments {
+        name: String,
+        loc: Loc,
+    },
+}
+
+impl ToDiagnostic for LowerError {
+    fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            Self::Unsupported { what, loc } => {
+                Diagnostic::error(format!("{what} are not supported yet"))
+                    .with_label(Label::primary(loc.clone()))
+            }
+            Self::AssetNotFound { path, loc } => {
+                Diagnostic::error(format!("asset file `{}` not found", path.display()))
+                    .with_label(Label::primary(loc.clone()))
+                    .with_note("asset paths are relative to the file declaring the sprite")
+            }
+            Self::MultipleDefaultCostumes { loc, previous } => {
+                Diagnostic::error("sprite has multiple default costumes")
+                    .with_label(Label::primary(loc.clone()).with_message("marked as default here"))
+                    .with_label(
+                        Label::secondary(previous.clone())
+                            .with_message("previously marked as default here"),
+                    )
+            }
+            Self::ForeignProcedure { name, sprite, loc } => {
+                Diagnostic::error(format!("procedure `{name}` belongs to sprite `{sprite}`"))
+                    .with_label(Label::primary(loc.clone()))
+                    .with_note("sprites can only call their own procedures")
+            }
+            Self::InvalidInlineAttribute { loc } => {
+                Diagnostic::error("invalid `#[inline]` attribute")
+                    .with_label(Label::primary(loc.clone()))
+                    .with_note("use either `#[inline]` or `#[inline(never)]`")
+            }
+            Self::StageClone { loc } => Diagnostic::error("the stage cannot be cloned")
+                .with_label(Label::primary(loc.clone()))
+                .with_note("only sprites have clones"),
+            Self::HandlerArguments { name, loc } => Diagnostic::error(format!(
+                "procedure `{name}` started by an event takes arguments"
+            ))
+            .with_label(Label::primary(loc.clone()))
+            .with_note("scratch can't pass arguments to scripts started by events"),
+        }
+    }
+}
+
+/// Error in the textual MIR, see [`crate::mir::Project`]'s `FromStr` implementation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirSyntaxError {
+    pub message: String,
+    pub position: LineCol,
+}
+
+impl std::fmt::Display for MirSyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for MirSyntaxError {}
+
+/// Statement of the MIR project an error was found in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirContext {
+    pub sprite: String,
+    pub procedure: Option<String>,
+    /// Statement written in the textual MIR.
+    pub statement: Option<String>,
+}
+
+impl MirContext {
+    fn add_notes(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        diagnostic = match &self.procedure {
+            Some(procedure) => diagnostic.with_note(format!(
+                "in procedure `{procedure}` of sprite `{}`",
+                self.sprite
+            )),
+            None => diagnostic.with_note(format!("in sprite `{}`", self.sprite)),
+        };
+        // Control flow is shortened to Its first line.
+        match self.statement.as_ref().and_then(|stmt| stmt.lines().next()) {
+            Some(statement) => diagnostic.with_note(format!("in statement `{statement}`")),
+            None => diagnostic,
+        }
+    }
+}
+
+/// Malformed MIR found by [`crate::mir::verify`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    pub kind: VerifyErrorKind,
+    pub context: MirContext,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyErrorKind {
+    UndefinedBlock(String),
+    UndefinedProcedure(String),
+    UndefinedArgument(usize),
+    UndefinedLocal(usize),
+    UndefinedList(usize),
+    UndefinedField(usize),
+    UndefinedSprite(String),
+    /// Clone of the stage is spawned, only sprites have clones.
+    CloneOfStage,
+    ArgumentCount {
+        callee: String,
+        expected: usize,
+        found: usize,
+    },
+    /// Statement is used as a value, but doesn't have one.
+    NotValue,
+    /// Assignment to something else than a local, variable, field of the sprite
+    /// or their fields.
+    NotPlace,
+    /// List operation on something else than a list reference.
+    NotList,
+    FieldOutOfRange {
+        index: usize,
+        count: usize,
+    },
+    VariantOutOfRange {
+        index: usize,
+        count: usize,
+    },
+    TypeMismatch {
+        expected: DataType,
+        found: DataType,
+    },
+    /// Value of a primitive type is expected.
+    NotPrimitive(DataType),
+    /// Primitive value is given where a value of the type is expected.
+    PrimitiveMismatch(DataType),
+    /// Type of the statement isn't a `kind` type, like "structure".
+    UnexpectedType {
+        kind: &'static str,
+        found: DataType,
+    },
+    LiteralSize {
+        expected: usize,
+        found: usize,
+    },
+    FieldCount {
+        expected: usize,
+        found: usize,
+    },
+    JumpOutsideLoop,
+    MissingReturnValue,
+    UnexpectedReturnValue,
+}
+
+impl std::fmt::Display for VerifyErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            Self::UndefinedBlock(id) => format!("block `{id}` is not defined"),
+            Self::UndefinedProcedure(name) => {
+                format!("procedure `{name}` is not defined in the sprite")
+            }
+            Self::UndefinedArgument(index) => {
+                format!("procedure has no argument {index}")
+            }
+            Self::UndefinedLocal(index) => format!("local %{index} is not declared"),
+            Self::UndefinedList(index) => format!("list {index} is not declared"),
+            Self::UndefinedField(index) => format!("field {index} of the sprite is not declared"),
+            Self::UndefinedSprite(name) => format!("sprite `{name}` is not defined"),
+            Self::CloneOfStage => "the stage can't have clones".to_owned(),
+            Self::ArgumentCount {
+                callee,
+                expected,
+                found,
+            } => format!("`{callee}` takes {expected} arguments, but {found} were passed"),
+            Self::NotValue => "statement is used as a value, but has none".to_owned(),
+            Self::NotPlace => {
+                "only locals, variables, fields of the sprite and their fields can be assigned to"
+                    .to_owned()
+            }
+            Self::NotList => "list operation is done on something else than a list".to_owned(),
+            Self::FieldOutOfRange { index, count } => {
+                format!("field {index} of a structure with {count} fields")
+            }
+            Self::VariantOutOfRange { index, count } => {
+                format!("variant {index} of an enum with {count} variants")
+            }
+            Self::TypeMismatch { expected, found } => {
+                format!("expected type `{expected}`, found `{found}`")
+            }
+            Self::NotPrimitive(found) => {
+                format!("expected a primitive type, found `{found}`")
+            }
+            Self::PrimitiveMismatch(expected) => {
+                format!("expected type `{expected}`, found a primitive value")
+            }
+            Self::UnexpectedType { kind, found } => {
+                format!("expected {kind} type, found `{found}`")
+            }
+            Self::LiteralSize { expected, found } => {
+                format!("structure literal needs {expected} values, but has {found}")
+            }
+            Self::FieldCount { expected, found } => {
+                format!("structure has {expected} fields, but {found} were given")
+            }
+            Self::JumpOutsideLoop => "`break` or `continue` outside of a loop".to_owned(),
+            Self::MissingReturnValue => "procedure returns a value, but none is given".to_owned(),
+            Self::UnexpectedReturnValue => {
+                "procedure returns nothing, but a value is given".to_owned()
+            }
+        };
+        f.write_str(&message)
+    }
+}
+
+impl ToDiagnostic for VerifyError {
+    fn to_diagnostic(&self) -> Diagnostic {
+        self.context
+            .add_notes(Diagnostic::error(format!("malformed MIR: {}", self.kind)))
+    }
+}
+
+/// Error which stopped [`crate::mir::MirRefinery`] from refining the project.
+#[derive(Debug)]
+pub struct RefineError {
+    pub kind: RefineErrorKind,
+    /// Boxed, so results of the refinery stay small.
+    pub context: Box<MirContext>,
+}
+
+#[derive(Debug)]
+pub enum RefineErrorKind {
+    /// Costume or sound couldn't be read.
+    Asset {
+        name: String,
+        source: PathBuf,
+        error: std::io::Error,
+    },
+    /// MIR which [`crate::mir::verify`] would reject.
+    Malformed(VerifyErrorKind),
+}
+
+impl ToDiagnostic for RefineError {
+    fn to_diagnostic(&self) -> Diagnostic {
+        let message = match &self.kind {
+            RefineErrorKind::Asset {
+                name,
+                source,
+                error,
+            } => format!(
+                "could not read asset `{name}` from `{}`: {error}",
+                source.display()
+            ),
+            RefineErrorKind::Malformed(kind) => format!("malformed MIR: {kind}"),
+        };
+        self.context.add_notes(Diagnostic::error(message))
+    }
 }
@@ -1,2 +1,6 @@
+pub mod diagnostic;
 pub mod error;
 pub mod location;
+pub mod source_map;
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,129 @@
+use std::path::{Path, PathBuf};
+
+use slotmap::SlotMap;
+
+use super::location::{Loc, SourceId};
+
+/// Registry of all source files taking part in the compilation.
+/// Every file is loaded exactly once and referenced later by Its [`SourceId`].
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: SlotMap<SourceId, SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads file from the disk and registers It in the map.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> std::io::Result<SourceId> {
+        let contents = std::fs::read_to_string(path.as_ref())?;
+        let name = path.as_ref().display().to_string();
+        Ok(self.files.insert(SourceFile::new(
+            name,
+            Some(path.as_ref().to_owned()),
+            contents,
+        )))
+    }
+
+    /// Registers in-memory source that doesn't exist on the disk.
+    pub fn add(&mut self, name: impl AsRef<str>, contents: impl Into<String>) -> SourceId {
+        self.files.insert(SourceFile::new(
+            name.as_ref().to_owned(),
+            None,
+            contents.into(),
+        ))
+    }
+
+    pub fn get(&self, id: SourceId) -> Option<&SourceFile> {
+        self.files.get(id)
+    }
+
+    /// Converts start of the location into line and column.
+    pub fn line_col(&self, loc: &Loc) -> Option<LineCol> {
+        self.get(loc.file).map(|file| file.line_col(loc.span.start))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    name: String,
+    path: Option<PathBuf>,
+    contents: String,
+    /// Byte offsets at which each line starts.
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    fn new(name: String, path: Option<PathBuf>, contents: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(contents.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self {
+            name,
+            path,
+            contents,
+            line_starts,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn contents(&self) -> &str {
+        &self.contents
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Index of the line containing given byte offset.
+    pub fn line_index(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        }
+    }
+
+    /// Contents of the line without trailing newline.
+    pub fn line(&self, index: usize) -> &str {
+        let start = self.line_starts[index];
+        let end = self
+            .line_starts
+            .get(index + 1)
+            .copied()
+            .unwrap_or(self.contents.len());
+        self.contents[start..end].trim_end_matches(['\n', '\r'])
+    }
+
+    /// Converts byte offset into zero-based line and column.
+    /// Columns are counted in characters, not bytes.
+    pub fn line_col(&self, offset: usize) -> LineCol {
+        let offset = offset.min(self.contents.len());
+        let line = self.line_index(offset);
+        let column = self.contents[self.line_starts[line]..offset]
+            .chars()
+            .count();
+        LineCol { line, column }
+    }
+}
+
+/// Zero-based position in the source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LineCol {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl std::fmt::Display for LineCol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.column + 1)
+    }
+}
@@ -0,0 +1,80 @@
+use super::{
+    diagnostic::{ColorChoice, Diagnostic, DiagnosticRenderer, Label},
+    location::{Loc, SourceId},
+    source_map::{LineCol, SourceMap},
+};
+
+fn render(text: &str, labels: impl IntoIterator<Item = Label>) -> String {
+    let mut source_map = SourceMap::new();
+    let file = source_map.add("main.sl", text);
+    let mut diagnostic = Diagnostic::error("problem");
+    for mut label in labels {
+        label.loc.file = file;
+        diagnostic = diagnostic.with_label(label);
+    }
+    DiagnosticRenderer::new(&source_map, ColorChoice::Never).render(&diagnostic)
+}
+
+fn loc(span: std::ops::Range<usize>) -> Loc {
+    Loc::new(span, SourceId::dummy())
+}
+
+#[test]
+fn line_col_counts_characters() {
+    let mut source_map = SourceMap::new();
+    let file = source_map.add("main.sl", "ab\n\"é\" c\n\nd");
+    let line_col = |offset| source_map.get(file).unwrap().line_col(offset);
+    assert_eq!(line_col(0), LineCol { line: 0, column: 0 });
+    // Newline belongs to the line It ends.
+    assert_eq!(line_col(2), LineCol { line: 0, column: 2 });
+    assert_eq!(line_col(3), LineCol { line: 1, column: 0 });
+    // `é` takes two bytes but one column.
+    assert_eq!(line_col(7), LineCol { line: 1, column: 3 });
+    assert_eq!(line_col(10), LineCol { line: 2, column: 0 });
+    // Offsets past the end are clamped to It.
+    assert_eq!(line_col(11), LineCol { line: 3, column: 0 });
+    assert_eq!(line_col(100), LineCol { line: 3, column: 1 });
+
+    assert_eq!(
+        source_map.line_col(&Loc::new(4..6, file)),
+        Some(LineCol { line: 1, column: 1 })
+    );
+}
+
+#[test]
+fn renders_labels_under_their_code() {
+    let text = "proc main() {\n    say(x, y)\n}\n";
+    assert_eq!(
+        render(
+            text,
+            [
+                Label::primary(loc(22..23)).with_message("unknown name"),
+                Label::secondary(loc(18..21)),
+            ]
+        ),
+        "error: problem\n --> main.sl:2:9\n  |\n2 |     say(x, y)\n  |         ^ unknown name\n  |     ---\n"
+    );
+}
+
+#[test]
+fn spans_ending_at_end_of_line_stay_on_the_line() {
+    let text = "proc main() {\n    let x\n}\n";
+    // Newline token after `x`.
+    assert_eq!(
+        render(
+            text,
+            [Label::primary(loc(23..24)).with_message("expected `=`")]
+        ),
+        "error: problem\n --> main.sl:2:10\n  |\n2 |     let x\n  |          ^ expected `=`\n"
+    );
+    // `let x` with the newline.
+    assert_eq!(
+        render(text, [Label::primary(loc(18..24))]),
+        "error: problem\n --> main.sl:2:5\n  |\n2 |     let x\n  |     ^^^^^\n"
+    );
+    // Whole first line, empty span at the end of the file.
+    assert_eq!(
+        render(text, [Label::primary(loc(0..14)), Label::secondary(loc(26..26))]),
+        "error: problem\n --> main.sl:1:1\n  |\n1 | proc main() {\n  | ^^^^^^^^^^^^^\n...\n4 | \n  | -\n"
+    );
+}
@@ -0,0 +1,352 @@
+//! Abstract syntax tree produced by the parser.
+//! Every node that later passes need to refer to has Its own [`NodeId`].
+
+use std::sync::atomic::AtomicU32;
+
+use crate::common::location::{Loc, SourceId};
+
+static NODE_ID_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+/// Identifier of the AST node, unique across all parsed files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn next() -> Self {
+        Self(NODE_ID_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+    }
+}
+
+/// Contents of a single source file.
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub source: SourceId,
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub loc: Loc,
+}
+
+/// Attribute like `@default` or `#[inline(never)]`.
+#[derive(Debug, Clone)]
+pub struct Attribute {
+    pub name: Ident,
+    pub args: Vec<Ident>,
+    pub loc: Loc,
+}
+
+#[derive(Debug, Clone)]
+pub struct Item {
+    pub id: NodeId,
+    pub attributes: Vec<Attribute>,
+    pub kind: ItemKind,
+    pub loc: Loc,
+}
+
+#[derive(Debug, Clone)]
+pub enum ItemKind {
+    Sprite(Sprite),
+    Import(ImportTree),
+    Proc(Proc),
+    Block(BlockDecl),
+    Struct(Struct),
+    Trait(Trait),
+    Impl(Impl),
+    Mod(ModDecl),
+}
+
+impl ItemKind {
+    /// Name under which the item is visible in Its module.
+    pub fn name(&self) -> Option<&Ident> {
+        match self {
+            Self::Sprite(sprite) => Some(&sprite.name),
+            Self::Proc(proc) => Some(&proc.name),
+            Self::Block(block) => Some(&block.name),
+            Self::Struct(structure) => Some(&structure.name),
+            Self::Trait(trait_decl) => Some(&trait_decl.name),
+            Self::Mod(module) => Some(&module.name),
+            Self::Import(_) | Self::Impl(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sprite {
+    pub name: Ident,
+    pub costumes: Vec<Asset>,
+    pub sounds: Vec<Asset>,
+    /// Variables every clone of the sprite has Its own copy of.
+    pub fields: Vec<StructField>,
+    pub items: Vec<Item>,
+}
+
+/// Costume or sound declaration, `@default background1: "background.svg"`.
+#[derive(Debug, Clone)]
+pub struct Asset {
+    pub attributes: Vec<Attribute>,
+    pub name: Ident,
+    pub path: String,
+    pub loc: Loc,
+}
+
+/// Import tree, `scratch::{math::sqrt, looks::*}`.
+#[derive(Debug, Clone)]
+pub struct ImportTree {
+    pub prefix: Vec<Ident>,
+    pub kind: ImportKind,
+    pub loc: Loc,
+}
+
+#[derive(Debug, Clone)]
+pub enum ImportKind {
+    /// Imports last segment of the prefix, optionally under another name.
+    Simple(Option<Ident>),
+    Glob,
+    Nested(Vec<ImportTree>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Proc {
+    pub name: Ident,
+    /// `self` parameter of methods, always the first one.
+    pub receiver: Option<Receiver>,
+    pub params: Vec<Param>,
+    pub ret: Option<TypeExpr>,
+    pub body: ProcBody,
+}
+
+#[derive(Debug, Clone)]
+pub enum ProcBody {
+    Block(Block),
+    /// Shorthand syntax, `proc answer() = 42`.
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub id: NodeId,
+    pub name: Ident,
+    pub ty: TypeExpr,
+    pub loc: Loc,
+}
+
+#[derive(Debug, Clone)]
+pub struct Receiver {
+    pub id: NodeId,
+    pub loc: Loc,
+}
+
+/// Declaration of the scratch block.
+/// ```text
+/// block sqrt(x: number) -> number as operator_mathop {
+///   inputs: ${ NUM: x },
+///   fields: ${ OPERATOR: "sqrt" },
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct BlockDecl {
+    pub name: Ident,
+    pub params: Vec<Param>,
+    pub ret: Option<TypeExpr>,
+    pub opcode: Ident,
+    pub inputs: Vec<(Ident, Expr)>,
+    pub fields: Vec<(Ident, Expr)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Struct {
+    pub name: Ident,
+    pub fields: Vec<StructField>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StructField {
+    pub name: Ident,
+    pub ty: TypeExpr,
+}
+
+/// Trait declaring methods that types implement, methods don't have bodies.
+/// ```text
+/// trait Shape {
+///   proc area(self) -> number
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Trait {
+    pub name: Ident,
+    pub methods: Vec<TraitMethod>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TraitMethod {
+    pub name: Ident,
+    pub params: Vec<Param>,
+    pub ret: Option<TypeExpr>,
+    pub loc: Loc,
+}
+
+/// Methods of the struct, `impl Vec2 { ... }`, or implementation of the trait
+/// for It, `impl Shape for Circle { ... }`. Every item is a procedure.
+#[derive(Debug, Clone)]
+pub struct Impl {
+    pub trait_ref: Option<Path>,
+    pub ty: TypeExpr,
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ModDecl {
+    pub name: Ident,
+    /// Items of the inline module, `None` for `mod name` which loads `name.sl`.
+    pub items: Option<Vec<Item>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Path {
+    pub id: NodeId,
+    pub segments: Vec<Ident>,
+    pub loc: Loc,
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeExpr {
+    pub id: NodeId,
+    pub kind: TypeExprKind,
+    pub loc: Loc,
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeExprKind {
+    Path(Path),
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub loc: Loc,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub id: NodeId,
+    pub kind: StmtKind,
+    pub loc: Loc,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
+    Let {
+        name: Ident,
+        ty: Option<TypeExpr>,
+        value: Expr,
+    },
+    /// Assignment, `op` is set for compound assignments like `+=`.
+    Assign {
+        target: Expr,
+        op: Option<BinaryOp>,
+        value: Expr,
+    },
+    Expr(Expr),
+    Return(Option<Expr>),
+    If {
+        condition: Expr,
+        then: Block,
+        /// `else if` is represented as block with a single `if` statement.
+        otherwise: Option<Block>,
+    },
+    While {
+        condition: Expr,
+        body: Block,
+    },
+    Repeat {
+        count: Expr,
+        body: Block,
+    },
+    Forever(Block),
+    Break,
+    Continue,
+    /// Clone of the sprite taking values of Its fields, `spawn Ball { speed: 2 }`.
+    Spawn {
+        sprite: Path,
+        fields: Vec<(Ident, Expr)>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub id: NodeId,
+    pub kind: ExprKind,
+    pub loc: Loc,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+    Number(f64),
+    Text(String),
+    Boolean(bool),
+    Path(Path),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    MethodCall(Box<Expr>, Ident, Vec<Expr>),
+    Field(Box<Expr>, Ident),
+    StructLiteral(Path, Vec<(Ident, Expr)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// Binding power of the operator, higher binds tighter.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Self::Or => 1,
+            Self::And => 2,
+            Self::Eq | Self::Ne => 3,
+            Self::Lt | Self::Gt | Self::Le | Self::Ge => 4,
+            Self::Add | Self::Sub => 5,
+            Self::Mul | Self::Div | Self::Mod => 6,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Mod => "%",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Gt => ">",
+            Self::Le => "<=",
+            Self::Ge => ">=",
+            Self::And => "&&",
+            Self::Or => "||",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
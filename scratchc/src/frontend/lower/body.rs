@@ -0,0 +1,535 @@
+use std::collections::HashMap;
+
+use pawgen::schema::Value;
+
+use crate::{
+    common::{error::LowerError, location::Loc},
+    frontend::{
+        ast::{self, NodeId},
+        resolve::{DefId, DefKind, Res},
+        typeck::Ty,
+    },
+    mir::{self, CodeBlock, DataType, Inline, Statement},
+};
+
+use super::{Lowerer, STAGE_NAME};
+
+/// State of the procedure being lowered.
+struct BodyCx {
+    /// Index of every parameter.
+    params: HashMap<NodeId, usize>,
+    /// Index of every local.
+    locals: HashMap<NodeId, usize>,
+    /// Locals of the procedure, in order of their declaration.
+    declared: Vec<(String, DataType)>,
+    /// Sprite declaring the procedure, `None` for procedures shared by all sprites.
+    sprite: Option<DefId>,
+}
+
+impl BodyCx {
+    /// Declares local at the current position of the code, returns reference to It.
+    fn declare_local(
+        &mut self,
+        id: NodeId,
+        name: &str,
+        dt: DataType,
+        code: &mut CodeBlock,
+    ) -> Statement {
+        let local = self.declared.len();
+        self.declared.push((name.to_owned(), dt.clone()));
+        self.locals.insert(id, local);
+        code.push_stmt(Statement::Let(local));
+        Statement::LocalRef(local, dt)
+    }
+}
+
+impl Lowerer<'_> {
+    pub(super) fn lower_procedure(
+        &mut self,
+        id: DefId,
+        item: &ast::Item,
+        proc: &ast::Proc,
+    ) -> Option<mir::Procedure> {
+        let signature = self.types.signature(id);
+        let name = self.procedure_name(id);
+        let is_warp = item
+            .attributes
+            .iter()
+            .any(|attribute| attribute.name.name == "warp");
+        let inputs: Vec<DataType> = signature
+            .params
+            .iter()
+            .map(|ty| self.types.data_type(*ty))
+            .collect();
+        let mut procedure = mir::Procedure::new(&name, is_warp, inputs);
+        if item
+            .attributes
+            .iter()
+            .any(|attribute| attribute.name.name == "export")
+        {
+            procedure.mark_as_entry();
+        }
+        if signature.ret != Ty::Unit {
+            procedure.set_return_type(self.types.data_type(signature.ret));
+        }
+        let inline = item
+            .attributes
+            .iter()
+            .find(|attribute| attribute.name.name == "inline");
+        if let Some(attribute) = inline {
+            match attribute.args.as_slice() {
+                [] => {
+                    procedure.set_inline(Inline::Always);
+                }
+                [arg] if arg.name == "never" => {
+                    procedure.set_inline(Inline::Never);
+                }
+                _ => self.error(LowerError::InvalidInlineAttribute {
+                    loc: attribute.loc.clone(),
+                }),
+            }
+        }
+
+        // Receiver of the method is the first argument.
+        let receiver = proc.receiver.as_ref().map(|receiver| receiver.id);
+        let mut cx = BodyCx {
+            params: receiver
+                .into_iter()
+                .chain(proc.params.iter().map(|param| param.id))
+                .enumerate()
+                .map(|(index, param)| (param, index))
+                .collect(),
+            locals: HashMap::new(),
+            declared: Vec::new(),
+            sprite: self.parent_sprite(id),
+        };
+        let errors = self.errors.len();
+        match &proc.body {
+            ast::ProcBody::Block(block) => self.lower_block(block, &mut cx, procedure.code_block()),
+            ast::ProcBody::Expr(expr) if signature.ret != Ty::Unit => {
+                self.lower_return(Some(expr), expr.id, &mut cx, procedure.code_block())
+            }
+            ast::ProcBody::Expr(expr) => {
+                self.lower_expr_stmt(expr, &mut cx, procedure.code_block())
+            }
+        }
+        for (name, dt) in cx.declared {
+            procedure.declare_local(name, dt);
+        }
+
+        (self.errors.len() == errors).then_some(procedure)
+    }
+
+    fn lower_block(&mut self, block: &ast::Block, cx: &mut BodyCx, code: &mut CodeBlock) {
+        for stmt in block.stmts.iter() {
+            self.lower_stmt(stmt, cx, code);
+        }
+    }
+
+    fn lower_nested_block(&mut self, block: &ast::Block, cx: &mut BodyCx) -> CodeBlock {
+        let mut code = CodeBlock::default();
+        self.lower_block(block, cx, &mut code);
+        code
+    }
+
+    fn lower_stmt(&mut self, stmt: &ast::Stmt, cx: &mut BodyCx, code: &mut CodeBlock) {
+        match &stmt.kind {
+            ast::StmtKind::Let { name, value, .. } => {
+                let dt = self.types.data_type(self.types.local_ty(stmt.id));
+                let local = cx.declare_local(stmt.id, &name.name, dt, code);
+                self.lower_assignment(local, value, cx, code);
+            }
+            ast::StmtKind::Assign { target, op, value } => {
+                let Some(place) = self.lower_expr(target, cx) else {
+                    return;
+                };
+                match op {
+                    None => self.lower_assignment(place, value, cx, code),
+                    Some(op) => {
+                        let ty = self.types.expr_ty(target.id);
+                        let Some(value) = self.lower_expr(value, cx) else {
+                            return;
+                        };
+                        let value = lower_binary(*op, ty, place.clone(), value);
+                        code.push_stmt(Statement::Assignment(Box::new(place), Box::new(value)));
+                    }
+                }
+            }
+            ast::StmtKind::Expr(expr) => self.lower_expr_stmt(expr, cx, code),
+            ast::StmtKind::Return(value) => self.lower_return(value.as_ref(), stmt.id, cx, code),
+            ast::StmtKind::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let Some(condition) = self.lower_expr(condition, cx) else {
+                    return;
+                };
+                let then = self.lower_nested_block(then, cx);
+                let otherwise = otherwise
+                    .as_ref()
+                    .map(|otherwise| self.lower_nested_block(otherwise, cx));
+                code.push_stmt(Statement::If(Box::new(condition), then, otherwise));
+            }
+            ast::StmtKind::While { condition, body } => {
+                let Some(condition) = self.lower_expr(condition, cx) else {
+                    return;
+                };
+                let body = self.lower_nested_block(body, cx);
+                code.push_stmt(Statement::While(Box::new(condition), body));
+            }
+            ast::StmtKind::Repeat { count, body } => {
+                let Some(count) = self.lower_expr(count, cx) else {
+                    return;
+                };
+                let body = self.lower_nested_block(body, cx);
+                code.push_stmt(Statement::Repeat(Box::new(count), body));
+            }
+            ast::StmtKind::Forever(body) => {
+                let body = self.lower_nested_block(body, cx);
+                code.push_stmt(Statement::Forever(body));
+            }
+            ast::StmtKind::Break => {
+                code.push_stmt(Statement::Break);
+            }
+            ast::StmtKind::Continue => {
+                code.push_stmt(Statement::Continue);
+            }
+            ast::StmtKind::Spawn { sprite, fields } => {
+                let Some(Res::Def(id)) = self.program.res(sprite.id) else {
+                    unreachable!()
+                };
+                let name = &self.program.def(id).name;
+                if name == STAGE_NAME {
+                    self.error(LowerError::StageClone {
+                        loc: sprite.loc.clone(),
+                    });
+                    return;
+                }
+                let declared = self.types.sprite_fields(id);
+                let mut fields: Vec<(usize, &ast::Expr)> = fields
+                    .iter()
+                    .map(|(name, value)| (declared.field_index(&name.name).unwrap(), value))
+                    .collect();
+                fields.sort_by_key(|(index, _)| *index);
+                let Some(values) = fields
+                    .into_iter()
+                    .map(|(_, value)| self.lower_expr(value, cx))
+                    .collect::<Option<Vec<_>>>()
+                else {
+                    return;
+                };
+                code.push_stmt(Statement::Spawn(name.clone(), values));
+            }
+        }
+    }
+
+    /// Struct literals with non-constant fields are assigned to a temporary local first.
+    fn lower_return(
+        &mut self,
+        value: Option<&ast::Expr>,
+        id: NodeId,
+        cx: &mut BodyCx,
+        code: &mut CodeBlock,
+    ) {
+        let Some(value) = value else {
+            code.push_stmt(Statement::Return(None));
+            return;
+        };
+        let value = match &value.kind {
+            ast::ExprKind::StructLiteral(..) if !is_constant(value) => {
+                let dt = self.types.data_type(self.types.expr_ty(value.id));
+                let local = cx.declare_local(id, "result", dt, code);
+                self.lower_assignment(local.clone(), value, cx, code);
+                local
+            }
+            _ => {
+                let Some(value) = self.lower_expr(value, cx) else {
+                    return;
+                };
+                value
+            }
+        };
+        code.push_stmt(Statement::Return(Some(Box::new(value))));
+    }
+
+    /// Scratch can't evaluate reporters on their own and they don't have side effects,
+    /// so only expressions without value and procedure calls are kept.
+    fn lower_expr_stmt(&mut self, expr: &ast::Expr, cx: &mut BodyCx, code: &mut CodeBlock) {
+        if self.types.expr_ty(expr.id) == Ty::Unit {
+            if let Some(stmt) = self.lower_expr(expr, cx) {
+                code.push_stmt(stmt);
+            }
+            return;
+        }
+
+        // Value is dropped, but procedures called while computing It still run.
+        match &expr.kind {
+            ast::ExprKind::Call(callee, args) => {
+                let ast::ExprKind::Path(path) = &callee.kind else {
+                    unreachable!("Type checker only allows calling paths")
+                };
+                let Some(Res::Def(id)) = self.program.res(path.id) else {
+                    unreachable!()
+                };
+                if self.program.def(id).kind != DefKind::Block {
+                    if let Some(stmt) = self.lower_expr(expr, cx) {
+                        code.push_stmt(stmt);
+                    }
+                    return;
+                }
+                for arg in args.iter() {
+                    self.lower_expr_stmt(arg, cx, code);
+                }
+            }
+            ast::ExprKind::MethodCall(..) => {
+                if let Some(stmt) = self.lower_expr(expr, cx) {
+                    code.push_stmt(stmt);
+                }
+            }
+            ast::ExprKind::Binary(_, lhs, rhs) => {
+                self.lower_expr_stmt(lhs, cx, code);
+                self.lower_expr_stmt(rhs, cx, code);
+            }
+            ast::ExprKind::Unary(_, operand) => self.lower_expr_stmt(operand, cx, code),
+            // `self` of the sprite has no value to compute.
+            ast::ExprKind::Field(target, _) if self.program.sprite_self(target).is_none() => {
+                self.lower_expr_stmt(target, cx, code)
+            }
+            ast::ExprKind::StructLiteral(_, fields) => {
+                for (_, value) in fields.iter() {
+                    self.lower_expr_stmt(value, cx, code);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sprite declaring the definition, `None` for definitions outside of sprites.
+    fn parent_sprite(&self, id: DefId) -> Option<DefId> {
+        self.program
+            .def(id)
+            .parent
+            .filter(|parent| self.program.def(*parent).kind == DefKind::Sprite)
+    }
+
+    /// Sprites can't call custom blocks of each other, reports calls to procedures
+    /// of other sprites.
+    fn is_callable_from(&mut self, id: DefId, cx: &BodyCx, loc: &Loc) -> bool {
+        let sprite = self.parent_sprite(id);
+        if let Some(sprite) = sprite.filter(|sprite| Some(*sprite) != cx.sprite) {
+            self.error(LowerError::ForeignProcedure {
+                name: self.procedure_name(id),
+                sprite: self.program.def(sprite).name.clone(),
+                loc: loc.clone(),
+            });
+            return false;
+        }
+        true
+    }
+
+    /// Assigns value to the place, struct literals are assigned field by field
+    /// so their fields don't have to be constant.
+    fn lower_assignment(
+        &mut self,
+        place: Statement,
+        value: &ast::Expr,
+        cx: &mut BodyCx,
+        code: &mut CodeBlock,
+    ) {
+        if let ast::ExprKind::StructLiteral(path, fields) = &value.kind {
+            if !is_constant(value) {
+                let Some(Res::Def(id)) = self.program.res(path.id) else {
+                    unreachable!()
+                };
+                let dt = self.types.data_type(Ty::Struct(id));
+                let structure = self.types.structure(id);
+                let mut fields: Vec<(usize, &ast::Expr)> = fields
+                    .iter()
+                    .map(|(name, value)| (structure.field_index(&name.name).unwrap(), value))
+                    .collect();
+                fields.sort_by_key(|(index, _)| *index);
+                for (index, value) in fields {
+                    let field = Statement::FieldRef(Box::new(place.clone()), index, dt.clone());
+                    self.lower_assignment(field, value, cx, code);
+                }
+                return;
+            }
+        }
+
+        if let Some(value) = self.lower_expr(value, cx) {
+            code.push_stmt(Statement::Assignment(Box::new(place), Box::new(value)));
+        }
+    }
+
+    fn lower_expr(&mut self, expr: &ast::Expr, cx: &mut BodyCx) -> Option<Statement> {
+        Some(match &expr.kind {
+            ast::ExprKind::Number(value) => Statement::Constant(Value::Number(*value)),
+            ast::ExprKind::Text(value) => Statement::Constant(Value::Text(value.clone())),
+            ast::ExprKind::Boolean(value) => Statement::Constant(Value::Text(value.to_string())),
+            ast::ExprKind::Path(path) => {
+                let Some(Res::Local(local)) = self.program.res(path.id) else {
+                    unreachable!("Type checker only allows locals as values")
+                };
+                let dt = self.types.data_type(self.types.local_ty(local));
+                match cx.params.get(&local) {
+                    Some(index) => Statement::ArgumentRef(*index, dt),
+                    None => Statement::LocalRef(cx.locals[&local], dt),
+                }
+            }
+            ast::ExprKind::Binary(op, lhs, rhs) => {
+                let ty = self.types.expr_ty(lhs.id);
+                let rhs_ty = self.types.expr_ty(rhs.id);
+                let lhs = self.lower_expr(lhs, cx)?;
+                let rhs = self.lower_expr(rhs, cx)?;
+                if let (ast::BinaryOp::Eq | ast::BinaryOp::Ne, Ty::Struct(_)) = (op, ty) {
+                    let dt = self.types.data_type(ty);
+                    let equals = Statement::Equals(Box::new(lhs), Box::new(rhs), dt);
+                    return Some(match op {
+                        ast::BinaryOp::Ne => {
+                            Statement::BlockCall("operator_not".to_owned(), vec![equals])
+                        }
+                        _ => equals,
+                    });
+                }
+                // Text on any side makes `+` join texts.
+                let ty = if rhs_ty == Ty::Text { rhs_ty } else { ty };
+                lower_binary(*op, ty, lhs, rhs)
+            }
+            ast::ExprKind::Unary(op, operand) => {
+                let operand = self.lower_expr(operand, cx)?;
+                match op {
+                    ast::UnaryOp::Neg => Statement::BlockCall(
+                        "operator_subtract".to_owned(),
+                        vec![Statement::Constant(Value::Number(0.0)), operand],
+                    ),
+                    ast::UnaryOp::Not => {
+                        Statement::BlockCall("operator_not".to_owned(), vec![operand])
+                    }
+                }
+            }
+            ast::ExprKind::Call(callee, args) => {
+                let ast::ExprKind::Path(path) = &callee.kind else {
+                    unreachable!("Type checker only allows calling paths")
+                };
+                let Some(Res::Def(id)) = self.program.res(path.id) else {
+                    unreachable!()
+                };
+                if !self.is_callable_from(id, cx, &expr.loc) {
+                    return None;
+                }
+                let args = args
+                    .iter()
+                    .map(|arg| self.lower_expr(arg, cx))
+                    .collect::<Option<Vec<_>>>()?;
+                match self.program.def(id).kind {
+                    DefKind::Block => Statement::BlockCall(self.program.path_of(id), args),
+                    _ => Statement::ProcedureCall(self.program.path_of(id), args),
+                }
+            }
+            ast::ExprKind::MethodCall(receiver, _, args) => {
+                let id = self.types.method_call(expr.id);
+                if !self.is_callable_from(id, cx, &expr.loc) {
+                    return None;
+                }
+                let args = std::iter::once(&**receiver)
+                    .chain(args.iter())
+                    .map(|arg| self.lower_expr(arg, cx))
+                    .collect::<Option<Vec<_>>>()?;
+                Statement::ProcedureCall(self.procedure_name(id), args)
+            }
+            ast::ExprKind::Field(target, _) => {
+                let index = self.types.field_index(expr.id);
+                if self.program.sprite_self(target).is_some() {
+                    let dt = self.types.data_type(self.types.expr_ty(expr.id));
+                    return Some(Statement::SelfField(index, dt));
+                }
+                let dt = self.types.data_type(self.types.expr_ty(target.id));
+                Statement::FieldRef(Box::new(self.lower_expr(target, cx)?), index, dt)
+            }
+            ast::ExprKind::StructLiteral(path, fields) => {
+                let dt = self.types.data_type(self.types.expr_ty(expr.id));
+                if is_constant(expr) {
+                    let mut values = Vec::new();
+                    self.flatten_constant(expr, &mut values);
+                    return Some(Statement::StructureLiteral(values, dt));
+                }
+                let Some(Res::Def(id)) = self.program.res(path.id) else {
+                    unreachable!()
+                };
+                let structure = self.types.structure(id);
+                let mut fields: Vec<(usize, &ast::Expr)> = fields
+                    .iter()
+                    .map(|(name, value)| (structure.field_index(&name.name).unwrap(), value))
+                    .collect();
+                fields.sort_by_key(|(index, _)| *index);
+                let fields = fields
+                    .into_iter()
+                    .map(|(_, value)| self.lower_expr(value, cx))
+                    .collect::<Option<Vec<_>>>()?;
+                Statement::Structure(fields, dt)
+            }
+        })
+    }
+
+    /// Collects values of the constant expression, with struct fields in order of
+    /// their declaration.
+    fn flatten_constant(&self, expr: &ast::Expr, values: &mut Vec<Value>) {
+        match &expr.kind {
+            ast::ExprKind::Number(value) => values.push(Value::Number(*value)),
+            ast::ExprKind::Text(value) => values.push(Value::Text(value.clone())),
+            ast::ExprKind::Boolean(value) => values.push(Value::Text(value.to_string())),
+            ast::ExprKind::StructLiteral(path, fields) => {
+                let Some(Res::Def(id)) = self.program.res(path.id) else {
+                    unreachable!()
+                };
+                let structure = self.types.structure(id);
+                for field in structure.fields.iter() {
+                    let (_, value) = fields
+                        .iter()
+                        .find(|(name, _)| name.name == field.name)
+                        .unwrap();
+                    self.flatten_constant(value, values);
+                }
+            }
+            _ => unreachable!("Expression is not a constant"),
+        }
+    }
+}
+
+/// Whether expression is a literal or struct literal made only of literals.
+fn is_constant(expr: &ast::Expr) -> bool {
+    match &expr.kind {
+        ast::ExprKind::Number(_) | ast::ExprKind::Text(_) | ast::ExprKind::Boolean(_) => true,
+        ast::ExprKind::StructLiteral(_, fields) => {
+            fields.iter().all(|(_, value)| is_constant(value))
+        }
+        _ => false,
+    }
+}
+
+/// Lowers binary operator into operator blocks, `ty` is the type of operands.
+fn lower_binary(op: ast::BinaryOp, ty: Ty, lhs: Statement, rhs: Statement) -> Statement {
+    use ast::BinaryOp::*;
+
+    let call = |opcode: &str, lhs: Statement, rhs: Statement| {
+        Statement::BlockCall(opcode.to_owned(), vec![lhs, rhs])
+    };
+    let not = |operand: Statement| Statement::BlockCall("operator_not".to_owned(), vec![operand]);
+    match op {
+        Add if ty == Ty::Text => call("operator_join", lhs, rhs),
+        Add => call("operator_add", lhs, rhs),
+        Sub => call("operator_subtract", lhs, rhs),
+        Mul => call("operator_multiply", lhs, rhs),
+        Div => call("operator_divide", lhs, rhs),
+        Mod => call("operator_mod", lhs, rhs),
+        Eq => call("operator_equals", lhs, rhs),
+        Ne => not(call("operator_equals", lhs, rhs)),
+        Lt => call("operator_lt", lhs, rhs),
+        Gt => call("operator_gt", lhs, rhs),
+        Le => not(call("operator_gt", lhs, rhs)),
+        Ge => not(call("operator_lt", lhs, rhs)),
+        And => call("operator_and", lhs, rhs),
+        Or => call("operator_or", lhs, rhs),
+    }
+}
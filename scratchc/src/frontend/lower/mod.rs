@@ -0,0 +1,390 @@
+//! Lowering of the type checked AST into MIR.
+//!
+//! Scratch doesn't allow calling procedures of other sprites, so every sprite gets
+//! Its own procedures together with copies of all the procedures defined outside of sprites.
+//! Sprite named `Stage` becomes the stage, an empty one is created when there is none.
+//!
+//! Procedures called `main` in the root of the crate being compiled or in a sprite are
+//! entry points of the stage or the sprite, run when the green flag is clicked unless
+//! they take arguments. Procedures marked with `@export` are entry points of every sprite.
+//! Other procedures are removed unless they are called.
+//!
+//! Fields of sprites become variables "for this sprite only", `spawn` creates a clone
+//! taking their values and procedures of the sprite marked with `@clone` run in every
+//! new clone.
+//!
+//! Methods become ordinary procedures taking the receiver as the first argument, named
+//! after the type and the trait they are implemented for, like `main::Vec2::length`
+//! or `<main::Circle as main::Shape>::area`.
+
+mod body;
+#[cfg(test)]
+mod tests;
+
+use std::path::Path;
+
+use crate::{
+    common::{error::LowerError, source_map::SourceMap},
+    mir::{self, BlockDefinition, BlockDefinitions, BlockInput, DataType},
+};
+
+use super::{
+    ast,
+    resolve::{DefId, DefKind, Program},
+    typeck::{Ty, TypeckResults},
+};
+
+/// Name of the sprite which becomes the stage.
+pub const STAGE_NAME: &str = "Stage";
+
+/// Lowers whole program into MIR project.
+pub fn lower(
+    program: &Program,
+    types: &TypeckResults,
+    source_map: &SourceMap,
+) -> Result<mir::Project, Vec<LowerError>> {
+    let mut lowerer = Lowerer {
+        program,
+        types,
+        source_map,
+        errors: Vec::new(),
+    };
+    let project = lowerer.lower_project();
+
+    if !lowerer.errors.is_empty() {
+        return Err(lowerer.errors);
+    }
+    Ok(project)
+}
+
+struct Lowerer<'a> {
+    program: &'a Program,
+    types: &'a TypeckResults,
+    source_map: &'a SourceMap,
+    errors: Vec<LowerError>,
+}
+
+/// Items of the program grouped by how they are lowered.
+#[derive(Default)]
+struct Collected<'a> {
+    sprites: Vec<(&'a ast::Item, &'a ast::Sprite)>,
+    /// Procedures defined outside of sprites.
+    shared: Vec<(DefId, &'a ast::Item, &'a ast::Proc)>,
+}
+
+impl<'a> Lowerer<'a> {
+    fn error(&mut self, error: LowerError) {
+        self.errors.push(error);
+    }
+
+    fn lower_project(&mut self) -> mir::Project {
+        let mut project = mir::Project::new();
+        define_operators(project.get_definitions_mut());
+
+        let mut collected = Collected::default();
+        for krate in self.program.crates.iter() {
+            self.collect(
+                &krate.module.items,
+                &mut collected,
+                project.get_definitions_mut(),
+            );
+        }
+
+        // `main` of the crate being compiled is the entry point of the stage.
+        let shared: Vec<(mir::Procedure, bool)> = collected
+            .shared
+            .iter()
+            .filter_map(|(id, item, proc)| {
+                let procedure = self.lower_procedure(*id, item, proc)?;
+                Some((procedure, self.is_main(*id)))
+            })
+            .collect();
+
+        if !collected
+            .sprites
+            .iter()
+            .any(|(_, sprite)| sprite.name.name == STAGE_NAME)
+        {
+            let mut stage = mir::Sprite::new(STAGE_NAME);
+            stage.mark_as_stage();
+            add_shared_procedures(&mut stage, &shared);
+            project.add_sprite(stage);
+        }
+
+        // Stage is always the first target.
+        collected
+            .sprites
+            .sort_by_key(|(_, sprite)| sprite.name.name != STAGE_NAME);
+        for (item, sprite) in collected.sprites.iter() {
+            let sprite = self.lower_sprite(item, sprite, &shared);
+            project.add_sprite(sprite);
+        }
+
+        project
+    }
+
+    fn collect(
+        &self,
+        items: &'a [ast::Item],
+        collected: &mut Collected<'a>,
+        definitions: &mut BlockDefinitions,
+    ) {
+        for item in items.iter() {
+            match &item.kind {
+                ast::ItemKind::Sprite(sprite) => collected.sprites.push((item, sprite)),
+                ast::ItemKind::Mod(module) => {
+                    if let Some(items) = module.items.as_ref() {
+                        self.collect(items, collected, definitions);
+                    }
+                }
+                ast::ItemKind::Proc(proc) => {
+                    let id = self.program.def_of_item(item.id).unwrap();
+                    collected.shared.push((id, item, proc));
+                }
+                ast::ItemKind::Block(_) => {
+                    let id = self.program.def_of_item(item.id).unwrap();
+                    definitions.define(
+                        self.program.path_of(id),
+                        self.types.block_definition(id).clone(),
+                    );
+                }
+                ast::ItemKind::Impl(implementation) => {
+                    self.collect(&implementation.items, collected, definitions);
+                }
+                ast::ItemKind::Struct(_) | ast::ItemKind::Trait(_) | ast::ItemKind::Import(_) => {}
+            }
+        }
+    }
+
+    fn lower_sprite(
+        &mut self,
+        item: &ast::Item,
+        sprite: &ast::Sprite,
+        shared: &[(mir::Procedure, bool)],
+    ) -> mir::Sprite {
+        let mut lowered = mir::Sprite::new(&sprite.name.name);
+        let is_stage = sprite.name.name == STAGE_NAME;
+        if is_stage {
+            lowered.mark_as_stage();
+        }
+        let id = self.program.def_of_item(item.id).unwrap();
+        for field in self.types.sprite_fields(id).fields.iter() {
+            lowered.declare_field(&field.name, self.types.data_type(field.ty));
+        }
+
+        let dir = self
+            .source_map
+            .get(item.loc.file)
+            .and_then(|file| file.path())
+            .and_then(|path| path.parent())
+            .unwrap_or(Path::new("."))
+            .to_owned();
+
+        // First costume is the default one.
+        let mut default: Option<&ast::Asset> = None;
+        for costume in sprite.costumes.iter() {
+            if !costume
+                .attributes
+                .iter()
+                .any(|attribute| attribute.name.name == "default")
+            {
+                continue;
+            }
+            match default {
+                Some(previous) => self.error(LowerError::MultipleDefaultCostumes {
+                    loc: costume.loc.clone(),
+                    previous: previous.loc.clone(),
+                }),
+                None => default = Some(costume),
+            }
+        }
+        let costumes = default.into_iter().chain(
+            sprite
+                .costumes
+                .iter()
+                .filter(|costume| !default.is_some_and(|default| std::ptr::eq(default, *costume))),
+        );
+        for costume in costumes {
+            if let Some(path) = self.asset_path(&dir, costume) {
+                lowered.add_costume(mir::Costume::new(&costume.name.name, path));
+            }
+        }
+        for sound in sprite.sounds.iter() {
+            if let Some(path) = self.asset_path(&dir, sound) {
+                lowered.add_sound(mir::Sound::new(&sound.name.name, path));
+            }
+        }
+
+        for item in sprite.items.iter() {
+            match &item.kind {
+                ast::ItemKind::Proc(proc) => {
+                    let id = self.program.def_of_item(item.id).unwrap();
+                    let Some(procedure) = self.lower_procedure(id, item, proc) else {
+                        continue;
+                    };
+                    let clone = item
+                        .attributes
+                        .iter()
+                        .find(|attribute| attribute.name.name == "clone");
+                    if let Some(attribute) = clone {
+                        self.add_clone_handler(&mut lowered, &procedure, attribute, is_stage);
+                    }
+                    if self.is_main(id) {
+                        add_main(&mut lowered, procedure);
+                    } else {
+                        lowered.add_procedure(procedure);
+                    }
+                }
+                ast::ItemKind::Impl(implementation) => {
+                    for item in implementation.items.iter() {
+                        let ast::ItemKind::Proc(proc) = &item.kind else {
+                            unreachable!("Impl blocks only contain procedures")
+                        };
+                        let id = self.program.def_of_item(item.id).unwrap();
+                        if let Some(procedure) = self.lower_procedure(id, item, proc) {
+                            lowered.add_procedure(procedure);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        add_shared_procedures(&mut lowered, shared);
+
+        lowered
+    }
+
+    /// Runs the procedure marked with `@clone` whenever a clone of the sprite starts.
+    fn add_clone_handler(
+        &mut self,
+        sprite: &mut mir::Sprite,
+        procedure: &mir::Procedure,
+        attribute: &ast::Attribute,
+        is_stage: bool,
+    ) {
+        if is_stage {
+            self.error(LowerError::StageClone {
+                loc: attribute.loc.clone(),
+            });
+        } else if !procedure.inputs().is_empty() {
+            self.error(LowerError::HandlerArguments {
+                name: procedure.name().to_owned(),
+                loc: attribute.loc.clone(),
+            });
+        } else {
+            sprite.add_handler(mir::EventHandler::new(
+                mir::Event::CloneStarted,
+                procedure.name(),
+            ));
+        }
+    }
+
+    /// Whether the procedure is `main` in the root of the crate being compiled or in a sprite.
+    fn is_main(&self, id: DefId) -> bool {
+        let def = self.program.def(id);
+        def.kind == DefKind::Proc
+            && def.name == "main"
+            && def.parent.is_some_and(|parent| {
+                parent == self.program.root_crate().root
+                    || self.program.def(parent).kind == DefKind::Sprite
+            })
+    }
+
+    /// Name of the procedure in MIR, methods are named after their type and trait.
+    fn procedure_name(&self, id: DefId) -> String {
+        let Some(method) = self.types.method(id) else {
+            return self.program.path_of(id);
+        };
+        let Ty::Struct(structure) = method.self_ty else {
+            unreachable!("Methods are only implemented for structs")
+        };
+        let ty = self.program.path_of(structure);
+        let name = &self.program.def(id).name;
+        match method.trait_id {
+            Some(trait_id) => format!("<{ty} as {}>::{name}", self.program.path_of(trait_id)),
+            None => format!("{ty}::{name}"),
+        }
+    }
+
+    fn asset_path(&mut self, dir: &Path, asset: &ast::Asset) -> Option<std::path::PathBuf> {
+        let path = dir.join(&asset.path);
+        if !path.is_file() {
+            self.error(LowerError::AssetNotFound {
+                path,
+                loc: asset.loc.clone(),
+            });
+            return None;
+        }
+        Some(path)
+    }
+}
+
+/// Adds copies of procedures defined outside of sprites, those marked as entry points
+/// only stay entry points of the stage.
+fn add_shared_procedures(sprite: &mut mir::Sprite, shared: &[(mir::Procedure, bool)]) {
+    for (procedure, is_entry) in shared.iter() {
+        if *is_entry && sprite.is_stage() {
+            add_main(sprite, procedure.clone());
+        } else {
+            sprite.add_procedure(procedure.clone());
+        }
+    }
+}
+
+/// Adds `main` of the sprite as Its entry point, started by the green flag.
+/// Scratch can't pass arguments to scripts, so `main` taking them is only kept.
+fn add_main(sprite: &mut mir::Sprite, mut procedure: mir::Procedure) {
+    procedure.mark_as_entry();
+    if procedure.inputs().is_empty() {
+        sprite.add_handler(mir::EventHandler::new(
+            mir::Event::FlagClicked,
+            procedure.name(),
+        ));
+    }
+    sprite.add_procedure(procedure);
+}
+
+/// Defines blocks used by operators under their opcodes, all of them are pure.
+fn define_operators(definitions: &mut BlockDefinitions) {
+    let mut binary = |opcode: &str, lhs: &str, rhs: &str, ty: DataType| {
+        definitions.define(
+            opcode,
+            BlockDefinition::new(
+                opcode,
+                true,
+                [
+                    BlockInput::new(lhs.to_owned(), ty.clone()),
+                    BlockInput::new(rhs.to_owned(), ty),
+                ],
+                [],
+            )
+            .mark_pure(),
+        );
+    };
+    for opcode in [
+        "operator_add",
+        "operator_subtract",
+        "operator_multiply",
+        "operator_divide",
+        "operator_mod",
+    ] {
+        binary(opcode, "NUM1", "NUM2", DataType::Number);
+    }
+    for opcode in ["operator_equals", "operator_lt", "operator_gt"] {
+        binary(opcode, "OPERAND1", "OPERAND2", DataType::Text);
+    }
+    for opcode in ["operator_and", "operator_or"] {
+        binary(opcode, "OPERAND1", "OPERAND2", DataType::Boolean);
+    }
+    binary("operator_join", "STRING1", "STRING2", DataType::Text);
+    definitions.define(
+        "operator_not",
+        BlockDefinition::new(
+            "operator_not",
+            true,
+            [BlockInput::new("OPERAND".to_owned(), DataType::Boolean)],
+            [],
+        )
+        .mark_pure(),
+    );
+}
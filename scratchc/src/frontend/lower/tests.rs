@@ -0,0 +1,531 @@
+use std::path::PathBuf;
+
+use pawgen::schema::Value;
+
+use crate::{
+    common::{error::LowerError, source_map::SourceMap},
+    frontend::{resolve::Resolver, typeck},
+    mir::{self, CodeBlock, DataType, MirRefinementConfig, MirRefinery, Statement},
+};
+
+use super::lower;
+
+/// Creates directory with `main.sl` and an empty costume, `cat.svg`.
+fn project_dir(name: &str, text: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("scratchc-lower-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("main.sl"), text).unwrap();
+    std::fs::write(
+        dir.join("cat.svg"),
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="1" height="1"></svg>"#,
+    )
+    .unwrap();
+    dir
+}
+
+fn lower_dir(dir: &std::path::Path) -> Result<mir::Project, Vec<LowerError>> {
+    let mut source_map = SourceMap::new();
+    let root = source_map.load(dir.join("main.sl")).unwrap();
+    let mut resolver = Resolver::new(&mut source_map);
+    resolver.add_builtin_libraries();
+    let program = resolver
+        .resolve(root)
+        .unwrap_or_else(|errors| panic!("{errors:?}"));
+    let types = typeck::check(&program).unwrap_or_else(|errors| panic!("{errors:?}"));
+    let project = lower(&program, &types, &source_map)?;
+    // Lowering should only produce well formed MIR.
+    mir::verify(&project).unwrap_or_else(|errors| panic!("{errors:?}\n{project}"));
+    Ok(project)
+}
+
+fn lower_text(name: &str, text: &str) -> Result<mir::Project, Vec<LowerError>> {
+    lower_dir(&project_dir(name, text))
+}
+
+fn procedure<'a>(project: &'a mir::Project, sprite: &str, name: &str) -> &'a mir::Procedure {
+    project
+        .sprites()
+        .iter()
+        .find(|candidate| candidate.name() == sprite)
+        .unwrap()
+        .procedures()
+        .iter()
+        .find(|procedure| procedure.name() == name)
+        .unwrap()
+}
+
+#[test]
+fn sprites_and_costumes() {
+    let project = lower_text(
+        "sprites",
+        "proc shared() { }\nsprite Cat {\n    costumes {\n        first: \"cat.svg\"\n        @default second: \"cat.svg\"\n    }\n    proc own() { }\n}",
+    )
+    .unwrap();
+
+    let sprites = project.sprites();
+    assert_eq!(sprites.len(), 2);
+    assert!(sprites[0].is_stage());
+    assert_eq!(sprites[0].name(), "Stage");
+
+    let cat = &sprites[1];
+    let costumes: Vec<&str> = cat
+        .costumes()
+        .iter()
+        .map(|costume| costume.name())
+        .collect();
+    assert_eq!(costumes, ["second", "first"]);
+    let procedures: Vec<&str> = cat
+        .procedures()
+        .iter()
+        .map(|procedure| procedure.name())
+        .collect();
+    assert_eq!(procedures, ["main::Cat::own", "main::shared"]);
+}
+
+#[test]
+fn reports_missing_assets() {
+    let errors = lower_text(
+        "assets",
+        "sprite Stage {\n    costumes {\n        @default a: \"cat.svg\"\n        @default b: \"missing.svg\"\n    }\n}",
+    )
+    .unwrap_err();
+    assert!(matches!(
+        errors[0],
+        LowerError::MultipleDefaultCostumes { .. }
+    ));
+    assert!(matches!(errors[1], LowerError::AssetNotFound { .. }));
+}
+
+#[test]
+fn lowers_expressions_into_block_calls() {
+    let project = lower_text(
+        "expressions",
+        "import scratch::looks::say\n@warp\nproc main(a: number) {\n    let x = -a + 1\n    x *= 2\n    say(\"x = \" + x)\n}",
+    )
+    .unwrap();
+    let main = procedure(&project, "Stage", "main::main");
+    assert!(main.is_warp());
+    assert_eq!(main.inputs(), [DataType::Number]);
+
+    assert_eq!(main.locals()[0].name(), "x");
+    let x = Statement::LocalRef(0, DataType::Number);
+    let call = |opcode: &str, args: Vec<Statement>| Statement::BlockCall(opcode.to_owned(), args);
+    assert_eq!(
+        main.body().statements(),
+        [
+            Statement::Let(0),
+            Statement::Assignment(
+                Box::new(x.clone()),
+                Box::new(call(
+                    "operator_add",
+                    vec![
+                        call(
+                            "operator_subtract",
+                            vec![
+                                Statement::Constant(Value::Number(0.0)),
+                                Statement::ArgumentRef(0, DataType::Number),
+                            ]
+                        ),
+                        Statement::Constant(Value::Number(1.0)),
+                    ]
+                )),
+            ),
+            Statement::Assignment(
+                Box::new(x.clone()),
+                Box::new(call(
+                    "operator_multiply",
+                    vec![x.clone(), Statement::Constant(Value::Number(2.0))]
+                )),
+            ),
+            call(
+                "scratch::looks::say",
+                vec![call(
+                    "operator_join",
+                    vec![Statement::Constant(Value::Text("x = ".to_owned())), x]
+                )],
+            ),
+        ]
+    );
+}
+
+#[test]
+fn struct_literals_are_assigned_field_by_field() {
+    let project = lower_text(
+        "structs",
+        "struct Vec2 { x: number, y: number }\nproc main(a: number) {\n    let constant = Vec2 { y: 2, x: 1 }\n    let v = Vec2 { y: a, x: constant.x }\n}",
+    )
+    .unwrap();
+    let dt = DataType::Structure(vec![DataType::Number, DataType::Number]);
+    let variable = |local: usize| Statement::LocalRef(local, dt.clone());
+    let field =
+        |target: Statement, index: usize| Statement::FieldRef(Box::new(target), index, dt.clone());
+    assert_eq!(
+        procedure(&project, "Stage", "main::main")
+            .body()
+            .statements(),
+        [
+            Statement::Let(0),
+            Statement::Assignment(
+                Box::new(variable(0)),
+                Box::new(Statement::StructureLiteral(
+                    vec![Value::Number(1.0), Value::Number(2.0)],
+                    dt.clone()
+                )),
+            ),
+            Statement::Let(1),
+            Statement::Assignment(
+                Box::new(field(variable(1), 0)),
+                Box::new(field(variable(0), 0)),
+            ),
+            Statement::Assignment(
+                Box::new(field(variable(1), 1)),
+                Box::new(Statement::ArgumentRef(0, DataType::Number)),
+            ),
+        ]
+    );
+}
+
+#[test]
+fn struct_values_are_compared_and_passed() {
+    let project = lower_text(
+        "struct-values",
+        "struct Vec2 { x: number, y: number }\nproc same(a: Vec2, b: Vec2) -> boolean {\n    return a == b\n}\nproc main(x: number) {\n    same(Vec2 { y: 1, x: x }, Vec2 { x: 1, y: 1 })\n}",
+    )
+    .unwrap();
+    let dt = DataType::Structure(vec![DataType::Number, DataType::Number]);
+    assert_eq!(
+        procedure(&project, "Stage", "main::same")
+            .body()
+            .statements(),
+        [Statement::Return(Some(Box::new(Statement::Equals(
+            Box::new(Statement::ArgumentRef(0, dt.clone())),
+            Box::new(Statement::ArgumentRef(1, dt.clone())),
+            dt.clone(),
+        ))))]
+    );
+    assert_eq!(
+        procedure(&project, "Stage", "main::main")
+            .body()
+            .statements(),
+        [Statement::ProcedureCall(
+            "main::same".to_owned(),
+            vec![
+                Statement::Structure(
+                    vec![
+                        Statement::ArgumentRef(0, DataType::Number),
+                        Statement::Constant(Value::Number(1.0)),
+                    ],
+                    dt.clone(),
+                ),
+                Statement::StructureLiteral(vec![Value::Number(1.0), Value::Number(1.0)], dt),
+            ],
+        )]
+    );
+}
+
+#[test]
+fn compiles_end_to_end() {
+    let dir = project_dir(
+        "end-to-end",
+        "import scratch::{looks::say_for, math::sqrt}\nsprite Stage {\n    costumes {\n        backdrop: \"cat.svg\"\n    }\n}\nsprite Cat {\n    costumes {\n        cat: \"cat.svg\"\n    }\n    proc greet(name: text) {\n        say_for(\"Hi \" + name + sqrt(4), 2)\n    }\n}",
+    );
+    let project = lower_dir(&dir).unwrap();
+    let builder = MirRefinery::new(MirRefinementConfig::default())
+        .refine_project(project)
+        .unwrap();
+    builder.bundle_project(dir.join("main.sb3")).unwrap();
+    assert!(dir.join("main.sb3").is_file());
+}
+
+#[test]
+fn lowers_control_flow() {
+    let project = lower_text(
+        "control-flow",
+        "proc main(a: boolean) {\n    if a { } else if !a { }\n    while a {\n        break\n    }\n    repeat 2 {\n        continue\n    }\n    forever { }\n}",
+    )
+    .unwrap();
+    let a = || Box::new(Statement::ArgumentRef(0, DataType::Boolean));
+    let not_a = Statement::BlockCall("operator_not".to_owned(), vec![*a()]);
+    assert_eq!(
+        procedure(&project, "Stage", "main::main")
+            .body()
+            .statements(),
+        [
+            Statement::If(
+                a(),
+                CodeBlock::default(),
+                Some(
+                    [Statement::If(Box::new(not_a), CodeBlock::default(), None)]
+                        .into_iter()
+                        .collect()
+                ),
+            ),
+            Statement::While(a(), [Statement::Break].into_iter().collect()),
+            Statement::Repeat(
+                Box::new(Statement::Constant(Value::Number(2.0))),
+                [Statement::Continue].into_iter().collect(),
+            ),
+            Statement::Forever(CodeBlock::default()),
+        ]
+    );
+}
+
+#[test]
+fn lowers_returns() {
+    let project = lower_text(
+        "returns",
+        "struct Vec2 { x: number, y: number }\nproc twice(a: number) = a * 2\nproc swap(v: Vec2) -> Vec2 {\n    if v.x > v.y {\n        return v\n    }\n    return Vec2 { x: v.y, y: v.x }\n}",
+    )
+    .unwrap();
+
+    let twice = procedure(&project, "Stage", "main::twice");
+    assert_eq!(twice.return_type(), Some(&DataType::Number));
+    assert!(matches!(
+        twice.body().statements(),
+        [Statement::Return(Some(value))] if matches!(**value, Statement::BlockCall(..))
+    ));
+
+    let dt = DataType::Structure(vec![DataType::Number, DataType::Number]);
+    let swap = procedure(&project, "Stage", "main::swap");
+    assert_eq!(swap.return_type(), Some(&dt));
+    assert_eq!(swap.locals()[0].name(), "result");
+    let result = Statement::LocalRef(0, dt.clone());
+    let statements = swap.body().statements();
+    assert!(matches!(&statements[0], Statement::If(_, then, None)
+        if then.statements() == [Statement::Return(Some(Box::new(Statement::ArgumentRef(0, dt.clone()))))]));
+    assert!(matches!(&statements[1..], [
+        Statement::Let(0),
+        Statement::Assignment(first, _),
+        Statement::Assignment(second, _),
+        Statement::Return(Some(value)),
+    ] if matches!(&**first, Statement::FieldRef(target, 0, _) if **target == result)
+        && matches!(&**second, Statement::FieldRef(target, 1, _) if **target == result)
+        && **value == result));
+}
+
+#[test]
+fn lowers_procedure_calls() {
+    let project = lower_text(
+        "calls",
+        "proc main() {\n    twice(1)\n    say(twice(2) + twice(3))\n}\nproc twice(a: number) = a * 2\nproc say(a: number) { }",
+    )
+    .unwrap();
+
+    let main = procedure(&project, "Stage", "main::main");
+    let call = |value: f64| {
+        Statement::ProcedureCall(
+            "main::twice".to_owned(),
+            vec![Statement::Constant(Value::Number(value))],
+        )
+    };
+    assert!(matches!(main.body().statements(), [
+        first,
+        Statement::ProcedureCall(name, args),
+    ] if *first == call(1.0)
+        && name == "main::say"
+        && matches!(&args[..], [Statement::BlockCall(_, operands)] if operands[..] == [call(2.0), call(3.0)])));
+}
+
+#[test]
+fn lowers_sprite_fields_and_spawn() {
+    let dir = project_dir(
+        "clones",
+        "struct Vec2 { x: number, y: number }\nsprite Ball {\n    costumes { ball: \"cat.svg\" }\n    fields { speed: number, position: Vec2 }\n    @clone\n    proc start() {\n        self.position.x += self.speed\n    }\n    proc main() {\n        let n = 2\n        spawn Ball { position: Vec2 { x: n, y: 0 }, speed: 1 }\n    }\n}",
+    );
+    let project = lower_dir(&dir).unwrap();
+    let ball = &project.sprites()[1];
+    let fields: Vec<_> = ball
+        .fields()
+        .iter()
+        .map(|field| (field.name(), field.data_type().clone()))
+        .collect();
+    let dt = DataType::Structure(vec![DataType::Number, DataType::Number]);
+    assert_eq!(
+        fields,
+        [("speed", DataType::Number), ("position", dt.clone())]
+    );
+    assert!(ball.handlers().contains(&mir::EventHandler::new(
+        mir::Event::CloneStarted,
+        "main::Ball::start"
+    )));
+
+    let x = Statement::FieldRef(Box::new(Statement::SelfField(1, dt.clone())), 0, dt.clone());
+    assert_eq!(
+        procedure(&project, "Ball", "main::Ball::start")
+            .body()
+            .statements(),
+        [Statement::Assignment(
+            Box::new(x.clone()),
+            Box::new(Statement::BlockCall(
+                "operator_add".to_owned(),
+                vec![x, Statement::SelfField(0, DataType::Number)],
+            )),
+        )]
+    );
+    // Values are passed in order of the fields.
+    assert_eq!(
+        procedure(&project, "Ball", "main::Ball::main")
+            .body()
+            .statements()
+            .last()
+            .unwrap(),
+        &Statement::Spawn(
+            "Ball".to_owned(),
+            vec![
+                Statement::Constant(Value::Number(1.0)),
+                Statement::Structure(
+                    vec![
+                        Statement::LocalRef(0, DataType::Number),
+                        Statement::Constant(Value::Number(0.0)),
+                    ],
+                    dt,
+                ),
+            ],
+        )
+    );
+    MirRefinery::new(MirRefinementConfig::default())
+        .refine_project(project)
+        .unwrap();
+
+    let errors = lower_text(
+        "bad-clones",
+        "sprite Stage {\n    @clone\n    proc start() { }\n    proc main() { spawn Stage { } }\n}\nsprite Ball {\n    @clone\n    proc start(a: number) { }\n}",
+    )
+    .unwrap_err();
+    assert!(matches!(
+        &errors[..],
+        [
+            LowerError::StageClone { .. },
+            LowerError::StageClone { .. },
+            LowerError::HandlerArguments { name, .. },
+        ] if name == "main::Ball::start"
+    ));
+}
+
+#[test]
+fn reports_foreign_procedures() {
+    let errors = lower_text(
+        "foreign",
+        "sprite Cat {\n    proc own() { }\n}\nsprite Dog {\n    proc other() { Cat::own() }\n}",
+    )
+    .unwrap_err();
+    assert!(
+        matches!(&errors[..], [LowerError::ForeignProcedure { name, sprite, .. }]
+        if name == "main::Cat::own" && sprite == "Cat")
+    );
+}
+
+#[test]
+fn lowers_inline_attributes() {
+    let project = lower_text(
+        "inline",
+        "#[inline]\nproc always() { }\n#[inline(never)]\nproc never() { }\nproc auto() { }",
+    )
+    .unwrap();
+    let inline = |name| procedure(&project, "Stage", name).inline();
+    assert_eq!(inline("main::always"), mir::Inline::Always);
+    assert_eq!(inline("main::never"), mir::Inline::Never);
+    assert_eq!(inline("main::auto"), mir::Inline::Auto);
+
+    let errors = lower_text("bad-inline", "#[inline(sometimes)]\nproc main() { }").unwrap_err();
+    assert!(matches!(
+        &errors[..],
+        [LowerError::InvalidInlineAttribute { .. }]
+    ));
+}
+
+#[test]
+fn marks_entry_procedures() {
+    let project = lower_text(
+        "entries",
+        "proc main() { }\nproc shared() { }\n@export\nproc exported() { }\nmod nested {\n    proc main() { }\n}\nsprite Cat {\n    costumes { first: \"cat.svg\" }\n    proc main() { }\n    proc own() { }\n}",
+    )
+    .unwrap();
+    let is_entry = |sprite, name| procedure(&project, sprite, name).is_entry();
+    // `main` of the crate is only run from the stage.
+    assert!(is_entry("Stage", "main::main"));
+    assert!(!is_entry("Cat", "main::main"));
+    assert!(is_entry("Stage", "main::exported"));
+    assert!(is_entry("Cat", "main::exported"));
+    assert!(is_entry("Cat", "main::Cat::main"));
+    // Both `main`s are started by the green flag.
+    let handlers = |sprite| {
+        project
+            .sprites()
+            .iter()
+            .find(|lowered| lowered.name() == sprite)
+            .unwrap()
+            .handlers()
+    };
+    let flag = |name| mir::EventHandler::new(mir::Event::FlagClicked, name);
+    assert_eq!(handlers("Stage"), [flag("main::main")]);
+    assert_eq!(handlers("Cat"), [flag("main::Cat::main")]);
+    let project = lower_text("arguments", "proc main(a: number) { }").unwrap();
+    assert!(project.sprites()[0].handlers().is_empty());
+    for (sprite, name) in [
+        ("Stage", "main::shared"),
+        ("Stage", "main::nested::main"),
+        ("Cat", "main::shared"),
+        ("Cat", "main::Cat::own"),
+    ] {
+        assert!(!is_entry(sprite, name), "{name} in {sprite}");
+    }
+}
+
+#[test]
+fn unused_procedures_of_the_crate_are_removed() {
+    let mut project = lower_text(
+        "unused",
+        "proc main() {\n    hello()\n}\n#[inline]\nproc hello() { }\nproc unused() { }",
+    )
+    .unwrap();
+    let dead = mir::eliminate_dead_code(&mut project);
+    assert_eq!(dead.procedures(), ["main::unused"]);
+    // Procedures left behind by inlining are removed by the next pass.
+    mir::inline_procedures(&mut project);
+    mir::eliminate_dead_code(&mut project);
+    let procedures: Vec<&str> = project.sprites()[0]
+        .procedures()
+        .iter()
+        .map(|procedure| procedure.name())
+        .collect();
+    assert_eq!(procedures, ["main::main"]);
+}
+
+#[test]
+fn lowers_methods_with_receiver_as_first_argument() {
+    let project = lower_text(
+        "methods",
+        "struct Circle { r: number }\ntrait Shape {\n    proc area(self) -> number\n}\nimpl Shape for Circle {\n    proc area(self) = self.r * self.r\n}\nimpl Circle {\n    proc grow(self, by: number) -> Circle = Circle { r: self.r + by }\n}\nproc main(c: Circle) {\n    c.grow(1).area()\n}",
+    )
+    .unwrap();
+    let dt = DataType::Structure(vec![DataType::Number]);
+    let receiver = Statement::ArgumentRef(0, dt.clone());
+    let radius = Statement::FieldRef(Box::new(receiver.clone()), 0, dt.clone());
+
+    let area = procedure(&project, "Stage", "<main::Circle as main::Shape>::area");
+    assert_eq!(area.inputs(), std::slice::from_ref(&dt));
+    assert!(!area.is_entry());
+    assert_eq!(
+        area.body().statements(),
+        [Statement::Return(Some(Box::new(Statement::BlockCall(
+            "operator_multiply".to_owned(),
+            vec![radius.clone(), radius.clone()],
+        ))))]
+    );
+    let grow = procedure(&project, "Stage", "main::Circle::grow");
+    assert_eq!(grow.inputs(), [dt.clone(), DataType::Number]);
+
+    assert_eq!(
+        procedure(&project, "Stage", "main::main")
+            .body()
+            .statements(),
+        [Statement::ProcedureCall(
+            "<main::Circle as main::Shape>::area".to_owned(),
+            vec![Statement::ProcedureCall(
+                "main::Circle::grow".to_owned(),
+                vec![receiver, Statement::Constant(Value::Number(1.0))],
+            )],
+        )]
+    );
+}
@@ -1 +1,5 @@
+pub mod ast;
+pub mod lower;
 pub mod parser;
+pub mod resolve;
+pub mod typeck;
@@ -0,0 +1,240 @@
+use crate::common::error::SyntaxError;
+
+use super::{
+    ast::{self, NodeId},
+    lexer::{Token, TokenInfo},
+    ParseResult, Parser,
+};
+
+impl Parser<'_> {
+    pub(super) fn parse_expr(&mut self) -> ParseResult<ast::Expr> {
+        self.parse_binary(0)
+    }
+
+    /// Binary operator following current expression. Only `&&` and `||`
+    /// are allowed to be placed at the beginning of the next line.
+    fn peek_binary_op(&mut self) -> Option<ast::BinaryOp> {
+        let op = match self.peek()? {
+            Token::Plus => ast::BinaryOp::Add,
+            Token::Minus => ast::BinaryOp::Sub,
+            Token::Star => ast::BinaryOp::Mul,
+            Token::Slash => ast::BinaryOp::Div,
+            Token::Percent => ast::BinaryOp::Mod,
+            Token::DoubleEquals => ast::BinaryOp::Eq,
+            Token::NotEquals => ast::BinaryOp::Ne,
+            Token::LeftAngle => ast::BinaryOp::Lt,
+            Token::RightAngle => ast::BinaryOp::Gt,
+            Token::LessEquals => ast::BinaryOp::Le,
+            Token::GreaterEquals => ast::BinaryOp::Ge,
+            Token::DoubleAmpersand => ast::BinaryOp::And,
+            Token::DoublePipe => ast::BinaryOp::Or,
+            Token::NL => match self.peek_after_newlines()? {
+                Token::DoubleAmpersand => ast::BinaryOp::And,
+                Token::DoublePipe => ast::BinaryOp::Or,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn parse_binary(&mut self, min_precedence: u8) -> ParseResult<ast::Expr> {
+        let mut lhs = self.parse_unary()?;
+
+        while let Some(op) = self.peek_binary_op() {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.skip_newlines();
+            self.next();
+            self.skip_newlines();
+
+            let rhs = self.parse_binary(op.precedence() + 1)?;
+            lhs = ast::Expr {
+                id: NodeId::next(),
+                loc: self.loc_from(&lhs.loc),
+                kind: ast::ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> ParseResult<ast::Expr> {
+        let op = match self.peek() {
+            Some(Token::Minus) => ast::UnaryOp::Neg,
+            Some(Token::Bang) => ast::UnaryOp::Not,
+            _ => return self.parse_postfix(),
+        };
+
+        self.begin_span();
+        self.next();
+        let operand = self.parse_unary()?;
+        Ok(ast::Expr {
+            id: NodeId::next(),
+            kind: ast::ExprKind::Unary(op, Box::new(operand)),
+            loc: self.end_span(),
+        })
+    }
+
+    fn parse_postfix(&mut self) -> ParseResult<ast::Expr> {
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            let start = expr.loc.clone();
+            let kind = if self.eat(Token::LeftParen) {
+                let args = self.parse_delimited(Token::RightParen, |this| this.parse_expr())?;
+                ast::ExprKind::Call(Box::new(expr), args)
+            } else if self.eat_after_newlines(Token::Dot) {
+                let name = self.expect_ident()?;
+                if self.eat(Token::LeftParen) {
+                    let args = self.parse_delimited(Token::RightParen, |this| this.parse_expr())?;
+                    ast::ExprKind::MethodCall(Box::new(expr), name, args)
+                } else {
+                    ast::ExprKind::Field(Box::new(expr), name)
+                }
+            } else {
+                break;
+            };
+
+            expr = ast::Expr {
+                id: NodeId::next(),
+                kind,
+                loc: self.loc_from(&start),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> ParseResult<ast::Expr> {
+        self.begin_span();
+        let kind = match self.peek() {
+            Some(Token::NumberLiteral) => {
+                self.next();
+                let text = self.tokens.text().unwrap().replace('_', "");
+                ast::ExprKind::Number(text.parse().unwrap())
+            }
+            Some(Token::StringLiteral) => {
+                self.next();
+                let info = self.tokens.current_info().unwrap();
+                ast::ExprKind::Text(Self::unescape_string(&info)?)
+            }
+            Some(Token::KwTrue) => {
+                self.next();
+                ast::ExprKind::Boolean(true)
+            }
+            Some(Token::KwFalse) => {
+                self.next();
+                ast::ExprKind::Boolean(false)
+            }
+            Some(Token::LeftParen) => {
+                self.next();
+                let inner = self.with_newlines(true, |this| {
+                    this.with_struct_literals(true, |this| {
+                        let inner = this.parse_expr()?;
+                        this.expect(Token::RightParen)?;
+                        Ok(inner)
+                    })
+                })?;
+                self.end_span();
+                return Ok(inner);
+            }
+            Some(Token::Identifier | Token::KwSelf | Token::KwSuper) => {
+                let path = self.parse_path()?;
+                if !self.no_struct_literal && self.eat(Token::LeftCurly) {
+                    ast::ExprKind::StructLiteral(path, self.parse_field_values()?)
+                } else {
+                    ast::ExprKind::Path(path)
+                }
+            }
+            _ => return Err(self.expected("expression")),
+        };
+
+        Ok(ast::Expr {
+            id: NodeId::next(),
+            kind,
+            loc: self.end_span(),
+        })
+    }
+
+    /// Parses values of fields after the opening brace, `{ x: 1, y }`,
+    /// used by struct literals and `spawn`.
+    pub(super) fn parse_field_values(&mut self) -> ParseResult<Vec<(ast::Ident, ast::Expr)>> {
+        self.with_struct_literals(true, |this| {
+            this.parse_delimited(Token::RightCurly, |this| {
+                let name = this.expect_ident()?;
+                let value = if this.eat(Token::Colon) {
+                    this.parse_expr()?
+                } else {
+                    // Shorthand, `Vec2 { x, y }`.
+                    ast::Expr {
+                        id: NodeId::next(),
+                        kind: ast::ExprKind::Path(ast::Path {
+                            id: NodeId::next(),
+                            segments: vec![name.clone()],
+                            loc: name.loc.clone(),
+                        }),
+                        loc: name.loc.clone(),
+                    }
+                };
+                Ok((name, value))
+            })
+        })
+    }
+
+    pub(super) fn parse_path_segment(&mut self) -> ParseResult<ast::Ident> {
+        match self.peek() {
+            Some(Token::Identifier | Token::KwSelf | Token::KwSuper) => {
+                self.next();
+                let info = self.tokens.current_info().unwrap();
+                Ok(ast::Ident {
+                    name: info.text(),
+                    loc: info.loc(),
+                })
+            }
+            _ => Err(self.unexpected(vec![Token::Identifier, Token::KwSelf, Token::KwSuper])),
+        }
+    }
+
+    pub(super) fn parse_path(&mut self) -> ParseResult<ast::Path> {
+        self.begin_span();
+        let mut segments = vec![self.parse_path_segment()?];
+        while self.eat(Token::DoubleColon) {
+            segments.push(self.parse_path_segment()?);
+        }
+
+        Ok(ast::Path {
+            id: NodeId::next(),
+            segments,
+            loc: self.end_span(),
+        })
+    }
+
+    pub(super) fn unescape_string(info: &TokenInfo) -> ParseResult<String> {
+        let text = info.text();
+        let mut chars = text[1..text.len() - 1].chars();
+        let mut result = String::new();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                result.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some('"') => result.push('"'),
+                Some('\\') => result.push('\\'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                        Some(c) => result.push(c),
+                        None => return Err(SyntaxError::InvalidEscape(info.loc())),
+                    }
+                }
+                _ => return Err(SyntaxError::InvalidEscape(info.loc())),
+            }
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,426 @@
+use crate::common::error::SyntaxError;
+
+use super::{
+    ast::{self, NodeId},
+    lexer::Token,
+    ParseResult, Parser,
+};
+
+impl Parser<'_> {
+    /// Parses items until the end of file or until `close` token.
+    pub(super) fn parse_items(&mut self, close: Option<Token>) -> ParseResult<Vec<ast::Item>> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.tokens.peek() {
+                None if close.is_none() => break,
+                Some(token) if Some(token) == close => break,
+                _ => {}
+            }
+            items.push(self.parse_item()?);
+            self.expect_terminator()?;
+        }
+        Ok(items)
+    }
+
+    /// Parses attributes written either as `@name(args)` or `#[name(args)]`.
+    fn parse_attributes(&mut self) -> ParseResult<Vec<ast::Attribute>> {
+        let mut attributes = Vec::new();
+        while let Some(open @ (Token::AtSymbol | Token::Hash)) = self.tokens.peek() {
+            self.begin_span();
+            self.expect(open)?;
+            let bracketed = open == Token::Hash;
+            if bracketed {
+                self.expect(Token::LeftBracket)?;
+            }
+            let name = self.expect_ident()?;
+            let args = if self.eat(Token::LeftParen) {
+                self.parse_delimited(Token::RightParen, |this| this.expect_ident())?
+            } else {
+                Vec::new()
+            };
+            if bracketed {
+                self.expect(Token::RightBracket)?;
+            }
+            attributes.push(ast::Attribute {
+                name,
+                args,
+                loc: self.end_span(),
+            });
+            self.skip_newlines();
+        }
+        Ok(attributes)
+    }
+
+    pub(super) fn parse_item(&mut self) -> ParseResult<ast::Item> {
+        self.begin_span();
+        let attributes = self.parse_attributes()?;
+        let kind = match self.peek() {
+            Some(Token::KwSprite) => ast::ItemKind::Sprite(self.parse_sprite()?),
+            Some(Token::KwImport) => {
+                self.next();
+                ast::ItemKind::Import(self.parse_import_tree()?)
+            }
+            Some(Token::KwProc) => ast::ItemKind::Proc(self.parse_proc(false)?),
+            Some(Token::KwBlock) => ast::ItemKind::Block(self.parse_block_decl()?),
+            Some(Token::KwStruct) => ast::ItemKind::Struct(self.parse_struct()?),
+            Some(Token::KwTrait) => ast::ItemKind::Trait(self.parse_trait()?),
+            Some(Token::KwImpl) => ast::ItemKind::Impl(self.parse_impl()?),
+            Some(Token::KwMod) => ast::ItemKind::Mod(self.parse_mod()?),
+            _ => return Err(self.expected("item")),
+        };
+
+        Ok(ast::Item {
+            id: NodeId::next(),
+            attributes,
+            kind,
+            loc: self.end_span(),
+        })
+    }
+
+    fn parse_sprite(&mut self) -> ParseResult<ast::Sprite> {
+        self.expect(Token::KwSprite)?;
+        let name = self.expect_ident()?;
+        self.expect(Token::LeftCurly)?;
+
+        let mut sprite = ast::Sprite {
+            name,
+            costumes: Vec::new(),
+            sounds: Vec::new(),
+            fields: Vec::new(),
+            items: Vec::new(),
+        };
+        self.with_newlines(false, |this| {
+            loop {
+                this.skip_newlines();
+                if this.eat(Token::RightCurly) {
+                    break;
+                }
+
+                let section = this.tokens.peek_info();
+                let is_section = section.as_ref().is_some_and(|info| {
+                    info.kind() == Token::Identifier
+                        && matches!(info.text().as_str(), "costumes" | "sounds" | "fields")
+                });
+                if is_section {
+                    this.next();
+                    match section.unwrap().text().as_str() {
+                        "costumes" => sprite.costumes.extend(this.parse_assets()?),
+                        "sounds" => sprite.sounds.extend(this.parse_assets()?),
+                        _ => {
+                            this.expect(Token::LeftCurly)?;
+                            sprite.fields.extend(this.parse_struct_fields()?);
+                        }
+                    }
+                } else {
+                    sprite.items.push(this.parse_item()?);
+                }
+                this.expect_terminator()?;
+            }
+            Ok(())
+        })?;
+
+        Ok(sprite)
+    }
+
+    fn parse_assets(&mut self) -> ParseResult<Vec<ast::Asset>> {
+        self.expect(Token::LeftCurly)?;
+        let mut assets = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.eat(Token::RightCurly) {
+                break;
+            }
+
+            self.begin_span();
+            let attributes = self.parse_attributes()?;
+            let name = self.expect_ident()?;
+            self.expect(Token::Colon)?;
+            self.skip_newlines();
+            let path = self.expect(Token::StringLiteral)?;
+            let path = Self::unescape_string(&path)?;
+            assets.push(ast::Asset {
+                attributes,
+                name,
+                path,
+                loc: self.end_span(),
+            });
+
+            if !self.eat(Token::Comma) {
+                self.expect_terminator()?;
+            }
+        }
+        Ok(assets)
+    }
+
+    /// Parses import tree, newlines are ignored only inside nested braces.
+    fn parse_import_tree(&mut self) -> ParseResult<ast::ImportTree> {
+        self.begin_span();
+        let mut prefix = Vec::new();
+        let kind = loop {
+            match self.peek() {
+                Some(Token::Star) => {
+                    self.next();
+                    break ast::ImportKind::Glob;
+                }
+                Some(Token::LeftCurly) => {
+                    self.next();
+                    let trees =
+                        self.parse_delimited(Token::RightCurly, |this| this.parse_import_tree())?;
+                    break ast::ImportKind::Nested(trees);
+                }
+                _ => prefix.push(self.parse_path_segment()?),
+            }
+
+            if !self.eat(Token::DoubleColon) {
+                let alias = if self.eat(Token::KwAs) {
+                    Some(self.expect_ident()?)
+                } else {
+                    None
+                };
+                break ast::ImportKind::Simple(alias);
+            }
+        };
+
+        Ok(ast::ImportTree {
+            prefix,
+            kind,
+            loc: self.end_span(),
+        })
+    }
+
+    fn parse_params(&mut self) -> ParseResult<Vec<ast::Param>> {
+        self.expect(Token::LeftParen)?;
+        self.parse_delimited(Token::RightParen, Self::parse_param)
+    }
+
+    fn parse_param(&mut self) -> ParseResult<ast::Param> {
+        self.begin_span();
+        let name = self.expect_ident()?;
+        self.expect(Token::Colon)?;
+        let ty = self.parse_type()?;
+        Ok(ast::Param {
+            id: NodeId::next(),
+            name,
+            ty,
+            loc: self.end_span(),
+        })
+    }
+
+    /// Parses parameters of the method, which start with `self`.
+    fn parse_method_params(&mut self) -> ParseResult<(ast::Receiver, Vec<ast::Param>)> {
+        self.expect(Token::LeftParen)?;
+        self.with_newlines(true, |this| {
+            let receiver = ast::Receiver {
+                id: NodeId::next(),
+                loc: this.expect(Token::KwSelf)?.loc(),
+            };
+            let params = if this.eat(Token::Comma) {
+                this.parse_delimited(Token::RightParen, Self::parse_param)?
+            } else {
+                this.expect(Token::RightParen)?;
+                Vec::new()
+            };
+            Ok((receiver, params))
+        })
+    }
+
+    fn parse_return_type(&mut self) -> ParseResult<Option<ast::TypeExpr>> {
+        if self.eat(Token::Arrow) {
+            self.skip_newlines();
+            Ok(Some(self.parse_type()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parses procedure, methods have to take `self` as the first parameter.
+    fn parse_proc(&mut self, is_method: bool) -> ParseResult<ast::Proc> {
+        self.expect(Token::KwProc)?;
+        let name = self.expect_ident()?;
+        let (receiver, params) = if is_method {
+            let (receiver, params) = self.parse_method_params()?;
+            (Some(receiver), params)
+        } else {
+            (None, self.parse_params()?)
+        };
+        let ret = self.parse_return_type()?;
+
+        let body = if self.eat(Token::Equals) {
+            self.skip_newlines();
+            ast::ProcBody::Expr(self.parse_expr()?)
+        } else {
+            ast::ProcBody::Block(self.parse_block()?)
+        };
+
+        Ok(ast::Proc {
+            name,
+            receiver,
+            params,
+            ret,
+            body,
+        })
+    }
+
+    fn parse_block_decl(&mut self) -> ParseResult<ast::BlockDecl> {
+        self.expect(Token::KwBlock)?;
+        let name = self.expect_ident()?;
+        let params = self.parse_params()?;
+        let ret = self.parse_return_type()?;
+        self.expect(Token::KwAs)?;
+        let opcode = self.expect_ident()?;
+
+        let mut inputs = Vec::new();
+        let mut fields = Vec::new();
+        self.expect(Token::LeftCurly)?;
+        let sections = self.parse_delimited(Token::RightCurly, |this| {
+            let section = this.expect_ident()?;
+            this.expect(Token::Colon)?;
+            this.expect(Token::DollarCurly)?;
+            let entries = this.parse_delimited(Token::RightCurly, |this| {
+                let key = this.expect_ident()?;
+                this.expect(Token::Colon)?;
+                Ok((key, this.parse_expr()?))
+            })?;
+            Ok((section, entries))
+        })?;
+        for (section, entries) in sections {
+            match section.name.as_str() {
+                "inputs" => inputs.extend(entries),
+                "fields" => fields.extend(entries),
+                _ => return Err(SyntaxError::UnknownBlockSection(section.loc)),
+            }
+        }
+
+        Ok(ast::BlockDecl {
+            name,
+            params,
+            ret,
+            opcode,
+            inputs,
+            fields,
+        })
+    }
+
+    fn parse_struct(&mut self) -> ParseResult<ast::Struct> {
+        self.expect(Token::KwStruct)?;
+        let name = self.expect_ident()?;
+        self.expect(Token::LeftCurly)?;
+        let fields = self.parse_struct_fields()?;
+
+        Ok(ast::Struct { name, fields })
+    }
+
+    /// Parses `name: Type` fields of structs and sprites up to the closing brace.
+    fn parse_struct_fields(&mut self) -> ParseResult<Vec<ast::StructField>> {
+        self.parse_delimited(Token::RightCurly, |this| {
+            let name = this.expect_ident()?;
+            this.expect(Token::Colon)?;
+            Ok(ast::StructField {
+                name,
+                ty: this.parse_type()?,
+            })
+        })
+    }
+
+    fn parse_trait(&mut self) -> ParseResult<ast::Trait> {
+        self.expect(Token::KwTrait)?;
+        let name = self.expect_ident()?;
+        self.expect(Token::LeftCurly)?;
+
+        let mut methods = Vec::new();
+        self.with_newlines(false, |this| {
+            loop {
+                this.skip_newlines();
+                if this.eat(Token::RightCurly) {
+                    break;
+                }
+
+                this.begin_span();
+                this.expect(Token::KwProc)?;
+                let name = this.expect_ident()?;
+                let (_, params) = this.parse_method_params()?;
+                let ret = this.parse_return_type()?;
+                methods.push(ast::TraitMethod {
+                    name,
+                    params,
+                    ret,
+                    loc: this.end_span(),
+                });
+                this.expect_terminator()?;
+            }
+            Ok(())
+        })?;
+
+        Ok(ast::Trait { name, methods })
+    }
+
+    /// Parses `impl Type { ... }` or `impl Trait for Type { ... }`.
+    fn parse_impl(&mut self) -> ParseResult<ast::Impl> {
+        self.expect(Token::KwImpl)?;
+        let first = self.parse_type()?;
+        let (trait_ref, ty) = if self.eat(Token::KwFor) {
+            let ast::TypeExprKind::Path(path) = first.kind;
+            (Some(path), self.parse_type()?)
+        } else {
+            (None, first)
+        };
+        self.expect(Token::LeftCurly)?;
+
+        let mut items = Vec::new();
+        self.with_newlines(false, |this| {
+            loop {
+                this.skip_newlines();
+                if this.eat(Token::RightCurly) {
+                    break;
+                }
+
+                this.begin_span();
+                let attributes = this.parse_attributes()?;
+                if this.peek() != Some(Token::KwProc) {
+                    return Err(this.expected("method"));
+                }
+                let kind = ast::ItemKind::Proc(this.parse_proc(true)?);
+                items.push(ast::Item {
+                    id: NodeId::next(),
+                    attributes,
+                    kind,
+                    loc: this.end_span(),
+                });
+                this.expect_terminator()?;
+            }
+            Ok(())
+        })?;
+
+        Ok(ast::Impl {
+            trait_ref,
+            ty,
+            items,
+        })
+    }
+
+    fn parse_mod(&mut self) -> ParseResult<ast::ModDecl> {
+        self.expect(Token::KwMod)?;
+        let name = self.expect_ident()?;
+        let items = if self.eat(Token::LeftCurly) {
+            let items =
+                self.with_newlines(false, |this| this.parse_items(Some(Token::RightCurly)))?;
+            self.expect(Token::RightCurly)?;
+            Some(items)
+        } else {
+            None
+        };
+
+        Ok(ast::ModDecl { name, items })
+    }
+
+    pub(super) fn parse_type(&mut self) -> ParseResult<ast::TypeExpr> {
+        self.begin_span();
+        let path = self.parse_path()?;
+        Ok(ast::TypeExpr {
+            id: NodeId::next(),
+            kind: ast::TypeExprKind::Path(path),
+            loc: self.end_span(),
+        })
+    }
+}
@@ -17,31 +17,143 @@ pub enum Token {
     #[token("]")] RightBracket,
     #[token("{")] LeftCurly,
     #[token("}")] RightCurly,
+    #[token("${")] DollarCurly,
     #[token("<")] LeftAngle,
     #[token(">")] RightAngle,
 
     #[token(":")] Colon,
     #[token("::")] DoubleColon,
     #[token(".")] Dot,
+    #[token(",")] Comma,
+    #[token(";")] Semicolon,
     #[token("->")] Arrow,
 
+    #[token("+")] Plus,
+    #[token("-")] Minus,
+    #[token("*")] Star,
+    #[token("/")] Slash,
+    #[token("%")] Percent,
+    #[token("=")] Equals,
+    #[token("+=")] PlusEquals,
+    #[token("-=")] MinusEquals,
+    #[token("*=")] StarEquals,
+    #[token("/=")] SlashEquals,
+    #[token("==")] DoubleEquals,
+    #[token("!=")] NotEquals,
+    #[token("<=")] LessEquals,
+    #[token(">=")] GreaterEquals,
+    #[token("&&")] DoubleAmpersand,
+    #[token("||")] DoublePipe,
+    #[token("!")] Bang,
+
     // ==< Symbols >==
     #[token("@")] AtSymbol,
+    #[token("#")] Hash,
 
     // ==< Keywords >==
     #[token("sprite")] KwSprite,
     #[token("import")] KwImport,
     #[token("proc")] KwProc,
+    #[token("block")] KwBlock,
+    #[token("struct")] KwStruct,
+    #[token("mod")] KwMod,
+    #[token("trait")] KwTrait,
+    #[token("impl")] KwImpl,
+    #[token("for")] KwFor,
+    #[token("as")] KwAs,
+    #[token("self")] KwSelf,
+    #[token("super")] KwSuper,
+    #[token("let")] KwLet,
+    #[token("return")] KwReturn,
+    #[token("if")] KwIf,
+    #[token("else")] KwElse,
+    #[token("while")] KwWhile,
+    #[token("repeat")] KwRepeat,
+    #[token("forever")] KwForever,
+    #[token("break")] KwBreak,
+    #[token("continue")] KwContinue,
+    #[token("spawn")] KwSpawn,
+    #[token("true")] KwTrue,
+    #[token("false")] KwFalse,
 
     // ==< Literals >==
-    #[regex(r#""([^"\\]|\\t|\\u|\\n|\\")*""#)] StringLiteral,
-    #[regex(r"[0-9][0-9_]*\.[0-9][0-9_]*")] NumberLiteral,
+    #[regex(r#""([^"\\]|\\t|\\u|\\n|\\"|\\\\)*""#)] StringLiteral,
+    #[regex(r"[0-9][0-9_]*(\.[0-9][0-9_]*)?")] NumberLiteral,
 
     // ==< Other >==
     #[token("\n")] NL,
     #[regex(r"[_\p{L}][_\p{L}\p{N}]*")] Identifier,
 }
 
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::LeftParen => "`(`",
+            Self::RightParen => "`)`",
+            Self::LeftBracket => "`[`",
+            Self::RightBracket => "`]`",
+            Self::LeftCurly => "`{`",
+            Self::RightCurly => "`}`",
+            Self::DollarCurly => "`${`",
+            Self::LeftAngle => "`<`",
+            Self::RightAngle => "`>`",
+            Self::Colon => "`:`",
+            Self::DoubleColon => "`::`",
+            Self::Dot => "`.`",
+            Self::Comma => "`,`",
+            Self::Semicolon => "`;`",
+            Self::Arrow => "`->`",
+            Self::Plus => "`+`",
+            Self::Minus => "`-`",
+            Self::Star => "`*`",
+            Self::Slash => "`/`",
+            Self::Percent => "`%`",
+            Self::Equals => "`=`",
+            Self::PlusEquals => "`+=`",
+            Self::MinusEquals => "`-=`",
+            Self::StarEquals => "`*=`",
+            Self::SlashEquals => "`/=`",
+            Self::DoubleEquals => "`==`",
+            Self::NotEquals => "`!=`",
+            Self::LessEquals => "`<=`",
+            Self::GreaterEquals => "`>=`",
+            Self::DoubleAmpersand => "`&&`",
+            Self::DoublePipe => "`||`",
+            Self::Bang => "`!`",
+            Self::AtSymbol => "`@`",
+            Self::Hash => "`#`",
+            Self::KwSprite => "`sprite`",
+            Self::KwImport => "`import`",
+            Self::KwProc => "`proc`",
+            Self::KwBlock => "`block`",
+            Self::KwStruct => "`struct`",
+            Self::KwMod => "`mod`",
+            Self::KwTrait => "`trait`",
+            Self::KwImpl => "`impl`",
+            Self::KwFor => "`for`",
+            Self::KwAs => "`as`",
+            Self::KwSelf => "`self`",
+            Self::KwSuper => "`super`",
+            Self::KwLet => "`let`",
+            Self::KwReturn => "`return`",
+            Self::KwIf => "`if`",
+            Self::KwElse => "`else`",
+            Self::KwWhile => "`while`",
+            Self::KwRepeat => "`repeat`",
+            Self::KwForever => "`forever`",
+            Self::KwBreak => "`break`",
+            Self::KwContinue => "`continue`",
+            Self::KwSpawn => "`spawn`",
+            Self::KwTrue => "`true`",
+            Self::KwFalse => "`false`",
+            Self::StringLiteral => "string literal",
+            Self::NumberLiteral => "number literal",
+            Self::NL => "newline",
+            Self::Identifier => "identifier",
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct TokenInfo(Token, String, Loc);
 
@@ -62,6 +174,8 @@ pub struct Tokens<'src> {
     current: usize,
     pub(crate) source: SourceId,
     span_stack: Vec<usize>,
+    /// Errors for the characters that couldn't be lexed, those are skipped.
+    errors: Vec<SyntaxError>,
 }
 
 impl<'src> Tokens<'src> {
@@ -73,6 +187,7 @@ impl<'src> Tokens<'src> {
             source,
 
             span_stack: Vec::new(),
+            errors: Vec::new(),
         }
     }
 
@@ -80,10 +195,17 @@ impl<'src> Tokens<'src> {
         Self::from_lexer(Token::lexer(text), source)
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<Token> {
         if self.current == self.stack.len() {
-            let Some(Ok(next_elem)) = self.iter.next() else {
-                return None;
+            let next_elem = loop {
+                match self.iter.next()? {
+                    Ok(next_elem) => break next_elem,
+                    Err(()) => self.errors.push(SyntaxError::InvalidToken(Loc::new(
+                        self.iter.span(),
+                        self.source,
+                    ))),
+                }
             };
 
             let slice = self.iter.slice().to_string();
@@ -132,6 +254,15 @@ impl<'src> Tokens<'src> {
         true
     }
 
+    /// Position that can be later restored with [`Tokens::rewind`].
+    pub fn checkpoint(&self) -> usize {
+        self.current
+    }
+
+    pub fn rewind(&mut self, checkpoint: usize) {
+        self.current = checkpoint;
+    }
+
     pub fn peek(&mut self) -> Option<Token> {
         let next = self.next();
         if next.is_some() {
@@ -153,7 +284,7 @@ impl<'src> Tokens<'src> {
         let next = self.next();
         if next == Some(token) {
             true
-        } else if next == None {
+        } else if next.is_none() {
             false
         } else {
             self.current -= 1;
@@ -186,6 +317,10 @@ impl<'src> Tokens<'src> {
         Some(self.stack[self.current - 1].1.as_str())
     }
 
+    pub fn errors(&self) -> &[SyntaxError] {
+        &self.errors
+    }
+
     // ==< Parser utilities >==
     pub fn expect(&mut self, token: Token) -> Result<TokenInfo, SyntaxError> {
         if self.next() != Some(token) {
@@ -199,7 +334,10 @@ impl<'src> Tokens<'src> {
     }
 
     pub fn begin_span(&mut self) {
-        let start = self.peek_info().unwrap().loc().span.start;
+        let start = self
+            .peek_info()
+            .map(|info| info.loc().span.start)
+            .unwrap_or(0);
         self.span_stack.push(start);
     }
 
@@ -1 +1,242 @@
+//! Recursive descent parser for scratchlet.
+//!
+//! Newline acts as a statement terminator (same as `;`), unless the expression
+//! obviously continues on the next line. The rules are similar to Go and Kotlin:
+//! - Inside parentheses, brackets, import trees, struct literals and `${ }` objects
+//!   newlines are ignored completely.
+//! - Newline after a binary operator, `=`, `->`, `:` or `,` is ignored.
+//! - Line starting with `.`, `&&` or `||` continues the previous expression,
+//!   so method chains can be split into multiple lines.
+//! - `else` may be placed on the line after closing brace of `if`.
+
+mod expr;
+mod item;
 pub mod lexer;
+mod stmt;
+#[cfg(test)]
+mod tests;
+
+use lexer::{Token, TokenInfo, Tokens};
+
+use crate::common::{
+    error::SyntaxError,
+    location::{Loc, SourceId},
+};
+
+use super::ast;
+
+pub type ParseResult<T> = Result<T, SyntaxError>;
+
+/// Parses the whole file. Returns all lexer errors or the first syntax error.
+pub fn parse_module(text: &str, source: SourceId) -> Result<ast::Module, Vec<SyntaxError>> {
+    let mut parser = Parser::new(text, source);
+    let result = parser.parse_module();
+
+    let mut errors = parser.tokens.errors().to_vec();
+    match result {
+        Ok(module) if errors.is_empty() => Ok(module),
+        Ok(_) => Err(errors),
+        Err(error) => {
+            errors.push(error);
+            Err(errors)
+        }
+    }
+}
+
+pub struct Parser<'src> {
+    tokens: Tokens<'src>,
+    /// Whether newlines are ignored at the current nesting level.
+    newline_modes: Vec<bool>,
+    /// Disallows struct literals, so `if x { ... }` doesn't parse as one.
+    no_struct_literal: bool,
+}
+
+impl<'src> Parser<'src> {
+    pub fn new(text: &'src str, source: SourceId) -> Self {
+        Self {
+            tokens: Tokens::from_string(text, source),
+            newline_modes: Vec::new(),
+            no_struct_literal: false,
+        }
+    }
+
+    pub fn parse_module(&mut self) -> ParseResult<ast::Module> {
+        let items = self.parse_items(None)?;
+        Ok(ast::Module {
+            source: self.tokens.source,
+            items,
+        })
+    }
+
+    // ==< Newline handling >==
+    fn ignores_newlines(&self) -> bool {
+        self.newline_modes.last().copied().unwrap_or(false)
+    }
+
+    /// Runs `f` with newlines being either ignored or significant.
+    fn with_newlines<T>(&mut self, ignore: bool, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.newline_modes.push(ignore);
+        let result = f(self);
+        self.newline_modes.pop();
+        result
+    }
+
+    fn with_struct_literals<T>(&mut self, allowed: bool, f: impl FnOnce(&mut Self) -> T) -> T {
+        let previous = std::mem::replace(&mut self.no_struct_literal, !allowed);
+        let result = f(self);
+        self.no_struct_literal = previous;
+        result
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.tokens.is(Token::NL) {}
+    }
+
+    /// Checks whether first token after any newlines is `token` and consumes It together
+    /// with the newlines. Nothing is consumed otherwise.
+    fn eat_after_newlines(&mut self, token: Token) -> bool {
+        let checkpoint = self.tokens.checkpoint();
+        self.skip_newlines();
+        if self.tokens.is(token) {
+            true
+        } else {
+            self.tokens.rewind(checkpoint);
+            false
+        }
+    }
+
+    fn peek_after_newlines(&mut self) -> Option<Token> {
+        let checkpoint = self.tokens.checkpoint();
+        self.skip_newlines();
+        let next = self.tokens.peek();
+        self.tokens.rewind(checkpoint);
+        next
+    }
+
+    /// Consumes statement terminator, that is newline or `;`.
+    /// Closing brace and end of file terminate statements without being consumed.
+    fn expect_terminator(&mut self) -> ParseResult<()> {
+        match self.tokens.peek() {
+            Some(Token::NL | Token::Semicolon) => {
+                self.tokens.next();
+                Ok(())
+            }
+            Some(Token::RightCurly) | None => Ok(()),
+            Some(_) => Err(self.unexpected(vec![Token::NL, Token::Semicolon])),
+        }
+    }
+
+    // ==< Token utilities >==
+    fn peek(&mut self) -> Option<Token> {
+        if self.ignores_newlines() {
+            self.skip_newlines();
+        }
+        self.tokens.peek()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        if self.ignores_newlines() {
+            self.skip_newlines();
+        }
+        self.tokens.next()
+    }
+
+    fn eat(&mut self, token: Token) -> bool {
+        if self.peek() == Some(token) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> ParseResult<TokenInfo> {
+        if self.peek() == Some(token) {
+            self.next();
+            Ok(self.tokens.current_info().unwrap())
+        } else {
+            Err(self.unexpected(vec![token]))
+        }
+    }
+
+    fn expect_ident(&mut self) -> ParseResult<ast::Ident> {
+        let info = self.expect(Token::Identifier)?;
+        Ok(ast::Ident {
+            name: info.text(),
+            loc: info.loc(),
+        })
+    }
+
+    /// Error for the next token, which doesn't match any of expected ones.
+    fn unexpected(&mut self, expected: Vec<Token>) -> SyntaxError {
+        match self.peek_info() {
+            Some(found) => SyntaxError::UnexpectedToken { expected, found },
+            None => SyntaxError::UnexpectedEof {
+                expected: expected.iter().map(|token| token.to_string()).collect(),
+                loc: self.eof_loc(),
+            },
+        }
+    }
+
+    fn expected(&mut self, what: impl AsRef<str>) -> SyntaxError {
+        match self.peek_info() {
+            Some(found) => SyntaxError::Expected {
+                what: what.as_ref().to_owned(),
+                found,
+            },
+            None => SyntaxError::UnexpectedEof {
+                expected: vec![what.as_ref().to_owned()],
+                loc: self.eof_loc(),
+            },
+        }
+    }
+
+    fn peek_info(&mut self) -> Option<TokenInfo> {
+        self.peek()?;
+        self.tokens.peek_info()
+    }
+
+    fn eof_loc(&self) -> Loc {
+        let end = self.tokens.loc().map(|loc| loc.span.end).unwrap_or(0);
+        Loc::new(end..end, self.tokens.source)
+    }
+
+    fn begin_span(&mut self) {
+        if self.ignores_newlines() {
+            self.skip_newlines();
+        }
+        self.tokens.begin_span();
+    }
+
+    fn end_span(&mut self) -> Loc {
+        self.tokens.end_span()
+    }
+
+    /// Location from the start of `start` to the end of the last consumed token.
+    fn loc_from(&self, start: &Loc) -> Loc {
+        Loc::new(
+            start.span.start..self.tokens.loc().unwrap().span.end,
+            start.file,
+        )
+    }
+
+    /// Parses comma separated list until `close` is found. Trailing comma is allowed.
+    /// Newlines are ignored inside the list.
+    fn parse_delimited<T>(
+        &mut self,
+        close: Token,
+        mut element: impl FnMut(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<Vec<T>> {
+        self.with_newlines(true, |this| {
+            let mut elements = Vec::new();
+            while !this.eat(close) {
+                elements.push(element(this)?);
+                if !this.eat(Token::Comma) {
+                    this.expect(close)?;
+                    break;
+                }
+            }
+            Ok(elements)
+        })
+    }
+}
@@ -0,0 +1,162 @@
+use super::{
+    ast::{self, NodeId},
+    lexer::Token,
+    ParseResult, Parser,
+};
+
+impl Parser<'_> {
+    /// Parses statements enclosed in curly braces.
+    pub(super) fn parse_block(&mut self) -> ParseResult<ast::Block> {
+        self.begin_span();
+        self.expect(Token::LeftCurly)?;
+        let stmts = self.with_newlines(false, |this| {
+            let mut stmts = Vec::new();
+            loop {
+                this.skip_newlines();
+                if this.eat(Token::RightCurly) {
+                    break;
+                }
+                stmts.push(this.parse_stmt()?);
+                this.expect_terminator()?;
+            }
+            Ok(stmts)
+        })?;
+
+        Ok(ast::Block {
+            stmts,
+            loc: self.end_span(),
+        })
+    }
+
+    pub(super) fn parse_stmt(&mut self) -> ParseResult<ast::Stmt> {
+        self.begin_span();
+        let kind = match self.peek() {
+            Some(Token::KwLet) => {
+                self.next();
+                let name = self.expect_ident()?;
+                let ty = if self.eat(Token::Colon) {
+                    Some(self.parse_type()?)
+                } else {
+                    None
+                };
+                self.expect(Token::Equals)?;
+                self.skip_newlines();
+                ast::StmtKind::Let {
+                    name,
+                    ty,
+                    value: self.parse_expr()?,
+                }
+            }
+            Some(Token::KwReturn) => {
+                self.next();
+                let value = match self.tokens.peek() {
+                    Some(Token::NL | Token::Semicolon | Token::RightCurly) | None => None,
+                    Some(_) => Some(self.parse_expr()?),
+                };
+                ast::StmtKind::Return(value)
+            }
+            Some(Token::KwIf) => self.parse_if()?,
+            Some(Token::KwWhile) => {
+                self.next();
+                ast::StmtKind::While {
+                    condition: self.parse_condition()?,
+                    body: self.parse_block()?,
+                }
+            }
+            Some(Token::KwRepeat) => {
+                self.next();
+                ast::StmtKind::Repeat {
+                    count: self.parse_condition()?,
+                    body: self.parse_block()?,
+                }
+            }
+            Some(Token::KwForever) => {
+                self.next();
+                ast::StmtKind::Forever(self.parse_block()?)
+            }
+            Some(Token::KwBreak) => {
+                self.next();
+                ast::StmtKind::Break
+            }
+            Some(Token::KwContinue) => {
+                self.next();
+                ast::StmtKind::Continue
+            }
+            Some(Token::KwSpawn) => {
+                self.next();
+                let sprite = self.parse_path()?;
+                self.expect(Token::LeftCurly)?;
+                ast::StmtKind::Spawn {
+                    sprite,
+                    fields: self.parse_field_values()?,
+                }
+            }
+            _ => {
+                let target = self.parse_expr()?;
+                let op = match self.peek() {
+                    Some(Token::Equals) => Some(None),
+                    Some(Token::PlusEquals) => Some(Some(ast::BinaryOp::Add)),
+                    Some(Token::MinusEquals) => Some(Some(ast::BinaryOp::Sub)),
+                    Some(Token::StarEquals) => Some(Some(ast::BinaryOp::Mul)),
+                    Some(Token::SlashEquals) => Some(Some(ast::BinaryOp::Div)),
+                    _ => None,
+                };
+                match op {
+                    Some(op) => {
+                        self.next();
+                        self.skip_newlines();
+                        ast::StmtKind::Assign {
+                            target,
+                            op,
+                            value: self.parse_expr()?,
+                        }
+                    }
+                    None => ast::StmtKind::Expr(target),
+                }
+            }
+        };
+
+        Ok(ast::Stmt {
+            id: NodeId::next(),
+            kind,
+            loc: self.end_span(),
+        })
+    }
+
+    fn parse_if(&mut self) -> ParseResult<ast::StmtKind> {
+        self.expect(Token::KwIf)?;
+        let condition = self.parse_condition()?;
+        let then = self.parse_block()?;
+
+        let otherwise = if self.eat_after_newlines(Token::KwElse) {
+            if self.peek() == Some(Token::KwIf) {
+                self.begin_span();
+                let kind = self.parse_if()?;
+                let loc = self.end_span();
+                Some(ast::Block {
+                    stmts: vec![ast::Stmt {
+                        id: NodeId::next(),
+                        kind,
+                        loc: loc.clone(),
+                    }],
+                    loc,
+                })
+            } else {
+                Some(self.parse_block()?)
+            }
+        } else {
+            None
+        };
+
+        Ok(ast::StmtKind::If {
+            condition,
+            then,
+            otherwise,
+        })
+    }
+
+    /// Expression directly followed by a block, struct literals are not allowed here.
+    fn parse_condition(&mut self) -> ParseResult<ast::Expr> {
+        self.with_struct_literals(false, |this| this.parse_expr())
+    }
+}
@@ -0,0 +1,326 @@
+use crate::{
+    common::{error::SyntaxError, location::SourceId},
+    frontend::ast,
+};
+
+use super::parse_module;
+
+fn parse(text: &str) -> ast::Module {
+    match parse_module(text, SourceId::dummy()) {
+        Ok(module) => module,
+        Err(errors) => panic!("Failed to parse {text:?}: {errors:?}"),
+    }
+}
+
+fn parse_err(text: &str) -> Vec<SyntaxError> {
+    parse_module(text, SourceId::dummy()).expect_err("Parsing should fail")
+}
+
+/// Parses statements as a body of a procedure.
+fn parse_stmts(body: &str) -> Vec<ast::Stmt> {
+    let module = parse(&format!("proc test() {{\n{body}\n}}"));
+    let ast::ItemKind::Proc(proc) = module.items.into_iter().next().unwrap().kind else {
+        unreachable!()
+    };
+    let ast::ProcBody::Block(block) = proc.body else {
+        unreachable!()
+    };
+    block.stmts
+}
+
+/// Renders expression as s-expression so tests can compare whole trees.
+fn sexpr(expr: &ast::Expr) -> String {
+    let list = |items: &[ast::Expr]| {
+        items
+            .iter()
+            .map(|item| format!(" {}", sexpr(item)))
+            .collect::<String>()
+    };
+    match &expr.kind {
+        ast::ExprKind::Number(value) => value.to_string(),
+        ast::ExprKind::Text(value) => format!("{value:?}"),
+        ast::ExprKind::Boolean(value) => value.to_string(),
+        ast::ExprKind::Path(path) => path
+            .segments
+            .iter()
+            .map(|segment| segment.name.as_str())
+            .collect::<Vec<_>>()
+            .join("::"),
+        ast::ExprKind::Binary(op, lhs, rhs) => {
+            format!("({} {} {})", op.as_str(), sexpr(lhs), sexpr(rhs))
+        }
+        ast::ExprKind::Unary(ast::UnaryOp::Neg, operand) => format!("(- {})", sexpr(operand)),
+        ast::ExprKind::Unary(ast::UnaryOp::Not, operand) => format!("(! {})", sexpr(operand)),
+        ast::ExprKind::Call(callee, args) => format!("(call {}{})", sexpr(callee), list(args)),
+        ast::ExprKind::MethodCall(receiver, name, args) => {
+            format!("(.{} {}{})", name.name, sexpr(receiver), list(args))
+        }
+        ast::ExprKind::Field(target, name) => format!("(. {} {})", sexpr(target), name.name),
+        ast::ExprKind::StructLiteral(path, fields) => format!(
+            "({}{})",
+            path.segments.last().unwrap().name,
+            fields
+                .iter()
+                .map(|(name, value)| format!(" {}: {}", name.name, sexpr(value)))
+                .collect::<String>()
+        ),
+    }
+}
+
+fn let_value(stmt: &ast::Stmt) -> String {
+    match &stmt.kind {
+        ast::StmtKind::Let { value, .. } => sexpr(value),
+        other => panic!("Expected let statement, found {other:?}"),
+    }
+}
+
+fn expr_value(stmt: &ast::Stmt) -> String {
+    match &stmt.kind {
+        ast::StmtKind::Expr(value) => sexpr(value),
+        other => panic!("Expected expression statement, found {other:?}"),
+    }
+}
+
+#[test]
+fn operator_precedence() {
+    let stmts = parse_stmts("let x = 1 + 2 * 3 - -4 < 5 && !a || b == c");
+    assert_eq!(
+        let_value(&stmts[0]),
+        "(|| (&& (< (- (+ 1 (* 2 3)) (- 4)) 5) (! a)) (== b c))"
+    );
+}
+
+#[test]
+fn binary_operator_at_end_of_line_continues_expression() {
+    let stmts = parse_stmts("let x = 1 +\n    2 *\n\n    3\nlet y = 4");
+    assert_eq!(stmts.len(), 2);
+    assert_eq!(let_value(&stmts[0]), "(+ 1 (* 2 3))");
+    assert_eq!(let_value(&stmts[1]), "4");
+}
+
+#[test]
+fn operator_at_start_of_line_starts_new_statement() {
+    let stmts = parse_stmts("let x = 1\n- 2");
+    assert_eq!(stmts.len(), 2);
+    assert_eq!(let_value(&stmts[0]), "1");
+    assert_eq!(expr_value(&stmts[1]), "(- 2)");
+}
+
+#[test]
+fn logical_operators_at_start_of_line_continue_expression() {
+    let stmts = parse_stmts("let x = a\n    && b\n    || c");
+    assert_eq!(stmts.len(), 1);
+    assert_eq!(let_value(&stmts[0]), "(|| (&& a b) c)");
+}
+
+#[test]
+fn method_chain_on_next_lines() {
+    let stmts = parse_stmts("let x = list\n    .first()\n    .add(1, 2)\n    .value\nfoo()");
+    assert_eq!(stmts.len(), 2);
+    assert_eq!(let_value(&stmts[0]), "(. (.add (.first list) 1 2) value)");
+    assert_eq!(expr_value(&stmts[1]), "(call foo)");
+}
+
+#[test]
+fn newlines_are_ignored_inside_parentheses() {
+    let stmts = parse_stmts("let x = (1\n    + 2\n)\nfoo(\n    a,\n    b,\n)");
+    assert_eq!(stmts.len(), 2);
+    assert_eq!(let_value(&stmts[0]), "(+ 1 2)");
+    assert_eq!(expr_value(&stmts[1]), "(call foo a b)");
+}
+
+#[test]
+fn multi_line_struct_literal() {
+    let stmts = parse_stmts("let v = Vec2 {\n    x: 1 +\n        2,\n\n    y\n}\nv.x = 3");
+    assert_eq!(stmts.len(), 2);
+    assert_eq!(let_value(&stmts[0]), "(Vec2 x: (+ 1 2) y: y)");
+    assert!(matches!(
+        stmts[1].kind,
+        ast::StmtKind::Assign { op: None, .. }
+    ));
+}
+
+#[test]
+fn struct_literal_is_not_allowed_in_condition() {
+    let stmts = parse_stmts("if a { b() }\nwhile (V { x: 1 }).x { }");
+    let ast::StmtKind::If { condition, .. } = &stmts[0].kind else {
+        panic!("Expected if statement")
+    };
+    assert_eq!(sexpr(condition), "a");
+    let ast::StmtKind::While { condition, .. } = &stmts[1].kind else {
+        panic!("Expected while statement")
+    };
+    assert_eq!(sexpr(condition), "(. (V x: 1) x)");
+}
+
+#[test]
+fn multi_line_import_tree() {
+    let module = parse(
+        "import scratch::{\n    math::sqrt,\n    looks::{\n        say, say_for as sayf\n    },\n    pen::*,\n}\nimport super::utils",
+    );
+    assert_eq!(module.items.len(), 2);
+
+    let ast::ItemKind::Import(tree) = &module.items[0].kind else {
+        panic!("Expected import")
+    };
+    assert_eq!(tree.prefix[0].name, "scratch");
+    let ast::ImportKind::Nested(trees) = &tree.kind else {
+        panic!("Expected nested import")
+    };
+    assert_eq!(trees.len(), 3);
+    assert!(matches!(trees[0].kind, ast::ImportKind::Simple(None)));
+    assert!(matches!(trees[2].kind, ast::ImportKind::Glob));
+
+    let ast::ImportKind::Nested(looks) = &trees[1].kind else {
+        panic!("Expected nested import")
+    };
+    let ast::ImportKind::Simple(Some(alias)) = &looks[1].kind else {
+        panic!("Expected import with alias")
+    };
+    assert_eq!(alias.name, "sayf");
+
+    let ast::ItemKind::Import(tree) = &module.items[1].kind else {
+        panic!("Expected import")
+    };
+    assert_eq!(tree.prefix.len(), 2);
+}
+
+#[test]
+fn import_must_be_terminated() {
+    let errors = parse_err("import a::b proc x() {}");
+    assert!(matches!(
+        errors[0],
+        SyntaxError::UnexpectedToken { ref expected, .. } if expected.contains(&super::lexer::Token::NL)
+    ));
+}
+
+#[test]
+fn else_on_next_line() {
+    let stmts = parse_stmts("if a {\n    b()\n}\nelse if c {\n}\nelse {\n    return\n}");
+    assert_eq!(stmts.len(), 1);
+    let ast::StmtKind::If {
+        otherwise: Some(otherwise),
+        ..
+    } = &stmts[0].kind
+    else {
+        panic!("Expected if statement with else")
+    };
+    assert!(matches!(
+        otherwise.stmts[0].kind,
+        ast::StmtKind::If {
+            otherwise: Some(_),
+            ..
+        }
+    ));
+}
+
+#[test]
+fn semicolons_separate_statements() {
+    let stmts = parse_stmts("let a = 1; let b = 2; return a");
+    assert_eq!(stmts.len(), 3);
+    assert!(matches!(stmts[2].kind, ast::StmtKind::Return(Some(_))));
+}
+
+#[test]
+fn two_expressions_on_one_line_are_an_error() {
+    let errors = parse_err("proc x() {\n    let a = 1 2\n}");
+    assert!(matches!(errors[0], SyntaxError::UnexpectedToken { .. }));
+}
+
+#[test]
+fn block_declaration() {
+    let module = parse(
+        "block sqrt(x: number) -> number as operator_mathop {\n    inputs: ${ NUM: x },\n    fields: ${\n        OPERATOR: \"sqrt\"\n    },\n}",
+    );
+    let ast::ItemKind::Block(block) = &module.items[0].kind else {
+        panic!("Expected block declaration")
+    };
+    assert_eq!(block.opcode.name, "operator_mathop");
+    assert_eq!(block.inputs.len(), 1);
+    assert_eq!(sexpr(&block.fields[0].1), "\"sqrt\"");
+}
+
+#[test]
+fn sprite_with_costumes_and_procedures() {
+    let module = parse(
+        "sprite Stage {\n    costumes {\n        @default background1: \"bg.svg\"\n        background2: \"bg2.svg\"\n    }\n    proc greet(name: text) = \"Hi, \" +\n        name\n}",
+    );
+    let ast::ItemKind::Sprite(sprite) = &module.items[0].kind else {
+        panic!("Expected sprite")
+    };
+    assert_eq!(sprite.costumes.len(), 2);
+    assert_eq!(sprite.costumes[0].attributes[0].name.name, "default");
+    assert_eq!(sprite.items.len(), 1);
+}
+
+#[test]
+fn traits_and_impls() {
+    let module = parse(
+        "trait Shape {\n    proc area(self) -> number\n    proc scale(self, by: number)\n}\nimpl Shape for Circle {\n    #[inline]\n    proc area(self) -> number = self.r * self.r\n}\nimpl Circle { }",
+    );
+    let ast::ItemKind::Trait(trait_decl) = &module.items[0].kind else {
+        panic!("Expected trait")
+    };
+    assert_eq!(trait_decl.methods.len(), 2);
+    assert_eq!(trait_decl.methods[1].params[0].name.name, "by");
+    assert!(trait_decl.methods[1].ret.is_none());
+
+    let ast::ItemKind::Impl(implementation) = &module.items[1].kind else {
+        panic!("Expected impl")
+    };
+    assert_eq!(
+        implementation.trait_ref.as_ref().unwrap().segments[0].name,
+        "Shape"
+    );
+    assert_eq!(implementation.items[0].attributes[0].name.name, "inline");
+    let ast::ItemKind::Proc(proc) = &implementation.items[0].kind else {
+        panic!("Expected method")
+    };
+    assert!(proc.receiver.is_some() && proc.params.is_empty());
+    let ast::ItemKind::Impl(implementation) = &module.items[2].kind else {
+        panic!("Expected impl")
+    };
+    assert!(implementation.trait_ref.is_none());
+
+    let errors = parse_err("impl Circle {\n    proc area(r: number) = 1\n}");
+    assert!(matches!(errors[0], SyntaxError::UnexpectedToken { .. }));
+    let errors = parse_err("proc area(self) = 1");
+    assert!(matches!(errors[0], SyntaxError::UnexpectedToken { .. }));
+}
+
+#[test]
+fn sprite_fields_and_spawn() {
+    let module = parse(
+        "sprite Ball {\n    fields {\n        speed: number,\n        position: Vec2\n    }\n    @clone\n    proc start() { self.position.x += self.speed }\n    proc main() {\n        spawn Ball {\n            speed: 2,\n            position\n        }\n    }\n}",
+    );
+    let ast::ItemKind::Sprite(sprite) = &module.items[0].kind else {
+        panic!("Expected sprite")
+    };
+    let fields: Vec<_> = sprite
+        .fields
+        .iter()
+        .map(|field| field.name.name.as_str())
+        .collect();
+    assert_eq!(fields, ["speed", "position"]);
+    assert_eq!(sprite.items.len(), 2);
+    assert_eq!(sprite.items[0].attributes[0].name.name, "clone");
+
+    let ast::ItemKind::Proc(proc) = &sprite.items[1].kind else {
+        panic!("Expected procedure")
+    };
+    let ast::ProcBody::Block(block) = &proc.body else {
+        panic!("Expected block body")
+    };
+    let ast::StmtKind::Spawn { sprite, fields } = &block.stmts[0].kind else {
+        panic!("Expected spawn statement")
+    };
+    assert_eq!(sprite.segments[0].name, "Ball");
+    let fields: Vec<_> = fields
+        .iter()
+        .map(|(name, value)| format!("{}: {}", name.name, sexpr(value)))
+        .collect();
+    assert_eq!(fields, ["speed: 2", "position: position"]);
+
+    let errors = parse_err("proc main() { spawn Ball }");
+    assert!(matches!(errors[0], SyntaxError::UnexpectedToken { .. }));
+}
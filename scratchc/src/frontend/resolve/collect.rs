@@ -0,0 +1,201 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    common::{
+        error::ResolveError,
+        location::{Loc, SourceId},
+    },
+    frontend::{ast, parser},
+};
+
+use super::{imports::PendingImport, Crate, DefId, DefKind, Definition, Resolver, Scope};
+
+impl Resolver<'_> {
+    pub(super) fn load_crate(&mut self, name: &str, source: SourceId) {
+        let Some(mut module) = self.parse_file(source) else {
+            return;
+        };
+
+        let root = self.defs.insert(Definition {
+            kind: DefKind::Module,
+            name: name.to_owned(),
+            parent: None,
+            item: None,
+            loc: Loc::new(0..0, source),
+        });
+        self.scopes.insert(root, Scope::default());
+
+        let dir = self.module_dir(source);
+        self.collect_items(root, &mut module.items, dir.as_deref());
+        self.crates.push(Crate {
+            name: name.to_owned(),
+            root,
+            module,
+        });
+    }
+
+    fn parse_file(&mut self, source: SourceId) -> Option<ast::Module> {
+        let file = self.source_map.get(source).unwrap();
+        match parser::parse_module(file.contents(), source) {
+            Ok(module) => Some(module),
+            Err(errors) => {
+                self.errors
+                    .extend(errors.into_iter().map(ResolveError::Syntax));
+                None
+            }
+        }
+    }
+
+    /// Directory in which files of modules declared in the crate root are searched.
+    fn module_dir(&self, source: SourceId) -> Option<PathBuf> {
+        let path = self.source_map.get(source)?.path()?;
+        Some(path.parent().unwrap_or(Path::new(".")).to_owned())
+    }
+
+    /// Registers definitions of all the items, loading files of `mod name` items.
+    /// `dir` is the directory in which files of the submodules are searched.
+    fn collect_items(&mut self, scope: DefId, items: &mut [ast::Item], dir: Option<&Path>) {
+        for item in items.iter_mut() {
+            let kind = match &item.kind {
+                ast::ItemKind::Sprite(_) => DefKind::Sprite,
+                ast::ItemKind::Proc(_) => DefKind::Proc,
+                ast::ItemKind::Block(_) => DefKind::Block,
+                ast::ItemKind::Struct(_) => DefKind::Struct,
+                ast::ItemKind::Trait(_) => DefKind::Trait,
+                ast::ItemKind::Mod(_) => DefKind::Module,
+                ast::ItemKind::Import(tree) => {
+                    self.collect_import(scope, Vec::new(), tree);
+                    continue;
+                }
+                ast::ItemKind::Impl(implementation) => {
+                    for method in implementation.items.iter() {
+                        let name = method.kind.name().unwrap();
+                        self.define_method(scope, name, method.id);
+                    }
+                    continue;
+                }
+            };
+            let name = item.kind.name().unwrap().clone();
+            let id = self.define(scope, kind, &name, item.id);
+
+            match &mut item.kind {
+                ast::ItemKind::Sprite(sprite) => {
+                    self.scopes.insert(id, Scope::default());
+                    self.collect_items(id, &mut sprite.items, dir);
+                }
+                ast::ItemKind::Mod(module) => {
+                    self.scopes.insert(id, Scope::default());
+                    if module.items.is_none() {
+                        module.items = self.load_module_file(&name, dir);
+                    }
+                    let child_dir = dir.map(|dir| dir.join(&name.name));
+                    if let Some(items) = module.items.as_mut() {
+                        self.collect_items(id, items, child_dir.as_deref());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn define(
+        &mut self,
+        scope: DefId,
+        kind: DefKind,
+        name: &ast::Ident,
+        item: ast::NodeId,
+    ) -> DefId {
+        let id = self.defs.insert(Definition {
+            kind,
+            name: name.name.clone(),
+            parent: Some(scope),
+            item: Some(item),
+            loc: name.loc.clone(),
+        });
+        self.item_defs.insert(item, id);
+
+        let items = &mut self.scopes.get_mut(&scope).unwrap().items;
+        if let Some(previous) = items.get(&name.name) {
+            let previous = self.defs[*previous].loc.clone();
+            self.error(ResolveError::Duplicate {
+                name: name.name.clone(),
+                loc: name.loc.clone(),
+                previous,
+            });
+        } else {
+            items.insert(name.name.clone(), id);
+        }
+        id
+    }
+
+    /// Registers method without making It visible in the scope.
+    fn define_method(&mut self, scope: DefId, name: &ast::Ident, item: ast::NodeId) {
+        let id = self.defs.insert(Definition {
+            kind: DefKind::Method,
+            name: name.name.clone(),
+            parent: Some(scope),
+            item: Some(item),
+            loc: name.loc.clone(),
+        });
+        self.item_defs.insert(item, id);
+    }
+
+    fn load_module_file(
+        &mut self,
+        name: &ast::Ident,
+        dir: Option<&Path>,
+    ) -> Option<Vec<ast::Item>> {
+        let Some(dir) = dir else {
+            self.error(ResolveError::ModuleFileNotFound {
+                name: name.name.clone(),
+                loc: name.loc.clone(),
+                path: None,
+            });
+            return None;
+        };
+
+        let path = dir.join(format!("{}.sl", name.name));
+        match self.source_map.load(&path) {
+            Ok(source) => self.parse_file(source).map(|module| module.items),
+            Err(_) => {
+                self.error(ResolveError::ModuleFileNotFound {
+                    name: name.name.clone(),
+                    loc: name.loc.clone(),
+                    path: Some(path),
+                });
+                None
+            }
+        }
+    }
+
+    /// Flattens import tree into separate imports.
+    fn collect_import(
+        &mut self,
+        scope: DefId,
+        mut prefix: Vec<ast::Ident>,
+        tree: &ast::ImportTree,
+    ) {
+        prefix.extend(tree.prefix.iter().cloned());
+        match &tree.kind {
+            ast::ImportKind::Simple(alias) => self.pending_imports.push(PendingImport {
+                scope,
+                path: prefix,
+                glob: false,
+                alias: alias.clone(),
+                loc: tree.loc.clone(),
+            }),
+            ast::ImportKind::Glob => self.pending_imports.push(PendingImport {
+                scope,
+                path: prefix,
+                glob: true,
+                alias: None,
+                loc: tree.loc.clone(),
+            }),
+            ast::ImportKind::Nested(trees) => {
+                for tree in trees.iter() {
+                    self.collect_import(scope, prefix.clone(), tree);
+                }
+            }
+        }
+    }
+}
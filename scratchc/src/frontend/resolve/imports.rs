@@ -0,0 +1,119 @@
+use crate::{
+    common::{error::ResolveError, location::Loc},
+    frontend::ast,
+};
+
+use super::{DefId, Resolver};
+
+/// Single import from the flattened import tree.
+#[derive(Debug, Clone)]
+pub(super) struct PendingImport {
+    pub scope: DefId,
+    pub path: Vec<ast::Ident>,
+    pub glob: bool,
+    pub alias: Option<ast::Ident>,
+    pub loc: Loc,
+}
+
+impl Resolver<'_> {
+    /// Imports can refer to names brought into scope by other imports,
+    /// so they are resolved repeatedly until no more progress is made.
+    pub(super) fn resolve_imports(&mut self) {
+        let mut pending = std::mem::take(&mut self.pending_imports);
+        loop {
+            let before = pending.len();
+            let mut unresolved = Vec::new();
+            for import in pending {
+                let errors = self.errors.len();
+                if !self.resolve_import(&import) {
+                    // Errors will be reported in the last round.
+                    self.errors.truncate(errors);
+                    unresolved.push(import);
+                }
+            }
+            pending = unresolved;
+
+            if pending.is_empty() || pending.len() == before {
+                break;
+            }
+        }
+
+        for import in pending.iter() {
+            self.resolve_import(import);
+        }
+    }
+
+    /// Returns whether the import was resolved.
+    fn resolve_import(&mut self, import: &PendingImport) -> bool {
+        let Some((last, namespace)) = import.path.split_last() else {
+            self.error(ResolveError::EmptyImport(import.loc.clone()));
+            return true;
+        };
+
+        if import.glob {
+            let Some(module) = self.resolve_namespace(import.scope, &import.path) else {
+                return false;
+            };
+            self.scopes
+                .get_mut(&import.scope)
+                .unwrap()
+                .globs
+                .push((module, import.loc.clone()));
+            return true;
+        }
+
+        let (target, name) = if last.name == "self" {
+            // `import module::{self}` imports the module itself.
+            if namespace.is_empty() {
+                self.error(ResolveError::EmptyImport(import.loc.clone()));
+                return true;
+            }
+            let Some(module) = self.resolve_namespace(import.scope, namespace) else {
+                return false;
+            };
+            let name = ast::Ident {
+                name: self.defs[module].name.clone(),
+                loc: last.loc.clone(),
+            };
+            (module, name)
+        } else {
+            let target = if namespace.is_empty() {
+                self.lookup_lexical(import.scope, last)
+            } else {
+                let Some(namespace_id) = self.resolve_namespace(import.scope, namespace) else {
+                    return false;
+                };
+                self.lookup_member(namespace_id, last)
+            };
+            let Some(target) = target else {
+                self.error(ResolveError::Unresolved {
+                    name: last.name.clone(),
+                    loc: last.loc.clone(),
+                    parent: namespace.last().map(|segment| segment.name.clone()),
+                });
+                return false;
+            };
+            (target, last.clone())
+        };
+
+        let name = import.alias.as_ref().unwrap_or(&name);
+        let scope = self.scopes.get_mut(&import.scope).unwrap();
+        let previous = match scope.items.get(&name.name) {
+            Some(item) => Some(self.defs[*item].loc.clone()),
+            None => scope.imports.get(&name.name).map(|(_, loc)| loc.clone()),
+        };
+        match previous {
+            Some(previous) => self.error(ResolveError::Duplicate {
+                name: name.name.clone(),
+                loc: name.loc.clone(),
+                previous,
+            }),
+            None => {
+                scope
+                    .imports
+                    .insert(name.name.clone(), (target, name.loc.clone()));
+            }
+        }
+        true
+    }
+}
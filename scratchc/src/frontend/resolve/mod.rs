@@ -0,0 +1,359 @@
+//! Name resolution. Builds module tree from files and `mod` items, resolves imports
+//! and binds every path in the program to the definition or local It refers to.
+//!
+//! Every file is a module, files of `mod name` declarations are searched for
+//! next to the declaring file (`name.sl`), or in the directory named after It
+//! when declaring file is not a crate root (`parent/name.sl`).
+//! Sprites behave like modules, but unlike modules they can see the names
+//! of the module they are defined in.
+
+mod collect;
+mod imports;
+mod paths;
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+
+use slotmap::SlotMap;
+
+use crate::common::{
+    error::ResolveError,
+    location::{Loc, SourceId},
+    source_map::SourceMap,
+};
+
+use super::ast::{self, NodeId};
+
+/// Source of the builtin `scratch` library.
+pub const SCRATCH_LIBRARY: &str = include_str!("../../../std/scratch.sl");
+
+slotmap::new_key_type! {
+    pub struct DefId;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefKind {
+    Module,
+    Sprite,
+    Proc,
+    Block,
+    Struct,
+    Trait,
+    /// Procedure of an `impl` block, It can only be called with method call syntax,
+    /// so It isn't visible in any scope.
+    Method,
+}
+
+impl DefKind {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Self::Module => "module",
+            Self::Sprite => "sprite",
+            Self::Proc => "procedure",
+            Self::Block => "block",
+            Self::Struct => "struct",
+            Self::Trait => "trait",
+            Self::Method => "method",
+        }
+    }
+
+    /// Whether paths can go through definitions of this kind, `a::b`.
+    pub fn is_namespace(&self) -> bool {
+        matches!(self, Self::Module | Self::Sprite)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Definition {
+    pub kind: DefKind,
+    pub name: String,
+    pub parent: Option<DefId>,
+    /// Item defining this, `None` for crate roots.
+    pub item: Option<NodeId>,
+    pub loc: Loc,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrimTy {
+    Number,
+    Text,
+    Boolean,
+}
+
+impl PrimTy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "number" => Some(Self::Number),
+            "text" => Some(Self::Text),
+            "boolean" => Some(Self::Boolean),
+            _ => None,
+        }
+    }
+}
+
+/// What the path resolved to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Res {
+    Def(DefId),
+    /// Local variable or parameter, identified by the `let` statement or parameter node.
+    Local(NodeId),
+    /// `self` in procedures of the sprite, the sprite or clone running them.
+    SpriteSelf(DefId),
+    PrimTy(PrimTy),
+}
+
+#[derive(Debug, Clone)]
+pub struct Crate {
+    pub name: String,
+    pub root: DefId,
+    /// Items of the root file with all `mod name` declarations filled with loaded files.
+    pub module: ast::Module,
+}
+
+/// Fully resolved program, input for the later passes.
+#[derive(Debug)]
+pub struct Program {
+    /// Libraries first, crate being compiled is the last one.
+    pub crates: Vec<Crate>,
+    defs: SlotMap<DefId, Definition>,
+    item_defs: HashMap<NodeId, DefId>,
+    resolutions: HashMap<NodeId, Res>,
+}
+
+impl Program {
+    pub fn def(&self, id: DefId) -> &Definition {
+        &self.defs[id]
+    }
+
+    pub fn defs(&self) -> impl Iterator<Item = (DefId, &Definition)> {
+        self.defs.iter()
+    }
+
+    /// Definition created by the item.
+    pub fn def_of_item(&self, item: NodeId) -> Option<DefId> {
+        self.item_defs.get(&item).copied()
+    }
+
+    /// Resolution of the path, identified by Its [`ast::Path::id`].
+    pub fn res(&self, path: NodeId) -> Option<Res> {
+        self.resolutions.get(&path).copied()
+    }
+
+    /// Sprite whose fields the expression gives access to, when It is `self` of the sprite.
+    pub fn sprite_self(&self, expr: &ast::Expr) -> Option<DefId> {
+        let ast::ExprKind::Path(path) = &expr.kind else {
+            return None;
+        };
+        match self.res(path.id) {
+            Some(Res::SpriteSelf(sprite)) => Some(sprite),
+            _ => None,
+        }
+    }
+
+    /// Full path of the definition, like `scratch::looks::say`.
+    pub fn path_of(&self, id: DefId) -> String {
+        let mut segments = Vec::new();
+        let mut current = Some(id);
+        while let Some(id) = current {
+            segments.push(self.defs[id].name.as_str());
+            current = self.defs[id].parent;
+        }
+        segments.reverse();
+        segments.join("::")
+    }
+
+    pub fn root_crate(&self) -> &Crate {
+        self.crates.last().unwrap()
+    }
+}
+
+/// Names visible inside of a module or sprite.
+#[derive(Debug, Default)]
+struct Scope {
+    items: HashMap<String, DefId>,
+    imports: HashMap<String, (DefId, Loc)>,
+    globs: Vec<(DefId, Loc)>,
+}
+
+pub struct Resolver<'sm> {
+    source_map: &'sm mut SourceMap,
+    crates: Vec<Crate>,
+    defs: SlotMap<DefId, Definition>,
+    scopes: HashMap<DefId, Scope>,
+    item_defs: HashMap<NodeId, DefId>,
+    resolutions: HashMap<NodeId, Res>,
+    pending_imports: Vec<imports::PendingImport>,
+    errors: Vec<ResolveError>,
+}
+
+impl<'sm> Resolver<'sm> {
+    pub fn new(source_map: &'sm mut SourceMap) -> Self {
+        Self {
+            source_map,
+            crates: Vec::new(),
+            defs: SlotMap::default(),
+            scopes: HashMap::new(),
+            item_defs: HashMap::new(),
+            resolutions: HashMap::new(),
+            pending_imports: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Adds library that can be referred to by Its name from every module.
+    pub fn add_library(&mut self, name: impl AsRef<str>, root: SourceId) -> &mut Self {
+        self.load_crate(name.as_ref(), root);
+        self
+    }
+
+    /// Adds libraries shipped with the compiler, like `scratch`.
+    pub fn add_builtin_libraries(&mut self) -> &mut Self {
+        let scratch = self.source_map.add("<scratch>", SCRATCH_LIBRARY);
+        self.add_library("scratch", scratch)
+    }
+
+    /// Loads root crate and resolves whole program.
+    pub fn resolve(mut self, root: SourceId) -> Result<Program, Vec<ResolveError>> {
+        let name = self
+            .source_map
+            .get(root)
+            .and_then(|file| file.path())
+            .and_then(|path| path.file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "main".to_owned());
+        self.load_crate(&name, root);
+
+        self.resolve_imports();
+        self.resolve_bodies();
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+        Ok(Program {
+            crates: self.crates,
+            defs: self.defs,
+            item_defs: self.item_defs,
+            resolutions: self.resolutions,
+        })
+    }
+
+    fn error(&mut self, error: ResolveError) {
+        self.errors.push(error);
+    }
+
+    /// Module in which definition lives, sprites are skipped.
+    fn enclosing_module(&self, mut id: DefId) -> DefId {
+        while self.defs[id].kind != DefKind::Module {
+            id = self.defs[id].parent.unwrap();
+        }
+        id
+    }
+
+    /// Looks up name directly in the scope, without looking at parent scopes.
+    fn lookup_in_scope(&mut self, scope: DefId, name: &ast::Ident) -> Option<DefId> {
+        let data = &self.scopes[&scope];
+        if let Some(id) = data.items.get(&name.name) {
+            return Some(*id);
+        }
+        if let Some((id, _)) = data.imports.get(&name.name) {
+            return Some(*id);
+        }
+
+        let mut candidates: Vec<(DefId, Loc)> = Vec::new();
+        for (module, loc) in data.globs.iter() {
+            if let Some(id) = self.scopes[module].items.get(&name.name) {
+                if !candidates.iter().any(|(candidate, _)| candidate == id) {
+                    candidates.push((*id, loc.clone()));
+                }
+            }
+        }
+        let first = candidates.first()?.0;
+        if candidates.len() > 1 {
+            self.error(ResolveError::Ambiguous {
+                name: name.name.clone(),
+                loc: name.loc.clone(),
+                candidates: candidates.into_iter().map(|(_, loc)| loc).collect(),
+            });
+        }
+        Some(first)
+    }
+
+    /// Looks up name visible from the scope, including names from enclosing modules
+    /// of sprites and libraries.
+    fn lookup_lexical(&mut self, mut scope: DefId, name: &ast::Ident) -> Option<DefId> {
+        loop {
+            if let Some(id) = self.lookup_in_scope(scope, name) {
+                return Some(id);
+            }
+            match self.defs[scope].kind {
+                DefKind::Sprite => scope = self.defs[scope].parent.unwrap(),
+                _ => break,
+            }
+        }
+
+        self.crates
+            .iter()
+            .find(|krate| krate.name == name.name)
+            .map(|krate| krate.root)
+    }
+
+    /// Resolves path made only of modules and sprites, `self` and `super` are
+    /// allowed at the beginning of the path.
+    fn resolve_namespace(&mut self, scope: DefId, segments: &[ast::Ident]) -> Option<DefId> {
+        let mut current: Option<DefId> = None;
+        for segment in segments.iter() {
+            let next = match (segment.name.as_str(), current) {
+                ("self", None) => self.enclosing_module(scope),
+                ("super", None) => {
+                    let module = self.enclosing_module(scope);
+                    self.parent_module(module, segment)?
+                }
+                ("super", Some(module)) if self.defs[module].kind == DefKind::Module => {
+                    self.parent_module(module, segment)?
+                }
+                _ => {
+                    let found = match current {
+                        None => self.lookup_lexical(scope, segment),
+                        Some(namespace) => self.lookup_member(namespace, segment),
+                    };
+                    let Some(found) = found else {
+                        self.error(ResolveError::Unresolved {
+                            name: segment.name.clone(),
+                            loc: segment.loc.clone(),
+                            parent: current.map(|id| self.defs[id].name.clone()),
+                        });
+                        return None;
+                    };
+                    found
+                }
+            };
+
+            if !self.defs[next].kind.is_namespace() {
+                self.error(ResolveError::NotANamespace {
+                    name: segment.name.clone(),
+                    kind: self.defs[next].kind.describe(),
+                    loc: segment.loc.clone(),
+                });
+                return None;
+            }
+            current = Some(next);
+        }
+        current
+    }
+
+    /// Looks up item defined directly in the module or sprite.
+    fn lookup_member(&self, namespace: DefId, name: &ast::Ident) -> Option<DefId> {
+        self.scopes[&namespace].items.get(&name.name).copied()
+    }
+
+    fn parent_module(&mut self, module: DefId, segment: &ast::Ident) -> Option<DefId> {
+        match self.defs[module].parent {
+            Some(parent) => Some(self.enclosing_module(parent)),
+            None => {
+                self.error(ResolveError::SuperOfRoot(segment.loc.clone()));
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+
+use crate::{common::error::ResolveError, frontend::ast};
+
+use super::{DefId, DefKind, PrimTy, Res, Resolver};
+
+/// Stack of local variable scopes, innermost last.
+type Locals = Vec<HashMap<String, ast::NodeId>>;
+
+impl Resolver<'_> {
+    pub(super) fn resolve_bodies(&mut self) {
+        let crates = std::mem::take(&mut self.crates);
+        for krate in crates.iter() {
+            self.walk_items(krate.root, &krate.module.items);
+        }
+        self.crates = crates;
+    }
+
+    fn walk_items(&mut self, scope: DefId, items: &[ast::Item]) {
+        for item in items.iter() {
+            match &item.kind {
+                ast::ItemKind::Sprite(sprite) => {
+                    let id = self.item_defs[&item.id];
+                    for field in sprite.fields.iter() {
+                        self.resolve_type(id, &field.ty);
+                    }
+                    self.walk_items(id, &sprite.items);
+                }
+                ast::ItemKind::Mod(module) => {
+                    let id = self.item_defs[&item.id];
+                    if let Some(items) = module.items.as_ref() {
+                        self.walk_items(id, items);
+                    }
+                }
+                ast::ItemKind::Proc(proc) => {
+                    let mut locals = self.walk_params(scope, &proc.params, proc.ret.as_ref());
+                    if let Some(receiver) = proc.receiver.as_ref() {
+                        locals[0].insert("self".to_owned(), receiver.id);
+                    }
+                    match &proc.body {
+                        ast::ProcBody::Block(block) => self.walk_block(scope, &mut locals, block),
+                        ast::ProcBody::Expr(expr) => self.walk_expr(scope, &mut locals, expr),
+                    }
+                }
+                ast::ItemKind::Block(block) => {
+                    let mut locals = self.walk_params(scope, &block.params, block.ret.as_ref());
+                    for (_, value) in block.inputs.iter().chain(block.fields.iter()) {
+                        self.walk_expr(scope, &mut locals, value);
+                    }
+                }
+                ast::ItemKind::Struct(structure) => {
+                    for field in structure.fields.iter() {
+                        self.resolve_type(scope, &field.ty);
+                    }
+                }
+                ast::ItemKind::Trait(trait_decl) => {
+                    for method in trait_decl.methods.iter() {
+                        self.walk_params(scope, &method.params, method.ret.as_ref());
+                    }
+                }
+                ast::ItemKind::Impl(implementation) => {
+                    self.resolve_type(scope, &implementation.ty);
+                    if let Some(trait_ref) = implementation.trait_ref.as_ref() {
+                        self.resolve_item_path(scope, trait_ref);
+                    }
+                    self.walk_items(scope, &implementation.items);
+                }
+                ast::ItemKind::Import(_) => {}
+            }
+        }
+    }
+
+    fn walk_params(
+        &mut self,
+        scope: DefId,
+        params: &[ast::Param],
+        ret: Option<&ast::TypeExpr>,
+    ) -> Locals {
+        let mut frame = HashMap::new();
+        for param in params.iter() {
+            self.resolve_type(scope, &param.ty);
+            frame.insert(param.name.name.clone(), param.id);
+        }
+        if let Some(ret) = ret {
+            self.resolve_type(scope, ret);
+        }
+        vec![frame]
+    }
+
+    fn walk_block(&mut self, scope: DefId, locals: &mut Locals, block: &ast::Block) {
+        locals.push(HashMap::new());
+        for stmt in block.stmts.iter() {
+            self.walk_stmt(scope, locals, stmt);
+        }
+        locals.pop();
+    }
+
+    fn walk_stmt(&mut self, scope: DefId, locals: &mut Locals, stmt: &ast::Stmt) {
+        match &stmt.kind {
+            ast::StmtKind::Let { name, ty, value } => {
+                if let Some(ty) = ty {
+                    self.resolve_type(scope, ty);
+                }
+                // Value is resolved first, so `let x = x + 1` refers to the previous `x`.
+                self.walk_expr(scope, locals, value);
+                locals
+                    .last_mut()
+                    .unwrap()
+                    .insert(name.name.clone(), stmt.id);
+            }
+            ast::StmtKind::Assign { target, value, .. } => {
+                self.walk_expr(scope, locals, target);
+                self.walk_expr(scope, locals, value);
+            }
+            ast::StmtKind::Expr(expr) | ast::StmtKind::Return(Some(expr)) => {
+                self.walk_expr(scope, locals, expr)
+            }
+            ast::StmtKind::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.walk_expr(scope, locals, condition);
+                self.walk_block(scope, locals, then);
+                if let Some(otherwise) = otherwise {
+                    self.walk_block(scope, locals, otherwise);
+                }
+            }
+            ast::StmtKind::While { condition, body }
+            | ast::StmtKind::Repeat {
+                count: condition,
+                body,
+            } => {
+                self.walk_expr(scope, locals, condition);
+                self.walk_block(scope, locals, body);
+            }
+            ast::StmtKind::Forever(body) => self.walk_block(scope, locals, body),
+            ast::StmtKind::Spawn { sprite, fields } => {
+                self.resolve_item_path(scope, sprite);
+                for (_, value) in fields.iter() {
+                    self.walk_expr(scope, locals, value);
+                }
+            }
+            ast::StmtKind::Return(None) | ast::StmtKind::Break | ast::StmtKind::Continue => {}
+        }
+    }
+
+    fn walk_expr(&mut self, scope: DefId, locals: &mut Locals, expr: &ast::Expr) {
+        match &expr.kind {
+            ast::ExprKind::Number(_) | ast::ExprKind::Text(_) | ast::ExprKind::Boolean(_) => {}
+            ast::ExprKind::Path(path) => self.resolve_value_path(scope, locals, path),
+            ast::ExprKind::Binary(_, lhs, rhs) => {
+                self.walk_expr(scope, locals, lhs);
+                self.walk_expr(scope, locals, rhs);
+            }
+            ast::ExprKind::Unary(_, operand) => self.walk_expr(scope, locals, operand),
+            ast::ExprKind::Call(callee, args) => {
+                self.walk_expr(scope, locals, callee);
+                for arg in args.iter() {
+                    self.walk_expr(scope, locals, arg);
+                }
+            }
+            ast::ExprKind::MethodCall(receiver, _, args) => {
+                self.walk_expr(scope, locals, receiver);
+                for arg in args.iter() {
+                    self.walk_expr(scope, locals, arg);
+                }
+            }
+            ast::ExprKind::Field(target, _) => self.walk_expr(scope, locals, target),
+            ast::ExprKind::StructLiteral(path, fields) => {
+                self.resolve_item_path(scope, path);
+                for (_, value) in fields.iter() {
+                    self.walk_expr(scope, locals, value);
+                }
+            }
+        }
+    }
+
+    fn resolve_value_path(&mut self, scope: DefId, locals: &Locals, path: &ast::Path) {
+        if let [name] = path.segments.as_slice() {
+            let local = locals
+                .iter()
+                .rev()
+                .find_map(|frame| frame.get(&name.name).copied());
+            if let Some(local) = local {
+                self.resolutions.insert(path.id, Res::Local(local));
+                return;
+            }
+            if name.name == "self" && self.defs[scope].kind == DefKind::Sprite {
+                self.resolutions.insert(path.id, Res::SpriteSelf(scope));
+                return;
+            }
+        }
+        self.resolve_item_path(scope, path);
+    }
+
+    pub(super) fn resolve_type(&mut self, scope: DefId, ty: &ast::TypeExpr) {
+        match &ty.kind {
+            ast::TypeExprKind::Path(path) => {
+                if let [name] = path.segments.as_slice() {
+                    if let Some(prim) = PrimTy::from_name(&name.name) {
+                        self.resolutions.insert(path.id, Res::PrimTy(prim));
+                        return;
+                    }
+                }
+                self.resolve_item_path(scope, path);
+            }
+        }
+    }
+
+    fn resolve_item_path(&mut self, scope: DefId, path: &ast::Path) {
+        let (last, namespace) = path.segments.split_last().unwrap();
+        let target = if namespace.is_empty() {
+            self.lookup_lexical(scope, last)
+        } else {
+            let Some(namespace_id) = self.resolve_namespace(scope, namespace) else {
+                return;
+            };
+            self.lookup_member(namespace_id, last)
+        };
+
+        match target {
+            Some(target) => {
+                self.resolutions.insert(path.id, Res::Def(target));
+            }
+            None => self.error(ResolveError::Unresolved {
+                name: last.name.clone(),
+                loc: last.loc.clone(),
+                parent: namespace.last().map(|segment| segment.name.clone()),
+            }),
+        }
+    }
+}
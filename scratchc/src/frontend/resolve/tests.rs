@@ -0,0 +1,270 @@
+use crate::{
+    common::{error::ResolveError, source_map::SourceMap},
+    frontend::ast,
+};
+
+use super::{DefKind, Program, Res, Resolver};
+
+fn resolve_with_library(library: &str, text: &str) -> Result<Program, Vec<ResolveError>> {
+    let mut source_map = SourceMap::new();
+    let library = source_map.add("lib.sl", library);
+    let root = source_map.add("main.sl", text);
+    let mut resolver = Resolver::new(&mut source_map);
+    resolver.add_library("lib", library);
+    resolver.resolve(root)
+}
+
+fn resolve(text: &str) -> Program {
+    resolve_with_library("", text).unwrap_or_else(|errors| panic!("{errors:?}"))
+}
+
+fn resolve_err(text: &str) -> Vec<ResolveError> {
+    resolve_with_library("", text).expect_err("Resolution should fail")
+}
+
+/// Collects resolutions of all the call targets in the root crate, in source order.
+fn callees(program: &Program) -> Vec<String> {
+    fn walk_items(program: &Program, items: &[ast::Item], out: &mut Vec<String>) {
+        for item in items.iter() {
+            match &item.kind {
+                ast::ItemKind::Sprite(sprite) => walk_items(program, &sprite.items, out),
+                ast::ItemKind::Mod(module) => {
+                    walk_items(program, module.items.as_ref().unwrap(), out)
+                }
+                ast::ItemKind::Proc(proc) => match &proc.body {
+                    ast::ProcBody::Expr(expr) => walk_expr(program, expr, out),
+                    ast::ProcBody::Block(block) => {
+                        for stmt in block.stmts.iter() {
+                            match &stmt.kind {
+                                ast::StmtKind::Expr(expr)
+                                | ast::StmtKind::Let { value: expr, .. } => {
+                                    walk_expr(program, expr, out)
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+    fn walk_expr(program: &Program, expr: &ast::Expr, out: &mut Vec<String>) {
+        if let ast::ExprKind::Call(callee, _) = &expr.kind {
+            let ast::ExprKind::Path(path) = &callee.kind else {
+                unreachable!()
+            };
+            out.push(match program.res(path.id) {
+                Some(Res::Def(id)) => program.path_of(id),
+                other => format!("{other:?}"),
+            });
+        }
+    }
+
+    let mut out = Vec::new();
+    walk_items(program, &program.root_crate().module.items, &mut out);
+    out
+}
+
+#[test]
+fn nested_import_tree_with_aliases() {
+    let program = resolve_with_library(
+        "mod math { proc sqrt(x: number) = x }\nmod looks { proc say(x: text) = x\nproc say_for(x: text) = x }",
+        "import lib::{\n    math::sqrt,\n    looks::{say, say_for as sayf},\n}\nproc main() {\n    sqrt(1)\n    say(\"a\")\n    sayf(\"b\")\n}",
+    )
+    .unwrap();
+    assert_eq!(
+        callees(&program),
+        ["lib::math::sqrt", "lib::looks::say", "lib::looks::say_for"]
+    );
+}
+
+#[test]
+fn imports_can_use_other_imports() {
+    let program =
+        resolve("import b::c\nimport a::b\nmod a { mod b { proc c() = 1 } }\nproc main() = c()");
+    assert_eq!(callees(&program), ["main::a::b::c"]);
+}
+
+#[test]
+fn self_and_super_paths() {
+    let program = resolve(
+        "proc top() = 1\nmod a {\n    import super::top\n    proc one() = self::two()\n    proc two() = top()\n    mod b {\n        import super::{self, one}\n        proc three() = a::one()\n        proc four() = super::super::top()\n    }\n}",
+    );
+    assert_eq!(
+        callees(&program),
+        ["main::a::two", "main::top", "main::a::one", "main::top"]
+    );
+}
+
+#[test]
+fn glob_imports_and_ambiguity() {
+    let program = resolve(
+        "mod a { proc x() = 1\nproc y() = 1 }\nmod b { proc y() = 2 }\nimport a::*\nproc main() = x()",
+    );
+    assert_eq!(callees(&program), ["main::a::x"]);
+
+    let errors = resolve_err(
+        "mod a { proc y() = 1 }\nmod b { proc y() = 2 }\nimport a::*\nimport b::*\nproc main() = y()",
+    );
+    assert!(matches!(
+        &errors[..],
+        [ResolveError::Ambiguous { name, candidates, .. }] if name == "y" && candidates.len() == 2
+    ));
+}
+
+#[test]
+fn explicit_names_shadow_glob_imports() {
+    let program = resolve(
+        "mod a { proc y() = 1 }\nmod b { proc y() = 2 }\nimport a::*\nimport b::*\nimport b::y\nproc main() = y()",
+    );
+    assert_eq!(callees(&program), ["main::b::y"]);
+}
+
+#[test]
+fn sprites_see_names_of_their_module() {
+    let program = resolve(
+        "proc helper() = 1\nsprite Cat {\n    proc go() = helper()\n}\nproc outside() = Cat::go()",
+    );
+    assert_eq!(callees(&program), ["main::helper", "main::Cat::go"]);
+}
+
+#[test]
+fn modules_dont_see_names_of_their_parent() {
+    let errors = resolve_err("proc helper() = 1\nmod a {\n    proc go() = helper()\n}");
+    assert!(matches!(
+        &errors[..],
+        [ResolveError::Unresolved { name, parent: None, .. }] if name == "helper"
+    ));
+}
+
+#[test]
+fn locals_shadow_items() {
+    let program = resolve("proc x() = 1\nproc main(x: number) {\n    let x = x\n    x\n}");
+    let ast::ItemKind::Proc(proc) = &program.root_crate().module.items[1].kind else {
+        unreachable!()
+    };
+    let ast::ProcBody::Block(block) = &proc.body else {
+        unreachable!()
+    };
+    let ast::StmtKind::Let {
+        value: ast::Expr {
+            kind: ast::ExprKind::Path(shadowed),
+            ..
+        },
+        ..
+    } = &block.stmts[0].kind
+    else {
+        unreachable!()
+    };
+    assert_eq!(
+        program.res(shadowed.id),
+        Some(Res::Local(proc.params[0].id))
+    );
+
+    let ast::StmtKind::Expr(ast::Expr {
+        kind: ast::ExprKind::Path(local),
+        ..
+    }) = &block.stmts[1].kind
+    else {
+        unreachable!()
+    };
+    assert_eq!(program.res(local.id), Some(Res::Local(block.stmts[0].id)));
+}
+
+#[test]
+fn types_resolve_to_primitives_and_structs() {
+    let program = resolve("struct Vec2 { x: number, y: number }\nproc f(v: Vec2) -> text = \"\"");
+    let ast::ItemKind::Proc(proc) = &program.root_crate().module.items[1].kind else {
+        unreachable!()
+    };
+    let ast::TypeExprKind::Path(param) = &proc.params[0].ty.kind;
+    let Some(Res::Def(id)) = program.res(param.id) else {
+        panic!("Parameter type should resolve to struct")
+    };
+    assert_eq!(program.def(id).kind, DefKind::Struct);
+    let ast::TypeExprKind::Path(ret) = &proc.ret.as_ref().unwrap().kind;
+    assert!(matches!(program.res(ret.id), Some(Res::PrimTy(_))));
+}
+
+#[test]
+fn reports_unresolved_and_invalid_paths() {
+    let errors = resolve_err(
+        "proc f() = 1\nimport missing::x\nimport f::y\nimport super::z\nmod a { }\nproc main() = a::nothing()",
+    );
+    assert!(matches!(&errors[0], ResolveError::Unresolved { name, .. } if name == "missing"));
+    assert!(matches!(&errors[1], ResolveError::NotANamespace { name, .. } if name == "f"));
+    assert!(matches!(&errors[2], ResolveError::SuperOfRoot(_)));
+    assert!(matches!(
+        &errors[3],
+        ResolveError::Unresolved { name, parent: Some(parent), .. } if name == "nothing" && parent == "a"
+    ));
+}
+
+#[test]
+fn duplicate_definitions() {
+    let errors =
+        resolve_err("proc a() = 1\nstruct a { }\nmod b { proc c() = 1 }\nimport b::c as a");
+    assert_eq!(errors.len(), 2);
+    assert!(errors
+        .iter()
+        .all(|error| matches!(error, ResolveError::Duplicate { name, .. } if name == "a")));
+}
+
+#[test]
+fn methods_are_only_visible_through_receivers() {
+    let program = resolve(
+        "struct Circle { r: number }\nimpl Circle {\n    proc area(self) -> number = self.r\n}",
+    );
+    let ast::ItemKind::Impl(implementation) = &program.root_crate().module.items[1].kind else {
+        unreachable!()
+    };
+    let item = &implementation.items[0];
+    let ast::ItemKind::Proc(proc) = &item.kind else {
+        unreachable!()
+    };
+    let id = program.def_of_item(item.id).unwrap();
+    assert_eq!(program.def(id).kind, DefKind::Method);
+    let ast::ProcBody::Expr(expr) = &proc.body else {
+        unreachable!()
+    };
+    let ast::ExprKind::Field(target, _) = &expr.kind else {
+        unreachable!()
+    };
+    let ast::ExprKind::Path(path) = &target.kind else {
+        unreachable!()
+    };
+    let receiver = proc.receiver.as_ref().unwrap();
+    assert_eq!(program.res(path.id), Some(Res::Local(receiver.id)));
+
+    let errors = resolve_err(
+        "struct Circle { r: number }\nimpl Circle {\n    proc area(self) = 1\n}\nproc main() = area()",
+    );
+    assert!(matches!(&errors[..], [ResolveError::Unresolved { name, .. }] if name == "area"));
+}
+
+#[test]
+fn self_of_sprites_refers_to_the_sprite() {
+    let program =
+        resolve("sprite Ball {\n    fields { speed: number }\n    proc speed() = self.speed\n}");
+    let ast::ItemKind::Sprite(sprite) = &program.root_crate().module.items[0].kind else {
+        unreachable!()
+    };
+    let ast::ItemKind::Proc(proc) = &sprite.items[0].kind else {
+        unreachable!()
+    };
+    let ast::ProcBody::Expr(ast::Expr {
+        kind: ast::ExprKind::Field(target, _),
+        ..
+    }) = &proc.body
+    else {
+        unreachable!()
+    };
+    let ball = program
+        .def_of_item(program.root_crate().module.items[0].id)
+        .unwrap();
+    assert_eq!(program.sprite_self(target), Some(ball));
+
+    let errors = resolve_err("proc speed() = self.speed");
+    assert!(matches!(&errors[..], [ResolveError::Unresolved { name, .. }] if name == "self"));
+}
@@ -0,0 +1,609 @@
+use std::collections::HashSet;
+
+use crate::{
+    common::{error::TypeError, location::Loc},
+    frontend::{
+        ast::{self, NodeId},
+        resolve::{DefKind, Res},
+    },
+};
+
+use super::{DefId, StructDef, Ty, TypeChecker};
+
+/// State of the procedure body being checked.
+struct BodyCx {
+    ret: Ty,
+    params: HashSet<NodeId>,
+    loops: usize,
+}
+
+impl TypeChecker<'_> {
+    pub(super) fn check_procs(&mut self) {
+        for (id, item) in self.items.clone() {
+            if let ast::ItemKind::Proc(_) = &item.kind {
+                self.check_proc(id);
+            }
+        }
+    }
+
+    pub(super) fn check_proc(&mut self, id: DefId) {
+        if !self.checked.insert(id) {
+            return;
+        }
+        let item = self.item_of[&id];
+        let ast::ItemKind::Proc(proc) = &item.kind else {
+            unreachable!()
+        };
+
+        let mut cx = BodyCx {
+            ret: self.results.signatures[&id].ret,
+            params: proc
+                .params
+                .iter()
+                .map(|param| param.id)
+                .chain(proc.receiver.as_ref().map(|receiver| receiver.id))
+                .collect(),
+            loops: 0,
+        };
+        match &proc.body {
+            ast::ProcBody::Expr(expr) if self.to_infer.contains(&id) => {
+                self.inferring.insert(id);
+                let ty = self.check_expr(expr, &mut cx);
+                self.results.signatures.get_mut(&id).unwrap().ret = ty;
+                self.inferring.remove(&id);
+                self.to_infer.remove(&id);
+            }
+            ast::ProcBody::Expr(expr) => {
+                let ret = cx.ret;
+                self.check_expr_expecting(expr, ret, &mut cx);
+            }
+            ast::ProcBody::Block(block) => {
+                self.check_block(block, &mut cx);
+                if !matches!(cx.ret, Ty::Unit | Ty::Error) && !block_returns(block) {
+                    self.error(TypeError::MissingReturn {
+                        name: proc.name.name.clone(),
+                        expected: self.ty_name(cx.ret),
+                        loc: proc.name.loc.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn check_block(&mut self, block: &ast::Block, cx: &mut BodyCx) {
+        for stmt in block.stmts.iter() {
+            self.check_stmt(stmt, cx);
+        }
+    }
+
+    fn check_loop_body(&mut self, body: &ast::Block, cx: &mut BodyCx) {
+        cx.loops += 1;
+        self.check_block(body, cx);
+        cx.loops -= 1;
+    }
+
+    fn check_stmt(&mut self, stmt: &ast::Stmt, cx: &mut BodyCx) {
+        match &stmt.kind {
+            ast::StmtKind::Let { ty, value, .. } => {
+                let ty = match ty {
+                    Some(ty) => {
+                        let ty = self.resolve_ty(ty);
+                        self.check_expr_expecting(value, ty, cx);
+                        ty
+                    }
+                    None => self.check_value(value, cx),
+                };
+                self.results.local_types.insert(stmt.id, ty);
+            }
+            ast::StmtKind::Assign { target, op, value } => {
+                let place = self.check_place(target, cx);
+                match op {
+                    None => self.check_expr_expecting(value, place, cx),
+                    Some(op) => {
+                        let rhs = self.check_value(value, cx);
+                        let result = self.binary_result(*op, place, rhs, &stmt.loc);
+                        self.expect_ty(place, result, &stmt.loc);
+                    }
+                }
+            }
+            ast::StmtKind::Expr(expr) => {
+                self.check_expr(expr, cx);
+            }
+            ast::StmtKind::Return(value) => match (value, cx.ret) {
+                (Some(value), Ty::Unit) => {
+                    self.check_expr(value, cx);
+                    self.error(TypeError::UnexpectedReturnValue(value.loc.clone()));
+                }
+                (Some(value), ret) => self.check_expr_expecting(value, ret, cx),
+                (None, Ty::Unit | Ty::Error) => {}
+                (None, ret) => self.error(TypeError::MissingReturnValue {
+                    expected: self.ty_name(ret),
+                    loc: stmt.loc.clone(),
+                }),
+            },
+            ast::StmtKind::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.check_expr_expecting(condition, Ty::Boolean, cx);
+                self.check_block(then, cx);
+                if let Some(otherwise) = otherwise {
+                    self.check_block(otherwise, cx);
+                }
+            }
+            ast::StmtKind::While { condition, body } => {
+                self.check_expr_expecting(condition, Ty::Boolean, cx);
+                self.check_loop_body(body, cx);
+            }
+            ast::StmtKind::Repeat { count, body } => {
+                self.check_expr_expecting(count, Ty::Number, cx);
+                self.check_loop_body(body, cx);
+            }
+            ast::StmtKind::Forever(body) => self.check_loop_body(body, cx),
+            ast::StmtKind::Spawn { sprite, fields } => {
+                let id = match self.program.res(sprite.id) {
+                    Some(Res::Def(id)) if self.program.def(id).kind == DefKind::Sprite => Some(id),
+                    Some(Res::Def(id)) => {
+                        let def = self.program.def(id);
+                        self.error(TypeError::NotASprite {
+                            name: def.name.clone(),
+                            kind: def.kind.describe(),
+                            loc: sprite.loc.clone(),
+                        });
+                        None
+                    }
+                    _ => None,
+                };
+                match id {
+                    Some(id) => {
+                        let name = self.program.def(id).name.clone();
+                        let sprite = self.results.sprites[&id].clone();
+                        self.check_field_values(name, &sprite, fields, &stmt.loc, cx);
+                    }
+                    None => {
+                        for (_, value) in fields.iter() {
+                            self.check_value(value, cx);
+                        }
+                    }
+                }
+            }
+            ast::StmtKind::Break | ast::StmtKind::Continue => {
+                if cx.loops == 0 {
+                    self.error(TypeError::OutsideLoop {
+                        keyword: if let ast::StmtKind::Break = stmt.kind {
+                            "break"
+                        } else {
+                            "continue"
+                        },
+                        loc: stmt.loc.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Checks target of the assignment, which has to be a local variable, field of
+    /// the sprite or field of either of them.
+    fn check_place(&mut self, expr: &ast::Expr, cx: &mut BodyCx) -> Ty {
+        let ty = match &expr.kind {
+            ast::ExprKind::Path(path) => match self.program.res(path.id) {
+                Some(Res::Local(local)) if cx.params.contains(&local) => {
+                    self.error(TypeError::AssignToParameter {
+                        name: path.segments[0].name.clone(),
+                        loc: expr.loc.clone(),
+                    });
+                    Ty::Error
+                }
+                Some(Res::Local(local)) => self.results.local_types[&local],
+                _ => {
+                    self.error(TypeError::InvalidAssignTarget(expr.loc.clone()));
+                    Ty::Error
+                }
+            },
+            ast::ExprKind::Field(target, name) => match self.program.sprite_self(target) {
+                Some(sprite) => self.sprite_field(sprite, name, expr.id),
+                None => {
+                    let ty = self.check_place(target, cx);
+                    self.field_of(ty, name, expr.id)
+                }
+            },
+            _ => {
+                self.error(TypeError::InvalidAssignTarget(expr.loc.clone()));
+                Ty::Error
+            }
+        };
+        self.results.expr_types.insert(expr.id, ty);
+        ty
+    }
+
+    fn check_expr_expecting(&mut self, expr: &ast::Expr, expected: Ty, cx: &mut BodyCx) {
+        let found = self.check_expr(expr, cx);
+        self.expect_ty(expected, found, &expr.loc);
+    }
+
+    /// Checks expression which has to have a value.
+    fn check_value(&mut self, expr: &ast::Expr, cx: &mut BodyCx) -> Ty {
+        match self.check_expr(expr, cx) {
+            Ty::Unit => {
+                self.error(TypeError::NoValue(expr.loc.clone()));
+                Ty::Error
+            }
+            ty => ty,
+        }
+    }
+
+    fn check_expr(&mut self, expr: &ast::Expr, cx: &mut BodyCx) -> Ty {
+        let ty = match &expr.kind {
+            ast::ExprKind::Number(_) => Ty::Number,
+            ast::ExprKind::Text(_) => Ty::Text,
+            ast::ExprKind::Boolean(_) => Ty::Boolean,
+            ast::ExprKind::Path(path) => self.check_path(path),
+            ast::ExprKind::Binary(op, lhs, rhs) => {
+                let lhs = self.check_value(lhs, cx);
+                let rhs = self.check_value(rhs, cx);
+                self.binary_result(*op, lhs, rhs, &expr.loc)
+            }
+            ast::ExprKind::Unary(op, operand) => {
+                let ty = self.check_value(operand, cx);
+                let (expected, op) = match op {
+                    ast::UnaryOp::Neg => (Ty::Number, "-"),
+                    ast::UnaryOp::Not => (Ty::Boolean, "!"),
+                };
+                if ty == expected || ty == Ty::Error {
+                    ty
+                } else {
+                    self.error(TypeError::InvalidOperand {
+                        op,
+                        ty: self.ty_name(ty),
+                        loc: expr.loc.clone(),
+                    });
+                    Ty::Error
+                }
+            }
+            ast::ExprKind::Call(callee, args) => self.check_call(callee, args, cx),
+            ast::ExprKind::MethodCall(receiver, method, args) => {
+                self.check_method_call(expr.id, receiver, method, args, cx)
+            }
+            ast::ExprKind::Field(target, name) => match self.program.sprite_self(target) {
+                Some(sprite) => self.sprite_field(sprite, name, expr.id),
+                None => {
+                    let ty = self.check_value(target, cx);
+                    self.field_of(ty, name, expr.id)
+                }
+            },
+            ast::ExprKind::StructLiteral(path, fields) => {
+                self.check_struct_literal(path, fields, &expr.loc, cx)
+            }
+        };
+        self.results.expr_types.insert(expr.id, ty);
+        ty
+    }
+
+    fn check_path(&mut self, path: &ast::Path) -> Ty {
+        match self.program.res(path.id) {
+            Some(Res::Local(local)) => self.results.local_types[&local],
+            Some(Res::Def(id)) => {
+                let def = self.program.def(id);
+                self.error(TypeError::NotAValue {
+                    name: def.name.clone(),
+                    kind: def.kind.describe(),
+                    loc: path.loc.clone(),
+                });
+                Ty::Error
+            }
+            Some(Res::PrimTy(_)) => {
+                self.error(TypeError::NotAValue {
+                    name: path.segments.last().unwrap().name.clone(),
+                    kind: "type",
+                    loc: path.loc.clone(),
+                });
+                Ty::Error
+            }
+            Some(Res::SpriteSelf(_)) => {
+                self.error(TypeError::SpriteSelfValue(path.loc.clone()));
+                Ty::Error
+            }
+            None => Ty::Error,
+        }
+    }
+
+    fn binary_result(&mut self, op: ast::BinaryOp, lhs: Ty, rhs: Ty, loc: &Loc) -> Ty {
+        use ast::BinaryOp::*;
+
+        if lhs == Ty::Error || rhs == Ty::Error {
+            return Ty::Error;
+        }
+        let result = match (op, lhs, rhs) {
+            (Add, Ty::Number, Ty::Number) => Some(Ty::Number),
+            // Adding text joins It with the other operand.
+            (Add, Ty::Text, Ty::Text | Ty::Number) | (Add, Ty::Number, Ty::Text) => Some(Ty::Text),
+            (Sub | Mul | Div | Mod, Ty::Number, Ty::Number) => Some(Ty::Number),
+            // Structs are compared field by field.
+            (Eq | Ne, lhs, rhs)
+                if lhs == rhs && (lhs.is_primitive() || matches!(lhs, Ty::Struct(_))) =>
+            {
+                Some(Ty::Boolean)
+            }
+            (Lt | Gt | Le | Ge, Ty::Number, Ty::Number)
+            | (Lt | Gt | Le | Ge, Ty::Text, Ty::Text) => Some(Ty::Boolean),
+            (And | Or, Ty::Boolean, Ty::Boolean) => Some(Ty::Boolean),
+            _ => None,
+        };
+        result.unwrap_or_else(|| {
+            self.error(TypeError::InvalidOperands {
+                op: op.as_str(),
+                lhs: self.ty_name(lhs),
+                rhs: self.ty_name(rhs),
+                loc: loc.clone(),
+            });
+            Ty::Error
+        })
+    }
+
+    fn check_call(&mut self, callee: &ast::Expr, args: &[ast::Expr], cx: &mut BodyCx) -> Ty {
+        let target = match &callee.kind {
+            ast::ExprKind::Path(path) => match self.program.res(path.id) {
+                Some(Res::Def(id))
+                    if matches!(self.program.def(id).kind, DefKind::Proc | DefKind::Block) =>
+                {
+                    Ok(id)
+                }
+                Some(Res::Def(id)) => {
+                    let def = self.program.def(id);
+                    Err(Some(format!("{} `{}`", def.kind.describe(), def.name)))
+                }
+                Some(Res::Local(_)) => Err(Some("local variable".to_owned())),
+                Some(Res::PrimTy(_)) => Err(Some("type".to_owned())),
+                Some(Res::SpriteSelf(_)) => Err(Some("`self`".to_owned())),
+                None => Err(None),
+            },
+            _ => {
+                let ty = self.check_value(callee, cx);
+                Err((ty != Ty::Error).then(|| format!("value of type `{}`", self.ty_name(ty))))
+            }
+        };
+
+        let id = match target {
+            Ok(id) => id,
+            Err(what) => {
+                if let Some(what) = what {
+                    self.error(TypeError::NotCallable {
+                        what,
+                        loc: callee.loc.clone(),
+                    });
+                }
+                for arg in args.iter() {
+                    self.check_value(arg, cx);
+                }
+                return Ty::Error;
+            }
+        };
+
+        let ret = self.call_return_ty(id, &callee.loc);
+        let params = self.results.signatures[&id].params.clone();
+        self.check_args(id, &params, args, &callee.loc, cx);
+        ret
+    }
+
+    /// Resolves the method from the type of the receiver, so the call is static.
+    fn check_method_call(
+        &mut self,
+        expr: NodeId,
+        receiver: &ast::Expr,
+        method: &ast::Ident,
+        args: &[ast::Expr],
+        cx: &mut BodyCx,
+    ) -> Ty {
+        let ty = self.check_value(receiver, cx);
+        let id = match ty {
+            Ty::Struct(structure) => self.lookup_method(structure, method),
+            _ => None,
+        };
+        let Some(id) = id else {
+            if ty != Ty::Error {
+                self.error(TypeError::NoMethod {
+                    ty: self.ty_name(ty),
+                    method: method.name.clone(),
+                    loc: method.loc.clone(),
+                });
+            }
+            for arg in args.iter() {
+                self.check_value(arg, cx);
+            }
+            return Ty::Error;
+        };
+
+        self.results.method_calls.insert(expr, id);
+        let ret = self.call_return_ty(id, &method.loc);
+        // Receiver is the first parameter.
+        let params = self.results.signatures[&id].params[1..].to_vec();
+        self.check_args(id, &params, args, &method.loc, cx);
+        ret
+    }
+
+    fn check_args(
+        &mut self,
+        id: DefId,
+        params: &[Ty],
+        args: &[ast::Expr],
+        loc: &Loc,
+        cx: &mut BodyCx,
+    ) {
+        if params.len() != args.len() {
+            let def = self.program.def(id);
+            self.error(TypeError::ArgumentCount {
+                name: def.name.clone(),
+                expected: params.len(),
+                found: args.len(),
+                loc: loc.clone(),
+                definition: def.loc.clone(),
+            });
+            for arg in args.iter() {
+                self.check_value(arg, cx);
+            }
+            return;
+        }
+        for (arg, param) in args.iter().zip(params) {
+            self.check_expr_expecting(arg, *param, cx);
+        }
+    }
+
+    /// Return type of the called procedure, inferring It if necessary.
+    fn call_return_ty(&mut self, id: DefId, loc: &Loc) -> Ty {
+        if self.to_infer.contains(&id) {
+            if self.inferring.contains(&id) {
+                self.error(TypeError::CannotInfer {
+                    name: self.program.def(id).name.clone(),
+                    loc: loc.clone(),
+                });
+                return Ty::Error;
+            }
+            self.check_proc(id);
+        }
+        self.results.signatures[&id].ret
+    }
+
+    fn field_of(&mut self, ty: Ty, name: &ast::Ident, expr: NodeId) -> Ty {
+        let field = match ty {
+            Ty::Error => return Ty::Error,
+            Ty::Struct(id) => self.results.structs[&id].field(&name.name),
+            _ => None,
+        };
+        let ty = self.ty_name(ty);
+        self.record_field(field, ty, name, expr)
+    }
+
+    fn sprite_field(&mut self, sprite: DefId, name: &ast::Ident, expr: NodeId) -> Ty {
+        let field = self.results.sprites[&sprite].field(&name.name);
+        let ty = self.program.def(sprite).name.clone();
+        self.record_field(field, ty, name, expr)
+    }
+
+    /// Records index of the accessed field, `ty` names the type for the error
+    /// when there is no such field.
+    fn record_field(
+        &mut self,
+        field: Option<(usize, Ty)>,
+        ty: String,
+        name: &ast::Ident,
+        expr: NodeId,
+    ) -> Ty {
+        let Some((index, field_ty)) = field else {
+            self.error(TypeError::UnknownField {
+                ty,
+                field: name.name.clone(),
+                loc: name.loc.clone(),
+            });
+            return Ty::Error;
+        };
+        self.results.field_indices.insert(expr, index);
+        field_ty
+    }
+
+    fn check_struct_literal(
+        &mut self,
+        path: &ast::Path,
+        fields: &[(ast::Ident, ast::Expr)],
+        loc: &Loc,
+        cx: &mut BodyCx,
+    ) -> Ty {
+        let id = match self.program.res(path.id) {
+            Some(Res::Def(id)) if self.program.def(id).kind == DefKind::Struct => Some(id),
+            Some(Res::Def(id)) => {
+                let def = self.program.def(id);
+                self.error(TypeError::NotAStruct {
+                    name: def.name.clone(),
+                    kind: def.kind.describe(),
+                    loc: path.loc.clone(),
+                });
+                None
+            }
+            _ => None,
+        };
+        let Some(id) = id else {
+            for (_, value) in fields.iter() {
+                self.check_value(value, cx);
+            }
+            return Ty::Error;
+        };
+
+        let structure = self.results.structs[&id].clone();
+        let ty = self.ty_name(Ty::Struct(id));
+        self.check_field_values(ty, &structure, fields, loc, cx);
+        Ty::Struct(id)
+    }
+
+    /// Checks values of struct literal or `spawn`, every field has to get exactly one.
+    fn check_field_values(
+        &mut self,
+        ty: String,
+        structure: &StructDef,
+        fields: &[(ast::Ident, ast::Expr)],
+        loc: &Loc,
+        cx: &mut BodyCx,
+    ) {
+        let mut initialized: Vec<Option<&ast::Ident>> = vec![None; structure.fields.len()];
+        for (name, value) in fields.iter() {
+            let Some(index) = structure.field_index(&name.name) else {
+                self.error(TypeError::UnknownField {
+                    ty: ty.clone(),
+                    field: name.name.clone(),
+                    loc: name.loc.clone(),
+                });
+                self.check_value(value, cx);
+                continue;
+            };
+            if let Some(previous) = initialized[index] {
+                self.error(TypeError::DuplicateField {
+                    field: name.name.clone(),
+                    loc: name.loc.clone(),
+                    previous: previous.loc.clone(),
+                });
+            }
+            initialized[index] = Some(name);
+            self.check_expr_expecting(value, structure.fields[index].ty, cx);
+        }
+
+        let missing: Vec<String> = structure
+            .fields
+            .iter()
+            .zip(initialized.iter())
+            .filter(|(_, initialized)| initialized.is_none())
+            .map(|(field, _)| field.name.clone())
+            .collect();
+        if !missing.is_empty() {
+            self.error(TypeError::MissingFields {
+                ty,
+                fields: missing,
+                loc: loc.clone(),
+            });
+        }
+    }
+}
+
+/// Whether block returns on every path.
+fn block_returns(block: &ast::Block) -> bool {
+    block.stmts.iter().any(|stmt| match &stmt.kind {
+        ast::StmtKind::Return(_) => true,
+        ast::StmtKind::If {
+            then,
+            otherwise: Some(otherwise),
+            ..
+        } => block_returns(then) && block_returns(otherwise),
+        // Loop that is never exited never reaches end of the procedure.
+        ast::StmtKind::Forever(body) => !block_breaks(body),
+        _ => false,
+    })
+}
+
+/// Whether block contains `break` exiting the loop It is body of.
+fn block_breaks(block: &ast::Block) -> bool {
+    block.stmts.iter().any(|stmt| match &stmt.kind {
+        ast::StmtKind::Break => true,
+        ast::StmtKind::If {
+            then, otherwise, ..
+        } => block_breaks(then) || otherwise.as_ref().is_some_and(block_breaks),
+        _ => false,
+    })
+}
@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use crate::{
+    common::error::TypeError,
+    frontend::{ast, resolve::Res},
+    mir::{BlockDefinition, BlockField, BlockInput},
+};
+
+use super::{DefId, FieldDef, Signature, StructDef, Ty, TypeChecker};
+
+impl<'p> TypeChecker<'p> {
+    pub(super) fn collect_items(&mut self) {
+        for krate in self.program.crates.iter() {
+            self.collect_from(&krate.module.items);
+        }
+    }
+
+    fn collect_from(&mut self, items: &'p [ast::Item]) {
+        for item in items.iter() {
+            match &item.kind {
+                ast::ItemKind::Sprite(sprite) => {
+                    let id = self.program.def_of_item(item.id).unwrap();
+                    self.items.push((id, item));
+                    self.collect_from(&sprite.items);
+                }
+                ast::ItemKind::Mod(module) => {
+                    if let Some(items) = module.items.as_ref() {
+                        self.collect_from(items);
+                    }
+                }
+                ast::ItemKind::Proc(_)
+                | ast::ItemKind::Block(_)
+                | ast::ItemKind::Struct(_)
+                | ast::ItemKind::Trait(_) => {
+                    let id = self.program.def_of_item(item.id).unwrap();
+                    self.items.push((id, item));
+                    self.item_of.insert(id, item);
+                }
+                ast::ItemKind::Impl(implementation) => {
+                    self.impls.push(implementation);
+                    self.collect_from(&implementation.items);
+                }
+                ast::ItemKind::Import(_) => {}
+            }
+        }
+    }
+
+    pub(super) fn check_structs(&mut self) {
+        for (id, item) in self.items.clone() {
+            let ast::ItemKind::Struct(structure) = &item.kind else {
+                continue;
+            };
+            let structure = self.check_fields(&structure.fields);
+            self.results.structs.insert(id, structure);
+        }
+
+        // Cycles are broken by replacing fields leading back to the struct with errors,
+        // so every cycle is reported only once.
+        for (id, item) in self.items.clone() {
+            let ast::ItemKind::Struct(structure) = &item.kind else {
+                continue;
+            };
+            let mut is_recursive = false;
+            for index in 0..self.results.structs[&id].fields.len() {
+                let ty = self.results.structs[&id].fields[index].ty;
+                if self.contains_struct(ty, id, &mut Vec::new()) {
+                    self.results.structs.get_mut(&id).unwrap().fields[index].ty = Ty::Error;
+                    is_recursive = true;
+                }
+            }
+            if is_recursive {
+                self.error(TypeError::RecursiveStruct {
+                    name: structure.name.name.clone(),
+                    loc: structure.name.loc.clone(),
+                });
+            }
+        }
+    }
+
+    /// Collects fields of sprites, which can't contain the sprite so they are never recursive.
+    pub(super) fn check_sprites(&mut self) {
+        for (id, item) in self.items.clone() {
+            let ast::ItemKind::Sprite(sprite) = &item.kind else {
+                continue;
+            };
+            let fields = self.check_fields(&sprite.fields);
+            self.results.sprites.insert(id, fields);
+        }
+    }
+
+    fn check_fields(&mut self, declared: &[ast::StructField]) -> StructDef {
+        let mut fields: Vec<FieldDef> = Vec::new();
+        let mut seen: HashMap<&str, &ast::Ident> = HashMap::new();
+        for field in declared.iter() {
+            if let Some(previous) = seen.insert(&field.name.name, &field.name) {
+                self.error(TypeError::DuplicateField {
+                    field: field.name.name.clone(),
+                    loc: field.name.loc.clone(),
+                    previous: previous.loc.clone(),
+                });
+                continue;
+            }
+            let ty = self.resolve_ty(&field.ty);
+            fields.push(FieldDef {
+                name: field.name.name.clone(),
+                ty,
+            });
+        }
+        StructDef { fields }
+    }
+
+    /// Whether value of type `ty` contains value of struct `target`.
+    fn contains_struct(&self, ty: Ty, target: DefId, visited: &mut Vec<DefId>) -> bool {
+        let Ty::Struct(id) = ty else {
+            return false;
+        };
+        if id == target {
+            return true;
+        }
+        if visited.contains(&id) {
+            return false;
+        }
+        visited.push(id);
+        self.results.structs[&id]
+            .fields
+            .iter()
+            .any(|field| self.contains_struct(field.ty, target, visited))
+    }
+
+    pub(super) fn check_blocks(&mut self) {
+        for (id, item) in self.items.clone() {
+            let ast::ItemKind::Block(block) = &item.kind else {
+                continue;
+            };
+            let errors = self.errors.len();
+
+            let mut params = Vec::new();
+            for param in block.params.iter() {
+                let ty = self.resolve_primitive_block_ty(&param.ty);
+                self.results.local_types.insert(param.id, ty);
+                params.push(ty);
+            }
+            let ret = match block.ret.as_ref() {
+                Some(ret) => self.resolve_primitive_block_ty(ret),
+                None => Ty::Unit,
+            };
+
+            // Block arguments are passed to inputs, so every parameter has to be used by
+            // exactly one of them.
+            let mut inputs: Vec<Option<(&ast::Ident, &ast::Expr)>> = vec![None; params.len()];
+            for (name, value) in block.inputs.iter() {
+                let param = match &value.kind {
+                    ast::ExprKind::Path(path) => match self.program.res(path.id) {
+                        Some(Res::Local(local)) => {
+                            block.params.iter().position(|param| param.id == local)
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                let Some(param) = param else {
+                    self.error(TypeError::InvalidBlockInput(value.loc.clone()));
+                    continue;
+                };
+                if let Some((_, previous)) = inputs[param] {
+                    self.error(TypeError::BlockParameterReused {
+                        name: block.params[param].name.name.clone(),
+                        loc: value.loc.clone(),
+                        previous: previous.loc.clone(),
+                    });
+                    continue;
+                }
+                inputs[param] = Some((name, value));
+            }
+            for (param, input) in block.params.iter().zip(inputs.iter()) {
+                if input.is_none() {
+                    self.error(TypeError::UnusedBlockParameter {
+                        name: param.name.name.clone(),
+                        loc: param.name.loc.clone(),
+                    });
+                }
+            }
+
+            let mut fields = Vec::new();
+            for (name, value) in block.fields.iter() {
+                let value = match &value.kind {
+                    ast::ExprKind::Text(text) => text.clone(),
+                    ast::ExprKind::Number(number) => number.to_string(),
+                    _ => {
+                        self.error(TypeError::InvalidBlockField(value.loc.clone()));
+                        continue;
+                    }
+                };
+                fields.push(BlockField::new(name.name.clone(), value));
+            }
+
+            if self.errors.len() != errors {
+                self.results
+                    .signatures
+                    .insert(id, Signature { params, ret });
+                continue;
+            }
+
+            let mut definition = BlockDefinition::new(
+                &block.opcode.name,
+                ret != Ty::Unit,
+                inputs.iter().zip(params.iter()).map(|(input, ty)| {
+                    BlockInput::new(input.unwrap().0.name.clone(), self.results.data_type(*ty))
+                }),
+                fields,
+            );
+            // Pure blocks can be evaluated during compilation.
+            if item
+                .attributes
+                .iter()
+                .any(|attribute| attribute.name.name == "pure")
+            {
+                definition = definition.mark_pure();
+            }
+            let params = definition
+                .inputs()
+                .iter()
+                .map(|input| Ty::from_data_type(input.ty()))
+                .collect();
+            self.results
+                .signatures
+                .insert(id, Signature { params, ret });
+            self.results.blocks.insert(id, definition);
+        }
+    }
+
+    fn resolve_primitive_block_ty(&mut self, ty: &ast::TypeExpr) -> Ty {
+        let resolved = self.resolve_ty(ty);
+        if let Ty::Struct(_) = resolved {
+            self.error(TypeError::NonPrimitiveBlockType {
+                ty: self.ty_name(resolved),
+                loc: ty.loc.clone(),
+            });
+            return Ty::Error;
+        }
+        resolved
+    }
+
+    pub(super) fn collect_proc_signatures(&mut self) {
+        for (id, item) in self.items.clone() {
+            let ast::ItemKind::Proc(proc) = &item.kind else {
+                continue;
+            };
+
+            let mut params = Vec::new();
+            if let Some(receiver) = proc.receiver.as_ref() {
+                let ty = self.results.methods[&id].self_ty;
+                self.results.local_types.insert(receiver.id, ty);
+                params.push(ty);
+            }
+            for param in proc.params.iter() {
+                let ty = self.resolve_ty(&param.ty);
+                self.results.local_types.insert(param.id, ty);
+                params.push(ty);
+            }
+            let ret = match (&proc.ret, &proc.body) {
+                (Some(ret), _) => self.resolve_ty(ret),
+                (None, ast::ProcBody::Block(_)) => Ty::Unit,
+                (None, ast::ProcBody::Expr(_)) => {
+                    self.to_infer.insert(id);
+                    Ty::Error
+                }
+            };
+            self.results
+                .signatures
+                .insert(id, Signature { params, ret });
+        }
+    }
+}
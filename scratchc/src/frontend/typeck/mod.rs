@@ -0,0 +1,295 @@
+//! Type checking. Checks that every value is used according to Its type and records
+//! types of expressions and locals, so lowering into MIR doesn't have to infer anything.
+//!
+//! Scratchlet has three primitive types, `number`, `text` and `boolean`, and structures
+//! made of those. Types of locals are inferred from their initializers and
+//! return types of shorthand procedures without explicit return type
+//! (`proc greet(name: text) = ...`) are inferred from their bodies.
+//!
+//! Methods come from `impl` blocks of structs, either inherent or implementing a trait.
+//! Type of the receiver is always known, so every method call is resolved to a single
+//! method here and later passes don't need any dynamic dispatch.
+//!
+//! Fields of sprites are only accessed through `self` of the sprite's procedures,
+//! `self` Itself isn't a value.
+
+mod body;
+mod items;
+#[cfg(test)]
+mod tests;
+mod traits;
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    common::{error::TypeError, location::Loc},
+    mir::{BlockDefinition, DataType},
+};
+
+use super::{
+    ast::{self, NodeId},
+    resolve::{DefId, DefKind, PrimTy, Program, Res},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Ty {
+    Number,
+    Text,
+    Boolean,
+    Struct(DefId),
+    /// Type of procedures and blocks that don't return anything.
+    Unit,
+    /// Type of expressions that already caused an error, compatible with everything
+    /// so one mistake doesn't cause a cascade of errors.
+    Error,
+}
+
+impl Ty {
+    pub fn is_primitive(&self) -> bool {
+        matches!(self, Self::Number | Self::Text | Self::Boolean)
+    }
+
+    fn from_prim(prim: PrimTy) -> Self {
+        match prim {
+            PrimTy::Number => Self::Number,
+            PrimTy::Text => Self::Text,
+            PrimTy::Boolean => Self::Boolean,
+        }
+    }
+
+    fn from_data_type(dt: &DataType) -> Self {
+        match dt {
+            DataType::Number => Self::Number,
+            DataType::Text => Self::Text,
+            DataType::Boolean => Self::Boolean,
+            DataType::Structure(_) | DataType::Enum(_) | DataType::List(_) => {
+                unreachable!("Blocks only accept primitive inputs")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldDef {
+    pub name: String,
+    pub ty: Ty,
+}
+
+#[derive(Debug, Clone)]
+pub struct StructDef {
+    pub fields: Vec<FieldDef>,
+}
+
+impl StructDef {
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|field| field.name == name)
+    }
+
+    /// Index and type of the field.
+    fn field(&self, name: &str) -> Option<(usize, Ty)> {
+        let index = self.field_index(name)?;
+        Some((index, self.fields[index].ty))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub params: Vec<Ty>,
+    pub ret: Ty,
+}
+
+/// Procedure of an `impl` block, Its receiver is the first parameter of Its signature.
+#[derive(Debug, Clone)]
+pub struct MethodDef {
+    /// Type the method is implemented for.
+    pub self_ty: Ty,
+    /// Implemented trait, `None` for methods of inherent impls.
+    pub trait_id: Option<DefId>,
+}
+
+/// Output of the type checker, used together with [`Program`] by the later passes.
+#[derive(Debug, Default)]
+pub struct TypeckResults {
+    structs: HashMap<DefId, StructDef>,
+    /// Fields of every sprite, each clone has Its own copy of them.
+    sprites: HashMap<DefId, StructDef>,
+    signatures: HashMap<DefId, Signature>,
+    blocks: HashMap<DefId, BlockDefinition>,
+    methods: HashMap<DefId, MethodDef>,
+    expr_types: HashMap<NodeId, Ty>,
+    local_types: HashMap<NodeId, Ty>,
+    field_indices: HashMap<NodeId, usize>,
+    method_calls: HashMap<NodeId, DefId>,
+}
+
+impl TypeckResults {
+    pub fn expr_ty(&self, expr: NodeId) -> Ty {
+        self.expr_types[&expr]
+    }
+
+    /// Type of the local, identified by the `let` statement or parameter node.
+    pub fn local_ty(&self, local: NodeId) -> Ty {
+        self.local_types[&local]
+    }
+
+    pub fn structure(&self, id: DefId) -> &StructDef {
+        &self.structs[&id]
+    }
+
+    pub fn sprite_fields(&self, id: DefId) -> &StructDef {
+        &self.sprites[&id]
+    }
+
+    /// Signature of the procedure or block.
+    pub fn signature(&self, id: DefId) -> &Signature {
+        &self.signatures[&id]
+    }
+
+    /// Definition of the declared block, with inputs in order of block's parameters.
+    pub fn block_definition(&self, id: DefId) -> &BlockDefinition {
+        &self.blocks[&id]
+    }
+
+    /// Method definition of the procedure, `None` for procedures that aren't methods.
+    pub fn method(&self, id: DefId) -> Option<&MethodDef> {
+        self.methods.get(&id)
+    }
+
+    /// Method called by the method call expression.
+    pub fn method_call(&self, expr: NodeId) -> DefId {
+        self.method_calls[&expr]
+    }
+
+    /// Index of the field accessed by field expression.
+    pub fn field_index(&self, expr: NodeId) -> usize {
+        self.field_indices[&expr]
+    }
+
+    pub fn data_type(&self, ty: Ty) -> DataType {
+        match ty {
+            Ty::Number => DataType::Number,
+            Ty::Text => DataType::Text,
+            Ty::Boolean => DataType::Boolean,
+            Ty::Struct(id) => DataType::Structure(
+                self.structs[&id]
+                    .fields
+                    .iter()
+                    .map(|field| self.data_type(field.ty))
+                    .collect(),
+            ),
+            Ty::Unit | Ty::Error => unreachable!("{ty:?} has no data type"),
+        }
+    }
+}
+
+/// Type checks whole resolved program.
+pub fn check(program: &Program) -> Result<TypeckResults, Vec<TypeError>> {
+    let mut checker = TypeChecker::new(program);
+    checker.collect_items();
+    checker.check_structs();
+    checker.check_sprites();
+    checker.check_blocks();
+    checker.check_traits();
+    checker.check_impls();
+    checker.collect_proc_signatures();
+    checker.check_impl_signatures();
+    checker.check_procs();
+
+    if !checker.errors.is_empty() {
+        return Err(checker.errors);
+    }
+    Ok(checker.results)
+}
+
+struct TypeChecker<'p> {
+    program: &'p Program,
+    /// Items defining sprites, structs, blocks and procedures, in source order.
+    items: Vec<(DefId, &'p ast::Item)>,
+    item_of: HashMap<DefId, &'p ast::Item>,
+    impls: Vec<&'p ast::Impl>,
+    /// Methods declared by every trait, with signatures not including the receiver.
+    traits: HashMap<DefId, Vec<(&'p ast::TraitMethod, Signature)>>,
+    /// Location of the implementation of the trait for the struct.
+    trait_impls: HashMap<(DefId, DefId), Loc>,
+    /// Methods that can be called on values of the struct.
+    struct_methods: HashMap<DefId, Vec<DefId>>,
+    results: TypeckResults,
+    /// Procedures with already checked bodies.
+    checked: HashSet<DefId>,
+    /// Shorthand procedures without return type, their signatures
+    /// have a placeholder return type until their body is checked.
+    to_infer: HashSet<DefId>,
+    inferring: HashSet<DefId>,
+    errors: Vec<TypeError>,
+}
+
+impl<'p> TypeChecker<'p> {
+    fn new(program: &'p Program) -> Self {
+        Self {
+            program,
+            items: Vec::new(),
+            item_of: HashMap::new(),
+            impls: Vec::new(),
+            traits: HashMap::new(),
+            trait_impls: HashMap::new(),
+            struct_methods: HashMap::new(),
+            results: TypeckResults::default(),
+            checked: HashSet::new(),
+            to_infer: HashSet::new(),
+            inferring: HashSet::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, error: TypeError) {
+        self.errors.push(error);
+    }
+
+    /// Name of the type as written in the source code.
+    fn ty_name(&self, ty: Ty) -> String {
+        match ty {
+            Ty::Number => "number".to_owned(),
+            Ty::Text => "text".to_owned(),
+            Ty::Boolean => "boolean".to_owned(),
+            Ty::Struct(id) => self.program.def(id).name.clone(),
+            Ty::Unit => "()".to_owned(),
+            Ty::Error => "{error}".to_owned(),
+        }
+    }
+
+    fn resolve_ty(&mut self, ty: &ast::TypeExpr) -> Ty {
+        let ast::TypeExprKind::Path(path) = &ty.kind;
+        match self.program.res(path.id) {
+            Some(Res::PrimTy(prim)) => Ty::from_prim(prim),
+            Some(Res::Def(id)) => {
+                let def = self.program.def(id);
+                if def.kind == DefKind::Struct {
+                    return Ty::Struct(id);
+                }
+                self.error(TypeError::NotAType {
+                    name: def.name.clone(),
+                    kind: def.kind.describe(),
+                    loc: path.loc.clone(),
+                });
+                Ty::Error
+            }
+            Some(Res::Local(_) | Res::SpriteSelf(_)) | None => Ty::Error,
+        }
+    }
+
+    /// Checks that `found` can be used where `expected` is required.
+    fn expect_ty(&mut self, expected: Ty, found: Ty, loc: &Loc) {
+        if expected == found || expected == Ty::Error || found == Ty::Error {
+            return;
+        }
+        if found == Ty::Unit {
+            self.error(TypeError::NoValue(loc.clone()));
+            return;
+        }
+        self.error(TypeError::Mismatch {
+            expected: self.ty_name(expected),
+            found: self.ty_name(found),
+            loc: loc.clone(),
+        });
+    }
+}
@@ -0,0 +1,339 @@
+use crate::{
+    common::{error::TypeError, source_map::SourceMap},
+    frontend::{
+        ast,
+        resolve::{Program, Resolver},
+    },
+    mir::DataType,
+};
+
+use super::{check, Ty, TypeckResults};
+
+fn check_text(text: &str) -> Result<(Program, TypeckResults), Vec<TypeError>> {
+    let mut source_map = SourceMap::new();
+    let root = source_map.add("main.sl", text);
+    let program = Resolver::new(&mut source_map)
+        .resolve(root)
+        .unwrap_or_else(|errors| panic!("{errors:?}"));
+    let results = check(&program)?;
+    Ok((program, results))
+}
+
+fn check_ok(text: &str) -> (Program, TypeckResults) {
+    check_text(text).unwrap_or_else(|errors| panic!("{errors:?}"))
+}
+
+fn check_err(text: &str) -> Vec<TypeError> {
+    check_text(text).expect_err("Type checking should fail")
+}
+
+fn proc_body(item: &ast::Item) -> &ast::Block {
+    let ast::ItemKind::Proc(ast::Proc {
+        body: ast::ProcBody::Block(block),
+        ..
+    }) = &item.kind
+    else {
+        unreachable!()
+    };
+    block
+}
+
+#[test]
+fn infers_types_of_locals() {
+    let (program, results) =
+        check_ok("proc main(a: number) {\n    let x = a * 2\n    let y = \"value: \" + x\n    let z = x > 1 && y == \"\"\n}");
+    let stmts = &proc_body(&program.root_crate().module.items[0]).stmts;
+    let types: Vec<Ty> = stmts.iter().map(|stmt| results.local_ty(stmt.id)).collect();
+    assert_eq!(types, [Ty::Number, Ty::Text, Ty::Boolean]);
+}
+
+#[test]
+fn infers_return_types_of_shorthand_procedures() {
+    let (program, results) = check_ok(
+        "proc greet(name: text) = \"Greetings, \" + name + \"!\"\nproc twice(name: text) = greet(greet(name))",
+    );
+    for item in program.root_crate().module.items.iter() {
+        let id = program.def_of_item(item.id).unwrap();
+        assert_eq!(results.signature(id).ret, Ty::Text);
+    }
+
+    let errors = check_err("proc a() = b()\nproc b() = a()");
+    assert!(matches!(&errors[..], [TypeError::CannotInfer { name, .. }] if name == "a"));
+}
+
+#[test]
+fn checks_arguments_against_block_inputs() {
+    let (program, results) = check_ok(
+        "block say(message: text, secs: number) as looks_sayforsecs {\n    inputs: ${ SECS: secs, MESSAGE: message },\n}\nproc main() = say(\"hi\", 2)",
+    );
+    let id = program
+        .def_of_item(program.root_crate().module.items[0].id)
+        .unwrap();
+    let definition = results.block_definition(id);
+    assert!(!definition.is_expression());
+    let inputs: Vec<_> = definition
+        .inputs()
+        .iter()
+        .map(|input| (input.name(), input.ty().clone()))
+        .collect();
+    assert_eq!(
+        inputs,
+        [("MESSAGE", DataType::Text), ("SECS", DataType::Number)]
+    );
+
+    let errors = check_err(
+        "block say(message: text, secs: number) as looks_sayforsecs {\n    inputs: ${ SECS: secs, MESSAGE: message },\n}\nproc main() {\n    say(1, 2)\n    say(\"a\")\n}",
+    );
+    assert!(matches!(
+        &errors[..],
+        [
+            TypeError::Mismatch { expected, found, .. },
+            TypeError::ArgumentCount { expected: 2, found: 1, .. },
+        ] if expected == "text" && found == "number"
+    ));
+}
+
+#[test]
+fn pure_blocks_are_marked() {
+    let (program, results) = check_ok(
+        "@pure\nblock round(x: number) -> number as operator_round { inputs: ${ NUM: x } }\nblock timer() -> number as sensing_timer { }",
+    );
+    let pure: Vec<bool> = program.root_crate().module.items[..2]
+        .iter()
+        .map(|item| {
+            let id = program.def_of_item(item.id).unwrap();
+            results.block_definition(id).is_pure()
+        })
+        .collect();
+    assert_eq!(pure, [true, false]);
+}
+
+#[test]
+fn validates_block_declarations() {
+    let errors = check_err(
+        "struct S { a: number }\nblock a(x: S, y: number, z: number) as op {\n    inputs: ${ A: y, B: y, C: 1 },\n    fields: ${ F: y },\n}",
+    );
+    assert!(matches!(errors[0], TypeError::NonPrimitiveBlockType { .. }));
+    assert!(matches!(errors[1], TypeError::BlockParameterReused { .. }));
+    assert!(matches!(errors[2], TypeError::InvalidBlockInput(_)));
+    assert!(matches!(&errors[3], TypeError::UnusedBlockParameter { name, .. } if name == "x"));
+    assert!(matches!(&errors[4], TypeError::UnusedBlockParameter { name, .. } if name == "z"));
+    assert!(matches!(errors[5], TypeError::InvalidBlockField(_)));
+}
+
+#[test]
+fn struct_fields_and_literals() {
+    let (program, results) = check_ok(
+        "struct Vec2 { x: number, y: number }\nstruct Line { from: Vec2, to: Vec2 }\nproc length(line: Line) -> number {\n    let d = line.to.y\n    return d\n}",
+    );
+    let line = program
+        .def_of_item(program.root_crate().module.items[1].id)
+        .unwrap();
+    assert_eq!(
+        results.data_type(Ty::Struct(line)),
+        DataType::Structure(vec![
+            DataType::Structure(vec![DataType::Number, DataType::Number]),
+            DataType::Structure(vec![DataType::Number, DataType::Number]),
+        ])
+    );
+
+    let errors = check_err(
+        "struct Vec2 { x: number, y: number }\nproc main() {\n    let v = Vec2 { x: 1, z: 2, x: 3 }\n    let w = v.z\n}",
+    );
+    assert!(matches!(&errors[0], TypeError::UnknownField { field, .. } if field == "z"));
+    assert!(matches!(&errors[1], TypeError::DuplicateField { field, .. } if field == "x"));
+    assert!(matches!(&errors[2], TypeError::MissingFields { fields, .. } if fields == &["y"]));
+    assert!(matches!(&errors[3], TypeError::UnknownField { field, .. } if field == "z"));
+}
+
+#[test]
+fn sprite_fields_and_spawn() {
+    let (program, results) = check_ok(
+        "struct Vec2 { x: number, y: number }\nsprite Ball {\n    fields { speed: number, position: Vec2 }\n    proc start() {\n        self.position.x += self.speed\n        let x = self.position.x\n    }\n}\nproc main() {\n    spawn Ball { position: Vec2 { x: 1, y: 2 }, speed: 3 }\n}",
+    );
+    let ball = program
+        .def_of_item(program.root_crate().module.items[1].id)
+        .unwrap();
+    let fields: Vec<_> = results
+        .sprite_fields(ball)
+        .fields
+        .iter()
+        .map(|field| (field.name.as_str(), field.ty))
+        .collect();
+    let vec2 = program
+        .def_of_item(program.root_crate().module.items[0].id)
+        .unwrap();
+    assert_eq!(
+        fields,
+        [("speed", Ty::Number), ("position", Ty::Struct(vec2))]
+    );
+
+    let errors = check_err(
+        "struct P { x: number }\nsprite Ball {\n    fields { speed: number, speed: text }\n    proc start() {\n        let b = self\n        self = 1\n        self.size = 2\n        spawn P { x: 1 }\n        spawn Ball { speed: \"fast\", size: 1 }\n        spawn Ball { }\n    }\n}",
+    );
+    assert!(matches!(&errors[0], TypeError::DuplicateField { field, .. } if field == "speed"));
+    assert!(matches!(errors[1], TypeError::SpriteSelfValue(_)));
+    assert!(matches!(errors[2], TypeError::InvalidAssignTarget(_)));
+    assert!(
+        matches!(&errors[3], TypeError::UnknownField { ty, field, .. } if ty == "Ball" && field == "size")
+    );
+    assert!(
+        matches!(&errors[4], TypeError::NotASprite { name, kind: "struct", .. } if name == "P")
+    );
+    assert!(matches!(errors[5], TypeError::Mismatch { .. }));
+    assert!(matches!(&errors[6], TypeError::UnknownField { field, .. } if field == "size"));
+    assert!(matches!(&errors[7], TypeError::MissingFields { fields, .. } if fields == &["speed"]));
+}
+
+#[test]
+fn structs_are_compared() {
+    check_ok(
+        "struct Vec2 { x: number, y: number }\nproc main(a: Vec2, b: Vec2) {\n    let same = a == b\n    let different = a != Vec2 { x: 1, y: 2 }\n}",
+    );
+    let errors = check_err(
+        "struct Vec2 { x: number, y: number }\nproc main(a: Vec2) {\n    let same = a == 1\n}",
+    );
+    assert!(matches!(
+        &errors[..],
+        [TypeError::InvalidOperands { op: "==", .. }]
+    ));
+}
+
+#[test]
+fn assignments() {
+    check_ok("struct P { x: number }\nproc main() {\n    let p = P { x: 1 }\n    p.x += 2\n    p = P { x: 3 }\n}");
+
+    let errors = check_err(
+        "proc main(a: number) {\n    a = 1\n    let t = \"\"\n    t = 1\n    t -= 1\n    main(1) = 2\n}",
+    );
+    assert!(matches!(errors[0], TypeError::AssignToParameter { .. }));
+    assert!(matches!(errors[1], TypeError::Mismatch { .. }));
+    assert!(matches!(
+        errors[2],
+        TypeError::InvalidOperands { op: "-", .. }
+    ));
+    assert!(matches!(errors[3], TypeError::InvalidAssignTarget(_)));
+}
+
+#[test]
+fn return_types() {
+    check_ok("proc f(a: boolean) -> number {\n    if a {\n        return 1\n    } else {\n        return 2\n    }\n}");
+    check_ok("proc f() -> number {\n    forever {\n        return 1\n    }\n}");
+
+    let errors = check_err(
+        "proc f(a: boolean) -> number {\n    if a {\n        return \"\"\n    }\n}\nproc g() {\n    return 1\n}\nproc h() -> text {\n    return\n}",
+    );
+    assert!(matches!(errors[0], TypeError::Mismatch { .. }));
+    assert!(matches!(&errors[1], TypeError::MissingReturn { name, .. } if name == "f"));
+    assert!(matches!(errors[2], TypeError::UnexpectedReturnValue(_)));
+    assert!(matches!(errors[3], TypeError::MissingReturnValue { .. }));
+}
+
+#[test]
+fn control_flow() {
+    let errors = check_err(
+        "proc main() {\n    if 1 { }\n    repeat \"a\" {\n        break\n    }\n    continue\n    let x = main()\n}",
+    );
+    assert!(matches!(errors[0], TypeError::Mismatch { .. }));
+    assert!(matches!(errors[1], TypeError::Mismatch { .. }));
+    assert!(matches!(
+        errors[2],
+        TypeError::OutsideLoop {
+            keyword: "continue",
+            ..
+        }
+    ));
+    assert!(matches!(errors[3], TypeError::NoValue(_)));
+    assert_eq!(errors.len(), 4);
+}
+
+#[test]
+fn recursive_structs() {
+    let errors = check_err("struct A { b: B }\nstruct B { a: A }\nstruct C { c: C }");
+    assert!(matches!(&errors[..], [
+        TypeError::RecursiveStruct { name: a, .. },
+        TypeError::RecursiveStruct { name: c, .. },
+    ] if a == "A" && c == "C"));
+}
+
+#[test]
+fn method_calls_are_resolved_statically() {
+    let (program, results) = check_ok(
+        "struct Circle { r: number }\ntrait Shape {\n    proc area(self) -> number\n}\ntrait Named {\n    proc area(self) -> number\n}\nimpl Shape for Circle {\n    proc area(self) = self.r * self.r * 3\n}\nimpl Circle {\n    proc scaled(self, by: number) -> Circle = Circle { r: self.r * by }\n}\nproc main(c: Circle) {\n    let a = c.scaled(2).area()\n}",
+    );
+    let items = &program.root_crate().module.items;
+    let method = |index: usize| {
+        let ast::ItemKind::Impl(implementation) = &items[index].kind else {
+            unreachable!()
+        };
+        program.def_of_item(implementation.items[0].id).unwrap()
+    };
+    let (area, scaled) = (method(3), method(4));
+    let circle = program.def_of_item(items[0].id).unwrap();
+    assert_eq!(results.signature(area).params, [Ty::Struct(circle)]);
+    assert_eq!(results.signature(area).ret, Ty::Number);
+    assert_eq!(
+        results.method(area).unwrap().trait_id,
+        program.def_of_item(items[1].id)
+    );
+    assert!(results.method(scaled).unwrap().trait_id.is_none());
+
+    let ast::StmtKind::Let { value, .. } = &proc_body(&items[5]).stmts[0].kind else {
+        unreachable!()
+    };
+    let ast::ExprKind::MethodCall(receiver, ..) = &value.kind else {
+        unreachable!()
+    };
+    assert_eq!(results.method_call(value.id), area);
+    assert_eq!(results.method_call(receiver.id), scaled);
+    assert_eq!(results.expr_ty(value.id), Ty::Number);
+
+    let errors = check_err(
+        "struct Circle { r: number }\nimpl Circle {\n    proc area(self) = 1\n}\nproc main(c: Circle) {\n    let a = c.area(1)\n    let b = c.perimeter()\n    let d = 1.area()\n}",
+    );
+    assert!(matches!(
+        errors[0],
+        TypeError::ArgumentCount {
+            expected: 0,
+            found: 1,
+            ..
+        }
+    ));
+    assert!(matches!(&errors[1], TypeError::NoMethod { method, .. } if method == "perimeter"));
+    assert!(matches!(&errors[2], TypeError::NoMethod { ty, .. } if ty == "number"));
+}
+
+#[test]
+fn reports_invalid_impls() {
+    let errors = check_err(
+        "struct Circle { r: number }\ntrait Shape {\n    proc area(self) -> number\n    proc name(self) -> text\n}\nimpl Shape for Circle {\n    proc area(self, by: number) -> number = by\n    proc perimeter(self) = 1\n}\nimpl Shape for Circle {\n    proc area(self) -> number = 1\n    proc name(self) = \"\"\n}\nimpl Circle {\n    proc r(self) = 1\n}\nimpl Circle {\n    proc r(self) = 2\n}\nimpl number { }\nimpl Circle for Circle { }",
+    );
+    assert!(
+        matches!(&errors[0], TypeError::NotATraitMethod { method, .. } if method == "perimeter")
+    );
+    assert!(
+        matches!(&errors[1], TypeError::MissingTraitMethods { methods, .. } if methods == &["name"])
+    );
+    assert!(
+        matches!(&errors[2], TypeError::ConflictingImpls { trait_name, .. } if trait_name == "Shape")
+    );
+    assert!(matches!(&errors[3], TypeError::DuplicateMethod { method, .. } if method == "r"));
+    assert!(matches!(&errors[4], TypeError::InvalidImplTarget { ty, .. } if ty == "number"));
+    assert!(matches!(
+        &errors[5],
+        TypeError::NotATrait { kind: "struct", .. }
+    ));
+    assert!(matches!(
+        &errors[6],
+        TypeError::TraitMethodMismatch { expected, found, .. }
+            if expected == "(self) -> number" && found == "(self, number) -> number"
+    ));
+    assert_eq!(errors.len(), 7);
+
+    let errors = check_err(
+        "struct Circle { r: number }\ntrait A {\n    proc name(self) -> text\n}\ntrait B {\n    proc name(self) -> text\n}\nimpl A for Circle {\n    proc name(self) = \"a\"\n}\nimpl B for Circle {\n    proc name(self) = \"b\"\n}\nproc main(c: Circle) = c.name()",
+    );
+    assert!(
+        matches!(&errors[..], [TypeError::AmbiguousMethod { traits, .. }] if traits == &["A", "B"])
+    );
+}
@@ -0,0 +1,316 @@
+use crate::{
+    common::{error::TypeError, location::Loc},
+    frontend::{
+        ast,
+        resolve::{DefKind, Res},
+    },
+};
+
+use super::{DefId, MethodDef, Signature, Ty, TypeChecker};
+
+impl<'p> TypeChecker<'p> {
+    pub(super) fn check_traits(&mut self) {
+        for (id, item) in self.items.clone() {
+            let ast::ItemKind::Trait(trait_decl) = &item.kind else {
+                continue;
+            };
+
+            let mut methods: Vec<(&ast::TraitMethod, Signature)> = Vec::new();
+            for method in trait_decl.methods.iter() {
+                let previous = methods
+                    .iter()
+                    .find(|(previous, _)| previous.name.name == method.name.name);
+                if let Some((previous, _)) = previous {
+                    self.error(TypeError::DuplicateMethod {
+                        ty: trait_decl.name.name.clone(),
+                        method: method.name.name.clone(),
+                        loc: method.name.loc.clone(),
+                        previous: previous.name.loc.clone(),
+                    });
+                    continue;
+                }
+                let params = method
+                    .params
+                    .iter()
+                    .map(|param| self.resolve_ty(&param.ty))
+                    .collect();
+                let ret = match method.ret.as_ref() {
+                    Some(ret) => self.resolve_ty(ret),
+                    None => Ty::Unit,
+                };
+                methods.push((method, Signature { params, ret }));
+            }
+            self.traits.insert(id, methods);
+        }
+    }
+
+    /// Registers methods of every `impl` block, so they can be found by method calls.
+    pub(super) fn check_impls(&mut self) {
+        for implementation in self.impls.clone() {
+            let ty = match self.resolve_ty(&implementation.ty) {
+                ty @ (Ty::Struct(_) | Ty::Error) => ty,
+                ty => {
+                    self.error(TypeError::InvalidImplTarget {
+                        ty: self.ty_name(ty),
+                        loc: implementation.ty.loc.clone(),
+                    });
+                    Ty::Error
+                }
+            };
+            let trait_id = implementation
+                .trait_ref
+                .as_ref()
+                .map(|path| self.resolve_trait(path));
+
+            let methods: Vec<(DefId, &ast::Proc)> = implementation
+                .items
+                .iter()
+                .map(|item| {
+                    let ast::ItemKind::Proc(proc) = &item.kind else {
+                        unreachable!("Impl blocks only contain procedures")
+                    };
+                    (self.program.def_of_item(item.id).unwrap(), proc)
+                })
+                .collect();
+            for (id, _) in methods.iter() {
+                self.results.methods.insert(
+                    *id,
+                    MethodDef {
+                        self_ty: ty,
+                        trait_id: trait_id.flatten(),
+                    },
+                );
+            }
+
+            let Ty::Struct(structure) = ty else {
+                continue;
+            };
+            match trait_id {
+                None => self.add_inherent_methods(structure, &methods),
+                Some(Some(trait_id)) => {
+                    self.add_trait_methods(structure, trait_id, implementation, &methods)
+                }
+                // Path of the trait was already reported.
+                Some(None) => {}
+            }
+        }
+    }
+
+    fn resolve_trait(&mut self, path: &ast::Path) -> Option<DefId> {
+        let Some(Res::Def(id)) = self.program.res(path.id) else {
+            return None;
+        };
+        let def = self.program.def(id);
+        if def.kind == DefKind::Trait {
+            return Some(id);
+        }
+        self.error(TypeError::NotATrait {
+            name: def.name.clone(),
+            kind: def.kind.describe(),
+            loc: path.loc.clone(),
+        });
+        None
+    }
+
+    fn add_inherent_methods(&mut self, structure: DefId, methods: &[(DefId, &ast::Proc)]) {
+        for (id, proc) in methods.iter() {
+            let previous = self
+                .struct_methods
+                .get(&structure)
+                .into_iter()
+                .flatten()
+                .find(|method| {
+                    self.results.methods[*method].trait_id.is_none()
+                        && self.program.def(**method).name == proc.name.name
+                })
+                .copied();
+            if let Some(previous) = previous {
+                self.error(TypeError::DuplicateMethod {
+                    ty: self.ty_name(Ty::Struct(structure)),
+                    method: proc.name.name.clone(),
+                    loc: proc.name.loc.clone(),
+                    previous: self.program.def(previous).loc.clone(),
+                });
+                continue;
+            }
+            self.struct_methods.entry(structure).or_default().push(*id);
+        }
+    }
+
+    fn add_trait_methods(
+        &mut self,
+        structure: DefId,
+        trait_id: DefId,
+        implementation: &ast::Impl,
+        methods: &[(DefId, &ast::Proc)],
+    ) {
+        let ty = self.ty_name(Ty::Struct(structure));
+        let trait_name = self.program.def(trait_id).name.clone();
+        let header = impl_header_loc(implementation);
+        if let Some(previous) = self.trait_impls.get(&(structure, trait_id)) {
+            self.error(TypeError::ConflictingImpls {
+                trait_name,
+                ty,
+                loc: header,
+                previous: previous.clone(),
+            });
+            return;
+        }
+        self.trait_impls
+            .insert((structure, trait_id), header.clone());
+
+        let declared = self.traits[&trait_id].clone();
+        let mut implemented: Vec<(DefId, &ast::Proc)> = Vec::new();
+        for (id, proc) in methods.iter() {
+            let previous = implemented
+                .iter()
+                .find(|(_, previous)| previous.name.name == proc.name.name);
+            if let Some((_, previous)) = previous {
+                self.error(TypeError::DuplicateMethod {
+                    ty: ty.clone(),
+                    method: proc.name.name.clone(),
+                    loc: proc.name.loc.clone(),
+                    previous: previous.name.loc.clone(),
+                });
+                continue;
+            }
+            if !declared
+                .iter()
+                .any(|(method, _)| method.name.name == proc.name.name)
+            {
+                self.error(TypeError::NotATraitMethod {
+                    trait_name: trait_name.clone(),
+                    method: proc.name.name.clone(),
+                    loc: proc.name.loc.clone(),
+                });
+                continue;
+            }
+            implemented.push((*id, proc));
+        }
+
+        let missing: Vec<String> = declared
+            .iter()
+            .filter(|(method, _)| {
+                !implemented
+                    .iter()
+                    .any(|(_, proc)| proc.name.name == method.name.name)
+            })
+            .map(|(method, _)| method.name.name.clone())
+            .collect();
+        if !missing.is_empty() {
+            self.error(TypeError::MissingTraitMethods {
+                trait_name,
+                ty,
+                methods: missing,
+                loc: header,
+            });
+        }
+        self.struct_methods
+            .entry(structure)
+            .or_default()
+            .extend(implemented.iter().map(|(id, _)| *id));
+    }
+
+    /// Checks that methods implementing traits have signatures declared by the traits.
+    pub(super) fn check_impl_signatures(&mut self) {
+        for (id, item) in self.items.clone() {
+            let Some(MethodDef {
+                trait_id: Some(trait_id),
+                ..
+            }) = self.results.methods.get(&id).cloned()
+            else {
+                continue;
+            };
+            let ast::ItemKind::Proc(proc) = &item.kind else {
+                unreachable!()
+            };
+            let declared = self.traits[&trait_id]
+                .iter()
+                .find(|(method, _)| method.name.name == proc.name.name)
+                .map(|(_, signature)| signature.clone());
+            let Some(expected) = declared else {
+                continue;
+            };
+
+            // Return type of a shorthand method is needed before the bodies are checked.
+            if self.to_infer.contains(&id) {
+                self.check_proc(id);
+            }
+            let found = self.results.signatures[&id].clone();
+            let params = &found.params[1..];
+            let compatible = |a: Ty, b: Ty| a == b || a == Ty::Error || b == Ty::Error;
+            let matches = params.len() == expected.params.len()
+                && params
+                    .iter()
+                    .zip(expected.params.iter())
+                    .all(|(found, expected)| compatible(*found, *expected))
+                && compatible(found.ret, expected.ret);
+            if !matches {
+                self.error(TypeError::TraitMethodMismatch {
+                    trait_name: self.program.def(trait_id).name.clone(),
+                    method: proc.name.name.clone(),
+                    expected: self.signature_text(&expected.params, expected.ret),
+                    found: self.signature_text(params, found.ret),
+                    loc: proc.name.loc.clone(),
+                });
+            }
+        }
+    }
+
+    /// Signature of the method as written in the source code, like `(self, number) -> text`.
+    fn signature_text(&self, params: &[Ty], ret: Ty) -> String {
+        let params: Vec<String> = std::iter::once("self".to_owned())
+            .chain(params.iter().map(|ty| self.ty_name(*ty)))
+            .collect();
+        match ret {
+            Ty::Unit => format!("({})", params.join(", ")),
+            ret => format!("({}) -> {}", params.join(", "), self.ty_name(ret)),
+        }
+    }
+
+    /// Finds method called on a value of the struct. Methods of inherent impls
+    /// shadow methods of traits, as in Rust.
+    pub(super) fn lookup_method(&mut self, structure: DefId, method: &ast::Ident) -> Option<DefId> {
+        let candidates: Vec<DefId> = self
+            .struct_methods
+            .get(&structure)
+            .into_iter()
+            .flatten()
+            .filter(|id| self.program.def(**id).name == method.name)
+            .copied()
+            .collect();
+        if let Some(inherent) = candidates
+            .iter()
+            .find(|id| self.results.methods[*id].trait_id.is_none())
+        {
+            return Some(*inherent);
+        }
+        if candidates.len() > 1 {
+            self.error(TypeError::AmbiguousMethod {
+                ty: self.ty_name(Ty::Struct(structure)),
+                method: method.name.clone(),
+                traits: candidates
+                    .iter()
+                    .map(|id| {
+                        let trait_id = self.results.methods[id].trait_id.unwrap();
+                        self.program.def(trait_id).name.clone()
+                    })
+                    .collect(),
+                loc: method.loc.clone(),
+            });
+        }
+        candidates.first().copied()
+    }
+}
+
+/// Location of `Trait for Type` part of the implementation.
+fn impl_header_loc(implementation: &ast::Impl) -> Loc {
+    let start = match implementation.trait_ref.as_ref() {
+        Some(path) => path.loc.span.start,
+        None => implementation.ty.loc.span.start,
+    };
+    Loc::new(
+        start..implementation.ty.loc.span.end,
+        implementation.ty.loc.file,
+    )
+}
@@ -0,0 +1,120 @@
+//! Maps locals of the procedure onto as few variables as possible.
+//! Locals share variables when their live ranges don't overlap.
+
+use super::{CodeBlock, Procedure, Statement};
+
+/// Variables needed by the procedure's locals.
+#[derive(Debug, Default)]
+pub(super) struct Allocation {
+    /// Slots of variables, every slot holds one local at a time.
+    pub slots: Vec<Slot>,
+    /// Slot of every local.
+    pub local_slots: Vec<usize>,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Slot {
+    /// Locals stored in the slot, in order of their declaration.
+    pub locals: Vec<usize>,
+    /// Number of variables, structures need one for every flattened field.
+    pub size: usize,
+}
+
+pub(super) fn allocate(procedure: &Procedure) -> Allocation {
+    let ranges = live_ranges(procedure);
+    let mut order: Vec<usize> = (0..procedure.locals.len()).collect();
+    order.sort_by_key(|local| ranges[*local].map(|(start, _)| start));
+
+    let mut allocation = Allocation {
+        slots: Vec::new(),
+        local_slots: vec![0; procedure.locals.len()],
+    };
+    // Position after which slot is free again.
+    let mut ends: Vec<usize> = Vec::new();
+    for local in order {
+        let (start, end) = ranges[local].unwrap_or_default();
+        let size = procedure.locals[local].dt.calculate_size();
+        let slot = match ends.iter().position(|free| *free < start) {
+            Some(slot) => slot,
+            None => {
+                allocation.slots.push(Slot::default());
+                ends.push(0);
+                ends.len() - 1
+            }
+        };
+        ends[slot] = end;
+        allocation.slots[slot].locals.push(local);
+        allocation.slots[slot].size = allocation.slots[slot].size.max(size);
+        allocation.local_slots[local] = slot;
+    }
+    for slot in allocation.slots.iter_mut() {
+        slot.locals.sort();
+    }
+
+    allocation
+}
+
+/// First and last position at which every local is alive, statements are numbered
+/// in order of their appearance. Locals used in a loop they were declared outside of
+/// are alive until the end of the loop. `None` for locals which are never referenced.
+pub(super) fn live_ranges(procedure: &Procedure) -> Vec<Option<(usize, usize)>> {
+    let mut ranges = LiveRanges {
+        position: 0,
+        ranges: vec![None; procedure.locals.len()],
+    };
+    ranges.visit_block(&procedure.block);
+    ranges.ranges
+}
+
+struct LiveRanges {
+    position: usize,
+    ranges: Vec<Option<(usize, usize)>>,
+}
+
+impl LiveRanges {
+    fn visit_block(&mut self, code: &CodeBlock) {
+        for stmt in code.code.iter() {
+            self.visit(stmt);
+        }
+    }
+
+    fn visit(&mut self, stmt: &Statement) {
+        let position = self.position;
+        self.position += 1;
+        match stmt {
+            Statement::Let(local) | Statement::LocalRef(local, _) => {
+                let range = self.ranges[*local].get_or_insert((position, position));
+                range.1 = position;
+            }
+            // Value is read before the target is written.
+            Statement::Assignment(target, value) => {
+                self.visit(value);
+                self.visit(target);
+            }
+            _ => {
+                stmt.children()
+                    .into_iter()
+                    .for_each(|child| self.visit(child));
+                for code in stmt.code_blocks() {
+                    self.visit_block(code);
+                }
+                if matches!(
+                    stmt,
+                    Statement::While(..) | Statement::Repeat(..) | Statement::Forever(_)
+                ) {
+                    self.extend_over_loop(position);
+                }
+            }
+        }
+    }
+
+    /// Values of locals declared before the loop are needed by Its next iteration.
+    fn extend_over_loop(&mut self, start: usize) {
+        let end = self.position - 1;
+        for (first, last) in self.ranges.iter_mut().flatten() {
+            if *first < start && *last >= start {
+                *last = end;
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Procedures of the sprite calling each other, used to find recursion and
+//! procedures which are never called.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{CodeBlock, Procedure, Statement};
+
+pub(super) struct CallGraph<'a> {
+    /// Procedures called by every procedure, directly or through other procedures.
+    reachable: HashMap<&'a str, HashSet<&'a str>>,
+}
+
+impl<'a> CallGraph<'a> {
+    pub fn new(procedures: &'a [Procedure]) -> Self {
+        let calls: HashMap<&str, HashSet<&str>> = procedures
+            .iter()
+            .map(|procedure| {
+                let mut calls = HashSet::new();
+                collect_calls(&procedure.block, &mut calls);
+                (procedure.name.as_str(), calls)
+            })
+            .collect();
+
+        let reachable = calls
+            .keys()
+            .map(|name| {
+                let mut reachable = HashSet::new();
+                let mut pending: Vec<&str> = calls[name].iter().copied().collect();
+                while let Some(callee) = pending.pop() {
+                    if reachable.insert(callee) {
+                        pending.extend(calls.get(callee).into_iter().flatten().copied());
+                    }
+                }
+                (*name, reachable)
+            })
+            .collect();
+
+        Self { reachable }
+    }
+
+    /// Whether procedure may call itself.
+    pub fn is_recursive(&self, name: &str) -> bool {
+        self.calls(name, name)
+    }
+
+    /// Whether both procedures may call each other, so their calls can be nested.
+    pub fn in_same_cycle(&self, first: &str, second: &str) -> bool {
+        self.calls(first, second) && self.calls(second, first)
+    }
+
+    /// Procedures called by the procedure, directly or through other procedures.
+    pub fn callees(&self, name: &str) -> impl Iterator<Item = &'a str> + '_ {
+        self.reachable.get(name).into_iter().flatten().copied()
+    }
+
+    fn calls(&self, caller: &str, callee: &str) -> bool {
+        self.reachable
+            .get(caller)
+            .is_some_and(|reachable| reachable.contains(callee))
+    }
+}
+
+fn collect_calls<'a>(code: &'a CodeBlock, calls: &mut HashSet<&'a str>) {
+    for stmt in code.code.iter() {
+        collect_stmt_calls(stmt, calls);
+    }
+}
+
+fn collect_stmt_calls<'a>(stmt: &'a Statement, calls: &mut HashSet<&'a str>) {
+    if let Statement::ProcedureCall(name, _) = stmt {
+        calls.insert(name);
+    }
+    for child in stmt.children() {
+        collect_stmt_calls(child, calls);
+    }
+    for code in stmt.code_blocks() {
+        collect_calls(code, calls);
+    }
+}
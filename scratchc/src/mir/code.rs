@@ -1,27 +1,27 @@
-use std::{
-    cell::{Ref, RefCell},
-    collections::HashMap,
-    sync::Arc,
-};
+use std::collections::HashMap;
 
-#[derive(Debug)]
+/// Blocks callable from the MIR, by their id. Definitions are only added while
+/// the project is built, so they can be shared by sprites refined in parallel.
+#[derive(Debug, Clone, Default)]
 pub struct BlockDefinitions {
-    defs: RefCell<HashMap<String, BlockDefinition>>,
+    defs: HashMap<String, BlockDefinition>,
 }
 
 impl BlockDefinitions {
-    pub fn new() -> Arc<Self> {
-        Arc::new(Self {
-            defs: RefCell::default(),
-        })
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn define(&mut self, id: impl AsRef<str>, block: BlockDefinition) {
+        self.defs.insert(id.as_ref().to_owned(), block);
     }
 
-    pub fn define(&self, id: impl AsRef<str>, block: BlockDefinition) {
-        self.defs.borrow_mut().insert(id.as_ref().to_owned(), block);
+    pub fn is_defined(&self, id: impl AsRef<str>) -> bool {
+        self.defs.contains_key(id.as_ref())
     }
 
-    pub fn get<'a>(&'a self, id: impl AsRef<str>) -> Ref<'a, BlockDefinition> {
-        Ref::map(self.defs.borrow(), |defs| defs.get(id.as_ref()).unwrap())
+    pub fn get(&self, id: impl AsRef<str>) -> Option<&BlockDefinition> {
+        self.defs.get(id.as_ref())
     }
 }
 
@@ -29,6 +29,9 @@ impl BlockDefinitions {
 pub struct BlockDefinition {
     pub(super) opcode: String,
     pub(super) is_expression: bool,
+    /// Whether block always reports the same value for the same inputs without
+    /// any side effects, so It can be evaluated during compilation.
+    pub(super) is_pure: bool,
     pub(super) inputs: Vec<BlockInput>,
     pub(super) fields: Vec<BlockField>,
 }
@@ -43,10 +46,33 @@ impl BlockDefinition {
         Self {
             opcode: opcode.as_ref().to_owned(),
             is_expression,
+            is_pure: false,
             inputs: inputs.into_iter().collect(),
             fields: fields.into_iter().collect(),
         }
     }
+
+    pub fn mark_pure(mut self) -> Self {
+        self.is_pure = true;
+        self
+    }
+
+    pub fn opcode(&self) -> &str {
+        &self.opcode
+    }
+
+    pub fn is_expression(&self) -> bool {
+        self.is_expression
+    }
+
+    pub fn is_pure(&self) -> bool {
+        self.is_pure
+    }
+
+    /// Inputs in order in which arguments are passed to the block.
+    pub fn inputs(&self) -> &[BlockInput] {
+        &self.inputs
+    }
 }
 
 #[derive(Debug, Clone, derive_more::Constructor)]
@@ -55,6 +81,16 @@ pub struct BlockInput {
     pub(super) ty: DataType,
 }
 
+impl BlockInput {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn ty(&self) -> &DataType {
+        &self.ty
+    }
+}
+
 #[derive(Debug, Clone, derive_more::Constructor)]
 pub struct BlockField {
     pub(super) name: String,
@@ -70,6 +106,12 @@ pub enum DataType {
 
     // Advanced types
     Structure(Vec<DataType>),
+    /// Tagged union, payload of every variant. Variants without payload
+    /// have an empty structure.
+    Enum(Vec<DataType>),
+    /// List of the sprite with type of Its items. Lists can't be stored in variables,
+    /// they are only referenced with `ListRef`.
+    List(Box<DataType>),
 }
 
 impl DataType {
@@ -81,23 +123,53 @@ impl DataType {
         self.flatten().len()
     }
 
+    /// Enums are flattened into the tag followed by payload slots shared by all
+    /// variants. Slots holding values of different types are texts.
     pub fn flatten(&self) -> Vec<DataType> {
         match self {
-            DataType::Structure(fields) => fields
-                .iter()
-                .map(|field| field.flatten())
-                .flatten()
-                .collect(),
-            x @ _ => vec![x.clone()],
+            DataType::Structure(fields) => {
+                fields.iter().flat_map(|field| field.flatten()).collect()
+            }
+            DataType::Enum(variants) => {
+                let mut slots: Vec<DataType> = Vec::new();
+                for variant in variants.iter() {
+                    for (i, field) in variant.flatten().into_iter().enumerate() {
+                        match slots.get_mut(i) {
+                            Some(slot) if *slot != field => *slot = DataType::Text,
+                            Some(_) => {}
+                            None => slots.push(field),
+                        }
+                    }
+                }
+                std::iter::once(DataType::Number).chain(slots).collect()
+            }
+            x => vec![x.clone()],
         }
     }
 }
 
+/// Hint whether calls of the procedure should be replaced with Its body.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Inline {
+    /// Procedure is inlined when It is short.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
 #[derive(Debug, Clone)]
 pub struct Procedure {
     pub(super) name: String,
     pub(super) is_warp: bool,
+    /// Whether procedure is kept even when nothing calls It.
+    pub(super) is_entry: bool,
+    pub(super) inline: Inline,
     pub(super) inputs: Vec<DataType>,
+    /// Type of the returned value, `None` when procedure returns nothing.
+    pub(super) returns: Option<DataType>,
+    /// Locals referenced by their index.
+    pub(super) locals: Vec<Local>,
     pub(super) block: CodeBlock,
 }
 
@@ -110,17 +182,95 @@ impl Procedure {
         Self {
             name: name.as_ref().to_owned(),
             is_warp,
+            is_entry: false,
+            inline: Inline::Auto,
             inputs: inputs.into_iter().collect(),
+            returns: None,
+            locals: Vec::new(),
             block: CodeBlock::default(),
         }
     }
 
+    pub fn set_return_type(&mut self, returns: DataType) -> &mut Self {
+        self.returns = Some(returns);
+        self
+    }
+
+    pub fn mark_as_entry(&mut self) -> &mut Self {
+        self.is_entry = true;
+        self
+    }
+
+    pub fn set_inline(&mut self, inline: Inline) -> &mut Self {
+        self.inline = inline;
+        self
+    }
+
+    /// Declares local, returns index used to reference It.
+    pub fn declare_local(&mut self, name: impl AsRef<str>, dt: DataType) -> usize {
+        self.locals.push(Local {
+            name: name.as_ref().to_owned(),
+            dt,
+        });
+        self.locals.len() - 1
+    }
+
     pub fn code_block(&mut self) -> &mut CodeBlock {
         &mut self.block
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_warp(&self) -> bool {
+        self.is_warp
+    }
+
+    pub fn is_entry(&self) -> bool {
+        self.is_entry
+    }
+
+    pub fn inline(&self) -> Inline {
+        self.inline
+    }
+
+    pub fn inputs(&self) -> &[DataType] {
+        &self.inputs
+    }
+
+    pub fn return_type(&self) -> Option<&DataType> {
+        self.returns.as_ref()
+    }
+
+    pub fn body(&self) -> &CodeBlock {
+        &self.block
+    }
+
+    pub fn locals(&self) -> &[Local] {
+        &self.locals
+    }
 }
 
-#[derive(Debug, Clone, Default)]
+/// Local of the procedure, locals which are never alive at the same time
+/// share variables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Local {
+    pub(super) name: String,
+    pub(super) dt: DataType,
+}
+
+impl Local {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn data_type(&self) -> &DataType {
+        &self.dt
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct CodeBlock {
     pub(super) code: Vec<Statement>,
 }
@@ -130,6 +280,18 @@ impl CodeBlock {
         self.code.push(stmt);
         self
     }
+
+    pub fn statements(&self) -> &[Statement] {
+        &self.code
+    }
+}
+
+impl FromIterator<Statement> for CodeBlock {
+    fn from_iter<T: IntoIterator<Item = Statement>>(iter: T) -> Self {
+        Self {
+            code: iter.into_iter().collect(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -138,7 +300,201 @@ pub enum Statement {
     BlockCall(String, Vec<Statement>),
     ArgumentRef(usize, DataType),
     VariableRef(String, String, DataType),
+    /// Value of the enum's variant, with Its payload.
+    Variant(usize, Option<Box<Statement>>, DataType),
+    /// Payload of the enum value's variant.
+    Payload(Box<Statement>, usize, DataType),
+    /// Declares local, Its value is assigned separately.
+    Let(usize),
+    LocalRef(usize, DataType),
     Assignment(Box<Statement>, Box<Statement>),
     FieldRef(Box<Statement>, usize, DataType),
     StructureLiteral(Vec<pawgen::schema::Value>, DataType),
+    /// Structure made of values of Its fields, in order of their declaration.
+    Structure(Vec<Statement>, DataType),
+    /// Whether values of the type are equal, structures are compared field by field.
+    Equals(Box<Statement>, Box<Statement>, DataType),
+    /// Calls procedure of the same sprite, arguments are in order of Its declaration.
+    ProcedureCall(String, Vec<Statement>),
+
+    // Control flow
+    /// Conditional with optional `else` branch.
+    If(Box<Statement>, CodeBlock, Option<CodeBlock>),
+    /// Value of the first statement when condition is true, otherwise of the second one.
+    Select(Box<Statement>, Box<Statement>, Box<Statement>, DataType),
+    While(Box<Statement>, CodeBlock),
+    Repeat(Box<Statement>, CodeBlock),
+    Forever(CodeBlock),
+    /// Runs arm of the enum value's variant, variants without arm run the last block.
+    Match(Box<Statement>, Vec<(usize, CodeBlock)>, Option<CodeBlock>),
+    /// Exits the innermost loop.
+    Break,
+    /// Skips rest of the innermost loop's iteration.
+    Continue,
+    /// Returns from the procedure, value is stored in Its return variables.
+    Return(Option<Box<Statement>>),
+
+    // Lists
+    /// List of the sprite, referenced by Its index.
+    ListRef(usize, DataType),
+    /// Operation on the referenced list, indices start at 0.
+    List(Box<Statement>, ListOperation),
+
+    // Events
+    /// Sends broadcast with the name, `true` waits until all scripts receiving
+    /// It finish.
+    Broadcast(String, bool),
+
+    // Clones
+    /// Field of the sprite or clone running the code, referenced by Its index.
+    SelfField(usize, DataType),
+    /// Creates clone of the sprite with the name, Its fields start with the values
+    /// in order of their declaration.
+    Spawn(String, Vec<Statement>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListOperation {
+    /// Appends item to the end of the list.
+    Push(Box<Statement>),
+    /// Removes the last item, returns It.
+    Pop,
+    /// Item at the index.
+    Index(Box<Statement>),
+    Length,
+    /// Inserts item before the index.
+    Insert(Box<Statement>, Box<Statement>),
+    /// Removes item at the index.
+    Delete(Box<Statement>),
+    /// Whether list contains the item.
+    Contains(Box<Statement>),
+}
+
+impl ListOperation {
+    /// Values passed to the operation, in order of their evaluation.
+    pub fn operands(&self) -> Vec<&Statement> {
+        match self {
+            Self::Push(value)
+            | Self::Index(value)
+            | Self::Delete(value)
+            | Self::Contains(value) => vec![value],
+            Self::Insert(index, value) => vec![index, value],
+            Self::Pop | Self::Length => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Statement> {
+        match self {
+            Self::Push(value)
+            | Self::Index(value)
+            | Self::Delete(value)
+            | Self::Contains(value) => vec![value],
+            Self::Insert(index, value) => vec![index, value],
+            Self::Pop | Self::Length => Vec::new(),
+        }
+    }
+}
+
+impl Statement {
+    /// Statements nested in this one, not including statements of Its code blocks.
+    pub fn children(&self) -> Vec<&Statement> {
+        match self {
+            Self::BlockCall(_, args)
+            | Self::ProcedureCall(_, args)
+            | Self::Structure(args, _)
+            | Self::Spawn(_, args) => args.iter().collect(),
+            Self::Assignment(first, second) | Self::Equals(first, second, _) => {
+                vec![first, second]
+            }
+            Self::FieldRef(target, ..) | Self::Payload(target, ..) => vec![target],
+            Self::Variant(_, payload, _) => payload.iter().map(Box::as_ref).collect(),
+            Self::Select(condition, then, otherwise, _) => vec![condition, then, otherwise],
+            Self::If(value, ..)
+            | Self::While(value, _)
+            | Self::Repeat(value, _)
+            | Self::Match(value, ..)
+            | Self::Return(Some(value)) => vec![value],
+            Self::List(target, operation) => {
+                let mut children = vec![target.as_ref()];
+                children.extend(operation.operands());
+                children
+            }
+            Self::Constant(_)
+            | Self::ArgumentRef(..)
+            | Self::VariableRef(..)
+            | Self::Let(_)
+            | Self::LocalRef(..)
+            | Self::StructureLiteral(..)
+            | Self::ListRef(..)
+            | Self::SelfField(..)
+            | Self::Forever(_)
+            | Self::Break
+            | Self::Continue
+            | Self::Broadcast(..)
+            | Self::Return(None) => Vec::new(),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Statement> {
+        match self {
+            Self::BlockCall(_, args)
+            | Self::ProcedureCall(_, args)
+            | Self::Structure(args, _)
+            | Self::Spawn(_, args) => args.iter_mut().collect(),
+            Self::Assignment(first, second) | Self::Equals(first, second, _) => {
+                vec![first, second]
+            }
+            Self::FieldRef(target, ..) | Self::Payload(target, ..) => vec![target],
+            Self::Variant(_, payload, _) => payload.iter_mut().map(Box::as_mut).collect(),
+            Self::Select(condition, then, otherwise, _) => vec![condition, then, otherwise],
+            Self::If(value, ..)
+            | Self::While(value, _)
+            | Self::Repeat(value, _)
+            | Self::Match(value, ..)
+            | Self::Return(Some(value)) => vec![value],
+            Self::List(target, operation) => {
+                let mut children = vec![target.as_mut()];
+                children.extend(operation.operands_mut());
+                children
+            }
+            Self::Constant(_)
+            | Self::ArgumentRef(..)
+            | Self::VariableRef(..)
+            | Self::Let(_)
+            | Self::LocalRef(..)
+            | Self::StructureLiteral(..)
+            | Self::ListRef(..)
+            | Self::SelfField(..)
+            | Self::Forever(_)
+            | Self::Break
+            | Self::Continue
+            | Self::Broadcast(..)
+            | Self::Return(None) => Vec::new(),
+        }
+    }
+
+    /// Code blocks of conditionals, loops and matches, in order of their appearance.
+    pub fn code_blocks(&self) -> Vec<&CodeBlock> {
+        match self {
+            Self::If(_, then, otherwise) => std::iter::once(then).chain(otherwise).collect(),
+            Self::While(_, body) | Self::Repeat(_, body) | Self::Forever(body) => vec![body],
+            Self::Match(_, arms, otherwise) => {
+                arms.iter().map(|(_, arm)| arm).chain(otherwise).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn code_blocks_mut(&mut self) -> Vec<&mut CodeBlock> {
+        match self {
+            Self::If(_, then, otherwise) => std::iter::once(then).chain(otherwise).collect(),
+            Self::While(_, body) | Self::Repeat(_, body) | Self::Forever(body) => vec![body],
+            Self::Match(_, arms, otherwise) => arms
+                .iter_mut()
+                .map(|(_, arm)| arm)
+                .chain(otherwise)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 }
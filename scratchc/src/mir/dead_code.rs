@@ -0,0 +1,299 @@
+//! Removes code which never runs or whose result is never used: procedures not
+//! reachable from entry points or event handlers, locals which are never read and statements after
+//! an unconditional `return`, `break` or `continue`.
+
+use std::collections::HashSet;
+
+use crate::common::diagnostic::{Diagnostic, ToDiagnostic};
+
+use super::{
+    call_graph::CallGraph, BlockDefinitions, CodeBlock, ListOperation, Procedure, Project,
+    Statement,
+};
+
+/// Code removed from the project, reported as a warning.
+#[derive(Debug, Default, PartialEq)]
+pub struct DeadCode {
+    /// Names of procedures removed from every sprite which had them.
+    procedures: Vec<String>,
+    /// Names of removed locals with names of their procedures.
+    locals: Vec<(String, String)>,
+    /// Number of removed unreachable statements.
+    statements: usize,
+}
+
+impl DeadCode {
+    pub fn is_empty(&self) -> bool {
+        self.procedures.is_empty() && self.locals.is_empty() && self.statements == 0
+    }
+
+    pub fn procedures(&self) -> &[String] {
+        &self.procedures
+    }
+
+    pub fn locals(&self) -> &[(String, String)] {
+        &self.locals
+    }
+
+    pub fn statements(&self) -> usize {
+        self.statements
+    }
+
+    fn remove_local(&mut self, name: &str, procedure: &str) {
+        let local = (name.to_owned(), procedure.to_owned());
+        // Procedures shared by sprites are reported once.
+        if !self.locals.contains(&local) {
+            self.locals.push(local);
+        }
+    }
+}
+
+impl ToDiagnostic for DeadCode {
+    fn to_diagnostic(&self) -> Diagnostic {
+        let mut diagnostic = Diagnostic::warning("unused code was removed");
+        for name in self.procedures.iter() {
+            diagnostic = diagnostic.with_note(format!("procedure `{name}` is never called"));
+        }
+        for (name, procedure) in self.locals.iter() {
+            diagnostic =
+                diagnostic.with_note(format!("local `{name}` of `{procedure}` is never read"));
+        }
+        let statements = match self.statements {
+            0 => None,
+            1 => Some("1 statement after `return`, `break` or `continue` never runs".to_owned()),
+            count => Some(format!(
+                "{count} statements after `return`, `break` or `continue` never run"
+            )),
+        };
+        if let Some(statements) = statements {
+            diagnostic = diagnostic.with_note(statements);
+        }
+        diagnostic
+    }
+}
+
+/// Removes dead code of every sprite, returns what was removed. Procedures shared by
+/// sprites are only reported when none of the sprites calls them.
+pub fn eliminate_dead_code(project: &mut Project) -> DeadCode {
+    let definitions = project.block_definitions.clone();
+    let mut dead = DeadCode::default();
+    let mut kept = HashSet::new();
+    for sprite in project.sprites.iter_mut() {
+        for procedure in sprite.procedures.iter_mut() {
+            dead.statements += remove_unreachable(&mut procedure.block);
+        }
+
+        // Procedures started by events are entry points too.
+        let graph = CallGraph::new(&sprite.procedures);
+        let handlers: HashSet<&str> = sprite
+            .handlers
+            .iter()
+            .map(|handler| handler.procedure.as_str())
+            .collect();
+        let reachable: HashSet<String> = sprite
+            .procedures
+            .iter()
+            .filter(|procedure| procedure.is_entry || handlers.contains(procedure.name.as_str()))
+            .flat_map(|procedure| {
+                std::iter::once(procedure.name.as_str()).chain(graph.callees(&procedure.name))
+            })
+            .map(str::to_owned)
+            .collect();
+        sprite.procedures.retain(|procedure| {
+            let is_reachable = reachable.contains(&procedure.name);
+            if !is_reachable && !dead.procedures.contains(&procedure.name) {
+                dead.procedures.push(procedure.name.clone());
+            }
+            is_reachable
+        });
+        kept.extend(reachable);
+
+        for procedure in sprite.procedures.iter_mut() {
+            for local in remove_unused_locals(procedure, &definitions) {
+                dead.remove_local(&local, &procedure.name);
+            }
+        }
+    }
+    dead.procedures.retain(|name| !kept.contains(name));
+    dead
+}
+
+/// Removes statements after the first one which always stops the code,
+/// returns number of removed statements.
+fn remove_unreachable(code: &mut CodeBlock) -> usize {
+    let mut removed = 0;
+    for stmt in code.code.iter_mut() {
+        for block in stmt.code_blocks_mut() {
+            removed += remove_unreachable(block);
+        }
+    }
+    if let Some(stop) = code.code.iter().position(always_stops) {
+        removed += code.code.len() - stop - 1;
+        code.code.truncate(stop + 1);
+    }
+    removed
+}
+
+/// Whether code after the statement never runs.
+fn always_stops(stmt: &Statement) -> bool {
+    let stops = |code: &CodeBlock| code.code.iter().any(always_stops);
+    match stmt {
+        Statement::Return(_) | Statement::Break | Statement::Continue => true,
+        Statement::If(_, then, Some(otherwise)) => stops(then) && stops(otherwise),
+        Statement::Match(_, arms, Some(otherwise)) => {
+            arms.iter().all(|(_, arm)| stops(arm)) && stops(otherwise)
+        }
+        Statement::Forever(body) => !breaks(body),
+        _ => false,
+    }
+}
+
+/// Whether code breaks out of the loop It is the body of.
+fn breaks(code: &CodeBlock) -> bool {
+    code.code.iter().any(|stmt| match stmt {
+        Statement::Break => true,
+        // Breaks in nested loops exit those loops.
+        Statement::While(..) | Statement::Repeat(..) | Statement::Forever(_) => false,
+        stmt => stmt.code_blocks().into_iter().any(breaks),
+    })
+}
+
+/// Removes locals which are never read together with their assignments,
+/// returns names of removed locals. Assignments of values with side effects
+/// are kept, so are their locals.
+fn remove_unused_locals(procedure: &mut Procedure, definitions: &BlockDefinitions) -> Vec<String> {
+    // Removed assignments may have been the only reads of other locals.
+    loop {
+        let mut read = vec![false; procedure.locals.len()];
+        for stmt in procedure.block.code.iter() {
+            mark_read(stmt, &mut read);
+        }
+        let removed = retain_statements(&mut procedure.block, &|stmt| match stmt {
+            Statement::Assignment(target, value) => assigned_local(target)
+                .is_none_or(|local| read[local] || has_side_effects(value, definitions)),
+            _ => true,
+        });
+        if removed == 0 {
+            break;
+        }
+    }
+
+    let mut used = vec![false; procedure.locals.len()];
+    for stmt in procedure.block.code.iter() {
+        mark_used(stmt, &mut used);
+    }
+    if used.iter().all(|used| *used) {
+        return Vec::new();
+    }
+    retain_statements(
+        &mut procedure.block,
+        &|stmt| !matches!(stmt, Statement::Let(local) if !used[*local]),
+    );
+
+    // Remaining locals are moved to the place of removed ones.
+    let mut indices = Vec::with_capacity(used.len());
+    let mut removed = Vec::new();
+    let mut kept = Vec::new();
+    for (local, used) in std::mem::take(&mut procedure.locals).into_iter().zip(used) {
+        indices.push(kept.len());
+        match used {
+            true => kept.push(local),
+            false => removed.push(local.name),
+        }
+    }
+    procedure.locals = kept;
+    for stmt in procedure.block.code.iter_mut() {
+        renumber(stmt, &indices);
+    }
+    removed
+}
+
+/// Keeps statements of the code and Its nested blocks for which `keep` returns
+/// true, returns number of removed statements.
+fn retain_statements(code: &mut CodeBlock, keep: &impl Fn(&Statement) -> bool) -> usize {
+    let count = code.code.len();
+    code.code.retain(keep);
+    let mut removed = count - code.code.len();
+    for stmt in code.code.iter_mut() {
+        for block in stmt.code_blocks_mut() {
+            removed += retain_statements(block, keep);
+        }
+    }
+    removed
+}
+
+/// Local assigned by the place, when It is a local or a field of one.
+fn assigned_local(place: &Statement) -> Option<usize> {
+    match place {
+        Statement::LocalRef(local, _) => Some(*local),
+        Statement::FieldRef(target, ..) | Statement::Payload(target, ..) => assigned_local(target),
+        _ => None,
+    }
+}
+
+fn mark_read(stmt: &Statement, read: &mut [bool]) {
+    match stmt {
+        Statement::LocalRef(local, _) => read[*local] = true,
+        Statement::Assignment(target, value) if assigned_local(target).is_some() => {
+            mark_read(value, read);
+            return;
+        }
+        _ => {}
+    }
+    for child in stmt.children() {
+        mark_read(child, read);
+    }
+    for code in stmt.code_blocks() {
+        for stmt in code.code.iter() {
+            mark_read(stmt, read);
+        }
+    }
+}
+
+fn mark_used(stmt: &Statement, used: &mut [bool]) {
+    if let Statement::LocalRef(local, _) = stmt {
+        used[*local] = true;
+    }
+    for child in stmt.children() {
+        mark_used(child, used);
+    }
+    for code in stmt.code_blocks() {
+        for stmt in code.code.iter() {
+            mark_used(stmt, used);
+        }
+    }
+}
+
+fn has_side_effects(stmt: &Statement, definitions: &BlockDefinitions) -> bool {
+    let effects = match stmt {
+        Statement::ProcedureCall(..) => true,
+        // Unknown blocks are reported by the verifier, until then they may do anything.
+        Statement::BlockCall(id, _) => !definitions
+            .get(id)
+            .is_some_and(|definition| definition.is_pure),
+        Statement::List(_, operation) => !matches!(
+            operation,
+            ListOperation::Index(_) | ListOperation::Length | ListOperation::Contains(_)
+        ),
+        _ => false,
+    };
+    effects
+        || stmt
+            .children()
+            .into_iter()
+            .any(|child| has_side_effects(child, definitions))
+}
+
+fn renumber(stmt: &mut Statement, indices: &[usize]) {
+    if let Statement::Let(local) | Statement::LocalRef(local, _) = stmt {
+        *local = indices[*local];
+    }
+    for child in stmt.children_mut() {
+        renumber(child, indices);
+    }
+    for code in stmt.code_blocks_mut() {
+        for stmt in code.code.iter_mut() {
+            renumber(stmt, indices);
+        }
+    }
+}
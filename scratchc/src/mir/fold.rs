@@ -0,0 +1,374 @@
+//! Evaluates pure blocks with constant inputs during compilation. Values are cast
+//! exactly like Scratch casts them, results which Scratch could compute differently
+//! are left to the project.
+
+use pawgen::schema::Value;
+
+use super::{BlockDefinitions, CodeBlock, ListOperation, Project, Statement};
+
+/// Folds constants in procedures of every sprite.
+pub fn fold_constants(project: &mut Project) {
+    let definitions = project.block_definitions.clone();
+    for sprite in project.sprites.iter_mut() {
+        for procedure in sprite.procedures.iter_mut() {
+            fold_code_block(&mut procedure.block, &definitions);
+        }
+    }
+}
+
+pub fn fold_code_block(code: &mut CodeBlock, definitions: &BlockDefinitions) {
+    for stmt in code.code.iter_mut() {
+        fold_stmt(stmt, definitions);
+    }
+}
+
+fn fold_stmt(stmt: &mut Statement, definitions: &BlockDefinitions) {
+    match stmt {
+        Statement::BlockCall(id, args) => {
+            args.iter_mut().for_each(|arg| fold_stmt(arg, definitions));
+            if let Some(folded) = fold_block(id, args, definitions) {
+                *stmt = folded;
+            }
+        }
+        Statement::ProcedureCall(_, args)
+        | Statement::Structure(args, _)
+        | Statement::Spawn(_, args) => args.iter_mut().for_each(|arg| fold_stmt(arg, definitions)),
+        Statement::Assignment(target, value) | Statement::Equals(target, value, _) => {
+            fold_stmt(target, definitions);
+            fold_stmt(value, definitions);
+        }
+        Statement::FieldRef(target, ..) | Statement::Payload(target, ..) => {
+            fold_stmt(target, definitions)
+        }
+        Statement::Variant(_, payload, _) => {
+            if let Some(payload) = payload {
+                fold_stmt(payload, definitions);
+            }
+        }
+        Statement::If(condition, then, otherwise) => {
+            fold_stmt(condition, definitions);
+            fold_code_block(then, definitions);
+            if let Some(otherwise) = otherwise {
+                fold_code_block(otherwise, definitions);
+            }
+        }
+        Statement::Select(condition, then, otherwise, _) => {
+            fold_stmt(condition, definitions);
+            fold_stmt(then, definitions);
+            fold_stmt(otherwise, definitions);
+        }
+        Statement::Match(value, arms, otherwise) => {
+            fold_stmt(value, definitions);
+            for (_, arm) in arms.iter_mut() {
+                fold_code_block(arm, definitions);
+            }
+            if let Some(otherwise) = otherwise {
+                fold_code_block(otherwise, definitions);
+            }
+        }
+        Statement::While(value, body) | Statement::Repeat(value, body) => {
+            fold_stmt(value, definitions);
+            fold_code_block(body, definitions);
+        }
+        Statement::Forever(body) => fold_code_block(body, definitions),
+        Statement::Return(Some(value)) => fold_stmt(value, definitions),
+        Statement::List(target, operation) => {
+            fold_stmt(target, definitions);
+            match operation {
+                ListOperation::Push(value)
+                | ListOperation::Index(value)
+                | ListOperation::Delete(value)
+                | ListOperation::Contains(value) => fold_stmt(value, definitions),
+                ListOperation::Insert(index, value) => {
+                    fold_stmt(index, definitions);
+                    fold_stmt(value, definitions);
+                }
+                ListOperation::Pop | ListOperation::Length => {}
+            }
+        }
+        Statement::Constant(_)
+        | Statement::ArgumentRef(..)
+        | Statement::VariableRef(..)
+        | Statement::Let(_)
+        | Statement::LocalRef(..)
+        | Statement::StructureLiteral(..)
+        | Statement::ListRef(..)
+        | Statement::Break
+        | Statement::Continue
+        | Statement::Broadcast(..)
+        | Statement::SelfField(..)
+        | Statement::Return(None) => {}
+    }
+}
+
+/// Value of the block call with already folded arguments, `None` when It can't
+/// be computed during compilation.
+fn fold_block(id: &str, args: &[Statement], definitions: &BlockDefinitions) -> Option<Statement> {
+    let def = definitions.get(id)?;
+    if !def.is_pure || !def.is_expression {
+        return None;
+    }
+
+    // `not not x` is `x`, booleans are only ever used as conditions or texts.
+    if def.opcode == "operator_not" {
+        if let [Statement::BlockCall(inner, inner_args)] = args {
+            if let [operand] = &inner_args[..] {
+                if definitions
+                    .get(inner)
+                    .is_some_and(|inner| inner.opcode == "operator_not")
+                {
+                    return Some(operand.clone());
+                }
+            }
+        }
+    }
+
+    let inputs = args
+        .iter()
+        .map(|arg| match arg {
+            Statement::Constant(value @ (Value::Number(_) | Value::Text(_))) => Some(value),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let field = |name: &str| {
+        def.fields
+            .iter()
+            .find(|field| field.name == name)
+            .map(|field| field.value.as_str())
+    };
+
+    // Calls with missing inputs are left to the verifier.
+    let input = |index: usize| inputs.get(index).copied();
+    let number = |index: usize| to_number(input(index)?);
+    let text = |index: usize| to_text(input(index)?);
+    let condition = |index: usize| to_condition(input(index)?);
+    let compare = |first: usize, second: usize| compare(input(first)?, input(second)?);
+    let value = match def.opcode.as_str() {
+        "operator_add" => Value::Number(number(0)? + number(1)?),
+        "operator_subtract" => Value::Number(number(0)? - number(1)?),
+        "operator_multiply" => Value::Number(number(0)? * number(1)?),
+        "operator_divide" => Value::Number(number(0)? / number(1)?),
+        "operator_mod" => {
+            let (n, modulus) = (number(0)?, number(1)?);
+            let mut result = n % modulus;
+            if result / modulus < 0.0 {
+                result += modulus;
+            }
+            Value::Number(result)
+        }
+        "operator_round" => Value::Number(js_round(number(0)?)),
+        "operator_mathop" => Value::Number(mathop(&field("OPERATOR")?.to_lowercase(), number(0)?)?),
+        "operator_join" => Value::Text(text(0)? + &text(1)?),
+        "operator_length" => Value::Number(text(0)?.encode_utf16().count() as f64),
+        "operator_letter_of" => {
+            let index = number(0)? - 1.0;
+            let units: Vec<u16> = text(1)?.encode_utf16().collect();
+            if index < 0.0 || index >= units.len() as f64 {
+                Value::Text(String::new())
+            } else {
+                // Halves of surrogate pairs can't be represented.
+                let letter = char::from_u32(units[index as usize] as u32)?;
+                Value::Text(letter.to_string())
+            }
+        }
+        "operator_contains" => {
+            let string = text(0)?.to_lowercase();
+            boolean(string.contains(&text(1)?.to_lowercase()))
+        }
+        "operator_equals" => boolean(compare(0, 1)? == 0.0),
+        "operator_lt" => boolean(compare(0, 1)? < 0.0),
+        "operator_gt" => boolean(compare(0, 1)? > 0.0),
+        "operator_and" => boolean(condition(0)? && condition(1)?),
+        "operator_or" => boolean(condition(0)? || condition(1)?),
+        "operator_not" => boolean(!condition(0)?),
+        _ => return None,
+    };
+
+    // Inputs can't hold NaN and infinities.
+    match value {
+        Value::Number(number) if !number.is_finite() => None,
+        value => Some(Statement::Constant(value)),
+    }
+}
+
+/// Operations of `operator_mathop` whose results don't depend on the JavaScript engine.
+fn mathop(operator: &str, n: f64) -> Option<f64> {
+    let radians = |degrees: f64| std::f64::consts::PI * degrees / 180.0;
+    Some(match operator {
+        "abs" => n.abs(),
+        "floor" => n.floor(),
+        "ceiling" => n.ceil(),
+        "sqrt" => n.sqrt(),
+        "sin" => round_to_ten_places(radians(n).sin())?,
+        "cos" => round_to_ten_places(radians(n).cos())?,
+        "tan" => match n % 360.0 {
+            -270.0 | 90.0 => f64::INFINITY,
+            -90.0 | 270.0 => f64::NEG_INFINITY,
+            angle => round_to_ten_places(radians(angle).tan())?,
+        },
+        _ => return None,
+    })
+}
+
+/// `parseFloat(n.toFixed(10))`.
+fn round_to_ten_places(n: f64) -> Option<f64> {
+    format!("{n:.10}").parse().ok()
+}
+
+/// `Math.round`, halves are rounded up.
+fn js_round(n: f64) -> f64 {
+    let floor = n.floor();
+    if n - floor >= 0.5 {
+        floor + 1.0
+    } else {
+        floor
+    }
+}
+
+fn boolean(value: bool) -> Value {
+    Value::Text(value.to_string())
+}
+
+/// `Cast.toNumber`, values which aren't numbers are 0.
+fn to_number(value: &Value) -> Option<f64> {
+    let number = match value {
+        Value::Number(number) => *number,
+        Value::Text(text) => js_number(text)?,
+        _ => return None,
+    };
+    Some(if number.is_nan() { 0.0 } else { number })
+}
+
+/// `Cast.toString`.
+fn to_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Number(number) => Some(js_string(*number)),
+        Value::Text(text) => Some(text.clone()),
+        _ => None,
+    }
+}
+
+/// Constants in boolean inputs are compared with `true`, see `into_condition`
+/// of the refinery.
+fn to_condition(value: &Value) -> Option<bool> {
+    Some(compare(value, &Value::Text("true".to_owned()))? == 0.0)
+}
+
+/// `Cast.compare`, values are compared as numbers when both of them are numbers,
+/// otherwise as case insensitive texts.
+fn compare(first: &Value, second: &Value) -> Option<f64> {
+    let number = |value: &Value| match value {
+        Value::Number(number) => Some(*number),
+        Value::Text(text) if text.trim_matches(is_js_whitespace).is_empty() => Some(f64::NAN),
+        Value::Text(text) => js_number(text),
+        _ => None,
+    };
+    let (n1, n2) = (number(first)?, number(second)?);
+    if n1.is_nan() || n2.is_nan() {
+        let s1: Vec<u16> = to_text(first)?.to_lowercase().encode_utf16().collect();
+        let s2: Vec<u16> = to_text(second)?.to_lowercase().encode_utf16().collect();
+        return Some(match s1.cmp(&s2) {
+            std::cmp::Ordering::Less => -1.0,
+            std::cmp::Ordering::Equal => 0.0,
+            std::cmp::Ordering::Greater => 1.0,
+        });
+    }
+    if n1.is_infinite() && n1 == n2 {
+        return Some(0.0);
+    }
+    Some(n1 - n2)
+}
+
+/// Whitespace removed by JavaScript's `String.prototype.trim`.
+fn is_js_whitespace(c: char) -> bool {
+    c == '\u{feff}' || (c.is_whitespace() && c != '\u{85}')
+}
+
+/// JavaScript's `Number(text)`, `None` when the result can't be computed exactly.
+fn js_number(text: &str) -> Option<f64> {
+    let text = text.trim_matches(is_js_whitespace);
+    if text.is_empty() {
+        return Some(0.0);
+    }
+    for (prefix, radix) in [("0x", 16), ("0o", 8), ("0b", 2)] {
+        let Some(digits) = text
+            .strip_prefix(prefix)
+            .or_else(|| text.strip_prefix(&prefix.to_uppercase()))
+        else {
+            continue;
+        };
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return Some(f64::NAN);
+        }
+        // Larger integers would have to be rounded.
+        let value = u64::from_str_radix(digits, radix).ok()?;
+        return (value <= 1 << f64::MANTISSA_DIGITS).then_some(value as f64);
+    }
+
+    let unsigned = text.strip_prefix(['+', '-']).unwrap_or(text);
+    if unsigned == "Infinity" {
+        return Some(match text.starts_with('-') {
+            true => f64::NEG_INFINITY,
+            false => f64::INFINITY,
+        });
+    }
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (unsigned, None),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    let valid_mantissa =
+        is_digits(integer) && is_digits(fraction) && !(integer.is_empty() && fraction.is_empty());
+    let valid_exponent = exponent.is_none_or(|exponent| {
+        let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        !digits.is_empty() && is_digits(digits)
+    });
+    if !valid_mantissa || !valid_exponent {
+        return Some(f64::NAN);
+    }
+    text.parse().ok()
+}
+
+/// JavaScript's `String(number)`.
+fn js_string(number: f64) -> String {
+    if number.is_nan() {
+        return "NaN".to_owned();
+    }
+    if number == 0.0 {
+        return "0".to_owned();
+    }
+    if number.is_infinite() {
+        return match number > 0.0 {
+            true => "Infinity".to_owned(),
+            false => "-Infinity".to_owned(),
+        };
+    }
+
+    let sign = if number < 0.0 { "-" } else { "" };
+    // Shortest digits which round trip, same as JavaScript uses.
+    let formatted = format!("{:e}", number.abs());
+    let (mantissa, exponent) = formatted.split_once('e').unwrap();
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    // Position of the decimal point relative to the digits.
+    let n = exponent.parse::<i32>().unwrap() + 1;
+    let unsigned = if k <= n && n <= 21 {
+        format!("{digits}{}", "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{digits}", "0".repeat(-n as usize))
+    } else {
+        let exponent = n - 1;
+        let exponent = match exponent < 0 {
+            true => format!("-{}", -exponent),
+            false => format!("+{exponent}"),
+        };
+        match k {
+            1 => format!("{digits}e{exponent}"),
+            _ => format!("{}.{}e{exponent}", &digits[..1], &digits[1..]),
+        }
+    };
+    format!("{sign}{unsigned}")
+}
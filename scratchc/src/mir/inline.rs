@@ -0,0 +1,283 @@
+//! Replaces calls of short procedures with their bodies. Arguments are substituted
+//! into the body and locals of the inlined procedure are appended to locals of the
+//! caller, so they never clash with Its own. Recursive procedures are never inlined.
+//! Only calls which are whole statements, assigned or returned values are inlined,
+//! calls nested in other expressions like `say(c.name())` are kept as custom blocks.
+
+use std::collections::HashMap;
+
+use super::{
+    call_graph::CallGraph, CodeBlock, DataType, Inline, Local, Procedure, Project, Statement,
+};
+
+/// Number of statements of the procedure up to which It is inlined without a hint.
+const INLINE_THRESHOLD: usize = 12;
+
+/// Inlines procedures of every sprite.
+pub fn inline_procedures(project: &mut Project) {
+    for sprite in project.sprites.iter_mut() {
+        inline_sprite_procedures(&mut sprite.procedures);
+    }
+}
+
+fn inline_sprite_procedures(procedures: &mut [Procedure]) {
+    // Every round inlines one level of calls, bodies of inlined procedures
+    // can contain calls which are inlined by the next round.
+    for _ in 0..procedures.len() {
+        let graph = CallGraph::new(procedures);
+        let callees: HashMap<String, Procedure> = procedures
+            .iter()
+            .filter(|procedure| is_inlinable(procedure, &graph))
+            .map(|procedure| (procedure.name.clone(), procedure.clone()))
+            .collect();
+        if callees.is_empty() {
+            break;
+        }
+
+        let mut changed = false;
+        for procedure in procedures.iter_mut() {
+            let mut cx = InlineCx {
+                callees: &callees,
+                is_warp: procedure.is_warp,
+                locals: &mut procedure.locals,
+                changed: false,
+            };
+            cx.inline_code_block(&mut procedure.block);
+            changed |= cx.changed;
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Whether calls of the procedure can be replaced with Its body. Returns are only
+/// allowed at the end of the body, as there is no way to exit inlined code early.
+fn is_inlinable(procedure: &Procedure, graph: &CallGraph) -> bool {
+    if procedure.inline == Inline::Never || graph.is_recursive(&procedure.name) {
+        return false;
+    }
+    let returns_early = match procedure.block.code.split_last() {
+        Some((Statement::Return(_), rest)) => rest.iter().any(contains_return),
+        _ => procedure.block.code.iter().any(contains_return),
+    };
+    !returns_early
+        && (procedure.inline == Inline::Always || code_size(&procedure.block) <= INLINE_THRESHOLD)
+}
+
+fn contains_return(stmt: &Statement) -> bool {
+    matches!(stmt, Statement::Return(_))
+        || stmt
+            .code_blocks()
+            .into_iter()
+            .any(|code| code.code.iter().any(contains_return))
+}
+
+fn code_size(code: &CodeBlock) -> usize {
+    code.code.iter().map(stmt_size).sum()
+}
+
+fn stmt_size(stmt: &Statement) -> usize {
+    1 + stmt.children().into_iter().map(stmt_size).sum::<usize>()
+        + stmt.code_blocks().into_iter().map(code_size).sum::<usize>()
+}
+
+/// Where value returned by the inlined procedure goes.
+enum Destination {
+    Discard,
+    Assign(Box<Statement>),
+    Return,
+}
+
+/// State of the procedure calls are inlined into.
+struct InlineCx<'a> {
+    callees: &'a HashMap<String, Procedure>,
+    is_warp: bool,
+    locals: &'a mut Vec<Local>,
+    changed: bool,
+}
+
+impl InlineCx<'_> {
+    fn inline_code_block(&mut self, code: &mut CodeBlock) {
+        for stmt in std::mem::take(&mut code.code) {
+            self.inline_stmt(stmt, &mut code.code);
+        }
+    }
+
+    fn inline_stmt(&mut self, mut stmt: Statement, code: &mut Vec<Statement>) {
+        for block in stmt.code_blocks_mut() {
+            self.inline_code_block(block);
+        }
+
+        let (call, destination) = match stmt {
+            Statement::ProcedureCall(..) => (stmt, Destination::Discard),
+            Statement::Assignment(target, value)
+                if matches!(*value, Statement::ProcedureCall(..)) =>
+            {
+                (*value, Destination::Assign(target))
+            }
+            Statement::Return(Some(value)) if matches!(*value, Statement::ProcedureCall(..)) => {
+                (*value, Destination::Return)
+            }
+            stmt => {
+                code.push(stmt);
+                return;
+            }
+        };
+        let Statement::ProcedureCall(name, args) = call else {
+            unreachable!()
+        };
+        // Warp procedure would not run without screen refresh in the caller.
+        let callees = self.callees;
+        let callee = callees
+            .get(&name)
+            .filter(|callee| !callee.is_warp || self.is_warp);
+        let Some(callee) = callee else {
+            let call = Statement::ProcedureCall(name, args);
+            code.push(match destination {
+                Destination::Discard => call,
+                Destination::Assign(target) => Statement::Assignment(target, Box::new(call)),
+                Destination::Return => Statement::Return(Some(Box::new(call))),
+            });
+            return;
+        };
+        self.changed = true;
+
+        let prefix = name.rsplit("::").next().unwrap_or(&name);
+        let offset = self.locals.len();
+        self.locals.extend(callee.locals.iter().map(|local| Local {
+            name: format!("{prefix}.{}", local.name),
+            dt: local.dt.clone(),
+        }));
+
+        // Arguments are evaluated before the body, those which could change their
+        // value or have side effects are stored in locals first.
+        let assigns_argument = callee.block.code.iter().any(assigns_argument);
+        let mut substitutes = Vec::with_capacity(args.len());
+        for (index, (arg, dt)) in args.into_iter().zip(callee.inputs.iter()).enumerate() {
+            if !assigns_argument && is_substitutable(&arg) {
+                substitutes.push(arg);
+                continue;
+            }
+            let local = self.declare_local(format!("{prefix}.arg{index}"), dt.clone(), code);
+            code.push(Statement::Assignment(
+                Box::new(local.clone()),
+                Box::new(arg),
+            ));
+            substitutes.push(local);
+        }
+
+        let mut body = callee.block.code.clone();
+        for stmt in body.iter_mut() {
+            substitute(stmt, offset, &substitutes);
+        }
+        let returned = match body.pop() {
+            Some(Statement::Return(value)) => value,
+            last => {
+                body.extend(last);
+                None
+            }
+        };
+        code.extend(body);
+
+        match (destination, returned) {
+            (Destination::Discard, None) => {}
+            (Destination::Discard, Some(value)) => {
+                if matches!(*value, Statement::ProcedureCall(..)) {
+                    code.push(*value);
+                } else if contains_call(&value) {
+                    let dt = callee.returns.clone().unwrap();
+                    let local = self.declare_local(format!("{prefix}.return"), dt, code);
+                    code.push(Statement::Assignment(Box::new(local), value));
+                }
+            }
+            (Destination::Assign(target), Some(value)) => {
+                code.push(Statement::Assignment(target, value))
+            }
+            (Destination::Assign(_), None) => unreachable!("assigned procedure returns nothing"),
+            (Destination::Return, value) => code.push(Statement::Return(value)),
+        }
+    }
+
+    fn declare_local(
+        &mut self,
+        name: String,
+        dt: DataType,
+        code: &mut Vec<Statement>,
+    ) -> Statement {
+        let local = self.locals.len();
+        self.locals.push(Local {
+            name,
+            dt: dt.clone(),
+        });
+        code.push(Statement::Let(local));
+        Statement::LocalRef(local, dt)
+    }
+}
+
+/// Whether argument can be used in place of the parameter, without changing
+/// the value between Its uses.
+fn is_substitutable(arg: &Statement) -> bool {
+    match arg {
+        Statement::Constant(_)
+        | Statement::ArgumentRef(..)
+        | Statement::LocalRef(..)
+        | Statement::StructureLiteral(..) => true,
+        Statement::FieldRef(target, ..) | Statement::Payload(target, ..) => {
+            is_substitutable(target)
+        }
+        _ => false,
+    }
+}
+
+fn assigns_argument(stmt: &Statement) -> bool {
+    let assigned = match stmt {
+        Statement::Assignment(target, _) => is_argument_place(target),
+        _ => false,
+    };
+    assigned
+        || stmt
+            .code_blocks()
+            .into_iter()
+            .any(|code| code.code.iter().any(assigns_argument))
+}
+
+fn is_argument_place(place: &Statement) -> bool {
+    match place {
+        Statement::ArgumentRef(..) => true,
+        Statement::FieldRef(target, ..) | Statement::Payload(target, ..) => {
+            is_argument_place(target)
+        }
+        _ => false,
+    }
+}
+
+fn contains_call(stmt: &Statement) -> bool {
+    matches!(
+        stmt,
+        Statement::ProcedureCall(..) | Statement::BlockCall(..) | Statement::List(..)
+    ) || stmt.children().into_iter().any(contains_call)
+}
+
+/// Replaces arguments of the inlined procedure and moves Its locals by the offset.
+fn substitute(stmt: &mut Statement, offset: usize, args: &[Statement]) {
+    match stmt {
+        Statement::ArgumentRef(index, _) => {
+            // Undefined arguments are reported by the verifier.
+            if let Some(arg) = args.get(*index) {
+                *stmt = arg.clone();
+            }
+            return;
+        }
+        Statement::Let(local) | Statement::LocalRef(local, _) => *local += offset,
+        _ => {}
+    }
+    for child in stmt.children_mut() {
+        substitute(child, offset, args);
+    }
+    for code in stmt.code_blocks_mut() {
+        for stmt in code.code.iter_mut() {
+            substitute(stmt, offset, args);
+        }
+    }
+}
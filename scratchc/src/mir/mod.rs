@@ -2,17 +2,37 @@
 //! It assumes that all checks have already been done so It does not perform those.
 //! This IR contains some basic abstractions that can be reused by the compiler to avoid reimplementing the same thing in multiple places.
 //! Those abstractions are:
-//! - Variables
+//! - Variables, locals share them when possible
 //! - Structure types
 //! - Procedure returns
 //! - Conditionals and loops
+//! - Lists, lists of structures use one Scratch list per field
+//!
+//! Short procedures are inlined and constants are folded by separate passes before
+//! the MIR is refined.
+//!
+//! Projects can be written as text and read back, which is useful for debugging and tests.
 
+mod allocator;
+mod call_graph;
 mod code;
+mod dead_code;
+mod fold;
+mod inline;
+mod parse;
 mod project;
 mod refinery;
 mod sprite;
+#[cfg(test)]
+mod tests;
+mod text;
+mod verify;
 
 pub use code::*;
+pub use dead_code::*;
+pub use fold::*;
+pub use inline::*;
 pub use project::*;
 pub use refinery::*;
 pub use sprite::*;
+pub use verify::*;
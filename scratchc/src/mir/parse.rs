@@ -0,0 +1,823 @@
+//! Parser of the textual MIR written by the `Display` implementations, see
+//! [`text`](super::text) for the format.
+
+use std::{collections::HashSet, str::FromStr};
+
+use pawgen::schema::Value;
+
+use crate::common::{error::MirSyntaxError, source_map::LineCol};
+
+use super::{
+    BlockDefinition, BlockField, BlockInput, CodeBlock, Costume, DataType, Event, EventHandler,
+    Inline, ListOperation, Procedure, Project, RotationStyle, Sensor, Sound, Sprite,
+    SpriteProperties, Statement,
+};
+
+impl FromStr for Project {
+    type Err = MirSyntaxError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+            blocks: HashSet::new(),
+        };
+        let mut project = Project::new();
+        while !parser.at(&Token::Eof) {
+            if parser.eat_keyword("block") {
+                let id = parser.string()?;
+                let definition = parser.block_definition()?;
+                project.get_definitions_mut().define(&id, definition);
+                parser.blocks.insert(id);
+            } else if parser.eat_keyword("sprite") {
+                let sprite = parser.sprite()?;
+                project.add_sprite(sprite);
+            } else {
+                return Err(parser.error("expected `block` or `sprite`"));
+            }
+        }
+        Ok(project)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Text(String),
+    /// Number as written, so indices can be read from It too.
+    Number(String),
+    Punct(&'static str),
+    Eof,
+}
+
+const PUNCTS: [&str; 14] = [
+    "->", "=>", "(", ")", "{", "}", "[", "]", ",", ":", ";", "=", "%", ">",
+];
+
+fn tokenize(text: &str) -> Result<Vec<(Token, LineCol)>, MirSyntaxError> {
+    let mut tokens = Vec::new();
+    let mut position = LineCol { line: 0, column: 0 };
+    let mut chars = text.chars().peekable();
+    let mut offset = 0;
+    let advance = |c: char, position: &mut LineCol, offset: &mut usize| {
+        *offset += c.len_utf8();
+        match c {
+            '\n' => {
+                *position = LineCol {
+                    line: position.line + 1,
+                    column: 0,
+                }
+            }
+            _ => position.column += 1,
+        }
+    };
+
+    while let Some(&c) = chars.peek() {
+        let start = position;
+        let rest = &text[offset..];
+        if c.is_whitespace() {
+            advance(c, &mut position, &mut offset);
+            chars.next();
+            continue;
+        }
+        if rest.starts_with("//") {
+            while let Some(c) = chars.next_if(|c| *c != '\n') {
+                advance(c, &mut position, &mut offset);
+            }
+            continue;
+        }
+
+        let token = if let Some(punct) = PUNCTS.iter().find(|punct| rest.starts_with(**punct)) {
+            for c in punct.chars() {
+                advance(c, &mut position, &mut offset);
+                chars.next();
+            }
+            Token::Punct(punct)
+        } else if c == '"' {
+            advance(c, &mut position, &mut offset);
+            chars.next();
+            let mut value = String::new();
+            loop {
+                let Some(c) = chars.next() else {
+                    return Err(MirSyntaxError {
+                        message: "unterminated text".to_owned(),
+                        position: start,
+                    });
+                };
+                advance(c, &mut position, &mut offset);
+                match c {
+                    '"' => break,
+                    '\\' => {
+                        let escaped = chars.next().unwrap_or_default();
+                        advance(escaped, &mut position, &mut offset);
+                        value.push(match escaped {
+                            'n' => '\n',
+                            'r' => '\r',
+                            't' => '\t',
+                            '0' => '\0',
+                            '\\' | '"' | '\'' => escaped,
+                            'u' => {
+                                let mut code = String::new();
+                                for c in chars.by_ref() {
+                                    advance(c, &mut position, &mut offset);
+                                    match c {
+                                        '{' => {}
+                                        '}' => break,
+                                        c => code.push(c),
+                                    }
+                                }
+                                u32::from_str_radix(&code, 16)
+                                    .ok()
+                                    .and_then(char::from_u32)
+                                    .ok_or_else(|| MirSyntaxError {
+                                        message: format!("invalid unicode escape `{code}`"),
+                                        position: start,
+                                    })?
+                            }
+                            c => {
+                                return Err(MirSyntaxError {
+                                    message: format!("unknown escape `\\{c}`"),
+                                    position: start,
+                                })
+                            }
+                        });
+                    }
+                    c => value.push(c),
+                }
+            }
+            Token::Text(value)
+        } else if c.is_ascii_digit() || c == '-' {
+            let mut number = String::new();
+            while let Some(c) = chars.next_if(|c| {
+                c.is_ascii_alphanumeric()
+                    || *c == '.'
+                    || (number.is_empty() && *c == '-')
+                    || ((*c == '-' || *c == '+') && number.ends_with(['e', 'E']))
+            }) {
+                advance(c, &mut position, &mut offset);
+                number.push(c);
+            }
+            Token::Number(number)
+        } else if c.is_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                advance(c, &mut position, &mut offset);
+                ident.push(c);
+            }
+            Token::Ident(ident)
+        } else {
+            return Err(MirSyntaxError {
+                message: format!("unexpected character `{c}`"),
+                position: start,
+            });
+        };
+        tokens.push((token, start));
+    }
+    tokens.push((Token::Eof, position));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, LineCol)>,
+    position: usize,
+    /// Blocks defined so far.
+    blocks: HashSet<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn at(&self, token: &Token) -> bool {
+        self.peek() == token
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Ident(ident) if ident == keyword)
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token != Token::Eof {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: impl AsRef<str>) -> MirSyntaxError {
+        let (token, position) = &self.tokens[self.position];
+        let found = match token {
+            Token::Ident(ident) => format!("`{ident}`"),
+            Token::Text(text) => format!("{text:?}"),
+            Token::Number(number) => format!("`{number}`"),
+            Token::Punct(punct) => format!("`{punct}`"),
+            Token::Eof => "end of file".to_owned(),
+        };
+        MirSyntaxError {
+            message: format!("{}, found {found}", message.as_ref()),
+            position: *position,
+        }
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Token::Punct(found) if *found == punct) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), MirSyntaxError> {
+        match self.eat_punct(punct) {
+            true => Ok(()),
+            false => Err(self.error(format!("expected `{punct}`"))),
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.at_keyword(keyword) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    fn ident(&mut self) -> Result<String, MirSyntaxError> {
+        match self.peek() {
+            Token::Ident(_) => {
+                let Token::Ident(ident) = self.next() else {
+                    unreachable!()
+                };
+                Ok(ident)
+            }
+            _ => Err(self.error("expected identifier")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, MirSyntaxError> {
+        match self.peek() {
+            Token::Text(_) => {
+                let Token::Text(text) = self.next() else {
+                    unreachable!()
+                };
+                Ok(text)
+            }
+            _ => Err(self.error("expected text")),
+        }
+    }
+
+    fn index(&mut self) -> Result<usize, MirSyntaxError> {
+        match self.peek() {
+            Token::Number(number) => match number.parse() {
+                Ok(index) => {
+                    self.position += 1;
+                    Ok(index)
+                }
+                Err(_) => Err(self.error("expected index")),
+            },
+            _ => Err(self.error("expected index")),
+        }
+    }
+
+    fn integer<T: FromStr>(&mut self) -> Result<T, MirSyntaxError> {
+        match self.peek() {
+            Token::Number(number) => match number.parse() {
+                Ok(integer) => {
+                    self.position += 1;
+                    Ok(integer)
+                }
+                Err(_) => Err(self.error("expected integer")),
+            },
+            _ => Err(self.error("expected integer")),
+        }
+    }
+
+    fn boolean(&mut self) -> Result<bool, MirSyntaxError> {
+        if self.eat_keyword("true") {
+            return Ok(true);
+        }
+        match self.eat_keyword("false") {
+            true => Ok(false),
+            false => Err(self.error("expected `true` or `false`")),
+        }
+    }
+
+    /// Comma separated items in the delimiters.
+    fn separated<T>(
+        &mut self,
+        open: &str,
+        close: &str,
+        mut item: impl FnMut(&mut Self) -> Result<T, MirSyntaxError>,
+    ) -> Result<Vec<T>, MirSyntaxError> {
+        self.expect_punct(open)?;
+        let mut items = Vec::new();
+        while !self.eat_punct(close) {
+            if !items.is_empty() {
+                self.expect_punct(",")?;
+            }
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    fn data_type(&mut self) -> Result<DataType, MirSyntaxError> {
+        if self.eat_keyword("text") {
+            Ok(DataType::Text)
+        } else if self.eat_keyword("number") {
+            Ok(DataType::Number)
+        } else if self.eat_keyword("boolean") {
+            Ok(DataType::Boolean)
+        } else if self.eat_keyword("enum") {
+            Ok(DataType::Enum(self.separated("{", "}", Self::data_type)?))
+        } else if self.at(&Token::Punct("{")) {
+            Ok(DataType::Structure(self.separated(
+                "{",
+                "}",
+                Self::data_type,
+            )?))
+        } else if self.eat_punct("[") {
+            let item = self.data_type()?;
+            self.expect_punct("]")?;
+            Ok(DataType::List(Box::new(item)))
+        } else {
+            Err(self.error("expected type"))
+        }
+    }
+
+    /// Type of the value following the colon.
+    fn typed(&mut self) -> Result<DataType, MirSyntaxError> {
+        self.expect_punct(":")?;
+        self.data_type()
+    }
+
+    fn block_definition(&mut self) -> Result<BlockDefinition, MirSyntaxError> {
+        self.expect_punct("=")?;
+        let is_expression = self.eat_keyword("expression");
+        let is_pure = self.eat_keyword("pure");
+        let opcode = self.ident()?;
+        let inputs = self.separated("(", ")", |parser| {
+            let name = parser.ident()?;
+            let ty = parser.typed()?;
+            Ok(BlockInput::new(name, ty))
+        })?;
+        let fields = match self.at(&Token::Punct("[")) {
+            true => self.separated("[", "]", |parser| {
+                let name = parser.ident()?;
+                parser.expect_punct("=")?;
+                Ok(BlockField::new(name, parser.string()?))
+            })?,
+            false => Vec::new(),
+        };
+        self.expect_punct(";")?;
+
+        let definition = BlockDefinition::new(opcode, is_expression, inputs, fields);
+        Ok(match is_pure {
+            true => definition.mark_pure(),
+            false => definition,
+        })
+    }
+
+    fn sprite(&mut self) -> Result<Sprite, MirSyntaxError> {
+        let mut sprite = Sprite::new(self.string()?);
+        if self.eat_keyword("stage") {
+            sprite.mark_as_stage();
+        }
+        self.expect_punct("{")?;
+        while !self.eat_punct("}") {
+            if self.eat_keyword("costume") {
+                let name = self.string()?;
+                sprite.add_costume(Costume::new(name, self.string()?));
+                self.expect_punct(";")?;
+            } else if self.eat_keyword("sound") {
+                let name = self.string()?;
+                sprite.add_sound(Sound::new(name, self.string()?));
+                self.expect_punct(";")?;
+            } else if self.eat_keyword("field") {
+                let expected = sprite.fields.len();
+                if self.index()? != expected {
+                    self.position -= 1;
+                    return Err(self.error(format!("expected field {expected}")));
+                }
+                let name = self.string()?;
+                let dt = self.typed()?;
+                sprite.declare_field(name, dt);
+                self.expect_punct(";")?;
+            } else if self.eat_keyword("list") {
+                let expected = sprite.lists.len();
+                if self.index()? != expected {
+                    self.position -= 1;
+                    return Err(self.error(format!("expected list {expected}")));
+                }
+                let name = self.string()?;
+                let item = self.typed()?;
+                sprite.declare_list(name, item);
+                self.expect_punct(";")?;
+            } else if self.eat_keyword("when") {
+                let handler = self.handler()?;
+                sprite.add_handler(handler);
+                self.expect_punct(";")?;
+            } else if self.eat_keyword("proc") {
+                let procedure = self.procedure()?;
+                sprite.add_procedure(procedure);
+            } else if let Some(properties) = self.property(sprite.properties.clone())? {
+                sprite.set_properties(properties);
+                self.expect_punct(";")?;
+            } else {
+                return Err(self.error(
+                    "expected `costume`, `sound`, `field`, `list`, `when`, `proc` or a property",
+                ));
+            }
+        }
+        Ok(sprite)
+    }
+
+    /// Sets the property written next, `None` when there is none.
+    fn property(
+        &mut self,
+        properties: SpriteProperties,
+    ) -> Result<Option<SpriteProperties>, MirSyntaxError> {
+        let properties = if self.eat_keyword("position") {
+            let x = self.integer()?;
+            properties.position(x, self.integer()?)
+        } else if self.eat_keyword("volume") {
+            properties.volume(self.integer()?)
+        } else if self.eat_keyword("size") {
+            properties.size(self.integer()?)
+        } else if self.eat_keyword("layer") {
+            properties.layer(self.integer()?)
+        } else if self.eat_keyword("direction") {
+            properties.direction(self.integer()?)
+        } else if self.eat_keyword("visible") {
+            properties.visible(self.boolean()?)
+        } else if self.eat_keyword("rotation_style") {
+            let Some(style) = RotationStyle::from_name(&self.string()?) else {
+                self.position -= 1;
+                return Err(self.error("expected rotation style"));
+            };
+            properties.rotation_style(style)
+        } else if self.eat_keyword("draggable") {
+            properties.draggable(self.boolean()?)
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(properties))
+    }
+
+    fn handler(&mut self) -> Result<EventHandler, MirSyntaxError> {
+        let event = if self.eat_keyword("flag") {
+            Event::FlagClicked
+        } else if self.eat_keyword("broadcast") {
+            Event::BroadcastReceived(self.string()?)
+        } else if self.eat_keyword("key") {
+            Event::KeyPressed(self.string()?)
+        } else if self.eat_keyword("clicked") {
+            Event::Clicked
+        } else if self.eat_keyword("clone") {
+            Event::CloneStarted
+        } else if self.eat_keyword("backdrop") {
+            Event::BackdropSwitched(self.string()?)
+        } else {
+            let sensor = match () {
+                _ if self.eat_keyword("timer") => Sensor::Timer,
+                _ if self.eat_keyword("loudness") => Sensor::Loudness,
+                _ => return Err(self.error("expected event")),
+            };
+            self.expect_punct(">")?;
+            let threshold = match self.peek() {
+                Token::Number(_) => self.value()?,
+                _ => None,
+            };
+            let Some(Value::Number(threshold)) = threshold else {
+                return Err(self.error("expected number"));
+            };
+            Event::Threshold(sensor, threshold)
+        };
+        self.expect_punct("->")?;
+        Ok(EventHandler::new(event, self.string()?))
+    }
+
+    fn procedure(&mut self) -> Result<Procedure, MirSyntaxError> {
+        let name = self.string()?;
+        let inputs = self.separated("(", ")", Self::data_type)?;
+        let returns = match self.eat_punct("->") {
+            true => Some(self.data_type()?),
+            false => None,
+        };
+        let mut is_warp = false;
+        let mut is_entry = false;
+        let mut inline = Inline::Auto;
+        loop {
+            if self.eat_keyword("warp") {
+                is_warp = true;
+            } else if self.eat_keyword("entry") {
+                is_entry = true;
+            } else if self.eat_keyword("inline") {
+                inline = Inline::Always;
+                if self.eat_punct("(") {
+                    if !self.eat_keyword("never") {
+                        return Err(self.error("expected `never`"));
+                    }
+                    self.expect_punct(")")?;
+                    inline = Inline::Never;
+                }
+            } else {
+                break;
+            }
+        }
+
+        let mut procedure = Procedure::new(name, is_warp, inputs);
+        if let Some(returns) = returns {
+            procedure.set_return_type(returns);
+        }
+        if is_entry {
+            procedure.mark_as_entry();
+        }
+        procedure.set_inline(inline);
+
+        self.expect_punct("{")?;
+        while self.eat_keyword("local") {
+            self.expect_punct("%")?;
+            let expected = procedure.locals.len();
+            if self.index()? != expected {
+                self.position -= 1;
+                return Err(self.error(format!("expected local %{expected}")));
+            }
+            let name = self.string()?;
+            let dt = self.typed()?;
+            procedure.declare_local(name, dt);
+            self.expect_punct(";")?;
+        }
+        *procedure.code_block() = self.code()?;
+        Ok(procedure)
+    }
+
+    /// Statements until the closing brace.
+    fn code(&mut self) -> Result<CodeBlock, MirSyntaxError> {
+        let mut code = CodeBlock::default();
+        while !self.eat_punct("}") {
+            code.push_stmt(self.stmt()?);
+        }
+        Ok(code)
+    }
+
+    fn block(&mut self) -> Result<CodeBlock, MirSyntaxError> {
+        self.expect_punct("{")?;
+        self.code()
+    }
+
+    fn stmt(&mut self) -> Result<Statement, MirSyntaxError> {
+        if self.eat_keyword("if") {
+            let condition = Box::new(self.expr()?);
+            let then = self.block()?;
+            let otherwise = match self.eat_keyword("else") {
+                true => Some(self.block()?),
+                false => None,
+            };
+            return Ok(Statement::If(condition, then, otherwise));
+        } else if self.eat_keyword("while") {
+            let condition = Box::new(self.expr()?);
+            return Ok(Statement::While(condition, self.block()?));
+        } else if self.eat_keyword("repeat") {
+            let times = Box::new(self.expr()?);
+            return Ok(Statement::Repeat(times, self.block()?));
+        } else if self.eat_keyword("forever") {
+            return Ok(Statement::Forever(self.block()?));
+        } else if self.eat_keyword("match") {
+            let value = Box::new(self.expr()?);
+            self.expect_punct("{")?;
+            let mut arms = Vec::new();
+            let mut otherwise = None;
+            while !self.eat_punct("}") {
+                if otherwise.is_some() {
+                    return Err(self.error("expected `}` after the `_` arm"));
+                }
+                match self.eat_keyword("_") {
+                    true => {
+                        self.expect_punct("=>")?;
+                        otherwise = Some(self.block()?);
+                    }
+                    false => {
+                        let variant = self.index()?;
+                        self.expect_punct("=>")?;
+                        arms.push((variant, self.block()?));
+                    }
+                }
+            }
+            return Ok(Statement::Match(value, arms, otherwise));
+        }
+
+        let stmt = if self.eat_keyword("let") {
+            self.expect_punct("%")?;
+            Statement::Let(self.index()?)
+        } else if self.eat_keyword("broadcast") {
+            let name = self.string()?;
+            if matches!(self.peek(), Token::Text(_)) {
+                // Constant broadcast value used as a statement.
+                Statement::Constant(Value::Broadcast(name, self.string()?))
+            } else if self.eat_keyword("and") {
+                if !self.eat_keyword("wait") {
+                    return Err(self.error("expected `wait`"));
+                }
+                Statement::Broadcast(name, true)
+            } else {
+                Statement::Broadcast(name, false)
+            }
+        } else if self.eat_keyword("break") {
+            Statement::Break
+        } else if self.eat_keyword("continue") {
+            Statement::Continue
+        } else if self.eat_keyword("return") {
+            match self.at(&Token::Punct(";")) {
+                true => Statement::Return(None),
+                false => Statement::Return(Some(Box::new(self.expr()?))),
+            }
+        } else {
+            let target = self.expr()?;
+            match self.eat_punct("=") {
+                true => Statement::Assignment(Box::new(target), Box::new(self.expr()?)),
+                false => target,
+            }
+        };
+        self.expect_punct(";")?;
+        Ok(stmt)
+    }
+
+    fn exprs(&mut self) -> Result<Vec<Statement>, MirSyntaxError> {
+        self.separated("(", ")", Self::expr)
+    }
+
+    /// Operands of the expression in parentheses, there must be exactly `N` of them.
+    fn operands<const N: usize>(&mut self) -> Result<[Box<Statement>; N], MirSyntaxError> {
+        let operands = self.exprs()?;
+        let count = operands.len();
+        operands
+            .into_iter()
+            .map(Box::new)
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| {
+                self.position -= 1;
+                self.error(format!("expected {N} operands, not {count}"))
+            })
+    }
+
+    fn expr(&mut self) -> Result<Statement, MirSyntaxError> {
+        if let Some(value) = self.value()? {
+            return Ok(Statement::Constant(value));
+        }
+        if self.eat_punct("%") {
+            let local = self.index()?;
+            return Ok(Statement::LocalRef(local, self.typed()?));
+        }
+        if !matches!(self.peek(), Token::Ident(_)) {
+            return Err(self.error("expected expression"));
+        }
+        let keyword = self.ident()?;
+        let expr = match keyword.as_str() {
+            "block" => {
+                let id = self.string()?;
+                if !self.blocks.contains(&id) {
+                    self.position -= 1;
+                    return Err(self.error("expected defined block"));
+                }
+                Statement::BlockCall(id, self.exprs()?)
+            }
+            "call" => {
+                let name = self.string()?;
+                Statement::ProcedureCall(name, self.exprs()?)
+            }
+            "arg" => {
+                let index = self.index()?;
+                Statement::ArgumentRef(index, self.typed()?)
+            }
+            "var" => {
+                let id = self.string()?;
+                let name = self.string()?;
+                Statement::VariableRef(id, name, self.typed()?)
+            }
+            "variant" => {
+                let index = self.index()?;
+                let payload = match self.at(&Token::Punct("(")) {
+                    true => {
+                        let [payload] = self.operands()?;
+                        Some(payload)
+                    }
+                    false => None,
+                };
+                Statement::Variant(index, payload, self.typed()?)
+            }
+            "payload" | "field" => {
+                self.expect_punct("(")?;
+                let target = Box::new(self.expr()?);
+                self.expect_punct(",")?;
+                let index = self.index()?;
+                // Type of the enum or structure, which is not the type of the result.
+                if !self.eat_keyword("of") {
+                    return Err(self.error("expected `of`"));
+                }
+                let dt = self.data_type()?;
+                self.expect_punct(")")?;
+                match keyword.as_str() {
+                    "payload" => Statement::Payload(target, index, dt),
+                    _ => Statement::FieldRef(target, index, dt),
+                }
+            }
+            "literal" => {
+                let values = self.separated("(", ")", |parser| match parser.value()? {
+                    Some(value) => Ok(value),
+                    None => Err(parser.error("expected constant")),
+                })?;
+                Statement::StructureLiteral(values, self.typed()?)
+            }
+            "struct" => {
+                let fields = self.exprs()?;
+                Statement::Structure(fields, self.typed()?)
+            }
+            "equals" => {
+                let [first, second] = self.operands()?;
+                Statement::Equals(first, second, self.typed()?)
+            }
+            "select" => {
+                let [condition, then, otherwise] = self.operands()?;
+                Statement::Select(condition, then, otherwise, self.typed()?)
+            }
+            "list" => {
+                let index = self.index()?;
+                Statement::ListRef(index, self.typed()?)
+            }
+            "self" => {
+                let index = self.index()?;
+                Statement::SelfField(index, self.typed()?)
+            }
+            "spawn" => {
+                let name = self.string()?;
+                Statement::Spawn(name, self.exprs()?)
+            }
+            "push" => {
+                let [target, value] = self.operands()?;
+                Statement::List(target, ListOperation::Push(value))
+            }
+            "pop" => {
+                let [target] = self.operands()?;
+                Statement::List(target, ListOperation::Pop)
+            }
+            "index" => {
+                let [target, index] = self.operands()?;
+                Statement::List(target, ListOperation::Index(index))
+            }
+            "length" => {
+                let [target] = self.operands()?;
+                Statement::List(target, ListOperation::Length)
+            }
+            "insert" => {
+                let [target, index, value] = self.operands()?;
+                Statement::List(target, ListOperation::Insert(index, value))
+            }
+            "delete" => {
+                let [target, index] = self.operands()?;
+                Statement::List(target, ListOperation::Delete(index))
+            }
+            "contains" => {
+                let [target, value] = self.operands()?;
+                Statement::List(target, ListOperation::Contains(value))
+            }
+            _ => {
+                self.position -= 1;
+                return Err(self.error("expected expression"));
+            }
+        };
+        Ok(expr)
+    }
+
+    /// Constant value, `None` when there is none.
+    fn value(&mut self) -> Result<Option<Value>, MirSyntaxError> {
+        let value = match self.peek().clone() {
+            Token::Text(text) => Value::Text(text),
+            Token::Number(number) => match number.parse() {
+                Ok(number) => Value::Number(number),
+                Err(_) => return Err(self.error("expected number")),
+            },
+            Token::Ident(ident) if ident == "NaN" || ident == "inf" => {
+                Value::Number(ident.parse().unwrap())
+            }
+            Token::Ident(ident) if ident == "pointer" => {
+                self.position += 1;
+                return Ok(Some(Value::Pointer(self.string()?)));
+            }
+            Token::Ident(ident) if ident == "variable" => {
+                self.position += 1;
+                let id = self.string()?;
+                return Ok(Some(Value::Variable(id, self.string()?)));
+            }
+            Token::Ident(ident) if ident == "broadcast" => {
+                self.position += 1;
+                let id = self.string()?;
+                return Ok(Some(Value::Broadcast(id, self.string()?)));
+            }
+            _ => return Ok(None),
+        };
+        self.position += 1;
+        Ok(Some(value))
+    }
+}
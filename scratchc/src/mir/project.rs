@@ -8,10 +8,16 @@ pub struct Project {
     pub(super) sprites: Vec<Sprite>,
 }
 
+impl Default for Project {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Project {
     pub fn new() -> Self {
         Self {
-            block_definitions: BlockDefinitions::new(),
+            block_definitions: Arc::new(BlockDefinitions::new()),
             sprites: Vec::new(),
         }
     }
@@ -20,7 +26,16 @@ impl Project {
         &self.block_definitions
     }
 
+    /// Definitions are copied when they are still shared with a refinery.
+    pub fn get_definitions_mut(&mut self) -> &mut BlockDefinitions {
+        Arc::make_mut(&mut self.block_definitions)
+    }
+
     pub fn add_sprite(&mut self, sprite: Sprite) {
         self.sprites.push(sprite)
     }
+
+    pub fn sprites(&self) -> &[Sprite] {
+        &self.sprites
+    }
 }
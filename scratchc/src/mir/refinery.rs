@@ -1,26 +1,60 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    num::NonZeroUsize,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use pawgen::codegen;
 
-use super::{BlockDefinitions, CodeBlock, DataType, Procedure, Project, Sprite, Statement};
+use crate::common::error::{MirContext, RefineError, RefineErrorKind, VerifyErrorKind};
+
+use super::{
+    allocator, call_graph::CallGraph, BlockDefinitions, CodeBlock, DataType, Event, EventHandler,
+    ListOperation, Procedure, Project, Sprite, Statement,
+};
 
 /// Refinery that converts MIR into pawgen Project.
 pub struct MirRefinery {
     config: MirRefinementConfig,
     block_definitions: Arc<BlockDefinitions>,
+    /// Names of broadcasts sent or received anywhere in the project.
+    broadcasts: BTreeSet<String>,
+    /// Types of fields of every sprite which can be cloned.
+    clones: HashMap<String, Vec<DataType>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct MirRefinementConfig {
     /// Whether to use thread variables from Turbowarp's "Temporary Variables" extension.
     use_thread_variables: bool,
+    /// Whether procedures returning primitives should become Turbowarp's custom reporters.
+    use_return_blocks: bool,
+    /// Whether indices of lists are checked before they are used.
+    check_bounds: bool,
 }
 
-impl Default for MirRefinementConfig {
-    fn default() -> Self {
-        Self {
-            use_thread_variables: false,
-        }
+impl MirRefinementConfig {
+    /// Procedures keep their locals in thread variables, so they don't clash between
+    /// scripts running at the same time and recursive calls. Requires TurboWarp.
+    pub fn use_thread_variables(mut self, enabled: bool) -> Self {
+        self.use_thread_variables = enabled;
+        self
+    }
+
+    pub fn use_return_blocks(mut self, enabled: bool) -> Self {
+        self.use_return_blocks = enabled;
+        self
+    }
+
+    /// Index out of list's bounds stops the project, meant for debug builds.
+    pub fn check_bounds(mut self, enabled: bool) -> Self {
+        self.check_bounds = enabled;
+        self
     }
 }
 
@@ -28,35 +62,116 @@ impl MirRefinery {
     pub fn new(config: MirRefinementConfig) -> Self {
         Self {
             config,
-            block_definitions: BlockDefinitions::new(),
+            block_definitions: Arc::new(BlockDefinitions::new()),
+            broadcasts: BTreeSet::new(),
+            clones: HashMap::new(),
         }
     }
 
-    pub fn refine_project(&mut self, project: Project) -> codegen::ProjectBuilder {
+    /// Refines every sprite of the project in parallel, returns error of the
+    /// first sprite which failed.
+    pub fn refine_project(
+        &mut self,
+        project: Project,
+    ) -> Result<codegen::ProjectBuilder, RefineError> {
         self.block_definitions = project.block_definitions.clone();
+        self.broadcasts = broadcasts(&project.sprites);
+        self.clones = project
+            .sprites
+            .iter()
+            .filter(|sprite| !sprite.is_stage)
+            .map(|sprite| {
+                let fields = sprite.fields.iter().map(|field| field.dt.clone());
+                (sprite.name.clone(), fields.collect())
+            })
+            .collect();
         let mut builder = codegen::ProjectBuilder::new();
+        if self.config.use_thread_variables {
+            builder.add_extension("lmsTempVars2");
+        }
 
-        for sprite in project.sprites.iter() {
-            self.refine_sprite(sprite, &mut builder);
+        let layer_orders = layer_orders(&project.sprites);
+        for (sprite, layer_order) in self
+            .refine_sprites(&project.sprites)
+            .into_iter()
+            .zip(layer_orders)
+        {
+            let sprite = sprite?;
+            sprite.set_layer_order(layer_order);
+            builder.add_sprite(sprite);
         }
 
-        builder
+        Ok(builder)
     }
 
-    fn refine_sprite(
+    /// Refines sprites on as many threads as are available, every thread takes
+    /// the next sprite nobody refines yet. Results are in order of the sprites.
+    fn refine_sprites(
         &self,
-        sprite: &Sprite,
-        builder: &mut codegen::ProjectBuilder,
-    ) -> codegen::SpriteBuilder {
-        let sb = builder.create_sprite(&sprite.name);
+        sprites: &[Sprite],
+    ) -> Vec<Result<codegen::SpriteBuilder, RefineError>> {
+        let threads = std::thread::available_parallelism()
+            .map_or(1, NonZeroUsize::get)
+            .min(sprites.len());
+        let next = AtomicUsize::new(0);
+        let mut refined: Vec<_> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut refined = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(sprite) = sprites.get(index) else {
+                                break refined;
+                            };
+                            refined.push((index, self.refine_sprite(sprite)));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        });
+        refined.sort_by_key(|(index, _)| *index);
+        refined.into_iter().map(|(_, sprite)| sprite).collect()
+    }
+
+    fn refine_sprite(&self, sprite: &Sprite) -> Result<codegen::SpriteBuilder, RefineError> {
+        let sb = codegen::SpriteBuilder::new(&sprite.name);
+        let properties = &sprite.properties;
+        sb.set_volume(properties.volume);
         if sprite.is_stage {
             sb.set_stage(true);
+            // Broadcasts are global, so the stage declares all of them.
+            for name in self.broadcasts.iter() {
+                sb.add_broadcast(name, name);
+            }
+            // Spawning sprite and the clone both need the hand-off lists.
+            for (sprite, fields) in self.clones.iter() {
+                for (id, name) in hand_off_lists(sprite, fields) {
+                    sb.add_list(id, name);
+                }
+            }
+        } else {
+            let (x, y) = properties.position;
+            sb.set_position(x, y)
+                .set_size(properties.size)
+                .set_direction(properties.direction)
+                .set_visible(properties.visible)
+                .set_rotation_style(properties.rotation_style.as_str().to_owned())
+                .set_draggable(properties.draggable);
         }
 
         for costume in sprite.costumes.iter() {
-            let asset = builder
+            let asset = sb
                 .register_asset(&costume.name, &costume.source)
-                .expect("Costume file should exist at this point");
+                .map_err(|error| asset_error(sprite, &costume.name, &costume.source, error))?;
             sb.add_costume(&asset);
         }
         if let Some(first) = sprite.costumes.first() {
@@ -64,56 +179,447 @@ impl MirRefinery {
         }
 
         for sound in sprite.sounds.iter() {
-            let _asset = builder
+            let _asset = sb
                 .register_asset(&sound.name, &sound.source)
-                .expect("Sound file should exist at this point");
+                .map_err(|error| asset_error(sprite, &sound.name, &sound.source, error))?;
             // TODO: sb.add_sound(&asset);
         }
 
+        // Structures are stored in one list for every flattened field.
+        let lists = sprite
+            .lists
+            .iter()
+            .map(|list| {
+                if list.item.is_primitive() {
+                    return vec![sb.make_list(&list.name)];
+                }
+                (0..list.item.calculate_size())
+                    .map(|i| sb.make_list(format!("{}:{i}", list.name)))
+                    .collect()
+            })
+            .collect();
+        // Variables of sprites are "for this sprite only", so every clone has Its own.
+        let variable = |name: String, value| {
+            let pawgen::schema::Value::Variable(id, name) = sb.make_variable(name, value) else {
+                unreachable!()
+            };
+            Storage::Variable((id, name))
+        };
+        let fields = sprite
+            .fields
+            .iter()
+            .map(|field| {
+                let name = format!("self.{}", field.name);
+                components((name.clone(), name), &field.dt)
+                    .into_iter()
+                    .map(|(_, name)| variable(name, pawgen::schema::Value::Number(0.0)))
+                    .collect()
+            })
+            .collect();
+        let spawned = (!sprite.is_stage && !sprite.fields.is_empty())
+            .then(|| variable("self.spawned".to_owned(), false_value()));
+        let data = SpriteData {
+            lists,
+            fields,
+            spawned,
+        };
+
+        // Procedures are declared first, so they can be called before their definition.
+        let graph = CallGraph::new(&sprite.procedures);
+        let mut procedures = HashMap::new();
+        for procedure in sprite.procedures.iter() {
+            let mut arguments = Vec::new();
+            for (ii, input) in procedure.inputs.iter().enumerate() {
+                for (fi, field) in input.flatten().iter().enumerate() {
+                    let arg_name = format!("__arg_{ii}:{fi}");
+                    let ty = self
+                        .refine_datatype_into_procargtype(field)
+                        .map_err(|kind| RefineError {
+                            kind: RefineErrorKind::Malformed(kind),
+                            context: Box::new(MirContext {
+                                sprite: sprite.name.clone(),
+                                procedure: Some(procedure.name.clone()),
+                                statement: None,
+                            }),
+                        })?;
+                    arguments.push((arg_name, ty));
+                }
+            }
+            if self.has_depth(&procedure.name, &graph) {
+                arguments.push((
+                    DEPTH_ARGUMENT.to_owned(),
+                    codegen::ProcedureArgumentType::NumberOrText,
+                ));
+            }
+            let returns_with_block = self.returns_with_block(procedure.returns.as_ref());
+            sb.declare_procedure(
+                &procedure.name,
+                arguments,
+                procedure.is_warp,
+                returns_with_block,
+            );
+            // Every sprite has Its own return variables, so clones calling the same
+            // procedure don't overwrite values returned to each other.
+            if let Some(returns) = procedure.returns.as_ref().filter(|_| !returns_with_block) {
+                let variable = return_variable(&variable_prefix(&procedure.name));
+                for (id, name) in components(variable, returns) {
+                    sb.add_variable(id, name);
+                }
+            }
+            procedures.insert(procedure.name.as_str(), procedure);
+        }
+
         for procedure in sprite.procedures.iter() {
-            self.refine_procedure(procedure, &sb);
+            self.refine_procedure(sprite, procedure, &procedures, &graph, &data, &sb)?;
+        }
+        for handler in sprite.handlers.iter() {
+            self.refine_handler(sprite, handler, &procedures, &graph, &data, &sb)?;
+        }
+        // Clones take their values even when nothing handles their start.
+        let clone_started = |handler: &EventHandler| handler.event == Event::CloneStarted;
+        if data.spawned.is_some() && !sprite.handlers.iter().any(clone_started) {
+            let mut bb = sb.blocks_builder();
+            bb.block("control_start_as_clone", false);
+            self.take_spawned_values(sprite, &data, &mut bb);
+            bb.end_stack();
         }
 
-        sb
+        Ok(sb)
     }
 
-    fn refine_procedure(&self, procedure: &Procedure, sb: &codegen::SpriteBuilder) {
-        let mut arguments = HashMap::new();
+    /// Creates the hat block of the event which calls the handler's procedure.
+    fn refine_handler(
+        &self,
+        sprite: &Sprite,
+        handler: &EventHandler,
+        procedures: &HashMap<&str, &Procedure>,
+        graph: &CallGraph<'_>,
+        data: &SpriteData,
+        sb: &codegen::SpriteBuilder,
+    ) -> Result<(), RefineError> {
+        let error = |kind| RefineError {
+            kind: RefineErrorKind::Malformed(kind),
+            context: Box::new(MirContext {
+                sprite: sprite.name.clone(),
+                procedure: Some(handler.procedure.clone()),
+                statement: None,
+            }),
+        };
+        let Some(procedure) = procedures.get(handler.procedure.as_str()) else {
+            let kind = VerifyErrorKind::UndefinedProcedure(handler.procedure.clone());
+            return Err(error(kind));
+        };
+        if !procedure.inputs.is_empty() {
+            return Err(error(VerifyErrorKind::ArgumentCount {
+                callee: handler.procedure.clone(),
+                expected: procedure.inputs.len(),
+                found: 0,
+            }));
+        }
 
-        for (ii, input) in procedure.inputs.iter().enumerate() {
-            for (fi, field) in input.flatten().iter().enumerate() {
-                let arg_name = format!("__arg_{ii}:{fi}");
-                arguments.insert(arg_name, self.refine_datatype_into_procargtype(field));
+        let mut bb = sb.blocks_builder();
+        let opcode = match &handler.event {
+            Event::FlagClicked => "event_whenflagclicked",
+            Event::BroadcastReceived(_) => "event_whenbroadcastreceived",
+            Event::KeyPressed(_) => "event_whenkeypressed",
+            Event::Clicked if sprite.is_stage => "event_whenstageclicked",
+            Event::Clicked => "event_whenthisspriteclicked",
+            Event::CloneStarted => "control_start_as_clone",
+            Event::BackdropSwitched(_) => "event_whenbackdropswitchesto",
+            Event::Threshold(..) => "event_whengreaterthan",
+        };
+        let mut b = bb.block(opcode, false);
+        match &handler.event {
+            Event::BroadcastReceived(name) => {
+                let field = pawgen::schema::BlockField::Variable(name.clone(), name.clone());
+                b.set_field("BROADCAST_OPTION", field);
+            }
+            Event::KeyPressed(key) => {
+                let field = pawgen::schema::BlockField::Argument(key.clone());
+                b.set_field("KEY_OPTION", field);
+            }
+            Event::BackdropSwitched(name) => {
+                let field = pawgen::schema::BlockField::Argument(name.clone());
+                b.set_field("BACKDROP", field);
             }
+            Event::Threshold(sensor, value) => {
+                let field = pawgen::schema::BlockField::Argument(sensor.as_str().to_owned());
+                b.set_field("WHENGREATERTHANMENU", field);
+                b.set_input("VALUE", &[pawgen::schema::Value::Number(*value)]);
+            }
+            Event::FlagClicked | Event::Clicked | Event::CloneStarted => {}
+        }
+        b.finish();
+        if handler.event == Event::CloneStarted {
+            self.take_spawned_values(sprite, data, &mut bb);
+        }
+
+        let mut arguments = Vec::new();
+        if self.has_depth(&procedure.name, graph) {
+            arguments.push(pawgen::schema::Value::Number(0.0));
         }
+        bb.call_procedure(&procedure.name, &arguments);
+        bb.end_stack();
+        Ok(())
+    }
+
+    /// Moves values the clone was spawned with from the hand-off lists into
+    /// Its fields. Every script started with the clone does this, but only the
+    /// first one takes the values. Clones start in order of their creation,
+    /// so they take values in the same order they were spawned with.
+    fn take_spawned_values(
+        &self,
+        sprite: &Sprite,
+        data: &SpriteData,
+        bb: &mut codegen::BlocksBuilder,
+    ) {
+        let Some(spawned) = &data.spawned else {
+            return;
+        };
+        let taken = is_set(spawned, bb);
+        let condition = reporter(bb, "operator_not", [("OPERAND", taken)]);
+        let lists = hand_off_lists(&sprite.name, &self.clones[&sprite.name]);
+        bb.control_if(
+            |bb| into_condition(condition, bb),
+            |bb| {
+                set_flag(spawned, bb);
+                for (field, list) in data.fields.iter().flatten().zip(lists.iter()) {
+                    let mut block = bb.block("data_itemoflist", true);
+                    block
+                        .set_input("INDEX", &[pawgen::schema::Value::Number(1.0)])
+                        .set_field("LIST", list_field(list));
+                    let item = pawgen::schema::Value::Pointer(block.finish());
+                    field.write(item, bb);
+                    bb.block("data_deleteoflist", false)
+                        .set_input("INDEX", &[pawgen::schema::Value::Number(1.0)])
+                        .set_field("LIST", list_field(list));
+                }
+            },
+        );
+    }
+
+    fn refine_procedure(
+        &self,
+        sprite: &Sprite,
+        procedure: &Procedure,
+        procedures: &HashMap<&str, &Procedure>,
+        graph: &CallGraph<'_>,
+        data: &SpriteData,
+        sb: &codegen::SpriteBuilder,
+    ) -> Result<(), RefineError> {
+        let prefix = variable_prefix(&procedure.name);
+        let recursive = self.has_depth(&procedure.name, graph);
+        // Variables can't be created while blocks are built, so locals are
+        // created before and temporaries after the blocks.
+        let locals = self.allocate_locals(procedure, &prefix, recursive, sb);
 
         let mut bb = sb.blocks_builder();
-        bb.define_procedure(&procedure.name, arguments, procedure.is_warp);
+        bb.define_procedure(&procedure.name);
 
-        self.refine_codeblock(&procedure.block, &mut bb);
+        let mut cx = ProcedureCx {
+            sprite: &sprite.name,
+            name: &procedure.name,
+            prefix,
+            returns: procedure.returns.clone(),
+            procedures,
+            graph,
+            thread_variables: self.config.use_thread_variables,
+            recursive,
+            locals,
+            lists: &data.lists,
+            fields: &data.fields,
+            spawned: data.spawned.as_ref(),
+            loops: Vec::new(),
+            loop_count: 0,
+            temporary_count: 0,
+            temporary_variables: RefCell::default(),
+            tail: true,
+        };
+        self.refine_codeblock(&procedure.block, &mut cx, &mut bb)?;
 
         bb.end_stack();
+        drop(bb);
+        for name in cx.temporary_variables.into_inner() {
+            sb.add_variable(&name, &name);
+        }
+        Ok(())
+    }
+
+    /// Whether procedure passes depth of Its calls to Itself, so Its thread variables
+    /// are separate for every call.
+    fn has_depth(&self, procedure: &str, graph: &CallGraph<'_>) -> bool {
+        self.config.use_thread_variables && graph.is_recursive(procedure)
     }
 
-    fn refine_codeblock(&self, codeblock: &CodeBlock, bb: &mut codegen::BlocksBuilder) {
-        for stmt in codeblock.code.iter() {
-            self.refine_stmt(stmt, bb);
+    /// Creates variables for locals of the procedure, returns variables of every local.
+    /// Variables are named after locals stored in them.
+    fn allocate_locals(
+        &self,
+        procedure: &Procedure,
+        prefix: &str,
+        recursive: bool,
+        sb: &codegen::SpriteBuilder,
+    ) -> Vec<Vec<Storage>> {
+        let allocation = allocator::allocate(procedure);
+        let mut names: HashMap<String, usize> = HashMap::new();
+        let slots: Vec<Vec<Storage>> = allocation
+            .slots
+            .iter()
+            .map(|slot| {
+                let mut locals: Vec<&str> = Vec::new();
+                for local in slot.locals.iter() {
+                    let name = procedure.locals[*local].name.as_str();
+                    if !locals.contains(&name) {
+                        locals.push(name);
+                    }
+                }
+                let mut name = format!("{prefix}.{}", locals.join("/"));
+                // Shadowed locals may need separate slots.
+                let count = names.entry(name.clone()).or_default();
+                if *count > 0 {
+                    name = format!("{name}#{count}");
+                }
+                *count += 1;
+
+                (0..slot.size)
+                    .map(|i| {
+                        let name = match slot.size {
+                            1 => name.clone(),
+                            _ => format!("{name}:{i}"),
+                        };
+                        if self.config.use_thread_variables {
+                            return Storage::Thread { name, recursive };
+                        }
+                        let pawgen::schema::Value::Variable(id, name) =
+                            sb.make_variable(name, pawgen::schema::Value::Number(0.0))
+                        else {
+                            unreachable!()
+                        };
+                        Storage::Variable((id, name))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        procedure
+            .locals
+            .iter()
+            .zip(allocation.local_slots)
+            .map(|(local, slot)| slots[slot][..local.dt.calculate_size()].to_vec())
+            .collect()
+    }
+
+    /// Whether value of the type is returned with Turbowarp's return block.
+    fn returns_with_block(&self, returns: Option<&DataType>) -> bool {
+        self.config.use_return_blocks && returns.is_some_and(DataType::is_primitive)
+    }
+
+    fn refine_codeblock(
+        &self,
+        codeblock: &CodeBlock,
+        cx: &mut ProcedureCx<'_>,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<(), RefineError> {
+        self.refine_statements(&codeblock.code, cx, bb)
+    }
+
+    fn refine_statements(
+        &self,
+        statements: &[Statement],
+        cx: &mut ProcedureCx<'_>,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<(), RefineError> {
+        let tail = cx.tail;
+        for (i, stmt) in statements.iter().enumerate() {
+            let rest = &statements[i + 1..];
+            cx.tail = tail && rest.is_empty();
+            match stmt {
+                Statement::ProcedureCall(name, args) => {
+                    // Reporters can't be placed into the stack on their own.
+                    if let Some(DataValue::Primitive(value @ pawgen::schema::Value::Pointer(_))) =
+                        self.refine_call(name, args, false, cx, bb)?
+                    {
+                        cx.temporary("discard").write(value, bb);
+                    }
+                }
+                stmt => {
+                    self.refine_stmt(stmt, cx, bb)?;
+                }
+            }
+
+            // Nothing after those is ever executed.
+            match stmt {
+                Statement::Break | Statement::Continue | Statement::Return(_) => break,
+                Statement::Forever(body) if !find_jumps(&body.code).breaks => break,
+                _ => {}
+            }
+
+            // Rest of the iteration is skipped after `break` or `continue`.
+            if !rest.is_empty() && self.may_skip(stmt, cx) {
+                cx.tail = tail;
+                let skip = cx.loops.last().unwrap().skip_flag.clone().unwrap();
+                let condition = is_set(&skip, bb);
+                let condition = reporter(bb, "operator_not", [("OPERAND", condition)]);
+                let deferred = Deferred::default();
+                bb.control_if(
+                    |bb| into_condition(condition, bb),
+                    |bb| deferred.run(|| self.refine_statements(rest, cx, bb)),
+                );
+                deferred.finish()?;
+                break;
+            }
         }
+        cx.tail = tail;
+        Ok(())
     }
 
-    fn refine_stmt(&self, stmt: &Statement, bb: &mut codegen::BlocksBuilder) -> Option<DataValue> {
-        match stmt {
+    /// Whether statement may skip rest of the innermost loop's iteration.
+    fn may_skip(&self, stmt: &Statement, cx: &ProcedureCx<'_>) -> bool {
+        let Some(current) = cx.loops.last() else {
+            return false;
+        };
+        if !matches!(stmt, Statement::If(..) | Statement::Match(..)) {
+            return false;
+        }
+        let jumps = find_jumps(std::slice::from_ref(stmt));
+        jumps.continues || (jumps.breaks && current.break_flag.is_some())
+    }
+
+    fn refine_stmt(
+        &self,
+        stmt: &Statement,
+        cx: &mut ProcedureCx<'_>,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<Option<DataValue>, RefineError> {
+        let value = match stmt {
             Statement::Constant(value) => Some(DataValue::Primitive(value.clone())),
             Statement::BlockCall(id, args) => {
-                let def = self.block_definitions.get(id);
-                let call_values: Vec<_> = args
-                    .iter()
-                    .map(|arg| {
-                        self.refine_stmt(arg, bb)
-                            .expect("Arguments should be values")
-                            .into_primitive(bb)
-                    })
-                    .collect();
+                let Some(def) = self.block_definitions.get(id) else {
+                    return Err(cx.error(stmt, VerifyErrorKind::UndefinedBlock(id.clone())));
+                };
+                if args.len() != def.inputs.len() {
+                    return Err(cx.error(
+                        stmt,
+                        VerifyErrorKind::ArgumentCount {
+                            callee: id.clone(),
+                            expected: def.inputs.len(),
+                            found: args.len(),
+                        },
+                    ));
+                }
+                let mut call_values = Vec::with_capacity(args.len());
+                for (arg, input) in args.iter().zip(def.inputs.iter()) {
+                    let value = self.refine_value(arg, cx, bb)?;
+                    let value = value
+                        .into_primitive(bb)
+                        .map_err(|kind| cx.error(arg, kind))?;
+                    call_values.push(match input.ty {
+                        DataType::Boolean => {
+                            pawgen::schema::Value::Pointer(into_condition(value, bb))
+                        }
+                        _ => value,
+                    });
+                }
                 let mut b = bb.block(&def.opcode, def.is_expression);
 
                 for (input, value) in def.inputs.iter().zip(call_values) {
@@ -132,95 +638,1393 @@ impl MirRefinery {
                 )))
             }
             Statement::ArgumentRef(index, dt) => Some(DataValue::Argument(*index, dt.clone())),
-            Statement::VariableRef(id, name, dt) => {
-                Some(DataValue::Variable(id.clone(), name.clone(), dt.clone()))
+            Statement::VariableRef(id, name, dt) => Some(DataValue::Variable(
+                components((id.clone(), name.clone()), dt)
+                    .into_iter()
+                    .map(Storage::Variable)
+                    .collect(),
+                dt.clone(),
+            )),
+            Statement::Variant(variant, payload, dt) => {
+                if !matches!(dt, DataType::Enum(_)) {
+                    return Err(cx.error(stmt, unexpected_type("enum", dt)));
+                }
+                let payload = match payload {
+                    Some(payload) => Some(Box::new(self.refine_value(payload, cx, bb)?)),
+                    None => None,
+                };
+                Some(DataValue::Variant(*variant, payload, dt.clone()))
+            }
+            Statement::Payload(target, variant, dt) => {
+                let DataType::Enum(variants) = dt else {
+                    return Err(cx.error(stmt, unexpected_type("enum", dt)));
+                };
+                let Some(payload) = variants.get(*variant) else {
+                    return Err(cx.error(
+                        stmt,
+                        VerifyErrorKind::VariantOutOfRange {
+                            index: *variant,
+                            count: variants.len(),
+                        },
+                    ));
+                };
+                // Payload follows the tag.
+                let target = self.refine_value(target, cx, bb)?;
+                Some(DataValue::slots(target, 1, payload.clone()))
+            }
+            Statement::Let(_) => None,
+            Statement::LocalRef(local, dt) => {
+                let Some(variables) = cx.locals.get(*local) else {
+                    return Err(cx.error(stmt, VerifyErrorKind::UndefinedLocal(*local)));
+                };
+                Some(DataValue::Variable(variables.clone(), dt.clone()))
             }
             Statement::StructureLiteral(values, dt) => {
                 Some(DataValue::StructureLiteral(values.clone(), dt.clone()))
             }
-            Statement::FieldRef(target, index, dt) => Some(DataValue::Field(
-                Box::new(self.refine_stmt(target, bb).unwrap()),
-                *index,
-                dt.clone(),
-            )),
-            Statement::Assignment(target, value) => {
-                let (id, name, dt) = match self.refine_stmt(&*target, bb).unwrap() {
-                    DataValue::Variable(id, name, dt) => (id, name, dt),
-                    ref v @ DataValue::Field(_, index, ref sdt) => {
-                        let pawgen::schema::Value::Variable(id, name) =
-                            v.clone().into_primitive(bb)
-                        else {
-                            unreachable!()
-                        };
-                        (id, name, sdt.clone().unwrap_structure()[index].clone())
-                    }
-                    _ => unreachable!(),
+            Statement::Structure(fields, dt) => {
+                let DataType::Structure(types) = dt else {
+                    return Err(cx.error(stmt, unexpected_type("structure", dt)));
                 };
-
-                if dt.is_primitive() {
-                    self.write_primitive_variable(
-                        id,
-                        name,
-                        self.refine_stmt(value, bb).unwrap(),
+                if fields.len() != types.len() {
+                    let (expected, found) = (types.len(), fields.len());
+                    return Err(cx.error(stmt, VerifyErrorKind::FieldCount { expected, found }));
+                }
+                // Fields may be reporters, which can't be read more than once.
+                let name = cx.next_temporary("struct");
+                let variables = cx.temporaries(&name, dt);
+                let mut offset = 0;
+                for (field, ty) in fields.iter().zip(types.iter()) {
+                    let value = self.refine_value(field, cx, bb)?;
+                    let size = ty.calculate_size();
+                    self.write_value(&variables[offset..offset + size], ty, value, bb)
+                        .map_err(|kind| cx.error(field, kind))?;
+                    offset += size;
+                }
+                Some(DataValue::Variable(variables, dt.clone()))
+            }
+            Statement::Equals(lhs, rhs, dt) => {
+                let lhs = self.refine_value(lhs, cx, bb)?;
+                let rhs = self.refine_value(rhs, cx, bb)?;
+                let mut equals = None;
+                for slot in 0..dt.calculate_size() {
+                    let lhs = lhs.slot(slot, bb).map_err(|kind| cx.error(stmt, kind))?;
+                    let rhs = rhs.slot(slot, bb).map_err(|kind| cx.error(stmt, kind))?;
+                    let slot = reporter(
                         bb,
+                        "operator_equals",
+                        [("OPERAND1", lhs), ("OPERAND2", rhs)],
                     );
-                } else {
-                    let rv = self.refine_stmt(value, bb).unwrap();
-                    let rvdt = rv.get_data_type();
-
-                    let is_suffixed = id.contains(":");
-                    let id_prefix = if is_suffixed {
-                        id.split(":").next().unwrap()
-                    } else {
-                        &id
-                    };
-                    let name_prefix = if is_suffixed {
-                        id.split(":").next().unwrap()
-                    } else {
-                        &name
-                    };
-                    let start_index: usize = if is_suffixed {
-                        id.split(":").last().unwrap().parse().unwrap()
-                    } else {
-                        0
+                    equals = Some(match equals {
+                        Some(equals) => reporter(
+                            bb,
+                            "operator_and",
+                            [("OPERAND1", equals), ("OPERAND2", slot)],
+                        ),
+                        None => slot,
+                    });
+                }
+                // Empty structures are always equal.
+                let equals =
+                    equals.unwrap_or_else(|| pawgen::schema::Value::Text("true".to_owned()));
+                Some(DataValue::Primitive(equals))
+            }
+            Statement::FieldRef(target, index, dt) => {
+                let DataType::Structure(fields) = dt else {
+                    return Err(cx.error(stmt, unexpected_type("structure", dt)));
+                };
+                let Some(field) = fields.get(*index) else {
+                    return Err(cx.error(
+                        stmt,
+                        VerifyErrorKind::FieldOutOfRange {
+                            index: *index,
+                            count: fields.len(),
+                        },
+                    ));
+                };
+                let offset = fields[..*index].iter().map(DataType::calculate_size).sum();
+                let target = self.refine_value(target, cx, bb)?;
+                Some(DataValue::slots(target, offset, field.clone()))
+            }
+            Statement::Assignment(target, value) => {
+                let place = self.refine_value(target, cx, bb)?;
+                let (offset, variables, dt) = match place {
+                    DataValue::Variable(variables, dt) => (0, variables, dt),
+                    DataValue::Slots(place, offset, dt) => match *place {
+                        DataValue::Variable(variables, _) => (offset, variables, dt),
+                        _ => return Err(cx.error(stmt, VerifyErrorKind::NotPlace)),
+                    },
+                    _ => return Err(cx.error(stmt, VerifyErrorKind::NotPlace)),
+                };
+
+                // Return variables of the callee are only read by this assignment.
+                let value = match value.as_ref() {
+                    Statement::ProcedureCall(callee, args) => self
+                        .refine_call(callee, args, false, cx, bb)?
+                        .ok_or_else(|| cx.error(value, VerifyErrorKind::NotValue))?,
+                    value => self.refine_value(value, cx, bb)?,
+                };
+                self.write_value(
+                    &variables[offset..offset + dt.calculate_size()],
+                    &dt,
+                    value,
+                    bb,
+                )
+                .map_err(|kind| cx.error(stmt, kind))?;
+
+                None
+            }
+            Statement::ProcedureCall(name, args) => self.refine_call(name, args, true, cx, bb)?,
+            Statement::If(condition, then, otherwise) => {
+                let condition = self.refine_condition(condition, cx, bb)?;
+                let condition = into_condition(condition, bb);
+                let deferred = Deferred::default();
+                match otherwise {
+                    None => bb.control_if(
+                        |_| condition,
+                        |bb| deferred.run(|| self.refine_codeblock(then, cx, bb)),
+                    ),
+                    Some(otherwise) => {
+                        // Both branches need mutable state, so It is passed between them.
+                        let cx = RefCell::new(&mut *cx);
+                        bb.control_if_else(
+                            |_| condition,
+                            |bb| {
+                                deferred
+                                    .run(|| self.refine_codeblock(then, &mut cx.borrow_mut(), bb))
+                            },
+                            |bb| {
+                                deferred.run(|| {
+                                    self.refine_codeblock(otherwise, &mut cx.borrow_mut(), bb)
+                                })
+                            },
+                        )
+                    }
+                }
+                deferred.finish()?;
+                None
+            }
+            Statement::Select(condition, then, otherwise, dt) => {
+                let name = cx.next_temporary("select");
+                let variables = cx.temporaries(&name, dt);
+                let condition = self.refine_condition(condition, cx, bb)?;
+                let condition = into_condition(condition, bb);
+                let deferred = Deferred::default();
+                let cx = RefCell::new(&mut *cx);
+                let branch = |value: &Statement, bb: &mut codegen::BlocksBuilder| {
+                    deferred.run(|| {
+                        let refined = self.refine_value(value, &mut cx.borrow_mut(), bb)?;
+                        self.write_value(&variables, dt, refined, bb)
+                            .map_err(|kind| cx.borrow().error(value, kind))
+                    })
+                };
+                bb.control_if_else(
+                    |_| condition,
+                    |bb| branch(then, bb),
+                    |bb| branch(otherwise, bb),
+                );
+                deferred.finish()?;
+                Some(DataValue::Variable(variables, dt.clone()))
+            }
+            Statement::Match(value, arms, otherwise) => {
+                let value = self.refine_value(value, cx, bb)?;
+                let mut arms: Vec<(usize, &CodeBlock)> =
+                    arms.iter().map(|(variant, arm)| (*variant, arm)).collect();
+                arms.sort_by_key(|(variant, _)| *variant);
+                self.refine_match(stmt, &value, &arms, otherwise.as_ref(), cx, bb)?;
+                None
+            }
+            Statement::While(..) | Statement::Repeat(..) | Statement::Forever(..) => {
+                self.refine_loop(stmt, cx, bb)?;
+                None
+            }
+            Statement::Broadcast(name, wait) => {
+                let opcode = match wait {
+                    true => "event_broadcastandwait",
+                    false => "event_broadcast",
+                };
+                let broadcast = pawgen::schema::Value::Broadcast(name.clone(), name.clone());
+                bb.block(opcode, false)
+                    .set_input("BROADCAST_INPUT", &[broadcast]);
+                None
+            }
+            Statement::Spawn(name, values) => {
+                let Some(fields) = self.clones.get(name) else {
+                    return Err(cx.error(stmt, VerifyErrorKind::UndefinedSprite(name.clone())));
+                };
+                if values.len() != fields.len() {
+                    return Err(cx.error(
+                        stmt,
+                        VerifyErrorKind::ArgumentCount {
+                            callee: name.clone(),
+                            expected: fields.len(),
+                            found: values.len(),
+                        },
+                    ));
+                }
+                let lists = hand_off_lists(name, fields);
+                let mut slots = Vec::with_capacity(lists.len());
+                for (value, dt) in values.iter().zip(fields) {
+                    let value = self.refine_value(value, cx, bb)?;
+                    for i in 0..dt.calculate_size() {
+                        slots.push(value.slot(i, bb).map_err(|kind| cx.error(stmt, kind))?);
+                    }
+                }
+                // The clone takes values from the lists when It starts.
+                for (list, slot) in lists.iter().zip(slots) {
+                    bb.block("data_addtolist", false)
+                        .set_input("ITEM", &[slot])
+                        .set_field("LIST", list_field(list));
+                }
+                // Clones copy variables of the sprite creating them, so a clone spawning
+                // another one clears Its flag for the new clone to take Its values.
+                let spawned = cx.spawned.filter(|_| name == cx.sprite);
+                if let Some(spawned) = spawned {
+                    spawned.write(false_value(), bb);
+                }
+                bb.control_create_clone_of(name);
+                if let Some(spawned) = spawned {
+                    set_flag(spawned, bb);
+                }
+                None
+            }
+            Statement::Break => {
+                let Some(current) = cx.loops.last() else {
+                    return Err(cx.error(stmt, VerifyErrorKind::JumpOutsideLoop));
+                };
+                match &current.break_flag {
+                    // Nothing runs after the loop, so the script can be stopped.
+                    None => bb.control_stop("this script"),
+                    Some(flag) => {
+                        let skip = current.skip_flag.clone().unwrap();
+                        set_flag(flag, bb);
+                        set_flag(&skip, bb);
+                    }
+                }
+                None
+            }
+            Statement::Continue => {
+                let Some(current) = cx.loops.last() else {
+                    return Err(cx.error(stmt, VerifyErrorKind::JumpOutsideLoop));
+                };
+                set_flag(current.skip_flag.as_ref().unwrap(), bb);
+                None
+            }
+            Statement::Return(value) => {
+                if let Some(value) = value {
+                    let Some(dt) = cx.returns.clone() else {
+                        return Err(cx.error(stmt, VerifyErrorKind::UnexpectedReturnValue));
                     };
+                    if self.returns_with_block(Some(&dt)) {
+                        // Return block also stops the procedure.
+                        let value = self
+                            .refine_value(value, cx, bb)?
+                            .into_primitive(bb)
+                            .map_err(|kind| cx.error(stmt, kind))?;
+                        bb.procedure_return(value);
+                        return Ok(None);
+                    }
+                    let (id, name) = return_variable(&cx.prefix);
+                    let target = Statement::VariableRef(id, name, dt);
+                    self.refine_stmt(
+                        &Statement::Assignment(Box::new(target), value.clone()),
+                        cx,
+                        bb,
+                    )?;
+                }
+                if !cx.tail {
+                    bb.control_stop("this script");
+                }
+                None
+            }
+            Statement::SelfField(index, dt) => {
+                let Some(field) = cx.fields.get(*index) else {
+                    return Err(cx.error(stmt, VerifyErrorKind::UndefinedField(*index)));
+                };
+                Some(DataValue::Variable(field.clone(), dt.clone()))
+            }
+            Statement::ListRef(list, dt) => {
+                let Some(lists) = cx.lists.get(*list) else {
+                    return Err(cx.error(stmt, VerifyErrorKind::UndefinedList(*list)));
+                };
+                Some(DataValue::List(lists.clone(), dt.clone()))
+            }
+            Statement::List(target, operation) => {
+                self.refine_list(stmt, target, operation, cx, bb)?
+            }
+        };
+        Ok(value)
+    }
+
+    /// Refines statement used as a value.
+    fn refine_value(
+        &self,
+        stmt: &Statement,
+        cx: &mut ProcedureCx<'_>,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<DataValue, RefineError> {
+        self.refine_stmt(stmt, cx, bb)?
+            .ok_or_else(|| cx.error(stmt, VerifyErrorKind::NotValue))
+    }
+
+    /// Refines operation on the list, lists of structures repeat It for list of
+    /// every flattened field.
+    fn refine_list(
+        &self,
+        stmt: &Statement,
+        target: &Statement,
+        operation: &ListOperation,
+        cx: &mut ProcedureCx<'_>,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<Option<DataValue>, RefineError> {
+        let Some(DataValue::List(lists, DataType::List(item))) =
+            self.refine_stmt(target, cx, bb)?
+        else {
+            return Err(cx.error(stmt, VerifyErrorKind::NotList));
+        };
+        let length = |bb: &mut codegen::BlocksBuilder| {
+            let mut block = bb.block("data_lengthoflist", true);
+            block.set_field("LIST", list_field(&lists[0]));
+            pawgen::schema::Value::Pointer(block.finish())
+        };
+
+        let value = match operation {
+            ListOperation::Push(value) => {
+                let value = self.refine_value(value, cx, bb)?;
+                for (i, list) in lists.iter().enumerate() {
+                    let field = value.slot(i, bb).map_err(|kind| cx.error(stmt, kind))?;
+                    bb.block("data_addtolist", false)
+                        .set_input("ITEM", &[field])
+                        .set_field("LIST", list_field(list));
+                }
+                None
+            }
+            ListOperation::Pop => {
+                // Items are copied before they are removed.
+                let value = self.read_items(&lists, &item, |_| last_index(), cx, bb);
+                for list in lists.iter() {
+                    bb.block("data_deleteoflist", false)
+                        .set_input("INDEX", &[last_index()])
+                        .set_field("LIST", list_field(list));
+                }
+                Some(value)
+            }
+            ListOperation::Index(index) => {
+                let index = self.refine_index(index, &lists, false, cx, bb)?;
+                if item.is_primitive() {
+                    let index = scratch_index(&index, bb);
+                    let mut block = bb.block("data_itemoflist", true);
+                    block
+                        .set_input("INDEX", &[index])
+                        .set_field("LIST", list_field(&lists[0]));
+                    return Ok(Some(DataValue::Primitive(pawgen::schema::Value::Pointer(
+                        block.finish(),
+                    ))));
+                }
+                let index = |bb: &mut codegen::BlocksBuilder| scratch_index(&index, bb);
+                Some(self.read_items(&lists, &item, index, cx, bb))
+            }
+            ListOperation::Length => Some(DataValue::Primitive(length(bb))),
+            ListOperation::Insert(index, value) => {
+                let index = self.refine_index(index, &lists, true, cx, bb)?;
+                let value = self.refine_value(value, cx, bb)?;
+                for (i, list) in lists.iter().enumerate() {
+                    let field = value.slot(i, bb).map_err(|kind| cx.error(stmt, kind))?;
+                    let index = scratch_index(&index, bb);
+                    bb.block("data_insertatlist", false)
+                        .set_input("ITEM", &[field])
+                        .set_input("INDEX", &[index])
+                        .set_field("LIST", list_field(list));
+                }
+                None
+            }
+            ListOperation::Delete(index) => {
+                let index = self.refine_index(index, &lists, false, cx, bb)?;
+                for list in lists.iter() {
+                    let index = scratch_index(&index, bb);
+                    bb.block("data_deleteoflist", false)
+                        .set_input("INDEX", &[index])
+                        .set_field("LIST", list_field(list));
+                }
+                None
+            }
+            ListOperation::Contains(value) => {
+                let value = self.refine_value(value, cx, bb)?;
+                if item.is_primitive() {
+                    let value = value
+                        .into_primitive(bb)
+                        .map_err(|kind| cx.error(stmt, kind))?;
+                    let mut block = bb.block("data_listcontainsitem", true);
+                    block
+                        .set_input("ITEM", &[value])
+                        .set_field("LIST", list_field(&lists[0]));
+                    return Ok(Some(DataValue::Primitive(pawgen::schema::Value::Pointer(
+                        block.finish(),
+                    ))));
+                }
+
+                // Structures are compared field by field with every item.
+                let name = cx.next_temporary("contains");
+                let searched = cx.temporaries(&name, &item);
+                if searched.is_empty() {
+                    let kind = unexpected_type("non-empty structure", &item);
+                    return Err(cx.error(stmt, kind));
+                }
+                self.write_value(&searched, &item, value, bb)
+                    .map_err(|kind| cx.error(stmt, kind))?;
+                let counter = cx.temporary(&format!("{name}.index"));
+                let found = cx.temporary(&format!("{name}.found"));
+                counter.write(pawgen::schema::Value::Number(0.0), bb);
+                found.write(false_value(), bb);
 
-                    for (i, _) in dt.flatten().iter().enumerate() {
-                        self.write_primitive_variable(
-                            format!("{id_prefix}:{}", start_index + i),
-                            format!("{name_prefix}:{}", start_index + i),
-                            DataValue::Field(Box::new(rv.clone()), i, rvdt.clone()),
+                let times = length(bb);
+                bb.control_repeat(
+                    |_| times,
+                    |bb| {
+                        let current = counter.read(bb);
+                        let next = reporter(
                             bb,
+                            "operator_add",
+                            [("NUM1", current), ("NUM2", 1f64.into())],
                         );
+                        counter.write(next, bb);
+
+                        let mut condition = None;
+                        for (list, field) in lists.iter().zip(searched.iter()) {
+                            let index = counter.read(bb);
+                            let mut block = bb.block("data_itemoflist", true);
+                            block
+                                .set_input("INDEX", &[index])
+                                .set_field("LIST", list_field(list));
+                            let item = pawgen::schema::Value::Pointer(block.finish());
+                            let field = field.read(bb);
+                            let equals = reporter(
+                                bb,
+                                "operator_equals",
+                                [("OPERAND1", item), ("OPERAND2", field)],
+                            );
+                            condition = Some(match condition {
+                                Some(condition) => reporter(
+                                    bb,
+                                    "operator_and",
+                                    [("OPERAND1", condition), ("OPERAND2", equals)],
+                                ),
+                                None => equals,
+                            });
+                        }
+                        if let Some(condition) = condition {
+                            bb.control_if(
+                                |bb| into_condition(condition, bb),
+                                |bb| set_flag(&found, bb),
+                            );
+                        }
+                    },
+                );
+                Some(DataValue::Variable(vec![found], DataType::Boolean))
+            }
+        };
+        Ok(value)
+    }
+
+    /// Refines index of the list, index which has to be read more than once is
+    /// stored in a temporary variable. With `check_bounds` the index is checked
+    /// first, `inclusive` indices may also point right after the last item.
+    fn refine_index(
+        &self,
+        index: &Statement,
+        lists: &[VariableName],
+        inclusive: bool,
+        cx: &mut ProcedureCx<'_>,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<Index, RefineError> {
+        let value = self.refine_value(index, cx, bb)?;
+        let value = value
+            .into_primitive(bb)
+            .map_err(|kind| cx.error(index, kind))?;
+        let reused = self.config.check_bounds || lists.len() > 1;
+        let index = match value {
+            value @ pawgen::schema::Value::Pointer(_) if reused => {
+                let name = cx.next_temporary("index");
+                let variable = cx.temporary(&name);
+                variable.write(value, bb);
+                Index::Variable(variable)
+            }
+            value => Index::Value(value),
+        };
+        if !self.config.check_bounds {
+            return Ok(index);
+        }
+
+        let value = index.read(bb);
+        let negative = reporter(
+            bb,
+            "operator_lt",
+            [("OPERAND1", value), ("OPERAND2", 0f64.into())],
+        );
+        let mut block = bb.block("data_lengthoflist", true);
+        block.set_field("LIST", list_field(&lists[0]));
+        let length = pawgen::schema::Value::Pointer(block.finish());
+        let value = index.read(bb);
+        let beyond = match inclusive {
+            true => reporter(
+                bb,
+                "operator_gt",
+                [("OPERAND1", value), ("OPERAND2", length)],
+            ),
+            false => {
+                let inside = reporter(
+                    bb,
+                    "operator_lt",
+                    [("OPERAND1", value), ("OPERAND2", length)],
+                );
+                reporter(bb, "operator_not", [("OPERAND", inside)])
+            }
+        };
+        let condition = reporter(
+            bb,
+            "operator_or",
+            [("OPERAND1", negative), ("OPERAND2", beyond)],
+        );
+        bb.control_if(
+            |bb| into_condition(condition, bb),
+            |bb| bb.control_stop("all"),
+        );
+        Ok(index)
+    }
+
+    /// Copies items at the index from lists of every field into temporary variables,
+    /// so they can be read after the lists change.
+    fn read_items(
+        &self,
+        lists: &[VariableName],
+        item: &DataType,
+        index: impl Fn(&mut codegen::BlocksBuilder) -> pawgen::schema::Value,
+        cx: &mut ProcedureCx<'_>,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> DataValue {
+        let name = cx.next_temporary("item");
+        let variables = cx.temporaries(&name, item);
+        for (list, variable) in lists.iter().zip(variables.iter()) {
+            let index = index(bb);
+            let mut block = bb.block("data_itemoflist", true);
+            block
+                .set_input("INDEX", &[index])
+                .set_field("LIST", list_field(list));
+            let value = pawgen::schema::Value::Pointer(block.finish());
+            variable.write(value, bb);
+        }
+        DataValue::Variable(variables, item.clone())
+    }
+
+    /// Calls procedure of the sprite, returns value It returned.
+    /// With `temporary` the returned value is copied, so next call of the
+    /// same procedure doesn't overwrite It before It is used.
+    fn refine_call(
+        &self,
+        name: &str,
+        args: &[Statement],
+        temporary: bool,
+        cx: &mut ProcedureCx<'_>,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<Option<DataValue>, RefineError> {
+        let call = || Statement::ProcedureCall(name.to_owned(), args.to_vec());
+        let Some(callee) = cx.procedures.get(name).copied() else {
+            let kind = VerifyErrorKind::UndefinedProcedure(name.to_owned());
+            return Err(cx.error(&call(), kind));
+        };
+        if args.len() != callee.inputs.len() {
+            let kind = VerifyErrorKind::ArgumentCount {
+                callee: name.to_owned(),
+                expected: callee.inputs.len(),
+                found: args.len(),
+            };
+            return Err(cx.error(&call(), kind));
+        }
+
+        let mut values = Vec::new();
+        for (arg, dt) in args.iter().zip(callee.inputs.iter()) {
+            let value = self.refine_value(arg, cx, bb)?;
+            for (j, field) in dt.flatten().iter().enumerate() {
+                let value = value.slot(j, bb).map_err(|kind| cx.error(arg, kind))?;
+                values.push(match field {
+                    DataType::Boolean => pawgen::schema::Value::Pointer(into_condition(value, bb)),
+                    _ => value,
+                });
+            }
+        }
+
+        if self.has_depth(name, cx.graph) {
+            // Calls which may be nested go one level deeper.
+            let depth = if cx.recursive && cx.graph.in_same_cycle(cx.name, name) {
+                let depth = depth_reporter(bb);
+                reporter(bb, "operator_add", [("NUM1", depth), ("NUM2", 1f64.into())])
+            } else {
+                pawgen::schema::Value::Number(0.0)
+            };
+            values.push(depth);
+        }
+
+        let block = bb.call_procedure(name, &values);
+        let Some(returns) = callee.returns.clone() else {
+            return Ok(None);
+        };
+        if self.returns_with_block(Some(&returns)) {
+            return Ok(Some(DataValue::Primitive(pawgen::schema::Value::Pointer(
+                block,
+            ))));
+        }
+
+        let variables = components(return_variable(&variable_prefix(name)), &returns)
+            .into_iter()
+            .map(Storage::Variable)
+            .collect();
+        let value = DataValue::Variable(variables, returns.clone());
+        if !temporary {
+            return Ok(Some(value));
+        }
+
+        let name = cx.next_temporary("call");
+        let variables = cx.temporaries(&name, &returns);
+        self.write_value(&variables, &returns, value, bb)
+            .map_err(|kind| cx.error(&call(), kind))?;
+        Ok(Some(DataValue::Variable(variables, returns)))
+    }
+
+    /// Whether refining the value places blocks into the stack, such values
+    /// can't be re-evaluated by a reporter.
+    fn emits_stack_blocks(&self, stmt: &Statement, cx: &ProcedureCx<'_>) -> bool {
+        match stmt {
+            Statement::ProcedureCall(name, args) => {
+                !cx.procedures.get(name.as_str()).is_some_and(|callee| {
+                    callee.returns.is_some() && self.returns_with_block(callee.returns.as_ref())
+                }) || args.iter().any(|arg| self.emits_stack_blocks(arg, cx))
+            }
+            Statement::BlockCall(_, args) => {
+                args.iter().any(|arg| self.emits_stack_blocks(arg, cx))
+            }
+            Statement::Equals(lhs, rhs, _) => {
+                self.emits_stack_blocks(lhs, cx) || self.emits_stack_blocks(rhs, cx)
+            }
+            Statement::Structure(..) | Statement::Select(..) => true,
+            Statement::FieldRef(target, ..) | Statement::Payload(target, ..) => {
+                self.emits_stack_blocks(target, cx)
+            }
+            Statement::Variant(_, payload, _) => payload
+                .as_ref()
+                .is_some_and(|payload| self.emits_stack_blocks(payload, cx)),
+            Statement::List(target, operation) => {
+                // Operations on something else than a list fail to refine anyway.
+                let Statement::ListRef(_, DataType::List(item)) = target.as_ref() else {
+                    return true;
+                };
+                match operation {
+                    ListOperation::Length => false,
+                    ListOperation::Index(index) => {
+                        !item.is_primitive()
+                            || self.config.check_bounds
+                            || self.emits_stack_blocks(index, cx)
                     }
+                    ListOperation::Contains(value) => {
+                        !item.is_primitive() || self.emits_stack_blocks(value, cx)
+                    }
+                    _ => true,
                 }
+            }
+            _ => false,
+        }
+    }
 
-                None
+    /// Refines arms sorted by their variant. Few arms are checked one by one,
+    /// more of them are found with a binary search on the tag.
+    fn refine_match(
+        &self,
+        stmt: &Statement,
+        value: &DataValue,
+        arms: &[(usize, &CodeBlock)],
+        otherwise: Option<&CodeBlock>,
+        cx: &mut ProcedureCx<'_>,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<(), RefineError> {
+        let compare = |bb: &mut codegen::BlocksBuilder, opcode: &str, variant: usize| {
+            let tag = value.slot(0, bb).map_err(|kind| cx.error(stmt, kind))?;
+            let condition = reporter(
+                bb,
+                opcode,
+                [
+                    ("OPERAND1", tag),
+                    ("OPERAND2", pawgen::schema::Value::Number(variant as f64)),
+                ],
+            );
+            Ok::<_, RefineError>(into_condition(condition, bb))
+        };
+
+        let (condition, then, rest) = match arms {
+            [] => {
+                if let Some(otherwise) = otherwise {
+                    self.refine_codeblock(otherwise, cx, bb)?;
+                }
+                return Ok(());
+            }
+            [(variant, arm), rest @ ..] if arms.len() <= MATCH_CHAIN_LENGTH => {
+                (compare(bb, "operator_equals", *variant)?, arm, rest)
+            }
+            _ => {
+                let (lower, upper) = arms.split_at(arms.len() / 2);
+                let condition = compare(bb, "operator_lt", upper[0].0)?;
+                let deferred = Deferred::default();
+                let cx = RefCell::new(cx);
+                bb.control_if_else(
+                    |_| condition,
+                    |bb| {
+                        deferred.run(|| {
+                            self.refine_match(
+                                stmt,
+                                value,
+                                lower,
+                                otherwise,
+                                &mut cx.borrow_mut(),
+                                bb,
+                            )
+                        })
+                    },
+                    |bb| {
+                        deferred.run(|| {
+                            self.refine_match(
+                                stmt,
+                                value,
+                                upper,
+                                otherwise,
+                                &mut cx.borrow_mut(),
+                                bb,
+                            )
+                        })
+                    },
+                );
+                return deferred.finish();
             }
+        };
+        let deferred = Deferred::default();
+        if rest.is_empty() && otherwise.is_none() {
+            bb.control_if(
+                |_| condition,
+                |bb| deferred.run(|| self.refine_codeblock(then, cx, bb)),
+            );
+            return deferred.finish();
         }
+        let cx = RefCell::new(cx);
+        bb.control_if_else(
+            |_| condition,
+            |bb| deferred.run(|| self.refine_codeblock(then, &mut cx.borrow_mut(), bb)),
+            |bb| {
+                deferred.run(|| {
+                    self.refine_match(stmt, value, rest, otherwise, &mut cx.borrow_mut(), bb)
+                })
+            },
+        );
+        deferred.finish()
     }
 
-    fn write_primitive_variable(
+    /// Refines loop, emulating `break` and `continue` with flag variables.
+    /// `break` in a loop after which procedure returns stops the script instead.
+    fn refine_loop(
         &self,
-        id: String,
-        name: String,
+        stmt: &Statement,
+        cx: &mut ProcedureCx<'_>,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<(), RefineError> {
+        let (Statement::While(_, body) | Statement::Repeat(_, body) | Statement::Forever(body)) =
+            stmt
+        else {
+            unreachable!()
+        };
+
+        let jumps = find_jumps(&body.code);
+        let uses_break_flag = jumps.breaks && !cx.tail;
+        let index = cx.loop_count;
+        cx.loop_count += 1;
+        let flag = |name: &str| cx.temporary(&format!("loop{index}.{name}"));
+        let current = LoopCx {
+            break_flag: uses_break_flag.then(|| flag("break")),
+            skip_flag: (uses_break_flag || jumps.continues).then(|| flag("skip")),
+        };
+        let counter =
+            (uses_break_flag && matches!(stmt, Statement::Repeat(..))).then(|| flag("counter"));
+        // Condition calling procedures can't be re-evaluated by a reporter, so
+        // It is stored in a variable before the loop and after every iteration.
+        let cached = match stmt {
+            Statement::While(condition, _) if self.emits_stack_blocks(condition, cx) => {
+                Some((condition, flag("condition")))
+            }
+            _ => None,
+        };
+
+        if let Some(flag) = &current.break_flag {
+            flag.write(false_value(), bb);
+        }
+
+        // Value which has to be computed before the loop starts.
+        let (value, condition) = match stmt {
+            Statement::While(condition, _) => (None, Some(condition)),
+            Statement::Repeat(times, _) => (Some(times), None),
+            Statement::Forever(_) => (None, None),
+            _ => unreachable!(),
+        };
+        let value = match value {
+            Some(times) => {
+                let times = self
+                    .refine_value(times, cx, bb)?
+                    .into_primitive(bb)
+                    .map_err(|kind| cx.error(stmt, kind))?;
+                if let Some(counter) = &counter {
+                    // Repeat rounds the number of iterations.
+                    let rounded = reporter(bb, "operator_round", [("NUM", times.clone())]);
+                    counter.write(rounded, bb);
+                }
+                Some(times)
+            }
+            None => None,
+        };
+        // Loop ends when condition is met.
+        let mut condition = match condition {
+            Some(condition) => {
+                let condition = match &cached {
+                    Some((_, variable)) => {
+                        let value = self.refine_condition(condition, cx, bb)?;
+                        variable.write(value, bb);
+                        is_set(variable, bb)
+                    }
+                    None => self.refine_condition(condition, cx, bb)?,
+                };
+                Some(reporter(bb, "operator_not", [("OPERAND", condition)]))
+            }
+            None => None,
+        };
+        if let Some(counter) = &counter {
+            let counter = counter.read(bb);
+            condition = Some(reporter(
+                bb,
+                "operator_lt",
+                [("OPERAND1", counter), ("OPERAND2", 1f64.into())],
+            ));
+        }
+        if let Some(flag) = &current.break_flag {
+            let broke = is_set(flag, bb);
+            condition = Some(match condition {
+                Some(condition) => reporter(
+                    bb,
+                    "operator_or",
+                    [("OPERAND1", condition), ("OPERAND2", broke)],
+                ),
+                None => broke,
+            });
+        }
+
+        let tail = cx.tail;
+        cx.tail = false;
+        cx.loops.push(current.clone());
+        let deferred = Deferred::default();
+        let flow = |bb: &mut codegen::BlocksBuilder| {
+            if let Some(counter) = &counter {
+                let counter_value = counter.read(bb);
+                let decremented = reporter(
+                    bb,
+                    "operator_subtract",
+                    [("NUM1", counter_value), ("NUM2", 1f64.into())],
+                );
+                counter.write(decremented, bb);
+            }
+            if let Some(skip) = &current.skip_flag {
+                skip.write(false_value(), bb);
+            }
+            deferred.run(|| self.refine_codeblock(body, cx, bb));
+            if let Some((condition, variable)) = &cached {
+                let mut update = |bb: &mut codegen::BlocksBuilder| {
+                    deferred.run(|| {
+                        let value = self.refine_condition(condition, cx, bb)?;
+                        variable.write(value, bb);
+                        Ok(())
+                    })
+                };
+                match &current.break_flag {
+                    Some(flag) => {
+                        let broke = is_set(flag, bb);
+                        let running = reporter(bb, "operator_not", [("OPERAND", broke)]);
+                        bb.control_if(|bb| into_condition(running, bb), update);
+                    }
+                    None => update(bb),
+                }
+            }
+        };
+        match (condition, value) {
+            (Some(condition), _) => {
+                bb.control_repeat_until(|bb| into_condition(condition, bb), flow)
+            }
+            (None, Some(times)) => bb.control_repeat(|_| times, flow),
+            (None, None) => bb.control_forever(flow),
+        }
+        cx.loops.pop();
+        cx.tail = tail;
+        deferred.finish()
+    }
+
+    /// Refines value used as a condition of the C block.
+    fn refine_condition(
+        &self,
+        condition: &Statement,
+        cx: &mut ProcedureCx<'_>,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<pawgen::schema::Value, RefineError> {
+        let value = self.refine_value(condition, cx, bb)?;
+        let value = value
+            .into_primitive(bb)
+            .map_err(|kind| cx.error(condition, kind))?;
+        Ok(pawgen::schema::Value::Pointer(into_condition(value, bb)))
+    }
+
+    /// Writes value of the type into variables, one for every flattened field.
+    fn write_value(
+        &self,
+        variables: &[Storage],
+        dt: &DataType,
         value: DataValue,
         bb: &mut codegen::BlocksBuilder,
-    ) {
-        let value = value.into_primitive(bb);
-        bb.block("data_setvariableto", false)
-            .set_field("VARIABLE", pawgen::schema::BlockField::Variable(id, name))
-            .set_input("VALUE", &[value]);
+    ) -> Result<(), VerifyErrorKind> {
+        if dt.is_primitive() {
+            let value = value.into_primitive(bb)?;
+            variables[0].write(value, bb);
+            return Ok(());
+        }
+
+        for (i, variable) in variables.iter().enumerate() {
+            let field = value.slot(i, bb)?;
+            variable.write(field, bb);
+        }
+        Ok(())
     }
 
     fn refine_datatype_into_procargtype(
         &self,
         datatype: &DataType,
-    ) -> codegen::ProcedureArgumentType {
+    ) -> Result<codegen::ProcedureArgumentType, VerifyErrorKind> {
         match datatype {
-            DataType::Text | DataType::Number => codegen::ProcedureArgumentType::NumberOrText,
-            DataType::Boolean => codegen::ProcedureArgumentType::Boolean,
-            _ => unreachable!("Datatype should be flattened before conversion"),
+            DataType::Text | DataType::Number => Ok(codegen::ProcedureArgumentType::NumberOrText),
+            DataType::Boolean => Ok(codegen::ProcedureArgumentType::Boolean),
+            // Lists can't be passed to procedures.
+            dt => Err(VerifyErrorKind::NotPrimitive(dt.clone())),
+        }
+    }
+}
+
+/// Id and name of a variable.
+type VariableName = (String, String);
+
+/// Index of a list item, stored in a variable when It is read more than once.
+#[derive(Debug, Clone)]
+enum Index {
+    Value(pawgen::schema::Value),
+    Variable(Storage),
+}
+
+impl Index {
+    fn read(&self, bb: &mut codegen::BlocksBuilder) -> pawgen::schema::Value {
+        match self {
+            Self::Value(value) => value.clone(),
+            Self::Variable(variable) => variable.read(bb),
+        }
+    }
+}
+
+/// Matches with at most this many arms check them one by one.
+const MATCH_CHAIN_LENGTH: usize = 4;
+
+/// Name of the argument holding depth of the recursive call.
+const DEPTH_ARGUMENT: &str = "__depth";
+
+/// Place holding a primitive value.
+#[derive(Debug, Clone)]
+enum Storage {
+    Variable(VariableName),
+    /// Thread variable of Turbowarp's "Temporary Variables" extension,
+    /// names of recursive procedures' variables are suffixed with depth of the call.
+    Thread {
+        name: String,
+        recursive: bool,
+    },
+}
+
+impl Storage {
+    fn read(&self, bb: &mut codegen::BlocksBuilder) -> pawgen::schema::Value {
+        match self {
+            Self::Variable((id, name)) => pawgen::schema::Value::Variable(id.clone(), name.clone()),
+            Self::Thread { .. } => {
+                let name = self.thread_name(bb);
+                reporter(bb, "lmsTempVars2_getThreadVariable", [("VAR", name)])
+            }
+        }
+    }
+
+    fn write(&self, value: pawgen::schema::Value, bb: &mut codegen::BlocksBuilder) {
+        match self {
+            Self::Variable(variable) => write_variable(variable.clone(), value, bb),
+            Self::Thread { .. } => {
+                // Name is created before the block, so It isn't chained into the stack.
+                let name = self.thread_name(bb);
+                bb.block("lmsTempVars2_setThreadVariable", false)
+                    .set_input("VAR", &[name])
+                    .set_input("STRING", &[value]);
+            }
+        }
+    }
+
+    fn thread_name(&self, bb: &mut codegen::BlocksBuilder) -> pawgen::schema::Value {
+        let Self::Thread { name, recursive } = self else {
+            unreachable!()
+        };
+        let name = pawgen::schema::Value::Text(name.clone());
+        if !recursive {
+            return name;
+        }
+        let depth = depth_reporter(bb);
+        reporter(bb, "operator_join", [("STRING1", name), ("STRING2", depth)])
+    }
+}
+
+impl ProcedureCx<'_> {
+    /// Error in the statement of the procedure.
+    fn error(&self, stmt: &Statement, kind: VerifyErrorKind) -> RefineError {
+        RefineError {
+            kind: RefineErrorKind::Malformed(kind),
+            context: Box::new(MirContext {
+                sprite: self.sprite.to_owned(),
+                procedure: Some(self.name.to_owned()),
+                statement: Some(stmt.to_string()),
+            }),
+        }
+    }
+
+    /// Storage for temporary value of the procedure.
+    fn temporary(&self, name: &str) -> Storage {
+        let name = format!("{}.{name}", self.prefix);
+        match self.thread_variables {
+            true => Storage::Thread {
+                name,
+                recursive: self.recursive,
+            },
+            false => {
+                self.temporary_variables.borrow_mut().insert(name.clone());
+                Storage::Variable((name.clone(), name))
+            }
+        }
+    }
+
+    /// Temporaries holding value of the type, one for every flattened field.
+    fn temporaries(&self, name: &str, dt: &DataType) -> Vec<Storage> {
+        components((name.to_owned(), name.to_owned()), dt)
+            .into_iter()
+            .map(|(name, _)| self.temporary(&name))
+            .collect()
+    }
+
+    /// Name of a new temporary value, numbered so It doesn't overwrite values
+    /// which are still used.
+    fn next_temporary(&mut self, name: &str) -> String {
+        self.temporary_count += 1;
+        format!("{name}{}", self.temporary_count - 1)
+    }
+}
+
+/// Scratch variables and lists holding data of the sprite.
+struct SpriteData {
+    /// Scratch lists of every list of the sprite.
+    lists: Vec<Vec<VariableName>>,
+    /// Variables of every field of the sprite.
+    fields: Vec<Vec<Storage>>,
+    /// Set in clones which took values they were spawned with, `None` when
+    /// there are no values to take.
+    spawned: Option<Storage>,
+}
+
+/// State of the procedure being refined.
+struct ProcedureCx<'a> {
+    /// Name of the sprite the procedure belongs to.
+    sprite: &'a str,
+    name: &'a str,
+    /// Prefix of variables generated for the procedure.
+    prefix: String,
+    returns: Option<DataType>,
+    /// Procedures of the sprite, by name.
+    procedures: &'a HashMap<&'a str, &'a Procedure>,
+    /// Procedures of the sprite calling each other.
+    graph: &'a CallGraph<'a>,
+    /// Whether temporary values are kept in thread variables.
+    thread_variables: bool,
+    /// Whether names of thread variables are suffixed with depth of the call.
+    recursive: bool,
+    /// Variables of every local.
+    locals: Vec<Vec<Storage>>,
+    /// Scratch lists of every list of the sprite.
+    lists: &'a [Vec<VariableName>],
+    /// Variables of every field of the sprite.
+    fields: &'a [Vec<Storage>],
+    /// Flag of the sprite set once the clone took Its spawned values.
+    spawned: Option<&'a Storage>,
+    /// Loops surrounding current statement, innermost last.
+    loops: Vec<LoopCx>,
+    /// Number of loops refined so far, used to name their variables.
+    loop_count: usize,
+    /// Number of temporary variables created so far.
+    temporary_count: usize,
+    /// Variables of temporary values, declared once blocks of the procedure are built.
+    temporary_variables: RefCell<BTreeSet<String>>,
+    /// Whether procedure returns right after the current statement.
+    tail: bool,
+}
+
+/// First error of refinement done in callbacks of the blocks builder, which
+/// can't return It.
+#[derive(Default)]
+struct Deferred(RefCell<Option<RefineError>>);
+
+impl Deferred {
+    fn run(&self, refine: impl FnOnce() -> Result<(), RefineError>) {
+        if let Err(error) = refine() {
+            self.0.borrow_mut().get_or_insert(error);
+        }
+    }
+
+    fn finish(self) -> Result<(), RefineError> {
+        match self.0.into_inner() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+fn asset_error(sprite: &Sprite, name: &str, source: &Path, error: std::io::Error) -> RefineError {
+    RefineError {
+        kind: RefineErrorKind::Asset {
+            name: name.to_owned(),
+            source: source.to_owned(),
+            error,
+        },
+        context: Box::new(MirContext {
+            sprite: sprite.name.clone(),
+            procedure: None,
+            statement: None,
+        }),
+    }
+}
+
+fn unexpected_type(kind: &'static str, found: &DataType) -> VerifyErrorKind {
+    VerifyErrorKind::UnexpectedType {
+        kind,
+        found: found.clone(),
+    }
+}
+
+/// Names of broadcasts sent by statements or received by handlers of the sprites.
+fn broadcasts(sprites: &[Sprite]) -> BTreeSet<String> {
+    fn collect(code: &CodeBlock, names: &mut BTreeSet<String>) {
+        for stmt in code.code.iter() {
+            if let Statement::Broadcast(name, _) = stmt {
+                names.insert(name.clone());
+            }
+            for block in stmt.code_blocks() {
+                collect(block, names);
+            }
+        }
+    }
+
+    let mut names = BTreeSet::new();
+    for sprite in sprites.iter() {
+        for procedure in sprite.procedures.iter() {
+            collect(&procedure.block, &mut names);
+        }
+        for handler in sprite.handlers.iter() {
+            if let Event::BroadcastReceived(name) = &handler.event {
+                names.insert(name.clone());
+            }
+        }
+    }
+    names
+}
+
+/// Lists passing values of the fields to clones of the sprite, one for every
+/// flattened field. Lists are global, so their ids are their names.
+fn hand_off_lists(sprite: &str, fields: &[DataType]) -> Vec<VariableName> {
+    if fields.is_empty() {
+        return Vec::new();
+    }
+    let name = format!("{sprite}.spawn");
+    components((name.clone(), name), &DataType::Structure(fields.to_vec()))
+}
+
+/// Layer order of every sprite, sprites are ordered by their layers starting
+/// at 1 and the stage is always at 0.
+fn layer_orders(sprites: &[Sprite]) -> Vec<u32> {
+    let mut layered: Vec<usize> = (0..sprites.len())
+        .filter(|index| !sprites[*index].is_stage)
+        .collect();
+    // Sort is stable, so sprites on the same layer keep their order.
+    layered.sort_by_key(|index| sprites[*index].properties.layer);
+    let mut orders = vec![0; sprites.len()];
+    for (order, index) in layered.into_iter().enumerate() {
+        orders[index] = order as u32 + 1;
+    }
+    orders
+}
+
+/// Prefix of variables generated for the procedure,
+/// `:` is reserved for fields of structure variables.
+fn variable_prefix(procedure: &str) -> String {
+    procedure.replace("::", ".")
+}
+
+/// Variable holding value returned from the procedure, structures use one
+/// variable per flattened field.
+fn return_variable(prefix: &str) -> VariableName {
+    let variable = format!("{prefix}.return");
+    (variable.clone(), variable)
+}
+
+/// Variables holding value of the type, fields of structures are suffixed with `:`
+/// and their index.
+fn components((id, name): VariableName, dt: &DataType) -> Vec<VariableName> {
+    if dt.is_primitive() {
+        return vec![(id, name)];
+    }
+    (0..dt.calculate_size())
+        .map(|i| (format!("{id}:{i}"), format!("{name}:{i}")))
+        .collect()
+}
+
+/// Flags used to emulate `break` and `continue`, Scratch has neither of them.
+#[derive(Clone)]
+struct LoopCx {
+    /// Set when the loop should end, `None` when `break` stops the script instead.
+    break_flag: Option<Storage>,
+    /// Set when rest of the iteration should be skipped.
+    skip_flag: Option<Storage>,
+}
+
+/// Jumps out of the loop's body, not including nested loops.
+#[derive(Default)]
+struct Jumps {
+    breaks: bool,
+    continues: bool,
+}
+
+impl Jumps {
+    fn join(self, other: Self) -> Self {
+        Self {
+            breaks: self.breaks || other.breaks,
+            continues: self.continues || other.continues,
+        }
+    }
+}
+
+fn find_jumps(statements: &[Statement]) -> Jumps {
+    statements
+        .iter()
+        .fold(Jumps::default(), |jumps, stmt| match stmt {
+            Statement::Break => jumps.join(Jumps {
+                breaks: true,
+                continues: false,
+            }),
+            Statement::Continue => jumps.join(Jumps {
+                breaks: false,
+                continues: true,
+            }),
+            Statement::If(_, then, otherwise) => {
+                let jumps = jumps.join(find_jumps(&then.code));
+                match otherwise {
+                    Some(otherwise) => jumps.join(find_jumps(&otherwise.code)),
+                    None => jumps,
+                }
+            }
+            Statement::Match(_, arms, otherwise) => arms
+                .iter()
+                .map(|(_, arm)| arm)
+                .chain(otherwise)
+                .fold(jumps, |jumps, code| jumps.join(find_jumps(&code.code))),
+            _ => jumps,
+        })
+}
+
+/// Scratch counts items of lists from 1.
+fn scratch_index(index: &Index, bb: &mut codegen::BlocksBuilder) -> pawgen::schema::Value {
+    match index.read(bb) {
+        pawgen::schema::Value::Number(index) => pawgen::schema::Value::Number(index + 1.0),
+        index => reporter(bb, "operator_add", [("NUM1", index), ("NUM2", 1f64.into())]),
+    }
+}
+
+/// Index of the last item of the list.
+fn last_index() -> pawgen::schema::Value {
+    pawgen::schema::Value::Text("last".to_owned())
+}
+
+fn list_field((id, name): &VariableName) -> pawgen::schema::BlockField {
+    pawgen::schema::BlockField::Variable(id.clone(), name.clone())
+}
+
+fn write_variable(
+    (id, name): VariableName,
+    value: pawgen::schema::Value,
+    bb: &mut codegen::BlocksBuilder,
+) {
+    bb.block("data_setvariableto", false)
+        .set_field("VARIABLE", pawgen::schema::BlockField::Variable(id, name))
+        .set_input("VALUE", &[value]);
+}
+
+fn false_value() -> pawgen::schema::Value {
+    pawgen::schema::Value::Text("false".to_owned())
+}
+
+fn set_flag(flag: &Storage, bb: &mut codegen::BlocksBuilder) {
+    flag.write(pawgen::schema::Value::Text("true".to_owned()), bb);
+}
+
+fn is_set(flag: &Storage, bb: &mut codegen::BlocksBuilder) -> pawgen::schema::Value {
+    let value = flag.read(bb);
+    pawgen::schema::Value::Pointer(into_condition(value, bb))
+}
+
+fn depth_reporter(bb: &mut codegen::BlocksBuilder) -> pawgen::schema::Value {
+    let mut block = bb.block("argument_reporter_string_number", true);
+    block.set_field(
+        "VALUE",
+        pawgen::schema::BlockField::Argument(DEPTH_ARGUMENT.to_owned()),
+    );
+    pawgen::schema::Value::Pointer(block.finish())
+}
+
+/// Creates reporter block, returns pointer to It.
+fn reporter<'a>(
+    bb: &mut codegen::BlocksBuilder,
+    opcode: &str,
+    inputs: impl IntoIterator<Item = (&'a str, pawgen::schema::Value)>,
+) -> pawgen::schema::Value {
+    let mut block = bb.block(opcode, true);
+    for (name, value) in inputs {
+        block.set_input(name, &[value]);
+    }
+    pawgen::schema::Value::Pointer(block.finish())
+}
+
+/// Boolean inputs only accept blocks, so other values are compared with `true`.
+/// Returns id of the block.
+fn into_condition(value: pawgen::schema::Value, bb: &mut codegen::BlocksBuilder) -> String {
+    match value {
+        pawgen::schema::Value::Pointer(id) => id,
+        value => {
+            let mut block = bb.block("operator_equals", true);
+            block.set_input("OPERAND1", &[value]).set_input(
+                "OPERAND2",
+                &[pawgen::schema::Value::Text("true".to_owned())],
+            );
+            block.finish()
         }
     }
 }
@@ -229,107 +2033,111 @@ impl MirRefinery {
 enum DataValue {
     Primitive(pawgen::schema::Value),
     Argument(usize, DataType),
-    Variable(String, String, DataType),
-    Field(Box<DataValue>, usize, DataType),
+    /// Variables holding the value, one for every flattened field.
+    Variable(Vec<Storage>, DataType),
+    /// Flattened fields of the value starting at the offset, used for fields of
+    /// structures and payloads of enums.
+    Slots(Box<DataValue>, usize, DataType),
     StructureLiteral(Vec<pawgen::schema::Value>, DataType),
+    /// Variant of the enum with Its payload.
+    Variant(usize, Option<Box<DataValue>>, DataType),
+    /// Scratch lists of every flattened field of the items.
+    List(Vec<VariableName>, DataType),
 }
 
 impl DataValue {
-    pub fn get_data_type(&self) -> DataType {
-        match self {
-            Self::Argument(_, dt) => dt.clone(),
-            Self::Field(_, index, dt) => match dt {
-                DataType::Structure(fields) => fields[*index].clone(),
-                _ => unreachable!(),
-            },
-            Self::Variable(_, _, dt) => dt.clone(),
-            Self::StructureLiteral(_, dt) => dt.clone(),
-            _ => unreachable!("Getting data type of {self:?} is prohibited"),
+    /// Value of the type stored in slots of the target, nested slots are
+    /// merged so they always refer to the same value.
+    pub fn slots(target: DataValue, offset: usize, dt: DataType) -> Self {
+        match target {
+            Self::Slots(target, start, _) => Self::Slots(target, start + offset, dt),
+            target => Self::Slots(Box::new(target), offset, dt),
         }
     }
 
-    pub fn into_primitive(self, bb: &mut codegen::BlocksBuilder) -> pawgen::schema::Value {
+    /// Type of the value, primitive values are cast by Scratch, so they have none.
+    pub fn get_data_type(&self) -> Option<&DataType> {
         match self {
-            Self::Primitive(value) => value,
-            Self::Argument(index, dt) => pawgen::schema::Value::Pointer(
-                bb.block(
-                    if matches!(dt, DataType::Boolean) {
-                        "argument_reporter_boolean"
-                    } else {
-                        "argument_reporter_string_number"
-                    },
-                    true,
-                )
-                .set_field(
-                    "VALUE",
-                    pawgen::schema::BlockField::Argument(format!("__arg_{index}:0")),
-                )
-                .id(),
-            ),
-            Self::Variable(id, name, _) => pawgen::schema::Value::Variable(id, name),
-            f @ Self::Field(..) => {
-                let (collected_fields, target) = f.collect_field_indices();
-                let t = target.clone().unwrap().into_primitive(bb);
-                match target.unwrap() {
-                    Self::Argument(..) => {
-                        let pawgen::schema::Value::Pointer(p) = t else {
-                            unreachable!()
-                        };
-                        let mut bb = bb.get_block_builder(p.clone());
-                        let br = bb.block_ref();
-                        let pawgen::schema::BlockField::Argument(previous_name) =
-                            br.fields.get("VALUE").unwrap()
-                        else {
-                            unreachable!()
-                        };
-                        br.fields.insert(
-                            "VALUE".to_owned(),
-                            pawgen::schema::BlockField::Argument(format!(
-                                "{}:{}",
-                                previous_name
-                                    .split(":")
-                                    .collect::<Vec<_>>()
-                                    .first()
-                                    .unwrap(),
-                                collected_fields
-                            )),
-                        );
-                        pawgen::schema::Value::Pointer(p)
-                    }
-                    Self::Variable(..) => {
-                        let pawgen::schema::Value::Variable(id, name) = t else {
-                            unreachable!()
-                        };
-                        pawgen::schema::Value::Variable(
-                            format!("{id}:{collected_fields}"),
-                            format!("{name}:{collected_fields}"),
-                        )
-                    }
-                    Self::StructureLiteral(values, ..) => values[collected_fields].clone(),
-                    _ => todo!(),
-                }
+            Self::Argument(_, dt)
+            | Self::Variable(_, dt)
+            | Self::Slots(_, _, dt)
+            | Self::StructureLiteral(_, dt)
+            | Self::Variant(_, _, dt)
+            | Self::List(_, dt) => Some(dt),
+            Self::Primitive(_) => None,
+        }
+    }
+
+    pub fn into_primitive(
+        self,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<pawgen::schema::Value, VerifyErrorKind> {
+        if let Some(dt) = self.get_data_type() {
+            if dt.calculate_size() != 1 {
+                return Err(VerifyErrorKind::NotPrimitive(dt.clone()));
             }
-            Self::StructureLiteral(..) => pawgen::schema::Value::Text(
-                "IF YOU SEE THIS REPORT PROBLEM WITH SCRATCHLET!!!".to_owned(),
-            ),
         }
+        self.slot(0, bb)
     }
 
-    fn collect_field_indices(&self) -> (usize, Option<DataValue>) {
-        match self {
-            Self::Field(target, index, dt) => {
-                if let DataType::Structure(fields) = dt {
-                    let mut n = 0;
-                    for i in 0..*index {
-                        n += fields[i].calculate_size();
-                    }
-                    let (tn, tt) = target.collect_field_indices();
-                    (n + tn, Some(tt.unwrap_or_else(|| *target.clone())))
-                } else {
-                    panic!("Cannot access field on non-structure/array value {self:?}")
+    /// Flattened field of the value, primitive values are their only field.
+    pub fn slot(
+        &self,
+        index: usize,
+        bb: &mut codegen::BlocksBuilder,
+    ) -> Result<pawgen::schema::Value, VerifyErrorKind> {
+        let out_of_range = |count: usize| VerifyErrorKind::FieldOutOfRange { index, count };
+        let value = match self {
+            Self::Primitive(value) => {
+                if index != 0 {
+                    return Err(out_of_range(1));
                 }
+                value.clone()
             }
-            _ => (0, None),
-        }
+            Self::Argument(argument, dt) => {
+                let fields = dt.flatten();
+                let Some(field) = fields.get(index) else {
+                    return Err(out_of_range(fields.len()));
+                };
+                let opcode = match field {
+                    DataType::Boolean => "argument_reporter_boolean",
+                    _ => "argument_reporter_string_number",
+                };
+                let mut block = bb.block(opcode, true);
+                block.set_field(
+                    "VALUE",
+                    pawgen::schema::BlockField::Argument(format!("__arg_{argument}:{index}")),
+                );
+                pawgen::schema::Value::Pointer(block.finish())
+            }
+            Self::Variable(variables, _) => match variables.get(index) {
+                Some(variable) => variable.read(bb),
+                None => return Err(out_of_range(variables.len())),
+            },
+            Self::Slots(target, offset, _) => return target.slot(offset + index, bb),
+            Self::StructureLiteral(values, _) => match values.get(index) {
+                Some(value) => value.clone(),
+                None => return Err(out_of_range(values.len())),
+            },
+            Self::Variant(variant, payload, dt) => {
+                let DataType::Enum(variants) = dt else {
+                    return Err(unexpected_type("enum", dt));
+                };
+                let Some(size) = variants.get(*variant).map(DataType::calculate_size) else {
+                    return Err(VerifyErrorKind::VariantOutOfRange {
+                        index: *variant,
+                        count: variants.len(),
+                    });
+                };
+                match (index, payload) {
+                    (0, _) => pawgen::schema::Value::Number(*variant as f64),
+                    (slot, Some(payload)) if slot <= size => return payload.slot(slot - 1, bb),
+                    // Slot isn't used by the variant.
+                    _ => pawgen::schema::Value::Text(String::new()),
+                }
+            }
+            Self::List(_, dt) => return Err(VerifyErrorKind::NotPrimitive(dt.clone())),
+        };
+        Ok(value)
     }
 }
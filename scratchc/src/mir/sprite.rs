@@ -1,6 +1,6 @@
 use std::path::{Path, PathBuf};
 
-use super::Procedure;
+use super::{DataType, Local, Procedure};
 
 #[derive(Debug)]
 pub struct Sprite {
@@ -9,8 +9,15 @@ pub struct Sprite {
     /// List of this sprite's costumes. First one is default.
     pub(super) costumes: Vec<Costume>,
     pub(super) sounds: Vec<Sound>,
+    pub(super) properties: SpriteProperties,
 
     pub(super) procedures: Vec<Procedure>,
+    /// Fields referenced by their index, every clone has Its own copy of them.
+    pub(super) fields: Vec<Local>,
+    /// Scripts started by events, in order of their declaration.
+    pub(super) handlers: Vec<EventHandler>,
+    /// Lists referenced by their index.
+    pub(super) lists: Vec<List>,
 }
 
 impl Sprite {
@@ -20,7 +27,11 @@ impl Sprite {
             is_stage: false,
             costumes: Vec::new(),
             sounds: Vec::new(),
+            properties: SpriteProperties::default(),
             procedures: Vec::new(),
+            fields: Vec::new(),
+            handlers: Vec::new(),
+            lists: Vec::new(),
         }
     }
 
@@ -38,10 +49,248 @@ impl Sprite {
         self
     }
 
+    pub fn set_properties(&mut self, properties: SpriteProperties) -> &mut Self {
+        self.properties = properties;
+        self
+    }
+
     pub fn add_procedure(&mut self, procedure: Procedure) -> &mut Self {
         self.procedures.push(procedure);
         self
     }
+
+    pub fn add_handler(&mut self, handler: EventHandler) -> &mut Self {
+        self.handlers.push(handler);
+        self
+    }
+
+    /// Declares field, returns index used to reference It. Fields are "for this
+    /// sprite only" variables, so clones don't share them.
+    pub fn declare_field(&mut self, name: impl AsRef<str>, dt: DataType) -> usize {
+        self.fields.push(Local {
+            name: name.as_ref().to_owned(),
+            dt,
+        });
+        self.fields.len() - 1
+    }
+
+    /// Declares list with items of the type, returns index used to reference It.
+    pub fn declare_list(&mut self, name: impl AsRef<str>, item: DataType) -> usize {
+        self.lists.push(List {
+            name: name.as_ref().to_owned(),
+            item,
+        });
+        self.lists.len() - 1
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_stage(&self) -> bool {
+        self.is_stage
+    }
+
+    pub fn costumes(&self) -> &[Costume] {
+        &self.costumes
+    }
+
+    pub fn sounds(&self) -> &[Sound] {
+        &self.sounds
+    }
+
+    pub fn properties(&self) -> &SpriteProperties {
+        &self.properties
+    }
+
+    pub fn procedures(&self) -> &[Procedure] {
+        &self.procedures
+    }
+
+    pub fn fields(&self) -> &[Local] {
+        &self.fields
+    }
+
+    pub fn handlers(&self) -> &[EventHandler] {
+        &self.handlers
+    }
+
+    pub fn lists(&self) -> &[List] {
+        &self.lists
+    }
+}
+
+/// Script started by the event, It calls procedure of the sprite which takes
+/// no arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventHandler {
+    pub(super) event: Event,
+    pub(super) procedure: String,
+}
+
+impl EventHandler {
+    pub fn new(event: Event, procedure: impl AsRef<str>) -> Self {
+        Self {
+            event,
+            procedure: procedure.as_ref().to_owned(),
+        }
+    }
+
+    pub fn event(&self) -> &Event {
+        &self.event
+    }
+
+    pub fn procedure(&self) -> &str {
+        &self.procedure
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    FlagClicked,
+    /// Broadcast with the name is sent.
+    BroadcastReceived(String),
+    /// Key with the name, like `space` or `a`, is pressed.
+    KeyPressed(String),
+    /// Sprite is clicked, for the stage It is clicked outside of sprites.
+    Clicked,
+    /// Clone of the sprite is created, the handler runs in the clone after
+    /// Its fields are set to values It was spawned with.
+    CloneStarted,
+    /// Backdrop with the name is switched to.
+    BackdropSwitched(String),
+    /// Value of the sensor becomes greater than the threshold.
+    Threshold(Sensor, f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sensor {
+    Timer,
+    Loudness,
+}
+
+impl Sensor {
+    /// Name of the sensor used by Scratch.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Timer => "TIMER",
+            Self::Loudness => "LOUDNESS",
+        }
+    }
+}
+
+/// State of the sprite when the project starts, the stage only uses Its volume.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteProperties {
+    pub(super) position: (i32, i32),
+    pub(super) volume: u32,
+    pub(super) size: u32,
+    pub(super) direction: i32,
+    pub(super) visible: bool,
+    pub(super) rotation_style: RotationStyle,
+    /// Sprites with higher layers are drawn over those with lower ones,
+    /// sprites on the same layer are drawn in order of their declaration.
+    pub(super) layer: u32,
+    pub(super) draggable: bool,
+}
+
+impl Default for SpriteProperties {
+    fn default() -> Self {
+        Self {
+            position: (0, 0),
+            volume: 100,
+            size: 100,
+            direction: 90,
+            visible: true,
+            rotation_style: RotationStyle::AllAround,
+            layer: 1,
+            draggable: false,
+        }
+    }
+}
+
+impl SpriteProperties {
+    pub fn position(mut self, x: i32, y: i32) -> Self {
+        self.position = (x, y);
+        self
+    }
+
+    pub fn volume(mut self, volume: u32) -> Self {
+        self.volume = volume;
+        self
+    }
+
+    pub fn size(mut self, size: u32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn direction(mut self, direction: i32) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn visible(mut self, visible: bool) -> Self {
+        self.visible = visible;
+        self
+    }
+
+    pub fn rotation_style(mut self, rotation_style: RotationStyle) -> Self {
+        self.rotation_style = rotation_style;
+        self
+    }
+
+    pub fn layer(mut self, layer: u32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn draggable(mut self, draggable: bool) -> Self {
+        self.draggable = draggable;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationStyle {
+    AllAround,
+    LeftRight,
+    DontRotate,
+}
+
+impl RotationStyle {
+    /// Name of the style used by Scratch.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::AllAround => "all around",
+            Self::LeftRight => "left-right",
+            Self::DontRotate => "don't rotate",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::AllAround, Self::LeftRight, Self::DontRotate]
+            .into_iter()
+            .find(|style| style.as_str() == name)
+    }
+}
+
+/// List of the sprite, lists of structures use one Scratch list for every
+/// flattened field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct List {
+    pub(super) name: String,
+    pub(super) item: DataType,
+}
+
+impl List {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn item_type(&self) -> &DataType {
+        &self.item
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -57,6 +306,14 @@ impl Costume {
             source: source.as_ref().to_owned(),
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,1939 @@
+use std::collections::HashMap;
+
+use pawgen::{
+    codegen::ProjectBuilder,
+    schema::{Block, BlockField, Value},
+};
+
+use crate::common::error::{RefineErrorKind, VerifyErrorKind};
+
+use super::{
+    eliminate_dead_code, fold_code_block, inline_procedures, BlockDefinition,
+    BlockField as MirBlockField, BlockInput, CodeBlock, DataType, Event, EventHandler, Inline,
+    ListOperation, MirRefinementConfig, MirRefinery, Procedure, Project, RotationStyle, Sprite,
+    SpriteProperties, Statement,
+};
+
+/// Refines procedure with given body, returns opcodes of Its blocks
+/// with substacks indented.
+fn refine(body: impl IntoIterator<Item = Statement>) -> Vec<String> {
+    let mut procedure = Procedure::new("test", false, []);
+    *procedure.code_block() = body.into_iter().collect();
+    refine_procedure(procedure, MirRefinementConfig::default())
+}
+
+fn refine_procedure(procedure: Procedure, config: MirRefinementConfig) -> Vec<String> {
+    refine_procedures([procedure], config, "test")
+}
+
+/// Refines procedures of the stage, returns outline of the one called `name`.
+fn refine_procedures(
+    procedures: impl IntoIterator<Item = Procedure>,
+    config: MirRefinementConfig,
+    name: &str,
+) -> Vec<String> {
+    outline_procedure(&refine_stage(procedures, config), name)
+}
+
+/// Outline of the procedure called `name`.
+fn outline_procedure(builder: &ProjectBuilder, name: &str) -> Vec<String> {
+    let project = builder.project();
+    let blocks = &project.targets[0].blocks.blocks;
+    let (_, definition) = blocks
+        .iter()
+        .find(|(_, block)| {
+            let Some(Value::Pointer(prototype)) = block
+                .inputs
+                .get("custom_block")
+                .map(|input| &input.values[0])
+            else {
+                return false;
+            };
+            let mutation = blocks[prototype].mutation.as_ref().unwrap();
+            mutation.proccode.split(' ').next() == Some(name)
+        })
+        .unwrap();
+    let mut lines = Vec::new();
+    outline(blocks, definition.next.clone(), 0, &mut lines);
+    lines
+}
+
+fn refine_stage(
+    procedures: impl IntoIterator<Item = Procedure>,
+    config: MirRefinementConfig,
+) -> ProjectBuilder {
+    let mut stage = Sprite::new("Stage");
+    stage.mark_as_stage();
+    for procedure in procedures {
+        stage.add_procedure(procedure);
+    }
+    refine_sprite(stage, config)
+}
+
+fn refine_sprite(sprite: Sprite, config: MirRefinementConfig) -> ProjectBuilder {
+    let mut project = Project::new();
+    project.get_definitions_mut().define(
+        "say",
+        BlockDefinition::new(
+            "looks_say",
+            false,
+            [BlockInput::new("MESSAGE".to_owned(), DataType::Text)],
+            [],
+        ),
+    );
+    project.get_definitions_mut().define(
+        "join",
+        BlockDefinition::new(
+            "operator_join",
+            true,
+            [
+                BlockInput::new("STRING1".to_owned(), DataType::Text),
+                BlockInput::new("STRING2".to_owned(), DataType::Text),
+            ],
+            [],
+        ),
+    );
+    project.add_sprite(sprite);
+
+    MirRefinery::new(config).refine_project(project).unwrap()
+}
+
+/// Names of variables declared by the first target, sorted.
+fn declared_variables(builder: &ProjectBuilder) -> Vec<String> {
+    let project = builder.project();
+    let mut variables: Vec<String> = project.targets[0]
+        .variables
+        .values()
+        .map(|variable| variable.display_name.clone())
+        .collect();
+    variables.sort();
+    variables
+}
+
+fn outline(
+    blocks: &HashMap<String, Block>,
+    mut next: Option<String>,
+    depth: usize,
+    lines: &mut Vec<String>,
+) {
+    while let Some(id) = next {
+        let block = &blocks[&id];
+        let indent = "  ".repeat(depth);
+        match block.fields.get("VARIABLE").or(block.fields.get("LIST")) {
+            Some(BlockField::Variable(_, name)) => {
+                lines.push(format!("{indent}{} {name}", block.opcode))
+            }
+            _ => lines.push(format!("{indent}{}", block.opcode)),
+        }
+        for substack in ["SUBSTACK", "SUBSTACK2"] {
+            if substack == "SUBSTACK2" && block.opcode == "control_if_else" {
+                lines.push(format!("{indent}else"));
+            }
+            if let Some(input) = block.inputs.get(substack) {
+                let Value::Pointer(first) = &input.values[0] else {
+                    unreachable!()
+                };
+                assert_eq!(blocks[first].parent.as_ref(), Some(&id));
+                outline(blocks, Some(first.clone()), depth + 1, lines);
+            }
+        }
+        next = block.next.clone();
+    }
+}
+
+fn say() -> Statement {
+    Statement::BlockCall(
+        "say".to_owned(),
+        vec![Statement::Constant(Value::Text("hi".to_owned()))],
+    )
+}
+
+fn condition() -> Box<Statement> {
+    Box::new(Statement::Constant(Value::Text("true".to_owned())))
+}
+
+fn code(statements: impl IntoIterator<Item = Statement>) -> CodeBlock {
+    statements.into_iter().collect()
+}
+
+#[test]
+fn control_flow_uses_c_blocks() {
+    let lines = refine([
+        Statement::If(condition(), code([say()]), Some(code([say(), say()]))),
+        Statement::While(condition(), code([say()])),
+        Statement::Repeat(Box::new(Statement::Constant(Value::Number(3.0))), code([])),
+        Statement::Forever(code([say()])),
+    ]);
+    assert_eq!(
+        lines,
+        [
+            "control_if_else",
+            "  looks_say",
+            "else",
+            "  looks_say",
+            "  looks_say",
+            "control_repeat_until",
+            "  looks_say",
+            "control_repeat",
+            "control_forever",
+            "  looks_say",
+        ]
+    );
+}
+
+#[test]
+fn break_and_continue_use_flags() {
+    let mut procedure = Procedure::new("test", false, []);
+    *procedure.code_block() = code([
+        Statement::While(
+            condition(),
+            code([
+                Statement::If(condition(), code([Statement::Continue, say()]), None),
+                say(),
+                Statement::If(condition(), code([Statement::Break]), None),
+                say(),
+            ]),
+        ),
+        say(),
+    ]);
+    let builder = refine_stage([procedure], MirRefinementConfig::default());
+    assert_eq!(
+        outline_procedure(&builder, "test"),
+        [
+            "data_setvariableto test.loop0.break",
+            "control_repeat_until",
+            "  data_setvariableto test.loop0.skip",
+            "  control_if",
+            "    data_setvariableto test.loop0.skip",
+            "  control_if",
+            "    looks_say",
+            "    control_if",
+            "      data_setvariableto test.loop0.break",
+            "      data_setvariableto test.loop0.skip",
+            "    control_if",
+            "      looks_say",
+            "looks_say",
+        ]
+    );
+    // Flags are declared by the sprite, so copies of the procedure in other
+    // sprites and clones don't share them.
+    assert_eq!(
+        declared_variables(&builder),
+        ["test.loop0.break", "test.loop0.skip"]
+    );
+}
+
+#[test]
+fn break_before_return_stops_script() {
+    let lines = refine([
+        say(),
+        Statement::Repeat(
+            Box::new(Statement::Constant(Value::Number(3.0))),
+            code([
+                Statement::If(condition(), code([Statement::Break]), None),
+                say(),
+            ]),
+        ),
+    ]);
+    assert_eq!(
+        lines,
+        [
+            "looks_say",
+            "control_repeat",
+            "  control_if",
+            "    control_stop",
+            "  looks_say",
+        ]
+    );
+}
+
+#[test]
+fn returns_use_variables() {
+    let dt = DataType::Structure(vec![DataType::Number, DataType::Text]);
+    let mut procedure = Procedure::new("test", false, []);
+    procedure.set_return_type(dt.clone());
+    let value = || {
+        Box::new(Statement::StructureLiteral(
+            vec![Value::Number(1.0), Value::Text("a".to_owned())],
+            dt.clone(),
+        ))
+    };
+    *procedure.code_block() = code([
+        Statement::If(condition(), code([Statement::Return(Some(value()))]), None),
+        Statement::Return(Some(value())),
+    ]);
+    assert_eq!(
+        refine_procedure(procedure, MirRefinementConfig::default()),
+        [
+            "control_if",
+            "  data_setvariableto test.return:0",
+            "  data_setvariableto test.return:1",
+            "  control_stop",
+            "data_setvariableto test.return:0",
+            "data_setvariableto test.return:1",
+        ]
+    );
+}
+
+#[test]
+fn return_variables_are_declared_by_sprite() {
+    let dt = DataType::Structure(vec![DataType::Number, DataType::Text]);
+    let mut pair = Procedure::new("main::pair", false, []);
+    pair.set_return_type(dt.clone());
+    *pair.code_block() = code([Statement::Return(Some(Box::new(
+        Statement::StructureLiteral(
+            vec![Value::Number(1.0), Value::Text("a".to_owned())],
+            dt.clone(),
+        ),
+    )))]);
+    let mut sprite = Sprite::new("Cat");
+    sprite.add_procedure(pair);
+
+    let builder = refine_sprite(sprite, MirRefinementConfig::default());
+    assert_eq!(
+        declared_variables(&builder),
+        ["main.pair.return:0", "main.pair.return:1"]
+    );
+    // Blocks refer to the declared variables by their ids.
+    let project = builder.project();
+    let target = &project.targets[0];
+    for block in target.blocks.blocks.values() {
+        if let Some(BlockField::Variable(id, _)) = block.fields.get("VARIABLE") {
+            assert!(target.variables.contains_key(id), "undeclared {id}");
+        }
+    }
+}
+
+#[test]
+fn returns_use_turbowarp_blocks() {
+    let mut procedure = Procedure::new("test", false, []);
+    procedure.set_return_type(DataType::Number);
+    *procedure.code_block() = code([
+        Statement::If(
+            condition(),
+            code([Statement::Return(Some(Box::new(Statement::Constant(
+                Value::Number(1.0),
+            ))))]),
+            None,
+        ),
+        Statement::Return(Some(Box::new(Statement::Constant(Value::Number(2.0))))),
+    ]);
+    assert_eq!(
+        refine_procedure(
+            procedure,
+            MirRefinementConfig::default().use_return_blocks(true)
+        ),
+        ["control_if", "  procedures_return", "procedures_return"]
+    );
+}
+
+#[test]
+fn calls_copy_returned_values() {
+    let twice = |value: f64| {
+        Statement::ProcedureCall(
+            "twice".to_owned(),
+            vec![Statement::Constant(Value::Number(value))],
+        )
+    };
+    let mut main = Procedure::new("main", false, []);
+    *main.code_block() = code([
+        twice(1.0),
+        Statement::BlockCall(
+            "say".to_owned(),
+            vec![Statement::BlockCall(
+                "join".to_owned(),
+                vec![twice(2.0), twice(3.0)],
+            )],
+        ),
+        Statement::While(
+            Box::new(Statement::BlockCall(
+                "join".to_owned(),
+                vec![twice(4.0), Statement::Constant(Value::Text("".to_owned()))],
+            )),
+            code([say()]),
+        ),
+    ]);
+    // Declared after the caller.
+    let mut twice = Procedure::new("twice", false, [DataType::Number]);
+    twice.set_return_type(DataType::Number);
+    *twice.code_block() = code([Statement::Return(Some(Box::new(Statement::ArgumentRef(
+        0,
+        DataType::Number,
+    ))))]);
+
+    let builder = refine_stage([main, twice], MirRefinementConfig::default());
+    assert_eq!(
+        outline_procedure(&builder, "main"),
+        [
+            "procedures_call",
+            "procedures_call",
+            "data_setvariableto main.call0",
+            "procedures_call",
+            "data_setvariableto main.call1",
+            "looks_say",
+            "procedures_call",
+            "data_setvariableto main.call2",
+            "data_setvariableto main.loop0.condition",
+            "control_repeat_until",
+            "  looks_say",
+            "  procedures_call",
+            "  data_setvariableto main.call3",
+            "  data_setvariableto main.loop0.condition",
+        ]
+    );
+    assert_eq!(
+        declared_variables(&builder),
+        [
+            "main.call0",
+            "main.call1",
+            "main.call2",
+            "main.call3",
+            "main.loop0.condition",
+            "twice.return",
+        ]
+    );
+}
+
+#[test]
+fn locals_share_variables() {
+    let dt = DataType::Structure(vec![DataType::Number, DataType::Number]);
+    let mut procedure = Procedure::new("test", false, []);
+    let a = procedure.declare_local("a", DataType::Number);
+    let b = procedure.declare_local("b", dt.clone());
+    let c = procedure.declare_local("c", DataType::Number);
+    let local = |local: usize, dt: &DataType| Box::new(Statement::LocalRef(local, dt.clone()));
+    let number = |value: f64| Box::new(Statement::Constant(Value::Number(value)));
+    let say_local = |local: usize| {
+        Statement::BlockCall(
+            "say".to_owned(),
+            vec![Statement::LocalRef(local, DataType::Number)],
+        )
+    };
+    *procedure.code_block() = code([
+        Statement::Let(a),
+        Statement::Assignment(local(a, &DataType::Number), number(1.0)),
+        say_local(a),
+        Statement::Let(b),
+        Statement::Assignment(
+            local(b, &dt),
+            Box::new(Statement::StructureLiteral(
+                vec![Value::Number(1.0), Value::Number(2.0)],
+                dt.clone(),
+            )),
+        ),
+        Statement::Repeat(
+            number(2.0),
+            code([
+                // `b` is needed by the next iteration, so `c` can't use Its variables.
+                Statement::BlockCall(
+                    "say".to_owned(),
+                    vec![Statement::FieldRef(local(b, &dt), 1, dt.clone())],
+                ),
+                Statement::Let(c),
+                Statement::Assignment(local(c, &DataType::Number), number(3.0)),
+                say_local(c),
+            ]),
+        ),
+    ]);
+
+    let builder = refine_stage([procedure], MirRefinementConfig::default());
+    let project = builder.project();
+    let mut variables: Vec<&str> = project.targets[0]
+        .variables
+        .values()
+        .map(|variable| variable.display_name.as_str())
+        .collect();
+    variables.sort();
+    assert_eq!(variables, ["test.a/b:0", "test.a/b:1", "test.c"]);
+
+    let blocks = &project.targets[0].blocks.blocks;
+    let mut written: Vec<&str> = blocks
+        .values()
+        .filter_map(|block| match block.fields.get("VARIABLE") {
+            Some(BlockField::Variable(_, name)) => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    written.sort();
+    assert_eq!(
+        written,
+        ["test.a/b:0", "test.a/b:0", "test.a/b:1", "test.c"]
+    );
+}
+
+#[test]
+fn thread_variables_are_separate_for_recursive_calls() {
+    let mut count = Procedure::new("count", false, [DataType::Number]);
+    let x = count.declare_local("x", DataType::Number);
+    let local = || Box::new(Statement::LocalRef(x, DataType::Number));
+    *count.code_block() = code([
+        Statement::Let(x),
+        Statement::Assignment(
+            local(),
+            Box::new(Statement::ArgumentRef(0, DataType::Number)),
+        ),
+        Statement::If(
+            condition(),
+            code([Statement::ProcedureCall(
+                "count".to_owned(),
+                vec![Statement::LocalRef(x, DataType::Number)],
+            )]),
+            None,
+        ),
+    ]);
+    let mut main = Procedure::new("main", false, []);
+    *main.code_block() = code([Statement::ProcedureCall(
+        "count".to_owned(),
+        vec![Statement::Constant(Value::Number(3.0))],
+    )]);
+
+    let config = || MirRefinementConfig::default().use_thread_variables(true);
+    assert_eq!(
+        refine_procedures([count.clone(), main.clone()], config(), "count"),
+        [
+            "lmsTempVars2_setThreadVariable",
+            "control_if",
+            "  procedures_call"
+        ]
+    );
+
+    let builder = refine_stage([count, main], config());
+    let project = builder.project();
+    assert!(project.extensions.contains("lmsTempVars2"));
+    assert!(project.targets[0].variables.is_empty());
+    let blocks = &project.targets[0].blocks.blocks;
+    // Depth of the nested call is one more than depth of the caller.
+    let depths: Vec<&str> = blocks
+        .values()
+        .filter(|block| block.opcode == "procedures_call")
+        .map(|block| {
+            let mutation = block.mutation.as_ref().unwrap();
+            assert_eq!(mutation.proccode, "count %s %s");
+            match &block.inputs[&mutation.argument_ids[1]].values[0] {
+                Value::Pointer(depth) => blocks[depth].opcode.as_str(),
+                Value::Number(_) => "number",
+                value => unreachable!("{value:?}"),
+            }
+        })
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    assert_eq!(depths, ["number", "operator_add"]);
+    let name = blocks
+        .values()
+        .find(|block| block.opcode == "lmsTempVars2_setThreadVariable")
+        .map(|block| &block.inputs["VAR"].values[0])
+        .unwrap();
+    let Value::Pointer(name) = name else {
+        unreachable!()
+    };
+    assert_eq!(blocks[name].opcode, "operator_join");
+}
+
+#[test]
+fn enums_share_payload_slots() {
+    let dt = DataType::Enum(vec![
+        DataType::Number,
+        DataType::Structure(vec![DataType::Boolean, DataType::Text]),
+        DataType::Structure(vec![]),
+    ]);
+    assert_eq!(
+        dt.flatten(),
+        [DataType::Number, DataType::Text, DataType::Text]
+    );
+}
+
+#[test]
+fn matches_check_tag() {
+    let dt = DataType::Enum(vec![DataType::Number, DataType::Structure(vec![])]);
+    let mut procedure = Procedure::new("test", false, []);
+    let e = procedure.declare_local("e", dt.clone());
+    let local = || Box::new(Statement::LocalRef(e, dt.clone()));
+    let payload = Statement::Payload(local(), 0, dt.clone());
+    *procedure.code_block() = code([
+        Statement::Let(e),
+        Statement::Assignment(
+            local(),
+            Box::new(Statement::Variant(
+                0,
+                Some(Box::new(Statement::Constant(Value::Number(5.0)))),
+                dt.clone(),
+            )),
+        ),
+        Statement::Match(
+            local(),
+            vec![
+                (
+                    0,
+                    code([Statement::BlockCall("say".to_owned(), vec![payload])]),
+                ),
+                (1, code([say()])),
+            ],
+            None,
+        ),
+    ]);
+    assert_eq!(
+        refine_procedure(procedure, MirRefinementConfig::default()),
+        [
+            "data_setvariableto test.e:0",
+            "data_setvariableto test.e:1",
+            "control_if_else",
+            "  looks_say",
+            "else",
+            "  control_if",
+            "    looks_say",
+        ]
+    );
+
+    let variants = (0..6).map(|_| DataType::Structure(vec![])).collect();
+    let dt = DataType::Enum(variants);
+    let arms = (0..6).map(|variant| (variant, code([say()]))).collect();
+    let lines = refine([Statement::Match(
+        Box::new(Statement::Variant(5, None, dt.clone())),
+        arms,
+        Some(code([])),
+    )]);
+    // Arms are split in halves, which are checked one by one.
+    assert_eq!(
+        lines,
+        [
+            "control_if_else",
+            "  control_if_else",
+            "    looks_say",
+            "  else",
+            "    control_if_else",
+            "      looks_say",
+            "    else",
+            "      control_if_else",
+            "        looks_say",
+            "      else",
+            "else",
+            "  control_if_else",
+            "    looks_say",
+            "  else",
+            "    control_if_else",
+            "      looks_say",
+            "    else",
+            "      control_if_else",
+            "        looks_say",
+            "      else",
+        ]
+    );
+}
+
+#[test]
+fn lists_of_structures_use_list_per_field() {
+    let dt = DataType::Structure(vec![DataType::Number, DataType::Text]);
+    let mut stage = Sprite::new("Stage");
+    stage.mark_as_stage();
+    let points = stage.declare_list("points", dt.clone());
+    let list = || {
+        Box::new(Statement::ListRef(
+            points,
+            DataType::List(Box::new(dt.clone())),
+        ))
+    };
+    let mut procedure = Procedure::new("test", false, []);
+    let p = procedure.declare_local("p", dt.clone());
+    let literal = Statement::StructureLiteral(
+        vec![Value::Number(1.0), Value::Text("a".to_owned())],
+        dt.clone(),
+    );
+    *procedure.code_block() = code([
+        Statement::List(list(), ListOperation::Push(Box::new(literal.clone()))),
+        Statement::Let(p),
+        Statement::Assignment(
+            Box::new(Statement::LocalRef(p, dt.clone())),
+            Box::new(Statement::List(list(), ListOperation::Pop)),
+        ),
+        Statement::If(
+            Box::new(Statement::List(
+                list(),
+                ListOperation::Contains(Box::new(literal)),
+            )),
+            code([say()]),
+            None,
+        ),
+    ]);
+    stage.add_procedure(procedure);
+
+    let builder = refine_sprite(stage, MirRefinementConfig::default());
+    let mut names: Vec<String> = builder.project().targets[0]
+        .lists
+        .values()
+        .map(|list| list.display_name.clone())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["points:0", "points:1"]);
+    assert_eq!(
+        outline_procedure(&builder, "test"),
+        [
+            "data_addtolist points:0",
+            "data_addtolist points:1",
+            "data_setvariableto test.item0:0",
+            "data_setvariableto test.item0:1",
+            "data_deleteoflist points:0",
+            "data_deleteoflist points:1",
+            "data_setvariableto test.p:0",
+            "data_setvariableto test.p:1",
+            "data_setvariableto test.contains1:0",
+            "data_setvariableto test.contains1:1",
+            "data_setvariableto test.contains1.index",
+            "data_setvariableto test.contains1.found",
+            "control_repeat",
+            "  data_setvariableto test.contains1.index",
+            "  control_if",
+            "    data_setvariableto test.contains1.found",
+            "control_if",
+            "  looks_say",
+        ]
+    );
+    assert_eq!(
+        declared_variables(&builder),
+        [
+            "test.contains1.found",
+            "test.contains1.index",
+            "test.contains1:0",
+            "test.contains1:1",
+            "test.item0:0",
+            "test.item0:1",
+            "test.p:0",
+            "test.p:1",
+        ]
+    );
+}
+
+#[test]
+fn indices_are_checked_in_debug_builds() {
+    let refine = |config| {
+        let mut stage = Sprite::new("Stage");
+        stage.mark_as_stage();
+        let names = stage.declare_list("names", DataType::Text);
+        let list = || {
+            Box::new(Statement::ListRef(
+                names,
+                DataType::List(Box::new(DataType::Text)),
+            ))
+        };
+        let item = |index| {
+            Statement::BlockCall(
+                "say".to_owned(),
+                vec![Statement::List(
+                    list(),
+                    ListOperation::Index(Box::new(index)),
+                )],
+            )
+        };
+        let mut procedure = Procedure::new("test", false, []);
+        *procedure.code_block() = code([
+            item(Statement::Constant(Value::Number(0.0))),
+            item(Statement::List(list(), ListOperation::Length)),
+        ]);
+        stage.add_procedure(procedure);
+        outline_procedure(&refine_sprite(stage, config), "test")
+    };
+
+    assert_eq!(
+        refine(MirRefinementConfig::default()),
+        ["looks_say", "looks_say"]
+    );
+    // Index is read more than once, so reporters are stored first.
+    assert_eq!(
+        refine(MirRefinementConfig::default().check_bounds(true)),
+        [
+            "control_if",
+            "  control_stop",
+            "looks_say",
+            "data_setvariableto test.index0",
+            "control_if",
+            "  control_stop",
+            "looks_say",
+        ]
+    );
+}
+
+#[test]
+fn fields_resolve_to_flattened_slots() {
+    let inner = DataType::Structure(vec![DataType::Number, DataType::Boolean]);
+    let outer = DataType::Structure(vec![DataType::Text, inner.clone()]);
+    let nested = |target: Statement, index| {
+        let field = Statement::FieldRef(Box::new(target), 1, outer.clone());
+        Statement::FieldRef(Box::new(field), index, inner.clone())
+    };
+    let say = |message| Statement::BlockCall("say".to_owned(), vec![message]);
+    let literal = Statement::StructureLiteral(
+        vec![
+            Value::Text("a".to_owned()),
+            Value::Number(1.0),
+            Value::Text("true".to_owned()),
+        ],
+        outer.clone(),
+    );
+    let mut procedure = Procedure::new("test", false, [outer.clone()]);
+    *procedure.code_block() = code([
+        say(nested(Statement::ArgumentRef(0, outer.clone()), 1)),
+        say(nested(literal, 0)),
+    ]);
+
+    let builder = refine_stage([procedure], MirRefinementConfig::default());
+    let project = builder.project();
+    let blocks = &project.targets[0].blocks.blocks;
+    let mut messages: Vec<String> = blocks
+        .values()
+        .filter(|block| block.opcode == "looks_say")
+        .map(|block| match &block.inputs["MESSAGE"].values[0] {
+            Value::Pointer(reporter) => {
+                let reporter = &blocks[reporter];
+                let Some(BlockField::Argument(name)) = reporter.fields.get("VALUE") else {
+                    unreachable!()
+                };
+                format!("{} {name}", reporter.opcode)
+            }
+            value => format!("{value:?}"),
+        })
+        .collect();
+    messages.sort();
+    assert_eq!(
+        messages,
+        ["Number(1.0)", "argument_reporter_boolean __arg_0:2"]
+    );
+}
+
+#[test]
+fn structure_values_use_temporaries() {
+    let dt = DataType::Structure(vec![DataType::Number, DataType::Text]);
+    let mut procedure = Procedure::new("test", false, [DataType::Boolean]);
+    let v = procedure.declare_local("v", dt.clone());
+    let local = || Box::new(Statement::LocalRef(v, dt.clone()));
+    let join = Statement::BlockCall(
+        "join".to_owned(),
+        vec![
+            Statement::Constant(Value::Text("a".to_owned())),
+            Statement::Constant(Value::Text("b".to_owned())),
+        ],
+    );
+    let structure = Statement::Structure(
+        vec![Statement::Constant(Value::Number(1.0)), join],
+        dt.clone(),
+    );
+    *procedure.code_block() = code([
+        Statement::Let(v),
+        Statement::Assignment(
+            local(),
+            Box::new(Statement::Select(
+                Box::new(Statement::ArgumentRef(0, DataType::Boolean)),
+                Box::new(structure),
+                local(),
+                dt.clone(),
+            )),
+        ),
+        Statement::If(
+            Box::new(Statement::Equals(
+                local(),
+                Box::new(Statement::StructureLiteral(
+                    vec![Value::Number(1.0), Value::Text("ab".to_owned())],
+                    dt.clone(),
+                )),
+                dt.clone(),
+            )),
+            code([say()]),
+            None,
+        ),
+    ]);
+    let builder = refine_stage([procedure], MirRefinementConfig::default());
+    assert_eq!(
+        outline_procedure(&builder, "test"),
+        [
+            "control_if_else",
+            "  data_setvariableto test.struct1:0",
+            "  data_setvariableto test.struct1:1",
+            "  data_setvariableto test.select0:0",
+            "  data_setvariableto test.select0:1",
+            "else",
+            "  data_setvariableto test.select0:0",
+            "  data_setvariableto test.select0:1",
+            "data_setvariableto test.v:0",
+            "data_setvariableto test.v:1",
+            "control_if",
+            "  looks_say",
+        ]
+    );
+    assert_eq!(
+        declared_variables(&builder),
+        [
+            "test.select0:0",
+            "test.select0:1",
+            "test.struct1:0",
+            "test.struct1:1",
+            "test.v:0",
+            "test.v:1",
+        ]
+    );
+}
+
+#[test]
+fn pure_blocks_are_folded() {
+    let mut project = Project::new();
+    let definitions = project.get_definitions_mut();
+    let mut define =
+        |id: &str, opcode: &str, inputs: &[(&str, DataType)], fields: &[(&str, &str)]| {
+            let inputs = inputs
+                .iter()
+                .map(|(name, ty)| BlockInput::new(name.to_string(), ty.clone()));
+            let fields = fields
+                .iter()
+                .map(|(name, value)| MirBlockField::new(name.to_string(), value.to_string()));
+            definitions.define(
+                id,
+                BlockDefinition::new(opcode, true, inputs, fields).mark_pure(),
+            );
+        };
+    let number = |name| (name, DataType::Number);
+    let text = |name| (name, DataType::Text);
+    define(
+        "add",
+        "operator_add",
+        &[number("NUM1"), number("NUM2")],
+        &[],
+    );
+    define(
+        "divide",
+        "operator_divide",
+        &[number("NUM1"), number("NUM2")],
+        &[],
+    );
+    define(
+        "join",
+        "operator_join",
+        &[text("STRING1"), text("STRING2")],
+        &[],
+    );
+    define(
+        "equals",
+        "operator_equals",
+        &[text("OPERAND1"), text("OPERAND2")],
+        &[],
+    );
+    define(
+        "lt",
+        "operator_lt",
+        &[text("OPERAND1"), text("OPERAND2")],
+        &[],
+    );
+    define(
+        "not",
+        "operator_not",
+        &[("OPERAND", DataType::Boolean)],
+        &[],
+    );
+    define(
+        "sin",
+        "operator_mathop",
+        &[number("NUM")],
+        &[("OPERATOR", "sin")],
+    );
+    define(
+        "ln",
+        "operator_mathop",
+        &[number("NUM")],
+        &[("OPERATOR", "ln")],
+    );
+    definitions.define(
+        "say",
+        BlockDefinition::new(
+            "looks_say",
+            false,
+            [BlockInput::new("MESSAGE".to_owned(), DataType::Text)],
+            [],
+        ),
+    );
+    let definitions = project.get_definitions();
+
+    let number = |value: f64| Statement::Constant(Value::Number(value));
+    let text = |value: &str| Statement::Constant(Value::Text(value.to_owned()));
+    let call = |id: &str, args: Vec<Statement>| Statement::BlockCall(id.to_owned(), args);
+    let fold = |value: Statement| {
+        let mut code = code([call("say", vec![value])]);
+        fold_code_block(&mut code, definitions);
+        let [Statement::BlockCall(_, args)] = code.statements() else {
+            unreachable!()
+        };
+        args[0].clone()
+    };
+
+    assert_eq!(
+        fold(call("add", vec![text(" 0x10 "), number(2.0)])),
+        number(18.0)
+    );
+    assert_eq!(
+        fold(call("add", vec![text("1_000"), text("1e3")])),
+        number(1000.0)
+    );
+    assert_eq!(
+        fold(call(
+            "join",
+            vec![call("add", vec![number(0.1), number(0.2)]), number(1e21)]
+        )),
+        text("0.300000000000000041e+21")
+    );
+    assert_eq!(
+        fold(call("join", vec![number(-1e-7), number(123.5)])),
+        text("-1e-7123.5")
+    );
+    // Texts are compared as numbers when both of them are numbers.
+    assert_eq!(
+        fold(call("equals", vec![text("10"), text("1e1")])),
+        text("true")
+    );
+    assert_eq!(
+        fold(call("equals", vec![text("Cat"), text("cAT")])),
+        text("true")
+    );
+    assert_eq!(fold(call("lt", vec![text(" "), number(0.0)])), text("true"));
+    assert_eq!(fold(call("sin", vec![number(180.0)])), number(0.0));
+    // Constant conditions are compared with `true`.
+    assert_eq!(fold(call("not", vec![text("yes")])), text("true"));
+
+    let argument = Statement::ArgumentRef(0, DataType::Boolean);
+    let not = |operand| call("not", vec![operand]);
+    assert_eq!(fold(not(not(argument.clone()))), argument);
+    // Infinities and results depending on the JavaScript engine are left to Scratch.
+    let division = call("divide", vec![number(1.0), number(0.0)]);
+    assert_eq!(fold(division.clone()), division);
+    let logarithm = call("ln", vec![number(2.0)]);
+    assert_eq!(fold(logarithm.clone()), logarithm);
+    // Calls with missing inputs are left to the verifier.
+    let missing = call("add", vec![number(1.0)]);
+    assert_eq!(fold(missing.clone()), missing);
+    let empty = not(call("not", Vec::new()));
+    assert_eq!(fold(empty.clone()), empty);
+}
+
+#[test]
+fn short_procedures_are_inlined() {
+    let number = DataType::Number;
+    let arg = || Box::new(Statement::ArgumentRef(0, DataType::Number));
+    let mut double = Procedure::new("main::double", false, [number.clone()]);
+    double.set_return_type(number.clone());
+    let x = double.declare_local("x", number.clone());
+    *double.code_block() = code([
+        Statement::Let(x),
+        Statement::Assignment(
+            Box::new(Statement::LocalRef(x, number.clone())),
+            Box::new(Statement::BlockCall("add".to_owned(), vec![*arg(), *arg()])),
+        ),
+        Statement::Return(Some(Box::new(Statement::LocalRef(x, number.clone())))),
+    ]);
+    let mut kept = double.clone();
+    kept.name = "main::kept".to_owned();
+    kept.set_inline(Inline::Never);
+    let mut count = Procedure::new("main::count", false, [number.clone()]);
+    *count.code_block() = code([Statement::ProcedureCall(
+        "main::count".to_owned(),
+        vec![*arg()],
+    )]);
+    count.set_inline(Inline::Always);
+
+    let mut main = Procedure::new("main::main", false, []);
+    let y = main.declare_local("x", number.clone());
+    let local = |index| Statement::LocalRef(index, DataType::Number);
+    let call = |name: &str, arg| Statement::ProcedureCall(name.to_owned(), vec![arg]);
+    *main.code_block() = code([
+        Statement::Let(y),
+        Statement::Assignment(
+            Box::new(local(y)),
+            Box::new(call(
+                "main::double",
+                Statement::Constant(Value::Number(2.0)),
+            )),
+        ),
+        Statement::Assignment(
+            Box::new(local(y)),
+            Box::new(call("main::double", call("main::kept", local(y)))),
+        ),
+        call("main::count", local(y)),
+    ]);
+
+    let mut project = Project::new();
+    let mut stage = Sprite::new("Stage");
+    stage.mark_as_stage();
+    for procedure in [double, kept, count, main] {
+        stage.add_procedure(procedure);
+    }
+    project.add_sprite(stage);
+    inline_procedures(&mut project);
+
+    let main = &project.sprites()[0].procedures()[3];
+    let names: Vec<&str> = main.locals().iter().map(|local| local.name()).collect();
+    assert_eq!(names, ["x", "double.x", "double.x", "double.arg0"]);
+    let add = |first: Statement| Statement::BlockCall("add".to_owned(), vec![first.clone(), first]);
+    let assign = |target, value| Statement::Assignment(Box::new(target), Box::new(value));
+    let two = Statement::Constant(Value::Number(2.0));
+    assert_eq!(
+        main.body().statements(),
+        [
+            Statement::Let(y),
+            // Constant is substituted, while locals of the callee are renamed.
+            Statement::Let(1),
+            assign(local(1), add(two.clone())),
+            assign(local(y), local(1)),
+            // Calls are evaluated before the body.
+            Statement::Let(3),
+            assign(local(3), call("main::kept", local(y))),
+            Statement::Let(2),
+            assign(local(2), add(local(3))),
+            assign(local(y), local(2)),
+            // Recursive procedures are never inlined.
+            call("main::count", local(y)),
+        ]
+    );
+}
+
+#[test]
+fn procedures_called_by_any_sprite_are_not_reported() {
+    let mut project: Project = r#"
+        sprite "Stage" stage {
+            proc "main"() entry {
+                call "shared"();
+            }
+
+            proc "shared"() {}
+
+            proc "unused"() {}
+        }
+
+        sprite "Cat" {
+            proc "main"() {}
+
+            proc "shared"() {}
+
+            proc "unused"() {}
+        }
+    "#
+    .parse()
+    .unwrap();
+    let dead = eliminate_dead_code(&mut project);
+
+    // Copies of procedures which are called elsewhere are removed silently.
+    assert_eq!(dead.procedures(), ["unused"]);
+    assert!(project.sprites()[1].procedures().is_empty());
+}
+
+#[test]
+fn nested_calls_are_not_inlined() {
+    let mut name = Procedure::new("main::name", false, []);
+    name.set_return_type(DataType::Text);
+    name.set_inline(Inline::Always);
+    *name.code_block() = code([Statement::Return(Some(Box::new(Statement::Constant(
+        Value::Text("cat".to_owned()),
+    ))))]);
+    let mut main = Procedure::new("main::main", false, []);
+    // Only calls which are whole statements, assigned or returned values are inlined.
+    let say = Statement::BlockCall(
+        "say".to_owned(),
+        vec![Statement::ProcedureCall(
+            "main::name".to_owned(),
+            Vec::new(),
+        )],
+    );
+    *main.code_block() = code([say.clone()]);
+
+    let mut project = Project::new();
+    let mut stage = Sprite::new("Stage");
+    stage.mark_as_stage();
+    stage.add_procedure(name);
+    stage.add_procedure(main);
+    project.add_sprite(stage);
+    inline_procedures(&mut project);
+
+    let main = &project.sprites()[0].procedures()[1];
+    assert!(main.locals().is_empty());
+    assert_eq!(main.body().statements(), [say]);
+}
+
+#[test]
+fn dead_code_is_removed() {
+    let mut project = Project::new();
+    project.get_definitions_mut().define(
+        "say",
+        BlockDefinition::new(
+            "looks_say",
+            false,
+            [BlockInput::new("MESSAGE".to_owned(), DataType::Text)],
+            [],
+        ),
+    );
+    let call = |name: &str| Statement::ProcedureCall(name.to_owned(), Vec::new());
+    let procedure = |name: &str, body: Vec<Statement>| {
+        let mut procedure = Procedure::new(name, false, []);
+        *procedure.code_block() = code(body);
+        procedure
+    };
+
+    let mut main = procedure("main", vec![]);
+    let unread = main.declare_local("unread", DataType::Number);
+    let called = main.declare_local("called", DataType::Number);
+    let kept = main.declare_local("kept", DataType::Number);
+    let local = |index| Box::new(Statement::LocalRef(index, DataType::Number));
+    let number = || Box::new(Statement::Constant(Value::Number(1.0)));
+    *main.code_block() = code([
+        Statement::Let(unread),
+        Statement::Assignment(local(unread), number()),
+        // Value with side effects is assigned even when It is never read.
+        Statement::Let(called),
+        Statement::Assignment(local(called), Box::new(call("helper"))),
+        Statement::Let(kept),
+        Statement::Assignment(local(kept), number()),
+        Statement::Forever(code([
+            Statement::If(
+                condition(),
+                code([Statement::Break]),
+                Some(code([Statement::Continue, say()])),
+            ),
+            say(),
+        ])),
+        Statement::BlockCall("say".to_owned(), vec![*local(kept)]),
+        Statement::If(
+            condition(),
+            code([Statement::Return(None)]),
+            Some(code([Statement::Forever(code([say()]))])),
+        ),
+        say(),
+        call("unused"),
+    ]);
+    main.mark_as_entry();
+
+    let mut stage = Sprite::new("Stage");
+    stage.mark_as_stage();
+    stage.add_procedure(main);
+    stage.add_procedure(procedure("helper", vec![call("nested")]));
+    stage.add_procedure(procedure("nested", vec![]));
+    stage.add_procedure(procedure("unused", vec![call("recursive")]));
+    stage.add_procedure(procedure("recursive", vec![call("recursive")]));
+    project.add_sprite(stage);
+    let dead = eliminate_dead_code(&mut project);
+
+    let stage = &project.sprites()[0];
+    let procedures: Vec<&str> = stage
+        .procedures()
+        .iter()
+        .map(|procedure| procedure.name())
+        .collect();
+    assert_eq!(procedures, ["main", "helper", "nested"]);
+    let locals: Vec<&str> = stage.procedures()[0]
+        .locals()
+        .iter()
+        .map(|local| local.name())
+        .collect();
+    assert_eq!(locals, ["called", "kept"]);
+    let statements = stage.procedures()[0].body().statements();
+    assert_eq!(
+        statements[..2],
+        [
+            Statement::Let(0),
+            Statement::Assignment(local(0), Box::new(call("helper")))
+        ]
+    );
+    assert_eq!(
+        statements[5],
+        Statement::BlockCall("say".to_owned(), vec![*local(1)])
+    );
+    assert_eq!(statements.len(), 7);
+
+    assert_eq!(dead.procedures(), ["unused", "recursive"]);
+    assert_eq!(dead.locals(), [("unread".to_owned(), "main".to_owned())]);
+    // Statements after `continue` and both `if`s which always stop.
+    assert_eq!(dead.statements(), 4);
+}
+
+#[test]
+fn undefined_blocks_are_left_to_the_verifier() {
+    let missing = |args| Statement::BlockCall("missing".to_owned(), args);
+    let mut main = Procedure::new("main", false, []);
+    let x = main.declare_local("x", DataType::Number);
+    *main.code_block() = code([
+        Statement::Let(x),
+        Statement::Assignment(
+            Box::new(Statement::LocalRef(x, DataType::Number)),
+            Box::new(missing(vec![missing(Vec::new())])),
+        ),
+    ]);
+    main.mark_as_entry();
+    let mut stage = Sprite::new("Stage");
+    stage.mark_as_stage();
+    stage.add_procedure(main);
+    let mut project = Project::new();
+    project.add_sprite(stage);
+
+    // Unknown block may have side effects, so Its value is still assigned.
+    assert!(eliminate_dead_code(&mut project).is_empty());
+    super::fold_constants(&mut project);
+    let errors = super::verify(&project).unwrap_err();
+    assert!(errors
+        .iter()
+        .all(|error| error.kind == VerifyErrorKind::UndefinedBlock("missing".to_owned())));
+}
+
+/// Every statement in the text format, as written by `Display`.
+const TEXT: &str = r#"block "join" = expression pure operator_join(STRING1: text, STRING2: text);
+block "sin" = expression pure operator_mathop(NUM: number) [OPERATOR = "sin"];
+
+sprite "Stage" stage {
+    costume "cat" "cat.svg";
+    sound "meow" "meow.wav";
+
+    list 0 "scores": {number, text};
+
+    proc "main"(number, enum {{}, {number}}) -> {number, text} warp entry inline(never) {
+        local %0 "pair": {number, text};
+        local %1 "\"quoted\"\n": boolean;
+
+        let %0;
+        %0: {number, text} = literal(-1.5, "a\tb"): {number, text};
+        field(%0: {number, text}, 1 of {number, text}) = block "join"("x", block "sin"(0.5));
+        let %1;
+        %1: boolean = equals(%0: {number, text}, struct(arg 0: number, "b"): {number, text}): {number, text};
+        if %1: boolean {
+            push(list 0: [{number, text}], %0: {number, text});
+            insert(list 0: [{number, text}], 0, pop(list 0: [{number, text}]));
+        } else {}
+        while contains(list 0: [{number, text}], %0: {number, text}) {
+            delete(list 0: [{number, text}], length(list 0: [{number, text}]));
+            continue;
+        }
+        repeat select(%1: boolean, 1, 2): number {
+            break;
+        }
+        match arg 1: enum {{}, {number}} {
+            0 => {}
+            1 => {
+                call "other"(payload(arg 1: enum {{}, {number}}, 1 of enum {{}, {number}}), variant 1(3): enum {{}, {number}});
+            }
+            _ => {
+                return index(list 0: [{number, text}], var "id" "name": number);
+            }
+        }
+        forever {
+            return;
+        }
+    }
+}
+
+sprite "Cat" {
+    proc "other"(number, enum {{}, {number}}) inline {}
+}
+"#;
+
+#[test]
+fn text_format_round_trips() {
+    let project: Project = TEXT.parse().unwrap();
+    assert_eq!(project.to_string(), TEXT);
+
+    let stage = &project.sprites()[0];
+    assert_eq!(stage.lists()[0].name(), "scores");
+    let main = &stage.procedures()[0];
+    assert!(main.is_warp() && main.is_entry());
+    assert_eq!(main.inline(), Inline::Never);
+    assert_eq!(main.locals()[1].name(), "\"quoted\"\n");
+    assert_eq!(
+        main.body().statements()[1],
+        Statement::Assignment(
+            Box::new(Statement::LocalRef(
+                0,
+                DataType::Structure(vec![DataType::Number, DataType::Text])
+            )),
+            Box::new(Statement::StructureLiteral(
+                vec![Value::Number(-1.5), Value::Text("a\tb".to_owned())],
+                DataType::Structure(vec![DataType::Number, DataType::Text])
+            ))
+        )
+    );
+}
+
+#[test]
+fn nested_fields_round_trip() {
+    let text = r#"sprite "Stage" stage {
+    proc "main"() {
+        local %0 "nested": {{number, text}, number};
+
+        let %0;
+        field(field(%0: {{number, text}, number}, 0 of {{number, text}, number}), 1 of {number, text}) = "a";
+    }
+}
+"#;
+    let project: Project = text.parse().unwrap();
+    assert_eq!(project.to_string(), text);
+
+    let inner = DataType::Structure(vec![DataType::Number, DataType::Text]);
+    let outer = DataType::Structure(vec![inner.clone(), DataType::Number]);
+    let Statement::Assignment(target, _) =
+        &project.sprites()[0].procedures()[0].body().statements()[1]
+    else {
+        panic!("Expected assignment")
+    };
+    // Every field is typed by the structure It is read from.
+    assert_eq!(
+        **target,
+        Statement::FieldRef(
+            Box::new(Statement::FieldRef(
+                Box::new(Statement::LocalRef(0, outer.clone())),
+                0,
+                outer
+            )),
+            1,
+            inner
+        )
+    );
+}
+
+#[test]
+fn text_format_reports_errors() {
+    let error = |text: &str| text.parse::<Project>().unwrap_err().to_string();
+    assert_eq!(
+        error("sprite \"Stage\" {\n    proc \"main\"() {\n        block \"say\"(1);\n    }\n}"),
+        "3:15: expected defined block, found \"say\""
+    );
+    assert_eq!(
+        error(
+            "sprite \"Stage\" {\n    proc \"main\"() {\n        local %1 \"x\": number;\n    }\n}"
+        ),
+        "3:16: expected local %0, found `1`"
+    );
+    assert_eq!(
+        error("sprite \"Stage\" { proc \"main\"() { 1 = ; } }"),
+        "1:38: expected expression, found `;`"
+    );
+}
+
+#[test]
+fn refines_textual_mir() {
+    let project: Project = r#"
+        block "say" = looks_say(MESSAGE: text);
+
+        sprite "Stage" stage {
+            proc "test"(number) {
+                local %0 "x": number;
+
+                let %0;
+                %0: number = arg 0: number;
+                repeat %0: number {
+                    block "say"(%0: number);
+                }
+            }
+        }
+    "#
+    .parse()
+    .unwrap();
+    let builder = MirRefinery::new(MirRefinementConfig::default())
+        .refine_project(project)
+        .unwrap();
+    assert_eq!(
+        outline_procedure(&builder, "test"),
+        ["data_setvariableto test.x", "control_repeat", "  looks_say"]
+    );
+}
+
+#[test]
+fn malformed_mir_is_reported() {
+    let project: Project = r#"
+        block "say" = looks_say(MESSAGE: text);
+
+        sprite "Stage" stage {
+            proc "main"(number) -> number {
+                local %0 "pair": {number, text};
+
+                1 = 2;
+                block "say"();
+                block "say"(block "say"("hi"));
+                %0: {number, text} = 1;
+                field(%0: {number, text}, 2 of {number, text});
+                call "missing"();
+                break;
+                return;
+            }
+        }
+    "#
+    .parse()
+    .unwrap();
+    let errors = super::verify(&project).unwrap_err();
+    let kinds: Vec<_> = errors.iter().map(|error| error.kind.clone()).collect();
+    assert_eq!(
+        kinds,
+        [
+            VerifyErrorKind::NotPlace,
+            VerifyErrorKind::ArgumentCount {
+                callee: "say".to_owned(),
+                expected: 1,
+                found: 0
+            },
+            VerifyErrorKind::NotValue,
+            VerifyErrorKind::PrimitiveMismatch(DataType::Structure(vec![
+                DataType::Number,
+                DataType::Text
+            ])),
+            VerifyErrorKind::FieldOutOfRange { index: 2, count: 2 },
+            VerifyErrorKind::UndefinedProcedure("missing".to_owned()),
+            VerifyErrorKind::JumpOutsideLoop,
+            VerifyErrorKind::MissingReturnValue,
+        ]
+    );
+    assert_eq!(errors[0].context.procedure.as_deref(), Some("main"));
+    assert_eq!(errors[0].context.statement.as_deref(), Some("1 = 2"));
+}
+
+#[test]
+fn refinement_errors_are_returned() {
+    let refine = |text: &str| {
+        let project: Project = text.parse().unwrap();
+        match MirRefinery::new(MirRefinementConfig::default()).refine_project(project) {
+            Ok(_) => panic!("refinement should fail"),
+            Err(error) => error,
+        }
+    };
+
+    let error = refine(
+        r#"
+        sprite "Cat" {
+            costume "cat" "does/not/exist.svg";
+        }
+    "#,
+    );
+    assert!(matches!(
+        &error.kind,
+        RefineErrorKind::Asset { name, .. } if name == "cat"
+    ));
+    assert_eq!(error.context.sprite, "Cat");
+    assert_eq!(error.context.procedure, None);
+
+    let source = std::env::temp_dir().join(format!("scratchc-asset-{}", std::process::id()));
+    std::fs::write(&source, "").unwrap();
+    let error = refine(&format!(
+        "sprite \"Cat\" {{\n    costume \"cat\" {:?};\n}}",
+        source.display().to_string()
+    ));
+    assert!(matches!(
+        &error.kind,
+        RefineErrorKind::Asset { error, .. } if error.kind() == std::io::ErrorKind::InvalidInput
+    ));
+
+    let error = refine(
+        r#"
+        sprite "Cat" {
+            proc "main"() {
+                if "true" {
+                    continue;
+                }
+            }
+        }
+    "#,
+    );
+    assert!(matches!(
+        error.kind,
+        RefineErrorKind::Malformed(VerifyErrorKind::JumpOutsideLoop)
+    ));
+    assert_eq!(error.context.procedure.as_deref(), Some("main"));
+    assert_eq!(error.context.statement.as_deref(), Some("continue"));
+
+    // Values which don't fit where they are used.
+    let malformed = |body: &str| {
+        let error = refine(&format!(
+            r#"
+            block "say" = looks_say(MESSAGE: text);
+
+            sprite "Cat" {{
+                proc "main"({{number, text}}) {{
+                    local %0 "pair": {{number, text}};
+
+                    let %0;
+                    {body}
+                }}
+            }}
+        "#
+        ));
+        match error.kind {
+            RefineErrorKind::Malformed(kind) => kind,
+            kind => panic!("unexpected error {kind:?}"),
+        }
+    };
+    let dt = DataType::Structure(vec![DataType::Number, DataType::Text]);
+    assert_eq!(
+        malformed(r#"block "say"(arg 0: {number, text});"#),
+        VerifyErrorKind::NotPrimitive(dt.clone())
+    );
+    assert_eq!(
+        malformed("%0: {number, text} = 1;"),
+        VerifyErrorKind::FieldOutOfRange { index: 1, count: 1 }
+    );
+    assert_eq!(
+        malformed("%0: {number, text} = struct(1): {number, text};"),
+        VerifyErrorKind::FieldCount {
+            expected: 2,
+            found: 1
+        }
+    );
+
+    let error = refine(
+        r#"
+        sprite "Cat" {
+            proc "main"([number]) {
+            }
+        }
+    "#,
+    );
+    assert!(matches!(
+        error.kind,
+        RefineErrorKind::Malformed(VerifyErrorKind::NotPrimitive(DataType::List(_)))
+    ));
+    assert_eq!(error.context.procedure.as_deref(), Some("main"));
+    assert_eq!(error.context.statement, None);
+}
+
+#[test]
+fn sprites_are_refined_in_parallel() {
+    fn shared_between_threads<T: Send + Sync>() {}
+    shared_between_threads::<Project>();
+    shared_between_threads::<MirRefinery>();
+
+    let mut project = Project::new();
+    for i in 0..20 {
+        let mut sprite = Sprite::new(format!("Sprite{i}"));
+        sprite.add_procedure(Procedure::new(format!("proc{i}"), false, []));
+        project.add_sprite(sprite);
+    }
+    let builder = MirRefinery::new(MirRefinementConfig::default())
+        .refine_project(project)
+        .unwrap();
+    let targets = &builder.project().targets;
+    assert_eq!(targets.len(), 20);
+    for (i, target) in targets.iter().enumerate() {
+        assert_eq!(target.name, format!("Sprite{i}"));
+        let proccodes: Vec<&str> = target
+            .blocks
+            .blocks
+            .values()
+            .filter_map(|block| Some(block.mutation.as_ref()?.proccode.as_str()))
+            .collect();
+        assert_eq!(proccodes, [format!("proc{i}")]);
+    }
+}
+
+#[test]
+fn sprite_properties_are_refined() {
+    const TEXT: &str = r#"sprite "Stage" stage {
+    volume 50;
+}
+
+sprite "Front" {
+    position -20 15;
+    size 50;
+    layer 3;
+    direction -90;
+    visible false;
+    rotation_style "left-right";
+    draggable true;
+}
+
+sprite "Back" {
+}
+
+sprite "Middle" {
+    layer 2;
+}
+"#;
+    let project: Project = TEXT.parse().unwrap();
+    assert_eq!(project.to_string(), TEXT);
+    assert_eq!(
+        project.sprites()[1].properties(),
+        &SpriteProperties::default()
+            .position(-20, 15)
+            .size(50)
+            .layer(3)
+            .direction(-90)
+            .visible(false)
+            .rotation_style(RotationStyle::LeftRight)
+            .draggable(true)
+    );
+
+    let builder = MirRefinery::new(MirRefinementConfig::default())
+        .refine_project(project)
+        .unwrap();
+    let targets = &builder.project().targets;
+    let layers: Vec<(&str, u32)> = targets
+        .iter()
+        .map(|target| (target.name.as_str(), target.layer_order))
+        .collect();
+    assert_eq!(
+        layers,
+        [("Stage", 0), ("Front", 3), ("Back", 1), ("Middle", 2)]
+    );
+    assert_eq!(targets[0].volume, 50);
+    let front = &targets[1];
+    assert_eq!((front.x, front.y, front.size), (-20, 15, 50));
+    assert_eq!(front.direction, -90);
+    assert!(!front.visible && front.draggable);
+    assert_eq!(front.rotation_style, "left-right");
+}
+
+#[test]
+fn event_handlers_and_broadcasts_are_refined() {
+    const TEXT: &str = r#"sprite "Stage" stage {
+    when flag -> "start";
+    when timer > 2.5 -> "start";
+
+    proc "start"() {
+        broadcast "go";
+        broadcast "ready" and wait;
+    }
+}
+
+sprite "Cat" {
+    when broadcast "go" -> "run";
+    when key "space" -> "run";
+    when clicked -> "run";
+    when clone -> "run";
+    when backdrop "night" -> "run";
+
+    proc "run"() {}
+
+    proc "unused"() {}
+}
+"#;
+    let mut project: Project = TEXT.parse().unwrap();
+    assert_eq!(project.to_string(), TEXT);
+    assert_eq!(
+        project.sprites()[1].handlers()[0],
+        EventHandler::new(Event::BroadcastReceived("go".to_owned()), "run")
+    );
+    assert!(super::verify(&project).is_ok());
+
+    // Procedures of handlers are kept.
+    let dead = eliminate_dead_code(&mut project);
+    assert_eq!(dead.procedures(), ["unused"]);
+
+    let builder = MirRefinery::new(MirRefinementConfig::default())
+        .refine_project(project)
+        .unwrap();
+    let targets = &builder.project().targets;
+    let broadcasts: HashMap<&str, &str> = targets[0]
+        .broadcasts
+        .iter()
+        .map(|(id, name)| (id.as_str(), name.as_str()))
+        .collect();
+    assert_eq!(
+        broadcasts,
+        HashMap::from([("go", "go"), ("ready", "ready")])
+    );
+    assert!(targets[1].broadcasts.is_empty());
+
+    let hats = |index: usize| {
+        let blocks = &targets[index].blocks.blocks;
+        let mut hats: Vec<Vec<String>> = blocks
+            .iter()
+            .filter(|(_, block)| block.top_level && block.opcode != "procedures_definition")
+            .map(|(id, _)| {
+                let mut lines = Vec::new();
+                outline(blocks, Some(id.clone()), 0, &mut lines);
+                lines
+            })
+            .collect();
+        hats.sort();
+        hats
+    };
+    let calls = |hats: &[&str]| -> Vec<Vec<String>> {
+        hats.iter()
+            .map(|hat| vec![hat.to_string(), "procedures_call".to_owned()])
+            .collect()
+    };
+    assert_eq!(
+        hats(0),
+        calls(&["event_whenflagclicked", "event_whengreaterthan"])
+    );
+    assert_eq!(
+        hats(1),
+        calls(&[
+            "control_start_as_clone",
+            "event_whenbackdropswitchesto",
+            "event_whenbroadcastreceived",
+            "event_whenkeypressed",
+            "event_whenthisspriteclicked",
+        ])
+    );
+    assert_eq!(
+        outline_procedure(&builder, "start"),
+        ["event_broadcast", "event_broadcastandwait"]
+    );
+
+    let project: Project = r#"
+        sprite "Cat" {
+            when flag -> "missing";
+        }
+    "#
+    .parse()
+    .unwrap();
+    let errors = super::verify(&project).unwrap_err();
+    assert_eq!(
+        errors[0].kind,
+        VerifyErrorKind::UndefinedProcedure("missing".to_owned())
+    );
+}
+
+#[test]
+fn clones_take_spawned_values() {
+    const TEXT: &str = r#"sprite "Stage" stage {
+    proc "main"() entry {
+        spawn "Ball"(3, literal(1, 2): {number, number});
+        spawn "Dot"("red");
+    }
+}
+
+sprite "Ball" {
+    field 0 "speed": number;
+    field 1 "position": {number, number};
+
+    when clone -> "move";
+
+    proc "move"() {
+        field(self 1: {number, number}, 0 of {number, number}) = self 0: number;
+        spawn "Ball"(1, self 1: {number, number});
+    }
+}
+
+sprite "Dot" {
+    field 0 "color": text;
+}
+"#;
+    let project: Project = TEXT.parse().unwrap();
+    assert_eq!(project.to_string(), TEXT);
+    assert_eq!(project.sprites()[1].fields()[0].name(), "speed");
+    super::verify(&project).unwrap();
+
+    let builder = MirRefinery::new(MirRefinementConfig::default())
+        .refine_project(project)
+        .unwrap();
+    let targets = &builder.project().targets;
+    let mut lists: Vec<(&str, &str)> = targets[0]
+        .lists
+        .iter()
+        .map(|(id, list)| (id.as_str(), list.display_name.as_str()))
+        .collect();
+    lists.sort();
+    assert_eq!(
+        lists,
+        [
+            ("Ball.spawn:0", "Ball.spawn:0"),
+            ("Ball.spawn:1", "Ball.spawn:1"),
+            ("Ball.spawn:2", "Ball.spawn:2"),
+            ("Dot.spawn:0", "Dot.spawn:0"),
+        ]
+    );
+    assert_eq!(
+        outline_procedure(&builder, "main"),
+        [
+            "data_addtolist Ball.spawn:0",
+            "data_addtolist Ball.spawn:1",
+            "data_addtolist Ball.spawn:2",
+            "control_create_clone_of",
+            "data_addtolist Dot.spawn:0",
+            "control_create_clone_of",
+        ]
+    );
+    let blocks = &targets[0].blocks.blocks;
+    let clone = blocks
+        .values()
+        .find(|block| block.opcode == "control_create_clone_of")
+        .unwrap();
+    let input = &clone.inputs["CLONE_OPTION"];
+    let Value::Pointer(menu) = &input.values[0] else {
+        panic!("menu should be a block");
+    };
+    assert_eq!(input.kind, 1);
+    assert!(blocks[menu].shadow);
+
+    let scripts = |index: usize| {
+        let blocks = &targets[index].blocks.blocks;
+        let (id, _) = blocks
+            .iter()
+            .find(|(_, block)| block.opcode == "control_start_as_clone")
+            .unwrap();
+        let mut lines = Vec::new();
+        outline(blocks, Some(id.clone()), 0, &mut lines);
+        lines
+    };
+    assert_eq!(
+        scripts(1),
+        [
+            "control_start_as_clone",
+            "control_if",
+            "  data_setvariableto self.spawned",
+            "  data_setvariableto self.speed",
+            "  data_deleteoflist Ball.spawn:0",
+            "  data_setvariableto self.position:0",
+            "  data_deleteoflist Ball.spawn:1",
+            "  data_setvariableto self.position:1",
+            "  data_deleteoflist Ball.spawn:2",
+            "procedures_call",
+        ]
+    );
+    // Clone spawning clone of Its own sprite doesn't pass on Its flag.
+    let blocks = &targets[1].blocks.blocks;
+    let definition = blocks
+        .values()
+        .find(|block| block.opcode == "procedures_definition")
+        .unwrap();
+    let mut lines = Vec::new();
+    outline(blocks, definition.next.clone(), 0, &mut lines);
+    assert_eq!(
+        lines[1..],
+        [
+            "data_addtolist Ball.spawn:0",
+            "data_addtolist Ball.spawn:1",
+            "data_addtolist Ball.spawn:2",
+            "data_setvariableto self.spawned",
+            "control_create_clone_of",
+            "data_setvariableto self.spawned",
+        ]
+    );
+    // Clones take their values even without handlers.
+    assert_eq!(
+        scripts(2),
+        [
+            "control_start_as_clone",
+            "control_if",
+            "  data_setvariableto self.spawned",
+            "  data_setvariableto self.color",
+            "  data_deleteoflist Dot.spawn:0",
+        ]
+    );
+
+    let project: Project = r#"
+        sprite "Stage" stage {
+            proc "main"() {
+                spawn "Stage"();
+                spawn "Missing"();
+                spawn "Ball"(1, 2);
+                self 0: number;
+            }
+        }
+
+        sprite "Ball" {
+            field 0 "speed": number;
+        }
+    "#
+    .parse()
+    .unwrap();
+    let errors = super::verify(&project).unwrap_err();
+    let kinds: Vec<_> = errors.iter().map(|error| error.kind.clone()).collect();
+    assert_eq!(
+        kinds,
+        [
+            VerifyErrorKind::CloneOfStage,
+            VerifyErrorKind::UndefinedSprite("Missing".to_owned()),
+            VerifyErrorKind::ArgumentCount {
+                callee: "Ball".to_owned(),
+                expected: 1,
+                found: 2
+            },
+            VerifyErrorKind::UndefinedField(0),
+        ]
+    );
+}
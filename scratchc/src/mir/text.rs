@@ -0,0 +1,464 @@
+//! Human readable text format of the MIR, meant for debugging and tests.
+//! Projects are written with the `Display` implementations and read back by the
+//! [`FromStr`](std::str::FromStr) implementation of [`Project`].
+//!
+//! ```text
+//! block "say" = looks_say(MESSAGE: text);
+//!
+//! sprite "Stage" stage {
+//!     field 0 "score": number;
+//!
+//!     list 0 "scores": number;
+//!
+//!     when flag -> "main::main";
+//!
+//!     proc "main::main"(number) -> {number, text} warp entry {
+//!         local %0 "x": number;
+//!
+//!         let %0;
+//!         %0 = arg 0: number;
+//!         block "say"(%0: number);
+//!         return literal(1, "a"): {number, text};
+//!     }
+//! }
+//! ```
+//!
+//! Only block definitions referenced by the project are written.
+
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display, Formatter, Write},
+};
+
+use pawgen::schema::Value;
+
+use super::{
+    BlockDefinition, CodeBlock, DataType, Event, EventHandler, Inline, ListOperation, Procedure,
+    Project, Sprite, SpriteProperties, Statement,
+};
+
+const INDENT: &str = "    ";
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text => f.write_str("text"),
+            Self::Number => f.write_str("number"),
+            Self::Boolean => f.write_str("boolean"),
+            Self::Structure(fields) => write!(f, "{{{}}}", Separated(fields)),
+            Self::Enum(variants) => write!(f, "enum {{{}}}", Separated(variants)),
+            Self::List(item) => write!(f, "[{item}]"),
+        }
+    }
+}
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_stmt(f, self, 0)
+    }
+}
+
+impl Display for Procedure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_procedure(f, self, 0)
+    }
+}
+
+impl Display for Sprite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "sprite {:?}", self.name)?;
+        if self.is_stage {
+            f.write_str(" stage")?;
+        }
+        f.write_str(" {\n")?;
+
+        let mut sections = Vec::new();
+        let mut assets = String::new();
+        for costume in self.costumes.iter() {
+            let source = costume.source.to_string_lossy();
+            writeln!(assets, "{INDENT}costume {:?} {source:?};", costume.name)?;
+        }
+        for sound in self.sounds.iter() {
+            let source = sound.source.to_string_lossy();
+            writeln!(assets, "{INDENT}sound {:?} {source:?};", sound.name)?;
+        }
+        sections.push(assets);
+        sections.push(properties(&self.properties)?);
+        let mut fields = String::new();
+        for (index, field) in self.fields.iter().enumerate() {
+            writeln!(
+                fields,
+                "{INDENT}field {index} {:?}: {};",
+                field.name, field.dt
+            )?;
+        }
+        sections.push(fields);
+        let mut lists = String::new();
+        for (index, list) in self.lists.iter().enumerate() {
+            writeln!(
+                lists,
+                "{INDENT}list {index} {:?}: {};",
+                list.name, list.item
+            )?;
+        }
+        sections.push(lists);
+        let mut handlers = String::new();
+        for handler in self.handlers.iter() {
+            writeln!(handlers, "{INDENT}{handler};")?;
+        }
+        sections.push(handlers);
+        for procedure in self.procedures.iter() {
+            let mut text = String::new();
+            write_procedure(&mut text, procedure, 1)?;
+            sections.push(text);
+        }
+
+        let sections: Vec<String> = sections
+            .into_iter()
+            .filter(|section| !section.is_empty())
+            .collect();
+        f.write_str(&sections.join("\n"))?;
+        f.write_str("}\n")
+    }
+}
+
+impl Display for EventHandler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("when ")?;
+        match &self.event {
+            Event::FlagClicked => f.write_str("flag")?,
+            Event::BroadcastReceived(name) => write!(f, "broadcast {name:?}")?,
+            Event::KeyPressed(key) => write!(f, "key {key:?}")?,
+            Event::Clicked => f.write_str("clicked")?,
+            Event::CloneStarted => f.write_str("clone")?,
+            Event::BackdropSwitched(name) => write!(f, "backdrop {name:?}")?,
+            Event::Threshold(sensor, value) => {
+                let sensor = sensor.as_str().to_lowercase();
+                write!(f, "{sensor} > {value}")?
+            }
+        }
+        write!(f, " -> {:?}", self.procedure)
+    }
+}
+
+/// Properties which differ from their defaults, one per line.
+fn properties(properties: &SpriteProperties) -> Result<String, fmt::Error> {
+    let defaults = SpriteProperties::default();
+    let mut text = String::new();
+    if properties.position != defaults.position {
+        let (x, y) = properties.position;
+        writeln!(text, "{INDENT}position {x} {y};")?;
+    }
+    let numbers = [
+        ("volume", properties.volume, defaults.volume),
+        ("size", properties.size, defaults.size),
+        ("layer", properties.layer, defaults.layer),
+    ];
+    for (name, value, default) in numbers {
+        if value != default {
+            writeln!(text, "{INDENT}{name} {value};")?;
+        }
+    }
+    if properties.direction != defaults.direction {
+        writeln!(text, "{INDENT}direction {};", properties.direction)?;
+    }
+    if properties.visible != defaults.visible {
+        writeln!(text, "{INDENT}visible {};", properties.visible)?;
+    }
+    if properties.rotation_style != defaults.rotation_style {
+        let style = properties.rotation_style.as_str();
+        writeln!(text, "{INDENT}rotation_style {style:?};")?;
+    }
+    if properties.draggable != defaults.draggable {
+        writeln!(text, "{INDENT}draggable {};", properties.draggable)?;
+    }
+    Ok(text)
+}
+
+impl Display for Project {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut blocks = BTreeSet::new();
+        for sprite in self.sprites.iter() {
+            for procedure in sprite.procedures.iter() {
+                collect_blocks(&procedure.block, &mut blocks);
+            }
+        }
+        // Undefined blocks are reported by the verifier.
+        for id in blocks.iter() {
+            if let Some(definition) = self.block_definitions.get(id) {
+                write_block_definition(f, id, definition)?;
+            }
+        }
+
+        for (index, sprite) in self.sprites.iter().enumerate() {
+            if index > 0 || !blocks.is_empty() {
+                f.write_char('\n')?;
+            }
+            write!(f, "{sprite}")?;
+        }
+        Ok(())
+    }
+}
+
+fn collect_blocks<'a>(code: &'a CodeBlock, blocks: &mut BTreeSet<&'a str>) {
+    fn collect_stmt<'a>(stmt: &'a Statement, blocks: &mut BTreeSet<&'a str>) {
+        if let Statement::BlockCall(id, _) = stmt {
+            blocks.insert(id);
+        }
+        for child in stmt.children() {
+            collect_stmt(child, blocks);
+        }
+        for code in stmt.code_blocks() {
+            collect_blocks(code, blocks);
+        }
+    }
+    for stmt in code.code.iter() {
+        collect_stmt(stmt, blocks);
+    }
+}
+
+fn write_block_definition(
+    f: &mut impl Write,
+    id: &str,
+    definition: &BlockDefinition,
+) -> fmt::Result {
+    write!(f, "block {id:?} = ")?;
+    if definition.is_expression {
+        f.write_str("expression ")?;
+    }
+    if definition.is_pure {
+        f.write_str("pure ")?;
+    }
+    let inputs: Vec<String> = definition
+        .inputs
+        .iter()
+        .map(|input| format!("{}: {}", input.name, input.ty))
+        .collect();
+    write!(f, "{}({})", definition.opcode, inputs.join(", "))?;
+    if !definition.fields.is_empty() {
+        let fields: Vec<String> = definition
+            .fields
+            .iter()
+            .map(|field| format!("{} = {:?}", field.name, field.value))
+            .collect();
+        write!(f, " [{}]", fields.join(", "))?;
+    }
+    f.write_str(";\n")
+}
+
+fn write_procedure(f: &mut impl Write, procedure: &Procedure, indent: usize) -> fmt::Result {
+    let prefix = INDENT.repeat(indent);
+    write!(
+        f,
+        "{prefix}proc {:?}({})",
+        procedure.name,
+        Separated(&procedure.inputs)
+    )?;
+    if let Some(returns) = &procedure.returns {
+        write!(f, " -> {returns}")?;
+    }
+    if procedure.is_warp {
+        f.write_str(" warp")?;
+    }
+    if procedure.is_entry {
+        f.write_str(" entry")?;
+    }
+    match procedure.inline {
+        Inline::Auto => {}
+        Inline::Always => f.write_str(" inline")?,
+        Inline::Never => f.write_str(" inline(never)")?,
+    }
+    if procedure.locals.is_empty() && procedure.block.code.is_empty() {
+        return f.write_str(" {}\n");
+    }
+    f.write_str(" {\n")?;
+
+    for (index, local) in procedure.locals.iter().enumerate() {
+        writeln!(
+            f,
+            "{prefix}{INDENT}local %{index} {:?}: {};",
+            local.name, local.dt
+        )?;
+    }
+    if !procedure.locals.is_empty() && !procedure.block.code.is_empty() {
+        f.write_char('\n')?;
+    }
+    write_code(f, &procedure.block, indent + 1)?;
+    writeln!(f, "{prefix}}}")
+}
+
+fn write_code(f: &mut impl Write, code: &CodeBlock, indent: usize) -> fmt::Result {
+    for stmt in code.code.iter() {
+        f.write_str(&INDENT.repeat(indent))?;
+        write_stmt(f, stmt, indent)?;
+        // Control flow ends with a block instead.
+        if !matches!(
+            stmt,
+            Statement::If(..)
+                | Statement::While(..)
+                | Statement::Repeat(..)
+                | Statement::Forever(_)
+                | Statement::Match(..)
+        ) {
+            f.write_char(';')?;
+        }
+        f.write_char('\n')?;
+    }
+    Ok(())
+}
+
+/// Writes block in braces, starting at the current line.
+fn write_block(f: &mut impl Write, code: &CodeBlock, indent: usize) -> fmt::Result {
+    if code.code.is_empty() {
+        return f.write_str("{}");
+    }
+    f.write_str("{\n")?;
+    write_code(f, code, indent + 1)?;
+    write!(f, "{}}}", INDENT.repeat(indent))
+}
+
+fn write_stmt(f: &mut impl Write, stmt: &Statement, indent: usize) -> fmt::Result {
+    let args = |args: &[Statement]| -> Result<String, fmt::Error> {
+        let mut text = String::new();
+        for (index, arg) in args.iter().enumerate() {
+            if index > 0 {
+                text.push_str(", ");
+            }
+            write_stmt(&mut text, arg, indent)?;
+        }
+        Ok(text)
+    };
+    let one = |stmt: &Statement| args(std::slice::from_ref(stmt));
+
+    match stmt {
+        Statement::Constant(value) => write_value(f, value),
+        Statement::BlockCall(id, arguments) => write!(f, "block {id:?}({})", args(arguments)?),
+        Statement::ArgumentRef(index, dt) => write!(f, "arg {index}: {dt}"),
+        Statement::VariableRef(id, name, dt) => write!(f, "var {id:?} {name:?}: {dt}"),
+        Statement::Variant(index, None, dt) => write!(f, "variant {index}: {dt}"),
+        Statement::Variant(index, Some(payload), dt) => {
+            write!(f, "variant {index}({}): {dt}", one(payload)?)
+        }
+        Statement::Payload(target, index, dt) => {
+            write!(f, "payload({}, {index} of {dt})", one(target)?)
+        }
+        Statement::Let(local) => write!(f, "let %{local}"),
+        Statement::LocalRef(local, dt) => write!(f, "%{local}: {dt}"),
+        Statement::Assignment(target, value) => {
+            write!(f, "{} = {}", one(target)?, one(value)?)
+        }
+        Statement::FieldRef(target, index, dt) => {
+            write!(f, "field({}, {index} of {dt})", one(target)?)
+        }
+        Statement::StructureLiteral(values, dt) => {
+            f.write_str("literal(")?;
+            for (index, value) in values.iter().enumerate() {
+                if index > 0 {
+                    f.write_str(", ")?;
+                }
+                write_value(f, value)?;
+            }
+            write!(f, "): {dt}")
+        }
+        Statement::Structure(fields, dt) => write!(f, "struct({}): {dt}", args(fields)?),
+        Statement::Equals(first, second, dt) => {
+            write!(f, "equals({}, {}): {dt}", one(first)?, one(second)?)
+        }
+        Statement::ProcedureCall(name, arguments) => {
+            write!(f, "call {name:?}({})", args(arguments)?)
+        }
+        Statement::If(condition, then, otherwise) => {
+            write!(f, "if {} ", one(condition)?)?;
+            write_block(f, then, indent)?;
+            if let Some(otherwise) = otherwise {
+                f.write_str(" else ")?;
+                write_block(f, otherwise, indent)?;
+            }
+            Ok(())
+        }
+        Statement::Select(condition, then, otherwise, dt) => write!(
+            f,
+            "select({}, {}, {}): {dt}",
+            one(condition)?,
+            one(then)?,
+            one(otherwise)?
+        ),
+        Statement::While(condition, body) => {
+            write!(f, "while {} ", one(condition)?)?;
+            write_block(f, body, indent)
+        }
+        Statement::Repeat(times, body) => {
+            write!(f, "repeat {} ", one(times)?)?;
+            write_block(f, body, indent)
+        }
+        Statement::Forever(body) => {
+            f.write_str("forever ")?;
+            write_block(f, body, indent)
+        }
+        Statement::Match(value, arms, otherwise) => {
+            let prefix = INDENT.repeat(indent + 1);
+            writeln!(f, "match {} {{", one(value)?)?;
+            for (variant, arm) in arms.iter() {
+                write!(f, "{prefix}{variant} => ")?;
+                write_block(f, arm, indent + 1)?;
+                f.write_char('\n')?;
+            }
+            if let Some(otherwise) = otherwise {
+                write!(f, "{prefix}_ => ")?;
+                write_block(f, otherwise, indent + 1)?;
+                f.write_char('\n')?;
+            }
+            write!(f, "{}}}", INDENT.repeat(indent))
+        }
+        Statement::Broadcast(name, false) => write!(f, "broadcast {name:?}"),
+        Statement::Broadcast(name, true) => write!(f, "broadcast {name:?} and wait"),
+        Statement::Break => f.write_str("break"),
+        Statement::Continue => f.write_str("continue"),
+        Statement::Return(None) => f.write_str("return"),
+        Statement::Return(Some(value)) => write!(f, "return {}", one(value)?),
+        Statement::ListRef(index, dt) => write!(f, "list {index}: {dt}"),
+        Statement::SelfField(index, dt) => write!(f, "self {index}: {dt}"),
+        Statement::Spawn(name, values) => write!(f, "spawn {name:?}({})", args(values)?),
+        Statement::List(target, operation) => {
+            let name = match operation {
+                ListOperation::Push(_) => "push",
+                ListOperation::Pop => "pop",
+                ListOperation::Index(_) => "index",
+                ListOperation::Length => "length",
+                ListOperation::Insert(..) => "insert",
+                ListOperation::Delete(_) => "delete",
+                ListOperation::Contains(_) => "contains",
+            };
+            write!(f, "{name}({}", one(target)?)?;
+            for operand in operation.operands() {
+                write!(f, ", {}", one(operand)?)?;
+            }
+            f.write_char(')')
+        }
+    }
+}
+
+fn write_value(f: &mut impl Write, value: &Value) -> fmt::Result {
+    match value {
+        Value::Number(number) => write!(f, "{number}"),
+        Value::Text(text) => write!(f, "{text:?}"),
+        Value::Pointer(id) => write!(f, "pointer {id:?}"),
+        Value::Variable(id, name) => write!(f, "variable {id:?} {name:?}"),
+        Value::Broadcast(id, name) => write!(f, "broadcast {id:?} {name:?}"),
+    }
+}
+
+/// Items separated by commas.
+struct Separated<'a, T>(&'a [T]);
+
+impl<T: Display> Display for Separated<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (index, item) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{item}")?;
+        }
+        Ok(())
+    }
+}
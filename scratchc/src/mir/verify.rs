@@ -0,0 +1,496 @@
+//! Checks that the MIR is well formed, so problems of the passes producing It
+//! are reported instead of panicking during refinement.
+
+use std::collections::HashMap;
+
+use crate::common::error::{MirContext, VerifyError, VerifyErrorKind};
+
+use super::{
+    BlockDefinitions, CodeBlock, DataType, ListOperation, Procedure, Project, Sprite, Statement,
+};
+
+/// Verifies every procedure of the project, returns all found problems.
+pub fn verify(project: &Project) -> Result<(), Vec<VerifyError>> {
+    let mut errors = Vec::new();
+    let sprites = project
+        .sprites
+        .iter()
+        .map(|sprite| (sprite.name.as_str(), sprite))
+        .collect();
+    for sprite in project.sprites.iter() {
+        let procedures = sprite
+            .procedures
+            .iter()
+            .map(|procedure| (procedure.name.as_str(), procedure))
+            .collect();
+        for procedure in sprite.procedures.iter() {
+            let mut cx = VerifyCx {
+                definitions: &project.block_definitions,
+                sprites: &sprites,
+                sprite,
+                procedures: &procedures,
+                procedure,
+                loops: 0,
+                errors: &mut errors,
+            };
+            cx.verify_code(&procedure.block);
+        }
+        for handler in sprite.handlers.iter() {
+            let kind = match procedures.get(handler.procedure.as_str()) {
+                None => VerifyErrorKind::UndefinedProcedure(handler.procedure.clone()),
+                Some(procedure) if !procedure.inputs.is_empty() => VerifyErrorKind::ArgumentCount {
+                    callee: handler.procedure.clone(),
+                    expected: procedure.inputs.len(),
+                    found: 0,
+                },
+                Some(_) => continue,
+            };
+            errors.push(VerifyError {
+                kind,
+                context: MirContext {
+                    sprite: sprite.name.clone(),
+                    procedure: Some(handler.procedure.clone()),
+                    statement: None,
+                },
+            });
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
+}
+
+struct VerifyCx<'a> {
+    definitions: &'a BlockDefinitions,
+    /// Sprites of the project, by name.
+    sprites: &'a HashMap<&'a str, &'a Sprite>,
+    sprite: &'a Sprite,
+    procedures: &'a HashMap<&'a str, &'a Procedure>,
+    procedure: &'a Procedure,
+    /// Number of loops around the current statement.
+    loops: usize,
+    errors: &'a mut Vec<VerifyError>,
+}
+
+impl VerifyCx<'_> {
+    fn error(&mut self, stmt: &Statement, kind: VerifyErrorKind) {
+        self.errors.push(VerifyError {
+            kind,
+            context: MirContext {
+                sprite: self.sprite.name.clone(),
+                procedure: Some(self.procedure.name.clone()),
+                statement: Some(stmt.to_string()),
+            },
+        });
+    }
+
+    fn verify_code(&mut self, code: &CodeBlock) {
+        for stmt in code.code.iter() {
+            self.verify_stmt(stmt);
+        }
+    }
+
+    fn verify_loop(&mut self, body: &CodeBlock) {
+        self.loops += 1;
+        self.verify_code(body);
+        self.loops -= 1;
+    }
+
+    /// Verifies statement and Its children, returns Its type when It is known.
+    /// Types of constants and block calls are unknown, as they are primitives
+    /// which Scratch casts when needed.
+    fn verify_stmt(&mut self, stmt: &Statement) -> Option<DataType> {
+        match stmt {
+            Statement::Constant(_) | Statement::Broadcast(..) => None,
+            Statement::BlockCall(id, args) => {
+                let arg_types: Vec<_> = args.iter().map(|arg| self.verify_value(arg)).collect();
+                let Some(definition) = self.definitions.get(id) else {
+                    self.error(stmt, VerifyErrorKind::UndefinedBlock(id.clone()));
+                    return None;
+                };
+                let inputs = definition.inputs.len();
+                self.check_count(stmt, id, inputs, args.len());
+                for ty in arg_types.into_iter().flatten() {
+                    self.expect_primitive(stmt, ty);
+                }
+                None
+            }
+            Statement::ArgumentRef(index, dt) => {
+                match self.procedure.inputs.get(*index) {
+                    Some(input) => self.expect_type(stmt, input, dt),
+                    None => self.error(stmt, VerifyErrorKind::UndefinedArgument(*index)),
+                }
+                Some(dt.clone())
+            }
+            Statement::VariableRef(.., dt) => Some(dt.clone()),
+            Statement::Variant(index, payload, dt) => {
+                let payload = payload
+                    .as_ref()
+                    .and_then(|payload| self.verify_value(payload));
+                if let Some(variant) = self.variant(stmt, dt, *index) {
+                    if let Some(payload) = payload {
+                        self.expect_type(stmt, &variant, &payload);
+                    }
+                }
+                Some(dt.clone())
+            }
+            Statement::Payload(target, index, dt) => {
+                if let Some(target) = self.verify_value(target) {
+                    self.expect_type(stmt, dt, &target);
+                }
+                self.variant(stmt, dt, *index)
+            }
+            Statement::Let(local) => {
+                if *local >= self.procedure.locals.len() {
+                    self.error(stmt, VerifyErrorKind::UndefinedLocal(*local));
+                }
+                None
+            }
+            Statement::LocalRef(local, dt) => {
+                match self.procedure.locals.get(*local) {
+                    Some(declared) => self.expect_type(stmt, &declared.dt, dt),
+                    None => self.error(stmt, VerifyErrorKind::UndefinedLocal(*local)),
+                }
+                Some(dt.clone())
+            }
+            Statement::Assignment(target, value) => {
+                let target_type = self.verify_stmt(target);
+                if !is_place(target) {
+                    self.error(stmt, VerifyErrorKind::NotPlace);
+                }
+                let value_type = self.verify_value(value);
+                if let Some(target) = target_type {
+                    self.expect_value_type(stmt, &target, value_type);
+                }
+                None
+            }
+            Statement::FieldRef(target, index, dt) => {
+                if let Some(target) = self.verify_value(target) {
+                    self.expect_type(stmt, dt, &target);
+                }
+                let DataType::Structure(fields) = dt else {
+                    self.unexpected_type(stmt, "structure", dt);
+                    return None;
+                };
+                match fields.get(*index) {
+                    Some(field) => Some(field.clone()),
+                    None => {
+                        let count = fields.len();
+                        self.error(
+                            stmt,
+                            VerifyErrorKind::FieldOutOfRange {
+                                index: *index,
+                                count,
+                            },
+                        );
+                        None
+                    }
+                }
+            }
+            Statement::StructureLiteral(values, dt) => {
+                let expected = dt.calculate_size();
+                if values.len() != expected {
+                    let found = values.len();
+                    self.error(stmt, VerifyErrorKind::LiteralSize { expected, found });
+                }
+                Some(dt.clone())
+            }
+            Statement::Structure(fields, dt) => {
+                let types: Vec<_> = fields
+                    .iter()
+                    .map(|field| self.verify_value(field))
+                    .collect();
+                let DataType::Structure(expected) = dt else {
+                    self.unexpected_type(stmt, "structure", dt);
+                    return Some(dt.clone());
+                };
+                if expected.len() != fields.len() {
+                    let (expected, found) = (expected.len(), fields.len());
+                    self.error(stmt, VerifyErrorKind::FieldCount { expected, found });
+                }
+                for (expected, found) in expected.iter().zip(types) {
+                    self.expect_value_type(stmt, expected, found);
+                }
+                Some(dt.clone())
+            }
+            Statement::Equals(first, second, dt) => {
+                for operand in [first, second] {
+                    let found = self.verify_value(operand);
+                    self.expect_value_type(stmt, dt, found);
+                }
+                Some(DataType::Boolean)
+            }
+            Statement::ProcedureCall(name, args) => {
+                let arg_types: Vec<_> = args.iter().map(|arg| self.verify_value(arg)).collect();
+                let Some(callee) = self.procedures.get(name.as_str()) else {
+                    self.error(stmt, VerifyErrorKind::UndefinedProcedure(name.clone()));
+                    return None;
+                };
+                self.check_count(stmt, name, callee.inputs.len(), args.len());
+                for (input, found) in callee.inputs.iter().zip(arg_types) {
+                    self.expect_value_type(stmt, input, found);
+                }
+                callee.returns.clone()
+            }
+            Statement::If(condition, then, otherwise) => {
+                self.verify_condition(condition);
+                self.verify_code(then);
+                if let Some(otherwise) = otherwise {
+                    self.verify_code(otherwise);
+                }
+                None
+            }
+            Statement::Select(condition, then, otherwise, dt) => {
+                self.verify_condition(condition);
+                for value in [then, otherwise] {
+                    let found = self.verify_value(value);
+                    self.expect_value_type(stmt, dt, found);
+                }
+                Some(dt.clone())
+            }
+            Statement::While(condition, body) => {
+                self.verify_condition(condition);
+                self.verify_loop(body);
+                None
+            }
+            Statement::Repeat(times, body) => {
+                self.verify_condition(times);
+                self.verify_loop(body);
+                None
+            }
+            Statement::Forever(body) => {
+                self.verify_loop(body);
+                None
+            }
+            Statement::Match(value, arms, otherwise) => {
+                match self.verify_value(value) {
+                    Some(DataType::Enum(variants)) => {
+                        for (index, _) in arms.iter() {
+                            if *index >= variants.len() {
+                                let count = variants.len();
+                                self.error(
+                                    stmt,
+                                    VerifyErrorKind::VariantOutOfRange {
+                                        index: *index,
+                                        count,
+                                    },
+                                );
+                            }
+                        }
+                    }
+                    Some(found) => self.unexpected_type(value, "enum", &found),
+                    None => {}
+                }
+                for (_, arm) in arms.iter() {
+                    self.verify_code(arm);
+                }
+                if let Some(otherwise) = otherwise {
+                    self.verify_code(otherwise);
+                }
+                None
+            }
+            Statement::Break | Statement::Continue => {
+                if self.loops == 0 {
+                    self.error(stmt, VerifyErrorKind::JumpOutsideLoop);
+                }
+                None
+            }
+            Statement::Return(value) => {
+                let found = value.as_ref().map(|value| self.verify_value(value));
+                match (&self.procedure.returns.clone(), found) {
+                    (Some(expected), Some(found)) => self.expect_value_type(stmt, expected, found),
+                    (Some(_), None) => self.error(stmt, VerifyErrorKind::MissingReturnValue),
+                    (None, Some(_)) => self.error(stmt, VerifyErrorKind::UnexpectedReturnValue),
+                    (None, None) => {}
+                }
+                None
+            }
+            Statement::SelfField(index, dt) => {
+                match self.sprite.fields.get(*index) {
+                    Some(field) => self.expect_type(stmt, &field.dt, dt),
+                    None => self.error(stmt, VerifyErrorKind::UndefinedField(*index)),
+                }
+                Some(dt.clone())
+            }
+            Statement::Spawn(name, values) => {
+                let found: Vec<_> = values
+                    .iter()
+                    .map(|value| self.verify_value(value))
+                    .collect();
+                let Some(sprite) = self.sprites.get(name.as_str()) else {
+                    self.error(stmt, VerifyErrorKind::UndefinedSprite(name.clone()));
+                    return None;
+                };
+                if sprite.is_stage {
+                    self.error(stmt, VerifyErrorKind::CloneOfStage);
+                    return None;
+                }
+                self.check_count(stmt, name, sprite.fields.len(), values.len());
+                for (field, found) in sprite.fields.iter().zip(found) {
+                    self.expect_value_type(stmt, &field.dt, found);
+                }
+                None
+            }
+            Statement::ListRef(index, dt) => {
+                match self.sprite.lists.get(*index) {
+                    Some(list) => {
+                        let expected = DataType::List(Box::new(list.item.clone()));
+                        self.expect_type(stmt, &expected, dt);
+                    }
+                    None => self.error(stmt, VerifyErrorKind::UndefinedList(*index)),
+                }
+                Some(dt.clone())
+            }
+            Statement::List(target, operation) => {
+                let item = match self.verify_stmt(target) {
+                    Some(DataType::List(item)) if matches!(**target, Statement::ListRef(..)) => {
+                        Some(*item)
+                    }
+                    _ => {
+                        self.error(stmt, VerifyErrorKind::NotList);
+                        None
+                    }
+                };
+                let operands: Vec<_> = operation
+                    .operands()
+                    .into_iter()
+                    .map(|operand| self.verify_value(operand))
+                    .collect();
+                let item = item?;
+                match operation {
+                    ListOperation::Push(_) | ListOperation::Contains(_) => {
+                        self.expect_value_type(stmt, &item, operands[0].clone())
+                    }
+                    ListOperation::Insert(..) => {
+                        self.expect_value_type(stmt, &item, operands[1].clone())
+                    }
+                    _ => {}
+                }
+                match operation {
+                    ListOperation::Pop | ListOperation::Index(_) => Some(item),
+                    ListOperation::Length => Some(DataType::Number),
+                    ListOperation::Contains(_) => Some(DataType::Boolean),
+                    ListOperation::Push(_)
+                    | ListOperation::Insert(..)
+                    | ListOperation::Delete(_) => None,
+                }
+            }
+        }
+    }
+
+    /// Verifies statement used as a value, returns Its type when It is known.
+    fn verify_value(&mut self, stmt: &Statement) -> Option<DataType> {
+        let is_value = match stmt {
+            Statement::BlockCall(id, _) => self
+                .definitions
+                .get(id)
+                .is_none_or(|definition| definition.is_expression),
+            Statement::ProcedureCall(name, _) => self
+                .procedures
+                .get(name.as_str())
+                .is_none_or(|callee| callee.returns.is_some()),
+            Statement::List(_, operation) => !matches!(
+                operation,
+                ListOperation::Push(_) | ListOperation::Insert(..) | ListOperation::Delete(_)
+            ),
+            Statement::Let(_)
+            | Statement::Assignment(..)
+            | Statement::If(..)
+            | Statement::While(..)
+            | Statement::Repeat(..)
+            | Statement::Forever(_)
+            | Statement::Match(..)
+            | Statement::Broadcast(..)
+            | Statement::Spawn(..)
+            | Statement::Break
+            | Statement::Continue
+            | Statement::Return(_) => false,
+            _ => true,
+        };
+        if !is_value {
+            self.error(stmt, VerifyErrorKind::NotValue);
+        }
+        self.verify_stmt(stmt)
+    }
+
+    /// Verifies primitive value, like a condition or a number of repetitions.
+    fn verify_condition(&mut self, stmt: &Statement) {
+        if let Some(found) = self.verify_value(stmt) {
+            self.expect_primitive(stmt, found);
+        }
+    }
+
+    /// Type of the enum's variant, when It exists.
+    fn variant(&mut self, stmt: &Statement, dt: &DataType, index: usize) -> Option<DataType> {
+        let DataType::Enum(variants) = dt else {
+            self.unexpected_type(stmt, "enum", dt);
+            return None;
+        };
+        let variant = variants.get(index).cloned();
+        if variant.is_none() {
+            let count = variants.len();
+            self.error(stmt, VerifyErrorKind::VariantOutOfRange { index, count });
+        }
+        variant
+    }
+
+    fn check_count(&mut self, stmt: &Statement, callee: &str, expected: usize, found: usize) {
+        if expected != found {
+            let callee = callee.to_owned();
+            self.error(
+                stmt,
+                VerifyErrorKind::ArgumentCount {
+                    callee,
+                    expected,
+                    found,
+                },
+            );
+        }
+    }
+
+    fn expect_type(&mut self, stmt: &Statement, expected: &DataType, found: &DataType) {
+        if expected != found {
+            let expected = expected.clone();
+            let found = found.clone();
+            self.error(stmt, VerifyErrorKind::TypeMismatch { expected, found });
+        }
+    }
+
+    /// Values of unknown types are primitives.
+    fn expect_value_type(
+        &mut self,
+        stmt: &Statement,
+        expected: &DataType,
+        found: Option<DataType>,
+    ) {
+        match found {
+            Some(found) => self.expect_type(stmt, expected, &found),
+            None => {
+                if !expected.is_primitive() {
+                    self.error(stmt, VerifyErrorKind::PrimitiveMismatch(expected.clone()));
+                }
+            }
+        }
+    }
+
+    fn expect_primitive(&mut self, stmt: &Statement, found: DataType) {
+        if !found.is_primitive() {
+            self.error(stmt, VerifyErrorKind::NotPrimitive(found));
+        }
+    }
+
+    fn unexpected_type(&mut self, stmt: &Statement, kind: &'static str, found: &DataType) {
+        let found = found.clone();
+        self.error(stmt, VerifyErrorKind::UnexpectedType { kind, found });
+    }
+}
+
+/// Whether statement can be assigned to.
+fn is_place(stmt: &Statement) -> bool {
+    match stmt {
+        Statement::LocalRef(..) | Statement::VariableRef(..) | Statement::SelfField(..) => true,
+        Statement::FieldRef(target, ..) | Statement::Payload(target, ..) => is_place(target),
+        _ => false,
+    }
+}